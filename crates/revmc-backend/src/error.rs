@@ -0,0 +1,146 @@
+use std::fmt;
+
+/// Compilation error.
+///
+/// This is the concrete error type of [`Error`](crate::Error) when the `eyre` feature is disabled.
+/// Otherwise, errors created by this crate and the backends can be retrieved from the
+/// `eyre::Report` with `downcast_ref::<RevmcError>()`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RevmcError {
+    /// The operation is not supported by the target or the backend.
+    Unsupported(String),
+    /// The bytecode is invalid and cannot be compiled.
+    InvalidBytecode(String),
+    /// The compiler was used incorrectly, e.g. compiling after finalizing the module.
+    InvalidUsage(String),
+    /// An error reported by the codegen backend.
+    Backend(String),
+    /// Linking the compiled objects failed.
+    Link(String),
+    /// A compiled function diverged from the interpreter during verification.
+    Verification(String),
+    /// An I/O error.
+    Io(std::io::Error),
+}
+
+impl fmt::Display for RevmcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported(msg) => write!(f, "unsupported: {msg}"),
+            Self::InvalidBytecode(msg) => write!(f, "invalid bytecode: {msg}"),
+            Self::InvalidUsage(msg) => f.write_str(msg),
+            Self::Backend(msg) => write!(f, "backend error: {msg}"),
+            Self::Link(msg) => write!(f, "linking failed: {msg}"),
+            Self::Verification(msg) => write!(f, "verification failed: {msg}"),
+            Self::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RevmcError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for RevmcError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl RevmcError {
+    /// Creates a new [`Backend`](Self::Backend) error from the given error message.
+    pub fn backend(msg: impl fmt::Display) -> Self {
+        Self::Backend(msg.to_string())
+    }
+}
+
+/// Returns early with a [`RevmcError`] of the given kind, converted into [`Error`](crate::Error).
+///
+/// # Examples
+///
+/// ```
+/// fn compile(finalized: bool) -> revmc_backend::Result<()> {
+///     if finalized {
+///         revmc_backend::bail!(InvalidUsage, "module is already finalized");
+///     }
+///     Ok(())
+/// }
+/// # assert!(compile(true).is_err());
+/// ```
+#[macro_export]
+macro_rules! bail {
+    ($kind:ident, $($arg:tt)+) => {
+        return ::core::result::Result::Err(
+            $crate::RevmcError::$kind(::std::format!($($arg)+)).into(),
+        )
+    };
+}
+
+/// Returns early with a [`RevmcError`] of the given kind if the condition is not satisfied.
+///
+/// See [`bail!`].
+#[macro_export]
+macro_rules! ensure {
+    ($cond:expr, $kind:ident, $($arg:tt)+) => {
+        if !$cond {
+            $crate::bail!($kind, $($arg)+);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Result;
+
+    fn check(len: usize) -> Result<usize> {
+        ensure!(len <= 4, InvalidBytecode, "too long: {len}");
+        if len == 0 {
+            bail!(Unsupported, "empty");
+        }
+        std::fs::metadata("/nonexistent/revmc").map_err(RevmcError::Io)?;
+        Ok(len)
+    }
+
+    #[allow(clippy::let_and_return)]
+    fn kind(r: Result<usize>) -> RevmcError {
+        let e = r.unwrap_err();
+        #[cfg(feature = "eyre")]
+        let e = e.downcast::<RevmcError>().unwrap();
+        e
+    }
+
+    #[test]
+    fn variants() {
+        match kind(check(5)) {
+            RevmcError::InvalidBytecode(msg) => assert_eq!(msg, "too long: 5"),
+            e => panic!("unexpected error: {e:?}"),
+        }
+        match kind(check(0)) {
+            RevmcError::Unsupported(msg) => assert_eq!(msg, "empty"),
+            e => panic!("unexpected error: {e:?}"),
+        }
+        match kind(check(1)) {
+            RevmcError::Io(e) => assert_eq!(e.kind(), std::io::ErrorKind::NotFound),
+            e => panic!("unexpected error: {e:?}"),
+        }
+
+        let e = RevmcError::backend("oops");
+        assert!(matches!(&e, RevmcError::Backend(msg) if msg == "oops"));
+        assert_eq!(e.to_string(), "backend error: oops");
+        assert!(std::error::Error::source(&e).is_none());
+
+        let e = RevmcError::from(std::io::Error::from(std::io::ErrorKind::NotFound));
+        let source = std::error::Error::source(&e).unwrap();
+        assert_eq!(
+            source.downcast_ref::<std::io::Error>().unwrap().kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+}
@@ -6,11 +6,15 @@
 mod traits;
 pub use traits::*;
 
+#[cfg(feature = "eyre")]
 #[doc(no_inline)]
 pub use eyre;
 #[doc(no_inline)]
 pub use ruint::{self, aliases::U256, uint};
 
+mod error;
+pub use error::RevmcError;
+
 mod pointer;
 pub use pointer::{Pointer, PointerBase};
 
@@ -18,4 +22,13 @@ pub use pointer::{Pointer, PointerBase};
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 /// Compilation error.
+///
+/// This is an `eyre::Report` if the `eyre` feature is enabled, and [`RevmcError`] otherwise.
+#[cfg(feature = "eyre")]
 pub type Error = eyre::Error;
+
+/// Compilation error.
+///
+/// This is an `eyre::Report` if the `eyre` feature is enabled, and [`RevmcError`] otherwise.
+#[cfg(not(feature = "eyre"))]
+pub type Error = RevmcError;
@@ -127,6 +127,8 @@ pub enum Attribute {
     HintInline,
     AlwaysInline,
     NoInline,
+    /// Disables optimizations on the function. Must be combined with [`NoInline`](Self::NoInline).
+    OptimizeNone,
     Speculatable,
 
     // Parameter attributes.
@@ -167,6 +169,20 @@ pub enum FunctionAttributeLocation {
     Function,
 }
 
+/// Function calling convention.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum CallingConvention {
+    /// The target's C calling convention.
+    #[default]
+    C,
+    /// Fast calling convention, only callable from code built with the same convention.
+    Fast,
+    /// Calling convention for functions that are rarely called.
+    Cold,
+    /// Calling convention that supports guaranteed tail calls with [`TailCallKind::MustTail`].
+    Tail,
+}
+
 /// Tail call kind.
 #[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
 pub enum TailCallKind {
@@ -227,6 +243,13 @@ pub trait Backend: BackendTypes + TypeMethods {
     fn jit_function(&mut self, id: Self::FuncId) -> Result<usize>;
     unsafe fn free_function(&mut self, id: Self::FuncId) -> Result<()>;
     unsafe fn free_all_functions(&mut self) -> Result<()>;
+    /// (JIT) Starts a new module for the functions built from now on, after the current one was
+    /// finalized. The functions of the previous modules must remain valid.
+    ///
+    /// Fails by default, meaning that no functions can be built after finalizing the module.
+    fn new_module(&mut self) -> Result<()> {
+        crate::bail!(Unsupported, "cannot create a new module with this backend")
+    }
 }
 
 pub trait TypeMethods: BackendTypes {
@@ -235,6 +258,14 @@ pub trait TypeMethods: BackendTypes {
     fn type_int(&self, bits: u32) -> Self::Type;
     fn type_array(&self, ty: Self::Type, size: u32) -> Self::Type;
     fn type_bit_width(&self, ty: Self::Type) -> u32;
+    /// Returns a vector type of `len` elements of the integer type `ty`, or `None` if unsupported.
+    ///
+    /// Vector values can only be used with [`bitcast`](Builder::bitcast) and the bitwise
+    /// operations.
+    fn type_vector(&self, ty: Self::Type, len: u32) -> Option<Self::Type> {
+        let _ = (ty, len);
+        None
+    }
 }
 
 pub trait Builder: BackendTypes + TypeMethods {
@@ -308,6 +339,11 @@ pub trait Builder: BackendTypes + TypeMethods {
     );
     fn br_indirect(&mut self, address: Self::Value, destinations: &[Self::BasicBlock]);
     fn phi(&mut self, ty: Self::Type, incoming: &[(Self::Value, Self::BasicBlock)]) -> Self::Value;
+    /// Returns `then_value` if the boolean `cond` is `true`, and `else_value` otherwise.
+    ///
+    /// Both values must have the same type, and are always evaluated, so this must not be used to
+    /// guard an operation that is undefined for some operands, such as a division by zero. Use
+    /// [`lazy_select`](Self::lazy_select) to only evaluate the selected value.
     fn select(
         &mut self,
         cond: Self::Value,
@@ -361,6 +397,14 @@ pub trait Builder: BackendTypes + TypeMethods {
     fn sext(&mut self, ty: Self::Type, value: Self::Value) -> Self::Value;
     #[doc(alias = "trunc")]
     fn ireduce(&mut self, to: Self::Type, value: Self::Value) -> Self::Value;
+    /// Reinterprets the bits of `value` as `ty`, which must have the same size.
+    ///
+    /// Defaults to storing `value` in a new stack slot and loading it back as `ty`.
+    fn bitcast(&mut self, ty: Self::Type, value: Self::Value) -> Self::Value {
+        let slot = self.new_stack_slot_raw(ty, "bitcast");
+        self.stack_store(value, slot);
+        self.stack_load(ty, slot, "bitcast")
+    }
 
     fn gep(
         &mut self,
@@ -393,6 +437,28 @@ pub trait Builder: BackendTypes + TypeMethods {
 
     fn unreachable(&mut self);
 
+    /// Records a stack map at the current location with the given `id`, keeping the `live`
+    /// values available to a stack walker.
+    ///
+    /// Does nothing by default.
+    fn stackmap(&mut self, id: u64, live: &[Self::Value]) {
+        let _ = (id, live);
+    }
+
+    /// Marks the start of the lifetime of the `size` bytes of stack memory at `ptr`.
+    ///
+    /// Does nothing by default.
+    fn lifetime_start(&mut self, ptr: Self::Value, size: u64) {
+        let _ = (ptr, size);
+    }
+
+    /// Marks the end of the lifetime of the `size` bytes of stack memory at `ptr`.
+    ///
+    /// Does nothing by default.
+    fn lifetime_end(&mut self, ptr: Self::Value, size: u64) {
+        let _ = (ptr, size);
+    }
+
     fn get_or_build_function(
         &mut self,
         name: &str,
@@ -427,4 +493,19 @@ pub trait Builder: BackendTypes + TypeMethods {
         attribute: Attribute,
         loc: FunctionAttributeLocation,
     );
+
+    /// Sets the calling convention of a function.
+    ///
+    /// If `function` is `None`, the calling convention is set on the current function.
+    /// Calls to the function use the same calling convention.
+    ///
+    /// Does nothing by default, in which case the function and the calls to it keep using the
+    /// default calling convention.
+    fn set_calling_convention(
+        &mut self,
+        function: Option<Self::Function>,
+        calling_convention: CallingConvention,
+    ) {
+        let _ = (function, calling_convention);
+    }
 }
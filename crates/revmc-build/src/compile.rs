@@ -0,0 +1,218 @@
+use revmc::{
+    primitives::{hex, keccak256, SpecId},
+    EvmCompiler, EvmLlvmBackend, OptimizationLevel, Result,
+};
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Compiles contracts ahead of time in a build script and statically links them into the crate.
+///
+/// [`compile`](Self::compile) writes `$OUT_DIR/<name>.rs`, which declares every contract as an
+/// `extern "C"` function and defines a `register` function that inserts all of them into an
+/// `EvmCompilerFns` map, keyed by the hash of their bytecode and their `SpecId`.
+///
+/// # Examples
+///
+/// In `build.rs`:
+///
+/// ```no_run
+/// # fn main() -> revmc::Result<()> {
+/// use revmc::primitives::SpecId;
+///
+/// revmc_build::emit();
+/// revmc_build::AotContracts::new("contracts")
+///     .add_file("weth", "contracts/weth.hex", SpecId::CANCUN)?
+///     .compile()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Then in the crate:
+///
+/// ```ignore
+/// include!(concat!(env!("OUT_DIR"), "/contracts.rs"));
+///
+/// let fns = revmc_context::EvmCompilerFns::new();
+/// register(&fns);
+/// ```
+#[derive(Debug)]
+pub struct AotContracts {
+    name: String,
+    opt_level: OptimizationLevel,
+    contracts: Vec<(String, Vec<u8>, SpecId)>,
+}
+
+impl AotContracts {
+    /// Creates a new, empty set of contracts.
+    ///
+    /// `name` is the name of the generated module and of the static library.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into(), opt_level: OptimizationLevel::Aggressive, contracts: Vec::new() }
+    }
+
+    /// Sets the optimization level. Defaults to [`OptimizationLevel::Aggressive`].
+    pub fn opt_level(&mut self, opt_level: OptimizationLevel) -> &mut Self {
+        self.opt_level = opt_level;
+        self
+    }
+
+    /// Adds a contract.
+    ///
+    /// `name` is the name of the declared function, and must be a valid Rust identifier that is
+    /// unique across all the linked contracts.
+    pub fn add(
+        &mut self,
+        name: impl Into<String>,
+        bytecode: impl Into<Vec<u8>>,
+        spec_id: SpecId,
+    ) -> &mut Self {
+        self.contracts.push((name.into(), bytecode.into(), spec_id));
+        self
+    }
+
+    /// Adds a contract from a file, which contains either the hex-encoded or the raw bytecode.
+    ///
+    /// The contents are decoded as hex if, once trimmed, they start with `0x` or only consist of
+    /// hex digits, in which case an error is returned if they are not valid hex. Otherwise, they
+    /// are the raw bytecode.
+    ///
+    /// See [`add`](Self::add).
+    pub fn add_file(
+        &mut self,
+        name: impl Into<String>,
+        path: impl AsRef<Path>,
+        spec_id: SpecId,
+    ) -> Result<&mut Self> {
+        let path = path.as_ref();
+        println!("cargo:rerun-if-changed={}", path.display());
+        let contents = fs::read(path)?;
+        let bytecode = match std::str::from_utf8(&contents).ok().map(str::trim) {
+            Some(s) if is_hex_like(s) => match hex::decode(s) {
+                Ok(bytecode) => bytecode,
+                Err(e) => {
+                    revmc::bail!(InvalidBytecode, "{}: invalid hex bytecode: {e}", path.display())
+                }
+            },
+            _ => contents,
+        };
+        Ok(self.add(name, bytecode, spec_id))
+    }
+
+    /// Compiles all the contracts into a static library linked into the crate, and generates the
+    /// module with their declarations.
+    ///
+    /// Must be called from a build script.
+    pub fn compile(&self) -> Result<()> {
+        for (name, ..) in &self.contracts {
+            revmc::ensure!(is_ident(name), InvalidUsage, "`{name}` is not a valid identifier");
+        }
+
+        let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").expect("OUT_DIR is not set"));
+        let cx = revmc::llvm::inkwell::context::Context::create();
+        let backend = EvmLlvmBackend::new(&cx, true, self.opt_level)?;
+        let mut compiler = EvmCompiler::new(backend);
+        compiler.set_module_name(&self.name);
+        for (name, bytecode, spec_id) in &self.contracts {
+            compiler.translate(name, &bytecode[..], *spec_id)?;
+        }
+        let object = out_dir.join(&self.name).with_extension("o");
+        compiler.write_object_to_file(&object)?;
+        cc::Build::new().object(&object).static_flag(true).compile(&self.name);
+
+        fs::write(out_dir.join(&self.name).with_extension("rs"), self.generate())?;
+        Ok(())
+    }
+
+    fn generate(&self) -> String {
+        let mut s = String::from("// @generated by revmc-build. Do not edit.\n\n");
+        s.push_str("::revmc_context::extern_revmc! {\n");
+        for (name, ..) in &self.contracts {
+            writeln!(s, "    pub fn {name};").unwrap();
+        }
+        s.push_str("}\n\n");
+
+        s.push_str("/// Registers all the statically linked contracts in `fns`.\n");
+        s.push_str("pub fn register(fns: &::revmc_context::EvmCompilerFns) {\n");
+        s.push_str("    use ::revmc_context::private::revm_primitives::{SpecId, B256};\n");
+        for (name, bytecode, spec_id) in &self.contracts {
+            let hash = keccak256(bytecode);
+            writeln!(s, "    // SAFETY: `{name}` was compiled from the bytecode with this hash.")
+                .unwrap();
+            writeln!(s, "    unsafe {{").unwrap();
+            writeln!(s, "        fns.insert(").unwrap();
+            writeln!(s, "            B256::new({:?}),", hash.0).unwrap();
+            writeln!(s, "            ::revmc_context::EvmCompilerFnInfo {{").unwrap();
+            writeln!(s, "                f: ::revmc_context::EvmCompilerFn::new({name}),").unwrap();
+            writeln!(s, "                spec_id: SpecId::{spec_id:?},").unwrap();
+            writeln!(s, "            }},").unwrap();
+            writeln!(s, "        );").unwrap();
+            writeln!(s, "    }}").unwrap();
+        }
+        s.push_str("}\n");
+        s
+    }
+}
+
+fn is_hex_like(s: &str) -> bool {
+    s.starts_with("0x") || (!s.is_empty() && s.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().is_some_and(|c| c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_file() {
+        let dir = std::env::temp_dir().join(format!("revmc-build-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut contracts = AotContracts::new("contracts");
+        let mut add_file = |contents: &[u8]| {
+            let path = dir.join("contract");
+            fs::write(&path, contents).unwrap();
+            contracts.add_file("contract", &path, SpecId::CANCUN).map(|c| c.contracts.len())
+        };
+        assert_eq!(add_file(b"6001600101\n").unwrap(), 1);
+        assert_eq!(add_file(b"0x6001").unwrap(), 2);
+        assert_eq!(add_file(&[0x60, 0x01, 0xff]).unwrap(), 3);
+        assert!(add_file(b"0").unwrap_err().to_string().contains("invalid hex"));
+        assert!(add_file(b"0x60zz").unwrap_err().to_string().contains("invalid hex"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let bytecodes = contracts.contracts.iter().map(|(_, b, _)| &b[..]).collect::<Vec<_>>();
+        assert_eq!(
+            bytecodes,
+            [&[0x60, 0x01, 0x60, 0x01, 0x01][..], &[0x60, 0x01], &[0x60, 0x01, 0xff]]
+        );
+    }
+
+    #[test]
+    fn generate() {
+        let add: &[u8] = &[0x60, 0x01, 0x60, 0x01, 0x01];
+        let stop: &[u8] = &[0x00];
+        let generated = AotContracts::new("contracts")
+            .add("add", add, SpecId::CANCUN)
+            .add("stop", stop, SpecId::SHANGHAI)
+            .generate();
+        assert!(generated.starts_with("// @generated"), "{generated}");
+        assert!(generated.contains("    pub fn add;\n    pub fn stop;\n"), "{generated}");
+        assert!(generated.contains("pub fn register(fns: &::revmc_context::EvmCompilerFns)"));
+        for (name, bytecode, spec_id) in [("add", add, "CANCUN"), ("stop", stop, "SHANGHAI")] {
+            let insert = format!(
+                "B256::new({:?}),\n            ::revmc_context::EvmCompilerFnInfo {{\n                \
+                 f: ::revmc_context::EvmCompilerFn::new({name}),\n                \
+                 spec_id: SpecId::{spec_id},",
+                keccak256(bytecode).0
+            );
+            assert!(generated.contains(&insert), "{generated}");
+        }
+    }
+}
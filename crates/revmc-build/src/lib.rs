@@ -2,6 +2,11 @@
 #![cfg_attr(not(test), warn(unused_extern_crates))]
 #![cfg_attr(docsrs, feature(doc_cfg, doc_auto_cfg))]
 
+#[cfg(feature = "compile")]
+mod compile;
+#[cfg(feature = "compile")]
+pub use compile::AotContracts;
+
 // Must be kept in sync with `remvc-builtins`.
 const MANGLE_PREFIX: &str = "__revmc_builtin_";
 
@@ -3,9 +3,26 @@ use revmc_backend::{Attribute, Backend, Builder, FunctionAttributeLocation, Type
 // Must be kept in sync with `remvc-build`.
 const MANGLE_PREFIX: &str = "__revmc_builtin_";
 
+/// Attributes of all the functions called by the compiled code, except for the panic builtin.
+///
+/// Builtins are intentionally not marked `ReadNone`/`ReadOnly` or `Speculatable`: they may read and
+/// write any memory reachable from their arguments (e.g. growing `EvmContext::memory`), so they
+/// must act as memory barriers for the compiled code which reads this state directly after the
+/// call.
+const DEFAULT_ATTRS: &[Attribute] = &[
+    Attribute::WillReturn,
+    Attribute::NoFree,
+    Attribute::NoRecurse,
+    Attribute::NoSync,
+    Attribute::NoUnwind,
+];
+
 /// Builtin cache.
 #[derive(Debug)]
-pub struct Builtins<B: Backend>([Option<B::Function>; Builtin::COUNT]);
+pub struct Builtins<B: Backend> {
+    functions: [Option<B::Function>; Builtin::COUNT],
+    reentrant: bool,
+}
 
 impl<B: Backend> Default for Builtins<B> {
     fn default() -> Self {
@@ -16,31 +33,83 @@ impl<B: Backend> Default for Builtins<B> {
 impl<B: Backend> Builtins<B> {
     /// Create a new cache.
     pub fn new() -> Self {
-        Self([None; Builtin::COUNT])
+        Self { functions: [None; Builtin::COUNT], reentrant: false }
     }
 
     /// Clear the cache.
     pub fn clear(&mut self) {
-        *self = Self::new();
+        self.functions = [None; Builtin::COUNT];
+    }
+
+    /// Sets whether the builtins may re-enter the compiled functions through the host.
+    ///
+    /// If so, the builtins are not marked as `NoRecurse`. Only affects builtins that are not yet
+    /// in the cache.
+    pub fn set_reentrant(&mut self, yes: bool) {
+        self.reentrant = yes;
     }
 
     /// Get the function for the given builtin.
     pub fn get(&mut self, builtin: Builtin, bcx: &mut B::Builder<'_>) -> B::Function {
-        *self.0[builtin as usize].get_or_insert_with(|| Self::init(builtin, bcx))
+        let reentrant = self.reentrant;
+        *self.functions[builtin as usize].get_or_insert_with(|| Self::init(builtin, reentrant, bcx))
     }
 
     #[cold]
-    fn init(builtin: Builtin, bcx: &mut B::Builder<'_>) -> B::Function {
+    fn init(builtin: Builtin, reentrant: bool, bcx: &mut B::Builder<'_>) -> B::Function {
         let name = builtin.name();
         debug_assert!(name.starts_with(MANGLE_PREFIX), "{name:?}");
         bcx.get_function(name).inspect(|r| trace!(name, ?r, "pre-existing")).unwrap_or_else(|| {
-            let r = Self::build(name, builtin, bcx);
+            let r = Self::build(name, builtin, reentrant, bcx);
             trace!(name, ?r, "built");
             r
         })
     }
 
-    fn build(name: &str, builtin: Builtin, bcx: &mut B::Builder<'_>) -> B::Function {
+    /// Returns the native function `name` located at `address`, declaring it with the given
+    /// signature if it is not yet in the module.
+    ///
+    /// This allows calling functions other than the builtins, such as user-provided handlers.
+    /// The function is declared with the same default attributes as the builtins, in addition to
+    /// `attrs`, and must use the C calling convention.
+    pub fn get_extern(
+        &self,
+        bcx: &mut B::Builder<'_>,
+        name: &str,
+        params: &[B::Type],
+        ret: Option<B::Type>,
+        address: usize,
+        attrs: &[Attribute],
+    ) -> B::Function {
+        bcx.get_function(name).unwrap_or_else(|| {
+            let linkage = revmc_backend::Linkage::Import;
+            let f = bcx.add_function(name, params, ret, Some(address), linkage);
+            Self::add_function_attributes(bcx, f, self.reentrant, DEFAULT_ATTRS, attrs);
+            trace!(name, ?f, "declared extern");
+            f
+        })
+    }
+
+    fn add_function_attributes(
+        bcx: &mut B::Builder<'_>,
+        f: B::Function,
+        reentrant: bool,
+        default_attrs: &[Attribute],
+        attrs: &[Attribute],
+    ) {
+        let default_attrs =
+            default_attrs.iter().filter(|&&attr| !(reentrant && attr == Attribute::NoRecurse));
+        for attr in default_attrs.chain(attrs).copied() {
+            bcx.add_function_attribute(Some(f), attr, FunctionAttributeLocation::Function);
+        }
+    }
+
+    fn build(
+        name: &str,
+        builtin: Builtin,
+        reentrant: bool,
+        bcx: &mut B::Builder<'_>,
+    ) -> B::Function {
         let ret = builtin.ret(bcx);
         let params = builtin.params(bcx);
         let address = builtin.addr();
@@ -55,18 +124,9 @@ impl<B: Backend> Builtins<B> {
                 Attribute::NoSync,
             ]
         } else {
-            &[
-                Attribute::WillReturn,
-                Attribute::NoFree,
-                Attribute::NoRecurse,
-                Attribute::NoSync,
-                Attribute::NoUnwind,
-                Attribute::Speculatable,
-            ]
+            DEFAULT_ATTRS
         };
-        for attr in default_attrs.iter().chain(builtin.attrs()).copied() {
-            bcx.add_function_attribute(Some(f), attr, FunctionAttributeLocation::Function);
-        }
+        Self::add_function_attributes(bcx, f, reentrant, default_attrs, builtin.attrs());
         let param_attrs = builtin.param_attrs();
         for (i, param_attrs) in param_attrs.iter().enumerate() {
             for attr in param_attrs {
@@ -82,49 +142,60 @@ impl<B: Backend> Builtins<B> {
 }
 
 macro_rules! builtins {
-    (@count) => { 0 };
-    (@count $first:tt $(, $rest:tt)*) => { 1 + builtins!(@count $($rest),*) };
-
     (@param_attr $default:ident) => { $default() };
     (@param_attr $default:ident $name:expr) => { $name };
 
+    (@success) => { revm_interpreter::InstructionResult::Continue };
+    (@success $success:ident) => { revm_interpreter::InstructionResult::$success };
+
     (@types |$bcx:ident| { $($types_init:tt)* }
      @param_attrs |$op:ident| { $($attrs_init:tt)* }
-     $($ident:ident = $(#[$attr:expr])* $name:ident($($(@[$param_attr:expr])? $params:expr),* $(,)?) $ret:expr),* $(,)?
+     $($(#[cfg($cfg:meta)])? $ident:ident = $(@[success = $success:ident])? $(#[$attr:expr])* $name:ident($($(@[$param_attr:expr])? $params:expr),* $(,)?) $ret:expr),* $(,)?
     ) => { paste::paste! {
         /// Builtins that can be called by the compiled functions.
         #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
         pub enum Builtin {
-            $($ident,)*
+            $($(#[cfg($cfg)])? $ident,)*
         }
 
         #[allow(unused_variables)]
         impl Builtin {
-            pub const COUNT: usize = builtins!(@count $($ident),*);
+            pub const COUNT: usize = [$($(#[cfg($cfg)])? Self::$ident),*].len();
 
             pub const fn name(self) -> &'static str {
                 match self {
-                    $(Self::$ident => stringify!($name),)*
+                    $($(#[cfg($cfg)])? Self::$ident => stringify!($name),)*
+                }
+            }
+
+            /// Returns the [`InstructionResult`](revm_interpreter::InstructionResult) that this
+            /// builtin returns on success, if it is fallible.
+            ///
+            /// Any other value is treated as a failure and returned from the compiled function.
+            /// Defaults to `Continue`.
+            pub const fn success(self) -> revm_interpreter::InstructionResult {
+                match self {
+                    $($(#[cfg($cfg)])? Self::$ident => builtins!(@success $($success)?),)*
                 }
             }
 
             pub fn addr(self) -> usize {
                 match self {
-                    $(Self::$ident => crate::$name as usize,)*
+                    $($(#[cfg($cfg)])? Self::$ident => crate::$name as usize,)*
                 }
             }
 
             pub fn ret<B: TypeMethods>(self, $bcx: &mut B) -> Option<B::Type> {
                 $($types_init)*
                 match self {
-                    $(Self::$ident => $ret,)*
+                    $($(#[cfg($cfg)])? Self::$ident => $ret,)*
                 }
             }
 
             pub fn params<B: TypeMethods>(self, $bcx: &mut B) -> Vec<B::Type> {
                 $($types_init)*
                 match self {
-                    $(Self::$ident => vec![$($params),*],)*
+                    $($(#[cfg($cfg)])? Self::$ident => vec![$($params),*],)*
                 }
             }
 
@@ -132,7 +203,7 @@ macro_rules! builtins {
                 #[allow(unused_imports)]
                 use Attribute::*;
                 match self {
-                    $(Self::$ident => &[$($attr)*]),*
+                    $($(#[cfg($cfg)])? Self::$ident => &[$($attr)*]),*
                 }
             }
 
@@ -144,7 +215,7 @@ macro_rules! builtins {
                 let default = || vec![Attribute::NoUndef];
                 $($attrs_init)*
                 match self {
-                    $(Self::$ident => vec![$(builtins!(@param_attr default $($param_attr)?)),*]),*
+                    $($(#[cfg($cfg)])? Self::$ident => vec![$(builtins!(@param_attr default $($param_attr)?)),*]),*
                 }
             }
 
@@ -157,9 +228,14 @@ macro_rules! builtins {
                 const FUNCSTACKPUSH: u8 = 0;
                 const FUNCSTACKPOP: u8 = 0;
                 const FUNCSTACKGROW: u8 = 0;
+                const BALANCECONST: u8 = BALANCE;
+                const EXTCODESIZECONST: u8 = EXTCODESIZE;
+                const EXTCODEHASHCONST: u8 = EXTCODEHASH;
+                #[cfg(any(test, feature = "__testing"))]
+                const TESTSTOP: u8 = 0;
 
                 match self {
-                    $(Self::$ident => [<$ident:upper>]),*
+                    $($(#[cfg($cfg)])? Self::$ident => [<$ident:upper>]),*
                 }
             }
         }
@@ -172,6 +248,8 @@ builtins! {
         let usize = bcx.type_ptr_sized_int();
         let bool = bcx.type_int(1);
         let u8 = bcx.type_int(8);
+        let u32 = bcx.type_int(32);
+        let u64 = bcx.type_int(64);
     }
 
     @param_attrs |op| {
@@ -202,6 +280,10 @@ builtins! {
 
         let sp_dyn = size_and_align2(None, core::mem::align_of::<revmc_context::EvmWord>());
 
+        // The result of an instruction whose inputs are passed as immediates.
+        let mut sp_out = size_and_align::<revmc_context::EvmWord>();
+        sp_out.push(Attribute::WriteOnly);
+
         let mut sp = sp_dyn.clone();
         // `sp` is at `top - inputs`, we have access to `max(inputs, outputs)` words.
         let n_stack_words = inputs.max(outputs);
@@ -222,14 +304,23 @@ builtins! {
     Exp            = __revmc_builtin_exp(@[ecx] ptr, @[sp] ptr, u8) Some(u8),
     Keccak256      = __revmc_builtin_keccak256(@[ecx] ptr, @[sp] ptr) Some(u8),
     Balance        = __revmc_builtin_balance(@[ecx] ptr, @[sp] ptr, u8) Some(u8),
+    BalanceConst   = __revmc_builtin_balance_const(
+        @[ecx] ptr, @[sp_out] ptr, u32, u64, u64, u8,
+    ) Some(u8),
     CallDataCopy   = __revmc_builtin_calldatacopy(@[ecx] ptr, @[sp] ptr) Some(u8),
     CodeSize       = __revmc_builtin_codesize(@[ecx] ptr) Some(usize),
     CodeCopy       = __revmc_builtin_codecopy(@[ecx] ptr, @[sp] ptr) Some(u8),
     GasPrice       = __revmc_builtin_gas_price(@[ecx] ptr, @[sp] ptr) None,
     ExtCodeSize    = __revmc_builtin_extcodesize(@[ecx] ptr, @[sp] ptr, u8) Some(u8),
+    ExtCodeSizeConst = __revmc_builtin_extcodesize_const(
+        @[ecx] ptr, @[sp_out] ptr, u32, u64, u64, u8,
+    ) Some(u8),
     ExtCodeCopy    = __revmc_builtin_extcodecopy(@[ecx] ptr, @[sp] ptr, u8) Some(u8),
     ReturnDataCopy = __revmc_builtin_returndatacopy(@[ecx] ptr, @[sp] ptr) Some(u8),
     ExtCodeHash    = __revmc_builtin_extcodehash(@[ecx] ptr, @[sp] ptr, u8) Some(u8),
+    ExtCodeHashConst = __revmc_builtin_extcodehash_const(
+        @[ecx] ptr, @[sp_out] ptr, u32, u64, u64, u8,
+    ) Some(u8),
     BlockHash      = __revmc_builtin_blockhash(@[ecx] ptr, @[sp] ptr) Some(u8),
     Difficulty     = __revmc_builtin_difficulty(@[ecx] ptr, @[sp] ptr, u8) None,
     SelfBalance    = __revmc_builtin_self_balance(@[ecx] ptr, @[sp] ptr) Some(u8),
@@ -259,4 +350,20 @@ builtins! {
     FuncStackGrow  = __revmc_builtin_func_stack_grow(@[ecx] ptr) None,
 
     ResizeMemory   = __revmc_builtin_resize_memory(@[ecx] ptr, usize) Some(u8),
+
+    #[cfg(any(test, feature = "__testing"))]
+    TestStop       = @[success = Stop] __revmc_builtin_test_stop(u8) Some(u8),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use revm_interpreter::InstructionResult;
+
+    #[test]
+    fn success() {
+        assert_eq!(Builtin::TestStop.success(), InstructionResult::Stop);
+        assert_eq!(Builtin::SelfDestruct.success(), InstructionResult::Continue);
+        assert_eq!(Builtin::Sstore.success(), InstructionResult::Continue);
+    }
 }
@@ -38,6 +38,16 @@ use utils::*;
 // NOTE: This is just a random value that cannot happen normally.
 pub const EXTCALL_LIGHT_FAILURE: InstructionResult = InstructionResult::PrecompileError;
 
+/// Splits an address into the big-endian parts passed to the `*_const` account query builtins,
+/// such as `__revmc_builtin_extcodesize_const`.
+#[inline]
+pub fn split_address(address: &Address) -> (u32, u64, u64) {
+    let hi = u32::from_be_bytes(address[..4].try_into().unwrap());
+    let mid = u64::from_be_bytes(address[4..12].try_into().unwrap());
+    let lo = u64::from_be_bytes(address[12..].try_into().unwrap());
+    (hi, mid, lo)
+}
+
 /// The kind of a `*CALL*` instruction.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u8)]
@@ -153,8 +163,30 @@ pub unsafe extern "C" fn __revmc_builtin_balance(
     address: &mut EvmWord,
     spec_id: SpecId,
 ) -> InstructionResult {
-    let state = try_host!(ecx.host.balance(address.to_address()));
-    *address = state.data.into();
+    balance(ecx, address.to_address(), address, spec_id)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __revmc_builtin_balance_const(
+    ecx: &mut EvmContext<'_>,
+    out: &mut EvmWord,
+    address_hi: u32,
+    address_mid: u64,
+    address_lo: u64,
+    spec_id: SpecId,
+) -> InstructionResult {
+    balance(ecx, join_address(address_hi, address_mid, address_lo), out, spec_id)
+}
+
+#[inline(always)]
+fn balance(
+    ecx: &mut EvmContext<'_>,
+    address: Address,
+    out: &mut EvmWord,
+    spec_id: SpecId,
+) -> InstructionResult {
+    let state = try_host!(ecx.host.balance(address));
+    *out = state.data.into();
     let gas = if spec_id.is_enabled_in(SpecId::BERLIN) {
         gas::warm_cold_cost(state.is_cold)
     } else if spec_id.is_enabled_in(SpecId::ISTANBUL) {
@@ -205,8 +237,30 @@ pub unsafe extern "C" fn __revmc_builtin_extcodesize(
     address: &mut EvmWord,
     spec_id: SpecId,
 ) -> InstructionResult {
-    let (code, state) = try_host!(ecx.host.code(address.to_address())).into_components();
-    *address = code.len().into();
+    extcodesize(ecx, address.to_address(), address, spec_id)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __revmc_builtin_extcodesize_const(
+    ecx: &mut EvmContext<'_>,
+    out: &mut EvmWord,
+    address_hi: u32,
+    address_mid: u64,
+    address_lo: u64,
+    spec_id: SpecId,
+) -> InstructionResult {
+    extcodesize(ecx, join_address(address_hi, address_mid, address_lo), out, spec_id)
+}
+
+#[inline(always)]
+fn extcodesize(
+    ecx: &mut EvmContext<'_>,
+    address: Address,
+    out: &mut EvmWord,
+    spec_id: SpecId,
+) -> InstructionResult {
+    let (code, state) = try_host!(ecx.host.code(address)).into_components();
+    *out = code.len().into();
     let gas = if spec_id.is_enabled_in(SpecId::BERLIN) {
         gas::warm_cold_cost_with_delegation(state)
     } else if spec_id.is_enabled_in(SpecId::TANGERINE) {
@@ -265,8 +319,30 @@ pub unsafe extern "C" fn __revmc_builtin_extcodehash(
     address: &mut EvmWord,
     spec_id: SpecId,
 ) -> InstructionResult {
-    let (hash, state) = try_host!(ecx.host.code_hash(address.to_address())).into_components();
-    *address = EvmWord::from_be_bytes(hash.0);
+    extcodehash(ecx, address.to_address(), address, spec_id)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __revmc_builtin_extcodehash_const(
+    ecx: &mut EvmContext<'_>,
+    out: &mut EvmWord,
+    address_hi: u32,
+    address_mid: u64,
+    address_lo: u64,
+    spec_id: SpecId,
+) -> InstructionResult {
+    extcodehash(ecx, join_address(address_hi, address_mid, address_lo), out, spec_id)
+}
+
+#[inline(always)]
+fn extcodehash(
+    ecx: &mut EvmContext<'_>,
+    address: Address,
+    out: &mut EvmWord,
+    spec_id: SpecId,
+) -> InstructionResult {
+    let (hash, state) = try_host!(ecx.host.code_hash(address)).into_components();
+    *out = EvmWord::from_be_bytes(hash.0);
     let gas = if spec_id.is_enabled_in(SpecId::BERLIN) {
         gas::warm_cold_cost_with_delegation(state)
     } else if spec_id.is_enabled_in(SpecId::ISTANBUL) {
@@ -838,13 +914,14 @@ pub unsafe extern "C" fn __revmc_builtin_do_return(
     result: InstructionResult,
 ) -> InstructionResult {
     let len = try_into_usize!(len);
-    let output = if len != 0 {
+    let (output, range) = if len != 0 {
         let offset = try_into_usize!(offset);
         ensure_memory!(ecx, offset, len);
-        ecx.memory.slice(offset, len).to_vec().into()
+        (ecx.memory.slice(offset, len).to_vec().into(), offset..offset + len)
     } else {
-        Bytes::new()
+        (Bytes::new(), 0..0)
     };
+    ecx.return_range = Some(range);
     *ecx.next_action =
         InterpreterAction::Return { result: InterpreterResult { output, gas: *ecx.gas, result } };
     InstructionResult::Continue
@@ -899,3 +976,10 @@ pub unsafe extern "C" fn __revmc_builtin_resize_memory(
 ) -> InstructionResult {
     resize_memory(ecx, new_size)
 }
+
+// Returns its input, declared with a custom success result of `Stop` in `Builtin::TestStop`.
+#[cfg(any(test, feature = "__testing"))]
+#[no_mangle]
+pub unsafe extern "C" fn __revmc_builtin_test_stop(result: InstructionResult) -> InstructionResult {
+    result
+}
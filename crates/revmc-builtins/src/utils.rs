@@ -1,5 +1,6 @@
 use crate::gas;
 use revm_interpreter::{as_usize_saturated, Gas, InstructionResult, SharedMemory};
+use revm_primitives::Address;
 use revmc_context::{EvmContext, EvmWord};
 
 /// Splits the stack pointer into `N` elements by casting it to an array.
@@ -16,6 +17,18 @@ pub(crate) unsafe fn read_words_rev<'a, const N: usize>(sp: *mut EvmWord) -> &'a
     &mut *sp.cast::<[EvmWord; N]>()
 }
 
+/// Joins the big-endian parts of an address passed as an immediate by the compiled code.
+///
+/// See [`split_address`](crate::split_address).
+#[inline(always)]
+pub(crate) fn join_address(hi: u32, mid: u64, lo: u64) -> Address {
+    let mut address = Address::ZERO;
+    address[..4].copy_from_slice(&hi.to_be_bytes());
+    address[4..12].copy_from_slice(&mid.to_be_bytes());
+    address[12..].copy_from_slice(&lo.to_be_bytes());
+    address
+}
+
 #[inline]
 pub(crate) fn ensure_memory(
     ecx: &mut EvmContext<'_>,
@@ -0,0 +1,140 @@
+//! [`revm`] handler integration.
+
+use crate::{EvmCompilerFn, Hotness};
+use alloc::{sync::Arc, vec::Vec};
+use core::cell::RefCell;
+use revm::{handler::register::EvmHandler, Database};
+use revm_interpreter::Contract;
+use revm_primitives::{SpecId, B256};
+
+/// Looks up the compiled function for a bytecode hash.
+///
+/// Implemented by the external context of a [`revm::Evm`] to execute compiled functions with
+/// [`register_handler`].
+///
+/// # Safety
+///
+/// The returned functions must be safe to call with any frame executing the bytecode they were
+/// compiled from with the given `SpecId`, see [`EvmCompilerFn::call`].
+///
+/// They must also remain valid until every frame that started executing them has finished, even
+/// if they are no longer returned, as such frames keep being executed by them when they are
+/// resumed, see [`register_handler`].
+pub unsafe trait EvmCompilerFnLookup {
+    /// Returns the function compiled for the bytecode with the given hash and `spec_id`, if any.
+    ///
+    /// Functions compiled for another `SpecId` must not be returned, as they may charge different
+    /// gas costs or enable different opcodes.
+    fn get_function(&self, bytecode_hash: B256, spec_id: SpecId) -> Option<EvmCompilerFn>;
+
+    /// Called after a frame of a bytecode without a compiled function was executed by the
+    /// fallback frame execution, e.g. the interpreter.
+    ///
+    /// `hotness` holds the gas used by this execution, and one invocation if the frame started
+    /// executing the bytecode, as a frame is executed again after every call it makes. This can
+    /// be used to only compile the bytecodes that are executed often, see `HotnessCounters`.
+    ///
+    /// Does nothing by default.
+    fn on_interpreted(
+        &self,
+        bytecode_hash: B256,
+        contract: &Contract,
+        spec_id: SpecId,
+        hotness: Hotness,
+    ) {
+        let _ = (bytecode_hash, contract, spec_id, hotness);
+    }
+}
+
+// SAFETY: Delegates to `T`.
+unsafe impl<T: EvmCompilerFnLookup + ?Sized> EvmCompilerFnLookup for Arc<T> {
+    fn get_function(&self, bytecode_hash: B256, spec_id: SpecId) -> Option<EvmCompilerFn> {
+        (**self).get_function(bytecode_hash, spec_id)
+    }
+
+    fn on_interpreted(
+        &self,
+        bytecode_hash: B256,
+        contract: &Contract,
+        spec_id: SpecId,
+        hotness: Hotness,
+    ) {
+        (**self).on_interpreted(bytecode_hash, contract, spec_id, hotness)
+    }
+}
+
+/// Registers a handler that executes every frame with the compiled function returned by the
+/// external context for the frame's bytecode hash and `SpecId`, falling back to the previously
+/// registered frame execution, e.g. the interpreter, if there is none.
+///
+/// The function executing a frame is looked up when the frame starts, and is kept until the frame
+/// finishes: frames are resumed by the same function, or by the fallback, after every call they
+/// make, regardless of the functions returned by the external context in the meantime. Frames
+/// of bytecodes without a hash are always executed by the fallback.
+///
+/// Frames executed by the fallback are reported to
+/// [`on_interpreted`](EvmCompilerFnLookup::on_interpreted).
+///
+/// The `'static` bounds are required by the `host-ext-any` feature.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut evm = revm::Evm::builder()
+///     .with_db(db)
+///     .with_external_context(compiled_functions)
+///     .append_handler_register(revmc_context::register_handler)
+///     .build();
+/// ```
+pub fn register_handler<EXT, DB>(handler: &mut EvmHandler<'_, EXT, DB>)
+where
+    EXT: EvmCompilerFnLookup + 'static,
+    DB: Database + 'static,
+{
+    let prev = handler.execution.execute_frame.clone();
+    // The compiled function executing the frame at each depth of the call stack, if any.
+    let frames = RefCell::new(Vec::<Option<EvmCompilerFn>>::new());
+    handler.execution.execute_frame = Arc::new(move |frame, memory, tables, context| {
+        let interpreter = frame.interpreter_mut();
+        let depth = context.evm.journaled_state.depth;
+        let spec_id = context.evm.spec_id();
+        // Resumed frames point past the call they made, or to the resume index if compiled.
+        let entered = interpreter.instruction_pointer == interpreter.bytecode.as_ptr();
+        let f = {
+            let mut frames = frames.borrow_mut();
+            if entered {
+                let f = interpreter
+                    .contract
+                    .hash
+                    .and_then(|hash| context.external.get_function(hash, spec_id));
+                frames.resize(depth + 1, None);
+                frames[depth] = f;
+                f
+            } else {
+                frames.get(depth).copied().flatten()
+            }
+        };
+        match (f, interpreter.contract.hash) {
+            (Some(f), _) => {
+                Ok(unsafe { f.call_with_interpreter_and_memory(interpreter, memory, context) })
+            }
+            (None, Some(bytecode_hash)) => {
+                let gas_spent = interpreter.gas.spent();
+                let result = prev(frame, memory, tables, context);
+                let interpreter = frame.interpreter();
+                let hotness = Hotness {
+                    invocations: entered as u64,
+                    gas_used: interpreter.gas.spent().saturating_sub(gas_spent),
+                };
+                context.external.on_interpreted(
+                    bytecode_hash,
+                    &interpreter.contract,
+                    spec_id,
+                    hotness,
+                );
+                result
+            }
+            (None, None) => prev(frame, memory, tables, context),
+        }
+    });
+}
@@ -6,7 +6,7 @@
 extern crate alloc;
 
 use alloc::vec::Vec;
-use core::{fmt, mem::MaybeUninit, ptr};
+use core::{fmt, mem::MaybeUninit, ops::Range, ptr};
 use revm_interpreter::{
     Contract, FunctionStack, Gas, Host, InstructionResult, Interpreter, InterpreterAction,
     InterpreterResult, SharedMemory, EMPTY_SHARED_MEMORY,
@@ -16,6 +16,16 @@ use revm_primitives::{Address, Bytes, Env, U256};
 #[cfg(feature = "host-ext-any")]
 use core::any::Any;
 
+#[cfg(feature = "std")]
+mod registry;
+#[cfg(feature = "std")]
+pub use registry::{EvmCompilerFnInfo, EvmCompilerFns, HotnessCounters};
+
+#[cfg(feature = "revm")]
+mod handler;
+#[cfg(feature = "revm")]
+pub use handler::{register_handler, EvmCompilerFnLookup};
+
 /// The EVM bytecode compiler runtime context.
 ///
 /// This is a simple wrapper around the interpreter's resources, allowing the compiled function to
@@ -39,6 +49,13 @@ pub struct EvmContext<'a> {
     pub is_static: bool,
     /// Whether the context is EOF init.
     pub is_eof_init: bool,
+    /// The memory range of the output of `RETURN` or `REVERT`, set when executing either
+    /// instruction.
+    ///
+    /// The output is also copied into [`next_action`](Self::next_action); this allows reading
+    /// it, for example a revert reason, directly from [`memory`](Self::memory). Empty if the
+    /// output is empty.
+    pub return_range: Option<Range<usize>>,
     /// An index that is used internally to keep track of where execution should resume.
     /// `0` is the initial state.
     #[doc(hidden)]
@@ -79,6 +96,7 @@ impl<'a> EvmContext<'a> {
             func_stack: &mut interpreter.function_stack,
             is_static: interpreter.is_static,
             is_eof_init: interpreter.is_eof_init,
+            return_range: None,
             resume_at,
         };
         (this, stack, stack_len)
@@ -106,6 +124,47 @@ impl<'a> EvmContext<'a> {
             next_action: self.next_action.clone(),
         }
     }
+
+    /// Returns the final gas accounting of the current context.
+    #[inline]
+    pub fn gas_report(&self) -> GasReport {
+        GasReport::from(&*self.gas)
+    }
+}
+
+/// Final gas accounting of a bytecode function call.
+///
+/// This is a stable view over [`Gas`] that does not require knowledge of its private layout.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct GasReport {
+    /// The remaining gas. This is never negative, as any overflow is reported as out of gas.
+    pub gas_remaining: u64,
+    /// The refunded gas. This can be negative, as refunds may be removed during execution,
+    /// for example when resetting a storage slot that was previously cleared.
+    pub gas_refunded: i64,
+    /// The spent gas, equal to the gas limit minus [`gas_remaining`](Self::gas_remaining).
+    ///
+    /// This does not account for [`gas_refunded`](Self::gas_refunded), which is only applied at
+    /// the end of the transaction.
+    pub gas_spent: u64,
+}
+
+impl From<&Gas> for GasReport {
+    #[inline]
+    fn from(gas: &Gas) -> Self {
+        Self {
+            gas_remaining: gas.remaining(),
+            gas_refunded: gas.refunded(),
+            gas_spent: gas.spent(),
+        }
+    }
+}
+
+impl From<Gas> for GasReport {
+    #[inline]
+    fn from(gas: Gas) -> Self {
+        Self::from(&gas)
+    }
 }
 
 /// Extension trait for [`Host`].
@@ -327,6 +386,20 @@ impl EvmCompilerFn {
         )
     }
 
+    /// Calls the function with the given named arguments.
+    ///
+    /// This is equivalent to [`call`](Self::call), but allows constructing the arguments
+    /// separately from the call. See [`EvmCompilerArgs`] for more information.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the arguments are valid and that the function is safe to call.
+    #[inline]
+    pub unsafe fn call_args(self, args: EvmCompilerArgs<'_>) -> InstructionResult {
+        let EvmCompilerArgs { gas, stack, stack_len, env, contract, ecx } = args;
+        (self.0)(gas, stack, stack_len, env, contract, ecx)
+    }
+
     /// Same as [`call`](Self::call) but with `#[inline(never)]`.
     ///
     /// Use of this method is discouraged, as setup and cleanup need to be done manually.
@@ -345,11 +418,62 @@ impl EvmCompilerFn {
     }
 }
 
+/// The arguments of a bytecode function.
+///
+/// This has the same fields, in the same order, as the parameters of [`RawEvmCompilerFn`].
+/// Prefer using [`new`](Self::new) to construct it, which derives the `gas`, `env`, and
+/// `contract` pointers from the context object.
+///
+/// See [`EvmCompilerFn::call_args`].
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct EvmCompilerArgs<'a> {
+    /// Pointer to the gas. Must be the same as `ecx.gas`.
+    pub gas: *mut Gas,
+    /// Pointer to the stack. Must not be null if `local_stack` is set to `false`.
+    pub stack: *mut EvmStack,
+    /// Pointer to the stack length. Must not be null if `inspect_stack_length` is set to `true`.
+    pub stack_len: *mut usize,
+    /// Pointer to the environment. Must be the same as `ecx.host.env()`.
+    pub env: *const Env,
+    /// Pointer to the contract. Must be the same as `ecx.contract`.
+    pub contract: *const Contract,
+    /// Pointer to the context object.
+    pub ecx: *mut EvmContext<'a>,
+}
+
+impl<'a> EvmCompilerArgs<'a> {
+    /// Creates the function arguments from the given stack, stack length, and context object.
+    ///
+    /// See [`EvmCompilerFn::call`] for more information about the arguments.
+    #[inline]
+    pub fn new(
+        stack: Option<&mut EvmStack>,
+        stack_len: Option<&mut usize>,
+        ecx: &mut EvmContext<'a>,
+    ) -> Self {
+        Self {
+            gas: ecx.gas,
+            stack: option_as_mut_ptr(stack),
+            stack_len: option_as_mut_ptr(stack_len),
+            env: ecx.host.env(),
+            contract: ecx.contract,
+            ecx,
+        }
+    }
+}
+
 /// EVM context stack.
 #[repr(C)]
-#[allow(missing_debug_implementations)]
 pub struct EvmStack([MaybeUninit<EvmWord>; 1024]);
 
+impl fmt::Debug for EvmStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The length is not known, use `debug_with_len` to print the words.
+        f.debug_struct("EvmStack").finish_non_exhaustive()
+    }
+}
+
 #[allow(clippy::new_without_default)]
 impl EvmStack {
     /// The size of the stack in bytes.
@@ -486,6 +610,27 @@ impl EvmStack {
     pub fn as_mut_slice(&mut self) -> &mut [EvmWord; Self::CAPACITY] {
         unsafe { &mut *self.0.as_mut_ptr().cast() }
     }
+
+    /// Returns a [`Debug`](fmt::Debug) formatter for the first `len` words of the stack, printed
+    /// as hexadecimal [`U256`]s from the bottom of the stack to the top.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is greater than [`EvmStack::CAPACITY`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use revmc_context::{EvmStack, EvmWord};
+    /// let mut stack_buf = EvmStack::new_heap();
+    /// let stack = EvmStack::from_mut_vec(&mut stack_buf);
+    /// stack.as_mut_slice()[0] = EvmWord::from(0x69u64);
+    /// assert_eq!(format!("{:?}", stack.debug_with_len(1)), "[0x69]");
+    /// ```
+    #[inline]
+    pub fn debug_with_len(&self, len: usize) -> impl fmt::Debug + '_ {
+        &self.as_slice()[..len]
+    }
 }
 
 /// A native-endian 256-bit unsigned integer, aligned to 8 bytes.
@@ -508,7 +653,14 @@ macro_rules! impl_fmt {
     };
 }
 
-impl_fmt!(Debug, Display, Binary, Octal, LowerHex, UpperHex);
+impl_fmt!(Display, Binary, Octal, LowerHex, UpperHex);
+
+impl fmt::Debug for EvmWord {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.to_u256())
+    }
+}
 
 macro_rules! impl_conversions_through_u256 {
     ($($ty:ty),*) => {
@@ -769,6 +921,15 @@ pub mod private {
     pub use revm_primitives;
 }
 
+/// Execution counters of a bytecode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Hotness {
+    /// The number of frames that executed the bytecode.
+    pub invocations: u64,
+    /// The total gas used by the frames that executed the bytecode.
+    pub gas_used: u64,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -781,6 +942,78 @@ mod tests {
         assert_eq!(usize::try_from(&mut word), Ok(0));
     }
 
+    #[test]
+    fn stack_debug() {
+        let mut stack_buf = EvmStack::new_heap();
+        let stack = EvmStack::from_mut_vec(&mut stack_buf);
+        stack.as_mut_slice()[0] = EvmWord::from(1u64);
+        stack.as_mut_slice()[1] = EvmWord::from(0xffu64);
+        stack.as_mut_slice()[2] = EvmWord::from_u256(U256::MAX);
+        assert_eq!(format!("{:?}", stack.debug_with_len(0)), "[]");
+        assert_eq!(format!("{:?}", stack.debug_with_len(2)), "[0x1, 0xff]");
+        assert_eq!(
+            format!("{:?}", stack.debug_with_len(3)),
+            format!("[0x1, 0xff, 0x{}]", "f".repeat(64))
+        );
+        assert_eq!(format!("{stack:?}"), "EvmStack { .. }");
+    }
+
+    #[test]
+    fn registry() {
+        let fns = EvmCompilerFns::new();
+        use revm_primitives::SpecId;
+
+        let info = EvmCompilerFnInfo { f: EvmCompilerFn::new(test_fn), spec_id: SpecId::CANCUN };
+        let hash = revm_primitives::B256::repeat_byte(1);
+        assert!(fns.is_empty());
+        assert_eq!(unsafe { fns.insert(hash, info) }, None);
+        assert_eq!(fns.get(&hash, SpecId::CANCUN), Some(info));
+        assert_eq!(fns.get(&hash, SpecId::SHANGHAI), None);
+        assert_eq!(fns.get(&revm_primitives::B256::ZERO, SpecId::CANCUN), None);
+        #[cfg(feature = "revm")]
+        {
+            assert_eq!(fns.get_function(hash, SpecId::CANCUN), Some(info.f));
+            assert_eq!(fns.get_function(hash, SpecId::SHANGHAI), None);
+        }
+
+        // Functions compiled for other `SpecId`s are kept separately.
+        let shanghai = EvmCompilerFnInfo { spec_id: SpecId::SHANGHAI, ..info };
+        assert_eq!(unsafe { fns.insert(hash, shanghai) }, None);
+        assert_eq!(fns.len(), 2);
+        assert_eq!(fns.get(&hash, SpecId::CANCUN), Some(info));
+        assert_eq!(fns.get(&hash, SpecId::SHANGHAI), Some(shanghai));
+
+        std::thread::scope(|s| {
+            s.spawn(|| assert_eq!(fns.get(&hash, SpecId::CANCUN), Some(info)));
+        });
+
+        assert_eq!(fns.remove(&hash, SpecId::CANCUN), Some(info));
+        assert_eq!(fns.remove(&hash, SpecId::SHANGHAI), Some(shanghai));
+        assert!(fns.is_empty());
+    }
+
+    #[test]
+    fn hotness_counters() {
+        let counters = HotnessCounters::new();
+        let hash = revm_primitives::B256::repeat_byte(1);
+        assert!(counters.is_empty());
+        assert_eq!(counters.get(&hash), None);
+
+        let hotness = Hotness { invocations: 1, gas_used: 100 };
+        assert_eq!(counters.record(hash, hotness), hotness);
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| counters.record(hash, Hotness { invocations: 0, gas_used: 10 }));
+            }
+        });
+        let expected = Hotness { invocations: 1, gas_used: 140 };
+        assert_eq!(counters.get(&hash), Some(expected));
+        assert_eq!(counters.len(), 1);
+
+        assert_eq!(counters.remove(&hash), Some(expected));
+        assert!(counters.is_empty());
+    }
+
     extern_revmc! {
         #[link_name = "__test_fn"]
         fn test_fn;
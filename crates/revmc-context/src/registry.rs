@@ -0,0 +1,173 @@
+use crate::{EvmCompilerFn, Hotness};
+use revm_primitives::{HashMap, SpecId, B256};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    PoisonError, RwLock,
+};
+
+/// A compiled function and the information it was compiled with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EvmCompilerFnInfo {
+    /// The compiled function.
+    pub f: EvmCompilerFn,
+    /// The `SpecId` the function was compiled for.
+    pub spec_id: SpecId,
+}
+
+/// A thread-safe map from bytecode hashes and `SpecId`s to the functions compiled from them.
+///
+/// This allows sharing compiled functions between threads, e.g. in an `Arc`. With the `revm`
+/// feature, it can be used directly as the external context of `register_handler`.
+#[derive(Debug, Default)]
+pub struct EvmCompilerFns {
+    map: RwLock<HashMap<(B256, SpecId), EvmCompilerFnInfo>>,
+}
+
+impl EvmCompilerFns {
+    /// Creates a new, empty map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of functions.
+    pub fn len(&self) -> usize {
+        self.map.read().unwrap_or_else(PoisonError::into_inner).len()
+    }
+
+    /// Returns `true` if there are no functions.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the function compiled from the bytecode with the given hash for `spec_id`, if any.
+    pub fn get(&self, bytecode_hash: &B256, spec_id: SpecId) -> Option<EvmCompilerFnInfo> {
+        let map = self.map.read().unwrap_or_else(PoisonError::into_inner);
+        map.get(&(*bytecode_hash, spec_id)).copied()
+    }
+
+    /// Inserts the function compiled from the bytecode with the given hash for `info.spec_id`,
+    /// returning the previous one, if any.
+    ///
+    /// # Safety
+    ///
+    /// `info.f` must be compiled from the bytecode with the given hash for `info.spec_id`, and
+    /// must be safe to call for as long as it is in the map, and until the frames that started
+    /// executing it have finished. See [`EvmCompilerFn::call`].
+    pub unsafe fn insert(
+        &self,
+        bytecode_hash: B256,
+        info: EvmCompilerFnInfo,
+    ) -> Option<EvmCompilerFnInfo> {
+        let mut map = self.map.write().unwrap_or_else(PoisonError::into_inner);
+        map.insert((bytecode_hash, info.spec_id), info)
+    }
+
+    /// Removes the function compiled from the bytecode with the given hash for `spec_id`,
+    /// returning it if it was present.
+    pub fn remove(&self, bytecode_hash: &B256, spec_id: SpecId) -> Option<EvmCompilerFnInfo> {
+        let mut map = self.map.write().unwrap_or_else(PoisonError::into_inner);
+        map.remove(&(*bytecode_hash, spec_id))
+    }
+
+    /// Removes all the functions.
+    pub fn clear(&self) {
+        self.map.write().unwrap_or_else(PoisonError::into_inner).clear();
+    }
+}
+
+// SAFETY: The functions are checked when inserted, and are only returned for their `SpecId`.
+#[cfg(feature = "revm")]
+unsafe impl crate::EvmCompilerFnLookup for EvmCompilerFns {
+    fn get_function(&self, bytecode_hash: B256, spec_id: SpecId) -> Option<EvmCompilerFn> {
+        self.get(&bytecode_hash, spec_id).map(|info| info.f)
+    }
+}
+
+/// A thread-safe map from bytecode hashes to their execution counters.
+///
+/// Updating the counters of a known bytecode only takes a read lock, so this is cheap enough to
+/// be updated on every frame, e.g. from `EvmCompilerFnLookup::on_interpreted` with the `revm`
+/// feature.
+#[derive(Debug, Default)]
+pub struct HotnessCounters {
+    map: RwLock<HashMap<B256, Counters>>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    invocations: AtomicU64,
+    gas_used: AtomicU64,
+}
+
+impl Counters {
+    fn add(&self, hotness: Hotness) -> Hotness {
+        Hotness {
+            invocations: self.invocations.fetch_add(hotness.invocations, Ordering::Relaxed)
+                + hotness.invocations,
+            gas_used: self.gas_used.fetch_add(hotness.gas_used, Ordering::Relaxed)
+                + hotness.gas_used,
+        }
+    }
+
+    fn get(&self) -> Hotness {
+        Hotness {
+            invocations: self.invocations.load(Ordering::Relaxed),
+            gas_used: self.gas_used.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl HotnessCounters {
+    /// Creates a new, empty map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of tracked bytecodes.
+    pub fn len(&self) -> usize {
+        self.map.read().unwrap_or_else(PoisonError::into_inner).len()
+    }
+
+    /// Returns `true` if no bytecode is tracked.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the counters of the bytecode with the given hash, if any.
+    pub fn get(&self, bytecode_hash: &B256) -> Option<Hotness> {
+        self.map
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(bytecode_hash)
+            .map(Counters::get)
+    }
+
+    /// Adds `hotness` to the counters of the bytecode with the given hash, returning the updated
+    /// counters.
+    pub fn record(&self, bytecode_hash: B256, hotness: Hotness) -> Hotness {
+        if let Some(counters) =
+            self.map.read().unwrap_or_else(PoisonError::into_inner).get(&bytecode_hash)
+        {
+            return counters.add(hotness);
+        }
+        self.map
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(bytecode_hash)
+            .or_default()
+            .add(hotness)
+    }
+
+    /// Removes the counters of the bytecode with the given hash, returning them if they were
+    /// present.
+    pub fn remove(&self, bytecode_hash: &B256) -> Option<Hotness> {
+        let removed =
+            self.map.write().unwrap_or_else(PoisonError::into_inner).remove(bytecode_hash);
+        removed.as_ref().map(Counters::get)
+    }
+
+    /// Removes all the counters.
+    pub fn clear(&self) {
+        self.map.write().unwrap_or_else(PoisonError::into_inner).clear();
+    }
+}
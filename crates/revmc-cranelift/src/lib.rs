@@ -12,7 +12,7 @@ use cranelift_module::{DataDescription, FuncId, FuncOrDataId, Linkage, Module, M
 use cranelift_object::{ObjectBuilder, ObjectModule};
 use pretty_clif::CommentWriter;
 use revmc_backend::{
-    eyre::eyre, Backend, BackendTypes, Builder, OptimizationLevel, Result, TailCallKind,
+    bail, Backend, BackendTypes, Builder, OptimizationLevel, Result, RevmcError, TailCallKind,
     TypeMethods, U256,
 };
 use std::{
@@ -24,6 +24,13 @@ use std::{
 
 mod pretty_clif;
 
+/// The minimum number of targets of a sparse `switch` to lower it to a `br_table` through a
+/// constant table of target indexes.
+const MIN_SPARSE_BR_TABLE_TARGETS: usize = 16;
+/// The maximum range of values of a sparse `switch` lowered to a `br_table`, which is the number
+/// of entries of the constant table.
+const MAX_SPARSE_BR_TABLE_RANGE: u64 = 1 << 16;
+
 pub use cranelift;
 pub use cranelift_jit;
 pub use cranelift_module;
@@ -198,6 +205,8 @@ impl Backend for EvmCraneliftBackend {
         linkage: revmc_backend::Linkage,
     ) -> Result<(Self::Builder<'_>, FuncId)> {
         self.ctx.func.clear();
+        // `clear` resets the signature to the `Fast` calling convention.
+        self.ctx.func.signature = self.module.get().make_signature();
         if let Some(ret) = ret {
             self.ctx.func.signature.returns.push(AbiParam::new(ret));
         }
@@ -206,11 +215,11 @@ impl Backend for EvmCraneliftBackend {
         }
         let _ = param_names;
         let ptr_type = self.type_ptr();
-        let id = self.module.get_mut().declare_function(
-            name,
-            convert_linkage(linkage),
-            &self.ctx.func.signature,
-        )?;
+        let id = self
+            .module
+            .get_mut()
+            .declare_function(name, convert_linkage(linkage), &self.ctx.func.signature)
+            .map_err(RevmcError::backend)?;
         self.functions.push(id);
         let bcx = FunctionBuilder::new(&mut self.ctx.func, &mut self.builder_context);
         let mut builder = EvmCraneliftBuilder {
@@ -222,6 +231,7 @@ impl Backend for EvmCraneliftBackend {
         };
         let entry = builder.bcx.create_block();
         builder.bcx.append_block_params_for_function_params(entry);
+        builder.bcx.switch_to_block(entry);
         Ok((builder, id))
     }
 
@@ -236,7 +246,10 @@ impl Backend for EvmCraneliftBackend {
         // defined. For this toy demo for now, we'll just finalize the
         // function below.
         for &id in &self.functions {
-            self.module.get_mut().define_function(id, &mut self.ctx)?;
+            self.module
+                .get_mut()
+                .define_function(id, &mut self.ctx)
+                .map_err(RevmcError::backend)?;
         }
         self.functions.clear();
 
@@ -245,7 +258,7 @@ impl Backend for EvmCraneliftBackend {
 
         // Finalize the functions which we just defined, which resolves any outstanding relocations
         // (patching in addresses, now that they're available).
-        self.module.finalize_definitions()?;
+        self.module.finalize_definitions().map_err(RevmcError::backend)?;
 
         self.comments.clear();
 
@@ -253,10 +266,11 @@ impl Backend for EvmCraneliftBackend {
     }
 
     fn write_object<W: std::io::Write>(&mut self, w: W) -> Result<()> {
-        let module =
-            self.finish_module()?.ok_or_else(|| eyre!("cannot write object in JIT mode"))?;
+        let Some(module) = self.finish_module()? else {
+            bail!(InvalidUsage, "cannot write object in JIT mode");
+        };
         let product = module.finish();
-        product.object.write_stream(w).map_err(|e| eyre!("{e}"))?;
+        product.object.write_stream(w).map_err(RevmcError::backend)?;
         Ok(())
     }
 
@@ -273,6 +287,11 @@ impl Backend for EvmCraneliftBackend {
     unsafe fn free_all_functions(&mut self) -> Result<()> {
         self.finish_module().map(drop)
     }
+
+    fn new_module(&mut self) -> Result<()> {
+        // The JIT module supports defining new functions after finalizing the previous ones.
+        Ok(())
+    }
 }
 
 /// The Cranelift-based EVM bytecode compiler function builder.
@@ -519,6 +538,16 @@ impl<'a> Builder for EvmCraneliftBuilder<'a> {
         default_is_cold: bool,
     ) {
         let _ = default_is_cold;
+        if targets.len() >= MIN_SPARSE_BR_TABLE_TARGETS {
+            let min = targets.iter().map(|&(value, _)| value).min().unwrap();
+            let max = targets.iter().map(|&(value, _)| value).max().unwrap();
+            let range = max - min + 1;
+            // Dense switches are already lowered to a `br_table` by `Switch`.
+            if range <= MAX_SPARSE_BR_TABLE_RANGE && range > targets.len() as u64 {
+                self.sparse_br_table(index, default, targets, min, range);
+                return;
+            }
+        }
         let mut switch = cranelift::frontend::Switch::new();
         for (value, block) in targets {
             switch.set_entry(*value as u128, *block);
@@ -701,6 +730,10 @@ impl<'a> Builder for EvmCraneliftBuilder<'a> {
         self.bcx.ins().ireduce(to, value)
     }
 
+    fn bitcast(&mut self, ty: Self::Type, value: Self::Value) -> Self::Value {
+        self.bcx.ins().bitcast(ty, MemFlags::new(), value)
+    }
+
     fn gep(
         &mut self,
         ty: Self::Type,
@@ -835,6 +868,83 @@ impl<'a> Builder for EvmCraneliftBuilder<'a> {
         let _ = loc;
         // TODO
     }
+
+    fn set_calling_convention(
+        &mut self,
+        function: Option<Self::Function>,
+        calling_convention: revmc_backend::CallingConvention,
+    ) {
+        let call_conv = convert_calling_convention(calling_convention, self.module.get().isa());
+        match function {
+            // NOTE: The declaration in the module is not updated, so other functions that
+            // reference this one must set the same calling convention on their `FuncRef`.
+            Some(function) => {
+                let sig = self.bcx.func.dfg.ext_funcs[function].signature;
+                self.bcx.func.dfg.signatures[sig].call_conv = call_conv;
+            }
+            None => self.bcx.func.signature.call_conv = call_conv,
+        }
+    }
+}
+
+impl EvmCraneliftBuilder<'_> {
+    /// Lowers a sparse `switch`, such as the dynamic jump table, to a `br_table` over the indexes
+    /// of the targets, which are looked up in a constant table by `index - min`.
+    ///
+    /// `Switch` would instead emit a tree of comparisons, as the values are not contiguous.
+    fn sparse_br_table(
+        &mut self,
+        index: Value,
+        default: Block,
+        targets: &[(u64, Block)],
+        min: u64,
+        range: u64,
+    ) {
+        // `0` is the default block, and `i + 1` is the `i`th target.
+        let mut slots = vec![0u32; range as usize];
+        for (i, &(value, _)) in targets.iter().enumerate() {
+            slots[(value - min) as usize] = i as u32 + 1;
+        }
+        let endianness = self.module.get().isa().endianness();
+        let bytes = slots
+            .iter()
+            .flat_map(|slot| match endianness {
+                codegen::ir::Endianness::Little => slot.to_le_bytes(),
+                codegen::ir::Endianness::Big => slot.to_be_bytes(),
+            })
+            .collect::<Vec<u8>>();
+        let mut data = DataDescription::new();
+        data.define(bytes.into_boxed_slice());
+        let table_id = self.module.get_mut().declare_anonymous_data(false, false).unwrap();
+        self.module.get_mut().define_data(table_id, &data).unwrap();
+        let local_table_id = self.module.get().declare_data_in_func(table_id, self.bcx.func);
+        let table = self.bcx.ins().global_value(self.ptr_type, local_table_id);
+
+        let offset = if min != 0 { self.bcx.ins().iadd_imm(index, -(min as i64)) } else { index };
+        let in_range = self.bcx.create_block();
+        let out_of_range =
+            self.bcx.ins().icmp_imm(IntCC::UnsignedGreaterThanOrEqual, offset, range as i64);
+        self.bcx.ins().brif(out_of_range, default, &[], in_range, &[]);
+
+        self.bcx.switch_to_block(in_range);
+        let ty = self.bcx.func.dfg.value_type(offset);
+        let offset = match ty.bits().cmp(&self.ptr_type.bits()) {
+            std::cmp::Ordering::Greater => self.bcx.ins().ireduce(self.ptr_type, offset),
+            std::cmp::Ordering::Less => self.bcx.ins().uextend(self.ptr_type, offset),
+            std::cmp::Ordering::Equal => offset,
+        };
+        let offset = self.bcx.ins().imul_imm(offset, 4);
+        let addr = self.bcx.ins().iadd(table, offset);
+        let flags = MemFlags::new().with_notrap().with_readonly();
+        let slot = self.bcx.ins().load(types::I32, flags, addr, 0);
+
+        let default = self.bcx.func.dfg.block_call(default, &[]);
+        let blocks = std::iter::once(default)
+            .chain(targets.iter().map(|&(_, block)| self.bcx.func.dfg.block_call(block, &[])))
+            .collect::<Vec<_>>();
+        let jt = self.bcx.create_jump_table(JumpTableData::new(default, &blocks));
+        self.bcx.ins().br_table(slot, jt);
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -872,19 +982,22 @@ impl ModuleWrapper {
         let mut builder = JITBuilder::with_flags(
             &[("opt_level", opt_level_flag(opt_level))],
             cranelift_module::default_libcall_names(),
-        )?;
+        )
+        .map_err(RevmcError::backend)?;
         builder.symbol_lookup_fn(Box::new(move |s| symbols.get(s)));
         Ok(Self::Jit(JITModule::new(builder)))
     }
 
     fn new_aot(opt_level: OptimizationLevel) -> Result<Self> {
         let mut flag_builder = settings::builder();
-        flag_builder.set("opt_level", opt_level_flag(opt_level))?;
-        let isa_builder = cranelift_native::builder().map_err(|s| eyre!(s))?;
-        let isa = isa_builder.finish(settings::Flags::new(flag_builder))?;
+        flag_builder.set("opt_level", opt_level_flag(opt_level)).map_err(RevmcError::backend)?;
+        let isa_builder = cranelift_native::builder().map_err(RevmcError::backend)?;
+        let isa =
+            isa_builder.finish(settings::Flags::new(flag_builder)).map_err(RevmcError::backend)?;
 
         let builder =
-            ObjectBuilder::new(isa, "jit".to_string(), cranelift_module::default_libcall_names())?;
+            ObjectBuilder::new(isa, "jit".to_string(), cranelift_module::default_libcall_names())
+                .map_err(RevmcError::backend)?;
         Ok(Self::Aot(ObjectModule::new(builder)))
     }
 
@@ -918,7 +1031,7 @@ impl ModuleWrapper {
     fn get_finalized_function(&self, id: FuncId) -> Result<*const u8> {
         match self {
             Self::Jit(module) => Ok(module.get_finalized_function(id)),
-            Self::Aot(_) => Err(eyre!("cannot get finalized JIT function in AOT mode")),
+            Self::Aot(_) => bail!(InvalidUsage, "cannot get finalized JIT function in AOT mode"),
         }
     }
 }
@@ -946,6 +1059,18 @@ fn convert_linkage(linkage: revmc_backend::Linkage) -> Linkage {
     }
 }
 
+fn convert_calling_convention(
+    cc: revmc_backend::CallingConvention,
+    isa: &dyn isa::TargetIsa,
+) -> isa::CallConv {
+    match cc {
+        revmc_backend::CallingConvention::C => isa.default_call_conv(),
+        revmc_backend::CallingConvention::Fast => isa::CallConv::Fast,
+        revmc_backend::CallingConvention::Cold => isa::CallConv::Cold,
+        revmc_backend::CallingConvention::Tail => isa::CallConv::Tail,
+    }
+}
+
 fn opt_level_flag(opt_level: OptimizationLevel) -> &'static str {
     match opt_level {
         OptimizationLevel::None => "none",
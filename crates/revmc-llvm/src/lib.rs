@@ -9,24 +9,27 @@ use inkwell::{
     attributes::{Attribute, AttributeLoc},
     basic_block::BasicBlock,
     execution_engine::ExecutionEngine,
+    memory_buffer::MemoryBuffer,
     module::{FlagBehavior, Module},
     passes::PassBuilderOptions,
     support::error_handling::install_fatal_error_handler,
     targets::{
-        CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetMachine, TargetTriple,
+        CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetData, TargetMachine,
+        TargetMachineOptions, TargetTriple,
     },
     types::{
         AnyType, AnyTypeEnum, BasicType, BasicTypeEnum, FunctionType, IntType, PointerType,
         StringRadix, VoidType,
     },
     values::{
-        BasicMetadataValueEnum, BasicValue, BasicValueEnum, FunctionValue, InstructionValue,
-        PointerValue,
+        BasicMetadataValueEnum, BasicValue, BasicValueEnum, FunctionValue, GlobalValue,
+        InstructionValue, PointerValue,
     },
     AddressSpace, IntPredicate, OptimizationLevel,
 };
 use revmc_backend::{
-    eyre, Backend, BackendTypes, Builder, Error, IntCC, Result, TailCallKind, TypeMethods, U256,
+    bail, ensure, Backend, BackendTypes, Builder, Error, IntCC, Result, RevmcError, TailCallKind,
+    TypeMethods, U256,
 };
 use rustc_hash::FxHashMap;
 use std::{
@@ -63,6 +66,8 @@ pub struct EvmLlvmBackend<'ctx> {
     _dh: dh::DiagnosticHandlerGuard<'ctx>,
     bcx: inkwell::builder::Builder<'ctx>,
     module: Module<'ctx>,
+    /// The modules that were finalized before `module` was started, see `new_module`.
+    old_modules: Vec<Module<'ctx>>,
     exec_engine: Option<ExecutionEngine<'ctx>>,
     machine: TargetMachine,
 
@@ -81,6 +86,14 @@ pub struct EvmLlvmBackend<'ctx> {
     /// Separate from `functions` to have always increasing IDs.
     function_counter: u32,
     functions: FxHashMap<u32, (String, FunctionValue<'ctx>)>,
+    /// String constants, shared by all the functions in the module.
+    strings: FxHashMap<String, GlobalValue<'ctx>>,
+    /// Bitcode linked into the module before optimizing it.
+    bitcode: Vec<Vec<u8>>,
+    /// The number of `bitcode` buffers already linked into the current module.
+    linked_bitcode: usize,
+    /// The data layout override, kept across modules.
+    data_layout: Option<String>,
 }
 
 impl<'ctx> EvmLlvmBackend<'ctx> {
@@ -96,6 +109,12 @@ impl<'ctx> EvmLlvmBackend<'ctx> {
     }
 
     /// Creates a new LLVM backend for the given target.
+    ///
+    /// Rust-style RISC-V triples, such as `riscv64gc-unknown-linux-gnu`, are also accepted, and
+    /// imply the `gc` extensions and the matching hard-float ABI unless features are specified.
+    ///
+    /// When cross-compiling, the backend must be AOT, and the builtins are left as external
+    /// symbols to be resolved when linking the object for the target.
     #[instrument(name = "new_llvm_backend", level = "debug", skip_all)]
     pub fn new_for_target(
         cx: &'ctx Context,
@@ -109,30 +128,35 @@ impl<'ctx> EvmLlvmBackend<'ctx> {
 
         let target_info = TargetInfo::new(target)?;
         let target = &target_info.target;
+        let mut options = TargetMachineOptions::new()
+            .set_cpu(&target_info.cpu)
+            .set_features(&target_info.features)
+            .set_level(opt_level)
+            .set_reloc_mode(RelocMode::PIC)
+            .set_code_model(if aot { CodeModel::Default } else { CodeModel::JITDefault });
+        if let Some(abi) = &target_info.abi {
+            options = options.set_abi(abi);
+        }
         let machine = target
-            .create_target_machine(
-                &target_info.triple,
-                &target_info.cpu,
-                &target_info.features,
-                opt_level,
-                RelocMode::PIC,
-                if aot { CodeModel::Default } else { CodeModel::JITDefault },
-            )
-            .ok_or_else(|| eyre::eyre!("failed to create target machine"))?;
+            .create_target_machine_from_options(&target_info.triple, options)
+            .ok_or_else(|| RevmcError::backend("failed to create target machine"))?;
 
         let module = create_module(cx, &machine)?;
 
         let exec_engine = if aot {
             None
         } else {
+            ensure!(
+                target_info.triple == TargetMachine::get_default_triple(),
+                Unsupported,
+                "cannot JIT-compile for {:?} when cross-compiling",
+                target_info.triple.as_str()
+            );
             if !target.has_jit() {
-                return Err(eyre::eyre!("target {:?} does not support JIT", target.get_name()));
+                bail!(Unsupported, "target {:?} does not support JIT", target.get_name());
             }
             if !target.has_target_machine() {
-                return Err(eyre::eyre!(
-                    "target {:?} does not have target machine",
-                    target.get_name()
-                ));
+                bail!(Unsupported, "target {:?} does not have target machine", target.get_name());
             }
             Some(module.create_jit_execution_engine(opt_level).map_err(error_msg)?)
         };
@@ -152,6 +176,7 @@ impl<'ctx> EvmLlvmBackend<'ctx> {
             _dh: dh::DiagnosticHandlerGuard::new(cx),
             bcx,
             module,
+            old_modules: Vec::new(),
             exec_engine,
             machine,
             ty_void,
@@ -167,6 +192,10 @@ impl<'ctx> EvmLlvmBackend<'ctx> {
             opt_level,
             function_counter: 0,
             functions: FxHashMap::default(),
+            strings: FxHashMap::default(),
+            bitcode: Vec::new(),
+            linked_bitcode: 0,
+            data_layout: None,
         })
     }
 
@@ -176,6 +205,79 @@ impl<'ctx> EvmLlvmBackend<'ctx> {
         self.cx
     }
 
+    /// Returns the LLVM function value of a function in the module by name, if it exists.
+    ///
+    /// This is an escape hatch for directly manipulating functions through `inkwell`, for example
+    /// to attach custom metadata, which is not exposed through the [`Backend`] trait.
+    #[inline]
+    pub fn get_function_value(&self, name: &str) -> Option<FunctionValue<'ctx>> {
+        self.module.get_function(name)
+    }
+
+    /// Returns the data layout of the module.
+    ///
+    /// This defaults to the data layout of the target machine.
+    pub fn data_layout(&self) -> String {
+        self.module.get_data_layout().as_str().to_string_lossy().into_owned()
+    }
+
+    /// Overrides the data layout of the module.
+    ///
+    /// The compiled code accesses the fields of the context structs with the offsets of the host,
+    /// so the data layout must agree with the target on the pointer size. Returns an error
+    /// otherwise.
+    pub fn set_data_layout(&mut self, data_layout: &str) -> Result<()> {
+        let target_data = TargetData::create(data_layout);
+        let ptr_bits = target_data.get_pointer_byte_size(None) * 8;
+        let isize_bits = self.ty_isize.get_bit_width();
+        ensure!(
+            ptr_bits == isize_bits,
+            InvalidUsage,
+            "data layout pointer size ({ptr_bits} bits) does not match the target's \
+             ({isize_bits} bits)"
+        );
+        self.module.set_data_layout(&target_data.get_data_layout());
+        self.data_layout = Some(data_layout.to_string());
+        Ok(())
+    }
+
+    /// Adds LLVM bitcode to link into the module before it is optimized.
+    ///
+    /// This allows the optimizer to inline the functions defined in the bitcode, such as the
+    /// builtins, into the compiled functions. The linked definitions are given internal linkage,
+    /// so they do not conflict with the symbols of the builtins crate when linking an AOT object.
+    ///
+    /// The bitcode can be obtained with `--emit=llvm-bc`, for example with
+    /// `cargo rustc -p revmc-builtins --release -- --emit=llvm-bc`, and must have been produced
+    /// by a compatible LLVM version.
+    ///
+    /// The bitcode is linked once into every module, the first time it is optimized.
+    pub fn add_bitcode(&mut self, bitcode: &[u8]) {
+        self.bitcode.push(bitcode.to_vec());
+    }
+
+    fn link_bitcode(&mut self) -> Result<()> {
+        for bitcode in &self.bitcode[self.linked_bitcode..] {
+            let buffer = MemoryBuffer::create_from_memory_range_copy(bitcode, "bitcode");
+            let module = Module::parse_bitcode_from_buffer(&buffer, self.cx).map_err(error_msg)?;
+            module.set_data_layout(&self.module.get_data_layout());
+            module.set_triple(&self.machine.get_triple());
+            let defined = module
+                .get_functions()
+                .filter(|f| f.count_basic_blocks() > 0)
+                .map(|f| f.get_name().to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            self.module.link_in_module(module).map_err(error_msg)?;
+            for name in &defined {
+                if let Some(function) = self.module.get_function(name) {
+                    function.set_linkage(inkwell::module::Linkage::Internal);
+                }
+            }
+            self.linked_bitcode += 1;
+        }
+        Ok(())
+    }
+
     fn exec_engine(&self) -> &ExecutionEngine<'ctx> {
         assert!(!self.aot, "requested JIT execution engine on AOT");
         self.exec_engine.as_ref().expect("missing JIT execution engine")
@@ -197,16 +299,28 @@ impl<'ctx> EvmLlvmBackend<'ctx> {
         &self.functions[&id].0
     }
 
+    /// Creates an empty module with the current data layout.
+    fn create_module(&self) -> Result<Module<'ctx>> {
+        let module = create_module(self.cx, &self.machine)?;
+        if let Some(data_layout) = &self.data_layout {
+            module.set_data_layout(&TargetData::create(data_layout).get_data_layout());
+        }
+        Ok(module)
+    }
+
     // Delete IR to lower memory consumption.
     // For some reason this does not happen when `Drop`ping either the `Module` or the engine.
     fn clear_module(&mut self) {
-        for function in self.module.get_functions() {
-            unsafe { function.delete() };
-        }
-        for global in self.module.get_globals() {
-            unsafe { global.delete() };
+        for module in iter::once(&self.module).chain(&self.old_modules) {
+            for function in module.get_functions() {
+                unsafe { function.delete() };
+            }
+            for global in module.get_globals() {
+                unsafe { global.delete() };
+            }
         }
         self.functions.clear();
+        self.strings.clear();
     }
 }
 
@@ -248,6 +362,10 @@ impl<'ctx> TypeMethods for EvmLlvmBackend<'ctx> {
     fn type_bit_width(&self, ty: Self::Type) -> u32 {
         ty.into_int_type().get_bit_width()
     }
+
+    fn type_vector(&self, ty: Self::Type, len: u32) -> Option<Self::Type> {
+        Some(ty.into_int_type().vec_type(len).into())
+    }
 }
 
 impl<'ctx> Backend for EvmLlvmBackend<'ctx> {
@@ -283,7 +401,7 @@ impl<'ctx> Backend for EvmLlvmBackend<'ctx> {
     }
 
     fn function_name_is_unique(&self, name: &str) -> bool {
-        self.module.get_function(name).is_none()
+        iter::once(&self.module).chain(&self.old_modules).all(|m| m.get_function(name).is_none())
     }
 
     fn dump_ir(&mut self, path: &Path) -> Result<()> {
@@ -331,6 +449,8 @@ impl<'ctx> Backend for EvmLlvmBackend<'ctx> {
     }
 
     fn optimize_module(&mut self) -> Result<()> {
+        self.link_bitcode()?;
+
         // From `opt --help`, `-passes`.
         let passes = match self.opt_level {
             OptimizationLevel::None => "default<O0>",
@@ -353,30 +473,50 @@ impl<'ctx> Backend for EvmLlvmBackend<'ctx> {
 
     fn jit_function(&mut self, id: Self::FuncId) -> Result<usize> {
         let name = self.id_to_name(id);
-        let addr = self.exec_engine().get_function_address(name)?;
+        let addr = self.exec_engine().get_function_address(name).map_err(RevmcError::backend)?;
         Ok(addr)
     }
 
     unsafe fn free_function(&mut self, id: Self::FuncId) -> Result<()> {
         let name = self.id_to_name(id);
-        let function = self.exec_engine().get_function_value(name)?;
+        let function = self.exec_engine().get_function_value(name).map_err(RevmcError::backend)?;
         self.exec_engine().free_fn_machine_code(function);
-        self.functions.clear();
+        // Only forget this function, so that the others can still be freed individually.
+        self.functions.remove(&id);
         Ok(())
     }
 
     unsafe fn free_all_functions(&mut self) -> Result<()> {
         self.clear_module();
         if let Some(exec_engine) = &self.exec_engine {
-            exec_engine.remove_module(&self.module).map_err(|e| Error::msg(e.to_string()))?;
+            for module in self.old_modules.drain(..) {
+                exec_engine.remove_module(&module).map_err(RevmcError::backend)?;
+            }
+            exec_engine.remove_module(&self.module).map_err(RevmcError::backend)?;
         }
-        self.module = create_module(self.cx, &self.machine)?;
+        self.module = self.create_module()?;
+        self.linked_bitcode = 0;
         if self.exec_engine.is_some() {
             self.exec_engine =
                 Some(self.module.create_jit_execution_engine(self.opt_level).map_err(error_msg)?);
         }
         Ok(())
     }
+
+    fn new_module(&mut self) -> Result<()> {
+        let module = self.create_module()?;
+        let Some(exec_engine) = &self.exec_engine else {
+            bail!(InvalidUsage, "cannot create a new module in AOT mode");
+        };
+        exec_engine
+            .add_module(&module)
+            .map_err(|()| RevmcError::backend("module is already in use"))?;
+        self.old_modules.push(std::mem::replace(&mut self.module, module));
+        self.linked_bitcode = 0;
+        // The strings are globals of the previous module.
+        self.strings.clear();
+        Ok(())
+    }
 }
 
 impl Drop for EvmLlvmBackend<'_> {
@@ -392,6 +532,8 @@ struct TargetInfo {
     target: Target,
     cpu: String,
     features: String,
+    /// The target ABI, if it has to be set explicitly.
+    abi: Option<String>,
 }
 
 // SAFETY: No mutability is exposed and `TargetTriple` is an owned string.
@@ -406,6 +548,7 @@ impl Clone for TargetInfo {
             triple,
             cpu: self.cpu.clone(),
             features: self.features.clone(),
+            abi: self.abi.clone(),
         }
     }
 }
@@ -421,20 +564,36 @@ impl TargetInfo {
                     let cpu = TargetMachine::get_host_cpu_name().to_string_lossy().into_owned();
                     let features =
                         TargetMachine::get_host_cpu_features().to_string_lossy().into_owned();
-                    Self { target, triple, cpu, features }
+                    Self { target, triple, cpu, features, abi: None }
                 })))
             }
             revmc_backend::Target::Triple { triple, cpu, features } => {
-                let triple = TargetTriple::create(triple);
+                let (triple, default_features, abi) = match riscv_gc_triple(triple) {
+                    Some((triple, features, abi)) => (triple, features, Some(abi.to_string())),
+                    None => (triple.clone(), "", None),
+                };
+                let triple = TargetTriple::create(&triple);
                 let target = Target::from_triple(&triple).map_err(error_msg)?;
                 let cpu = cpu.as_ref().cloned().unwrap_or_default();
-                let features = features.as_ref().cloned().unwrap_or_default();
-                Ok(Cow::Owned(Self { target, triple, cpu, features }))
+                let features = features.clone().unwrap_or_else(|| default_features.to_string());
+                Ok(Cow::Owned(Self { target, triple, cpu, features, abi }))
             }
         }
     }
 }
 
+/// Converts a Rust-style RISC-V `gc` triple to the LLVM triple, returning it along with the
+/// features and ABI implied by the `gc` extensions.
+fn riscv_gc_triple(triple: &str) -> Option<(String, &'static str, &'static str)> {
+    let (arch, rest) = triple.split_once('-')?;
+    let (arch, abi) = match arch {
+        "riscv64gc" => ("riscv64", "lp64d"),
+        "riscv32gc" => ("riscv32", "ilp32d"),
+        _ => return None,
+    };
+    Some((format!("{arch}-{rest}"), "+m,+a,+f,+d,+c", abi))
+}
+
 /// The LLVM-based EVM bytecode compiler function builder.
 #[derive(Debug)]
 #[must_use]
@@ -497,6 +656,14 @@ impl<'a, 'ctx> EvmLlvmBuilder<'a, 'ctx> {
             .unwrap();
     }
 
+    fn lifetime_marker(&mut self, name: &str, ptr: BasicValueEnum<'ctx>, size: u64) {
+        let marker = self.get_or_add_function(name, |this| {
+            this.ty_void.fn_type(&[this.ty_i64.into(), this.ty_ptr.into()], false)
+        });
+        let size = self.ty_i64.const_int(size, false);
+        self.bcx.build_call(marker, &[size.into(), ptr.into()], "").unwrap();
+    }
+
     #[allow(dead_code)]
     fn call_overflow_function(
         &mut self,
@@ -589,6 +756,10 @@ impl<'a, 'ctx> TypeMethods for EvmLlvmBuilder<'a, 'ctx> {
     fn type_bit_width(&self, ty: Self::Type) -> u32 {
         self.backend.type_bit_width(ty)
     }
+
+    fn type_vector(&self, ty: Self::Type, len: u32) -> Option<Self::Type> {
+        self.backend.type_vector(ty, len)
+    }
 }
 
 impl<'a, 'ctx> Builder for EvmLlvmBuilder<'a, 'ctx> {
@@ -668,7 +839,12 @@ impl<'a, 'ctx> Builder for EvmLlvmBuilder<'a, 'ctx> {
     }
 
     fn str_const(&mut self, value: &str) -> Self::Value {
-        self.bcx.build_global_string_ptr(value, "").unwrap().as_pointer_value().into()
+        if let Some(global) = self.strings.get(value) {
+            return global.as_pointer_value().into();
+        }
+        let global = self.bcx.build_global_string_ptr(value, "").unwrap();
+        self.strings.insert(value.to_string(), global);
+        global.as_pointer_value().into()
     }
 
     fn nullptr(&mut self) -> Self::Value {
@@ -933,14 +1109,26 @@ impl<'a, 'ctx> Builder for EvmLlvmBuilder<'a, 'ctx> {
     }
 
     fn bitor(&mut self, lhs: Self::Value, rhs: Self::Value) -> Self::Value {
+        if lhs.is_vector_value() {
+            let (lhs, rhs) = (lhs.into_vector_value(), rhs.into_vector_value());
+            return self.bcx.build_or(lhs, rhs, "").unwrap().into();
+        }
         self.bcx.build_or(lhs.into_int_value(), rhs.into_int_value(), "").unwrap().into()
     }
 
     fn bitand(&mut self, lhs: Self::Value, rhs: Self::Value) -> Self::Value {
+        if lhs.is_vector_value() {
+            let (lhs, rhs) = (lhs.into_vector_value(), rhs.into_vector_value());
+            return self.bcx.build_and(lhs, rhs, "").unwrap().into();
+        }
         self.bcx.build_and(lhs.into_int_value(), rhs.into_int_value(), "").unwrap().into()
     }
 
     fn bitxor(&mut self, lhs: Self::Value, rhs: Self::Value) -> Self::Value {
+        if lhs.is_vector_value() {
+            let (lhs, rhs) = (lhs.into_vector_value(), rhs.into_vector_value());
+            return self.bcx.build_xor(lhs, rhs, "").unwrap().into();
+        }
         self.bcx.build_xor(lhs.into_int_value(), rhs.into_int_value(), "").unwrap().into()
     }
 
@@ -993,6 +1181,10 @@ impl<'a, 'ctx> Builder for EvmLlvmBuilder<'a, 'ctx> {
         self.bcx.build_int_truncate(value.into_int_value(), to.into_int_type(), "").unwrap().into()
     }
 
+    fn bitcast(&mut self, ty: Self::Type, value: Self::Value) -> Self::Value {
+        self.bcx.build_bit_cast(value, ty, "").unwrap()
+    }
+
     fn gep(
         &mut self,
         elem_ty: Self::Type,
@@ -1014,6 +1206,7 @@ impl<'a, 'ctx> Builder for EvmLlvmBuilder<'a, 'ctx> {
     ) -> Option<Self::Value> {
         let args = args.iter().copied().map(Into::into).collect::<Vec<_>>();
         let callsite = self.bcx.build_call(function, &args, "").unwrap();
+        callsite.set_call_convention(function.get_call_conventions());
         if tail_call != TailCallKind::None {
             callsite.set_tail_call_kind(convert_tail_call_kind(tail_call));
         }
@@ -1041,6 +1234,27 @@ impl<'a, 'ctx> Builder for EvmLlvmBuilder<'a, 'ctx> {
         self.bcx.build_unreachable().unwrap();
     }
 
+    fn stackmap(&mut self, id: u64, live: &[Self::Value]) {
+        let stackmap = self.get_or_add_function("llvm.experimental.stackmap", |this| {
+            this.ty_void.fn_type(&[this.ty_i64.into(), this.ty_i32.into()], true)
+        });
+        let id = self.ty_i64.const_int(id, false);
+        let shadow_bytes = self.ty_i32.const_zero();
+        let args = [id.into(), shadow_bytes.into()]
+            .into_iter()
+            .chain(live.iter().map(|&v| v.into()))
+            .collect::<Vec<_>>();
+        self.bcx.build_call(stackmap, &args, "").unwrap();
+    }
+
+    fn lifetime_start(&mut self, ptr: Self::Value, size: u64) {
+        self.lifetime_marker("llvm.lifetime.start.p0", ptr, size);
+    }
+
+    fn lifetime_end(&mut self, ptr: Self::Value, size: u64) {
+        self.lifetime_marker("llvm.lifetime.end.p0", ptr, size);
+    }
+
     fn get_or_build_function(
         &mut self,
         name: &str,
@@ -1111,6 +1325,15 @@ impl<'a, 'ctx> Builder for EvmLlvmBuilder<'a, 'ctx> {
         let attr = convert_attribute(self, attribute);
         function.unwrap_or(self.function).add_attribute(loc, attr);
     }
+
+    fn set_calling_convention(
+        &mut self,
+        function: Option<Self::Function>,
+        calling_convention: revmc_backend::CallingConvention,
+    ) {
+        let cc = convert_calling_convention(calling_convention);
+        function.unwrap_or(self.function).set_call_conventions(cc);
+    }
 }
 
 fn init() -> Result<()> {
@@ -1240,6 +1463,7 @@ fn convert_attribute(bcx: &EvmLlvmBuilder<'_, '_>, attr: revmc_backend::Attribut
         OurAttr::HintInline => ("inlinehint", AttrValue::Enum(1)),
         OurAttr::AlwaysInline => ("alwaysinline", AttrValue::Enum(1)),
         OurAttr::NoInline => ("noinline", AttrValue::Enum(1)),
+        OurAttr::OptimizeNone => ("optnone", AttrValue::Enum(1)),
         OurAttr::Speculatable => ("speculatable", AttrValue::Enum(1)),
 
         OurAttr::NoAlias => ("noalias", AttrValue::Enum(1)),
@@ -1287,6 +1511,16 @@ fn convert_linkage(linkage: revmc_backend::Linkage) -> inkwell::module::Linkage
     }
 }
 
+fn convert_calling_convention(cc: revmc_backend::CallingConvention) -> u32 {
+    // See `llvm::CallingConv::ID`.
+    match cc {
+        revmc_backend::CallingConvention::C => 0,
+        revmc_backend::CallingConvention::Fast => 8,
+        revmc_backend::CallingConvention::Cold => 9,
+        revmc_backend::CallingConvention::Tail => 18,
+    }
+}
+
 fn convert_tail_call_kind(kind: TailCallKind) -> inkwell::llvm_sys::LLVMTailCallKind {
     match kind {
         TailCallKind::None => inkwell::llvm_sys::LLVMTailCallKind::LLVMTailCallKindNone,
@@ -1297,8 +1531,8 @@ fn convert_tail_call_kind(kind: TailCallKind) -> inkwell::llvm_sys::LLVMTailCall
 }
 
 // No `#[track_caller]` because `map_err` doesn't propagate it.
-fn error_msg(msg: inkwell::support::LLVMString) -> revmc_backend::Error {
-    revmc_backend::Error::msg(msg.to_string_lossy().trim_end().to_string())
+fn error_msg(msg: inkwell::support::LLVMString) -> Error {
+    RevmcError::backend(msg.to_string_lossy().trim_end()).into()
 }
 
 fn fmt_ty(ty: BasicTypeEnum<'_>) -> impl std::fmt::Display {
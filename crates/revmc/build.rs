@@ -0,0 +1,12 @@
+fn main() {
+    // Export the builtins from the binaries of this package, so that they can be resolved by the
+    // shared libraries compiled and loaded in the tests. This applies to all of its bins,
+    // examples, benches and tests, as `rustc-link-arg-tests` only applies to integration tests.
+    //
+    // Same as `revmc_build::emit`, which cannot be used here since `revmc-build` depends on this
+    // crate with its `compile` feature.
+    let target_vendor = std::env::var("CARGO_CFG_TARGET_VENDOR").unwrap();
+    let flag =
+        if target_vendor == "apple" { "-exported_symbol" } else { "--export-dynamic-symbol" };
+    println!("cargo:rustc-link-arg=-Wl,{flag},__revmc_builtin_*");
+}
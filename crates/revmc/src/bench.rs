@@ -0,0 +1,79 @@
+//! Benchmarking helpers.
+
+use crate::{EvmCompilerFn, EvmContext, EvmContextSnapshot};
+use revm_interpreter::{opcode as op, Host, InstructionResult};
+use revm_primitives::{spec_to_generic, SpecId};
+use std::{hint::black_box, time::Instant};
+
+/// The result of [`compare_throughput`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Throughput {
+    /// The average time of a call to the compiled function, in nanoseconds.
+    pub jit_ns_per_call: f64,
+    /// The average time of an execution of the interpreter, in nanoseconds.
+    pub interp_ns_per_call: f64,
+    /// `interp_ns_per_call / jit_ns_per_call`.
+    pub speedup: f64,
+    /// The result of the last call to the compiled function.
+    pub jit_result: InstructionResult,
+    /// The result of the last execution of the interpreter.
+    pub interp_result: InstructionResult,
+}
+
+/// Executes `bytecode` in the `input` context `iters` times with both the compiled function `f`
+/// and the `revm` interpreter, and reports the average time of each.
+///
+/// Every execution is done on a fresh interpreter and a clone of the host, and the time to set
+/// these up is included in both measurements.
+///
+/// # Panics
+///
+/// Panics if `iters` is `0`.
+///
+/// # Safety
+///
+/// `f` must be compiled from `bytecode` with the given `spec_id`, and must be safe to call with
+/// the given context. See [`EvmCompilerFn::call`].
+pub unsafe fn compare_throughput<H: Host + Clone + 'static>(
+    f: EvmCompilerFn,
+    bytecode: &[u8],
+    spec_id: SpecId,
+    input: &EvmContextSnapshot<H>,
+    iters: u32,
+) -> Throughput {
+    assert!(iters > 0, "`iters` must be greater than 0");
+    let contract = input.contract(bytecode);
+
+    let mut jit_result = InstructionResult::Continue;
+    let start = Instant::now();
+    for _ in 0..iters {
+        let mut interpreter = input.interpreter(contract.clone());
+        let mut host = input.host.clone();
+        let (mut ecx, stack, stack_len) =
+            EvmContext::from_interpreter_with_stack(&mut interpreter, &mut host);
+        jit_result = black_box(unsafe { f.call(Some(stack), Some(stack_len), &mut ecx) });
+    }
+    let jit_time = start.elapsed();
+
+    let table = spec_to_generic!(spec_id, op::make_instruction_table::<H, SPEC>());
+    let mut interp_result = InstructionResult::Continue;
+    let start = Instant::now();
+    for _ in 0..iters {
+        let mut interpreter = input.interpreter(contract.clone());
+        let mut host = input.host.clone();
+        let memory = interpreter.take_memory();
+        black_box(interpreter.run(memory, &table, &mut host));
+        interp_result = interpreter.instruction_result;
+    }
+    let interp_time = start.elapsed();
+
+    let jit_ns_per_call = jit_time.as_nanos() as f64 / iters as f64;
+    let interp_ns_per_call = interp_time.as_nanos() as f64 / iters as f64;
+    Throughput {
+        jit_ns_per_call,
+        interp_ns_per_call,
+        speedup: interp_ns_per_call / jit_ns_per_call,
+        jit_result,
+        interp_result,
+    }
+}
@@ -0,0 +1,193 @@
+use super::{Bytecode, CounterLoop, Inst, InstData, OpcodeInfo, OpcodeSet, SelectorSwitch};
+use crate::CustomOpcode;
+use bitvec::vec::BitVec;
+use revm_primitives::{keccak256, Eof, SpecId, B256, U256};
+use revmc_backend::Result;
+use rustc_hash::FxHashMap;
+use std::{borrow::Cow, fmt};
+
+/// Cache of bytecode analysis results.
+///
+/// Analysis results are keyed by `keccak256(bytecode)`, the [`SpecId`], the
+/// [allowed opcodes](crate::EvmCompiler::set_allowed_opcodes), the
+/// [pinned opcode infos](crate::EvmCompiler::set_opcode_infos) and the stack I/O and gas of the
+/// [custom opcodes](crate::EvmCompiler::register_opcode) they were computed with, as well as
+/// whether [constant folding](crate::EvmCompiler::constant_folding) was enabled, and can be reused
+/// across modules and compilations of the same bytecode.
+///
+/// See [`EvmCompiler::set_analysis_cache`](crate::EvmCompiler::set_analysis_cache).
+#[derive(Default)]
+pub struct AnalysisCache {
+    map: FxHashMap<Key, Analysis>,
+    hits: u64,
+    misses: u64,
+}
+
+type Key = (
+    B256,
+    SpecId,
+    Option<OpcodeSet>,
+    Option<[OpcodeInfo; 256]>,
+    Option<[Option<(u8, u8, u16)>; 256]>,
+    bool,
+);
+
+impl fmt::Debug for AnalysisCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AnalysisCache")
+            .field("len", &self.map.len())
+            .field("hits", &self.hits)
+            .field("misses", &self.misses)
+            .finish()
+    }
+}
+
+impl AnalysisCache {
+    /// Creates a new, empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of cached analyses.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns the number of cache hits.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Returns the number of cache misses.
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Clears the cache and resets the statistics.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Returns the analyzed bytecode, either from the cache or by analyzing it and then caching
+    /// the result.
+    #[instrument(name = "cached_analysis", level = "debug", skip_all)]
+    pub(crate) fn get_or_analyze<'a>(
+        &mut self,
+        code: &'a [u8],
+        eof: Option<Cow<'a, Eof>>,
+        spec_id: SpecId,
+        allowed_opcodes: Option<&OpcodeSet>,
+        op_infos: Option<&[OpcodeInfo; 256]>,
+        custom_opcodes: Option<&[Option<CustomOpcode>; 256]>,
+        constant_folding: bool,
+    ) -> Result<Bytecode<'a>> {
+        let custom_key = custom_opcodes
+            .map(|custom| custom.map(|op| op.map(|op| (op.inputs, op.outputs, op.gas))));
+        let key = (
+            keccak256(code),
+            spec_id,
+            allowed_opcodes.copied(),
+            op_infos.copied(),
+            custom_key,
+            constant_folding,
+        );
+        if let Some(analysis) = self.map.get(&key) {
+            trace!(hash=%key.0, "hit");
+            self.hits += 1;
+            return Ok(Bytecode::from_analysis(code, eof, spec_id, analysis.clone()));
+        }
+
+        trace!(hash=%key.0, "miss");
+        self.misses += 1;
+        let mut bytecode = Bytecode::new(code, eof, spec_id);
+        if let Some(custom) = custom_opcodes {
+            bytecode.set_custom_opcodes(custom);
+        }
+        if let Some(allowed) = allowed_opcodes {
+            bytecode.restrict_opcodes(allowed);
+        }
+        if let Some(op_infos) = op_infos {
+            bytecode.pin_gas_costs(op_infos);
+        }
+        if !constant_folding {
+            bytecode.disable_constant_folding();
+        }
+        bytecode.analyze()?;
+        self.map.insert(key, Analysis::from_bytecode(&bytecode));
+        Ok(bytecode)
+    }
+}
+
+/// The results of [`Bytecode::analyze`].
+#[derive(Clone)]
+struct Analysis {
+    insts: Vec<InstData>,
+    jumpdests: BitVec,
+    has_dynamic_jumps: bool,
+    may_suspend: bool,
+    pc_to_inst: FxHashMap<u32, u32>,
+    eof_called_by: Vec<Vec<Inst>>,
+    counter_loops: Vec<CounterLoop>,
+    selector_switches: Vec<SelectorSwitch>,
+    constants: Vec<U256>,
+    constant_folding: bool,
+}
+
+impl Analysis {
+    fn from_bytecode(bytecode: &Bytecode<'_>) -> Self {
+        Self {
+            insts: bytecode.insts.clone(),
+            jumpdests: bytecode.jumpdests.clone(),
+            has_dynamic_jumps: bytecode.has_dynamic_jumps,
+            may_suspend: bytecode.may_suspend,
+            pc_to_inst: bytecode.pc_to_inst.clone(),
+            eof_called_by: bytecode.eof_called_by.clone(),
+            counter_loops: bytecode.counter_loops.clone(),
+            selector_switches: bytecode.selector_switches.clone(),
+            constants: bytecode.constants.clone(),
+            constant_folding: bytecode.constant_folding,
+        }
+    }
+}
+
+impl<'a> Bytecode<'a> {
+    fn from_analysis(
+        code: &'a [u8],
+        eof: Option<Cow<'a, Eof>>,
+        spec_id: SpecId,
+        analysis: Analysis,
+    ) -> Self {
+        let Analysis {
+            insts,
+            jumpdests,
+            has_dynamic_jumps,
+            may_suspend,
+            pc_to_inst,
+            eof_called_by,
+            counter_loops,
+            selector_switches,
+            constants,
+            constant_folding,
+        } = analysis;
+        Self {
+            code: Self::code_section(code, eof.as_deref()),
+            eof,
+            insts,
+            jumpdests,
+            spec_id,
+            has_dynamic_jumps,
+            may_suspend,
+            pc_to_inst,
+            eof_called_by,
+            counter_loops,
+            selector_switches,
+            constants,
+            constant_folding,
+        }
+    }
+}
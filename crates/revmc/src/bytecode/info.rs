@@ -2,7 +2,7 @@ use revm_interpreter::{gas, opcode as op};
 use revm_primitives::{spec_to_generic, SpecId};
 
 /// Opcode information.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct OpcodeInfo(u16);
 
 impl OpcodeInfo {
@@ -94,12 +94,121 @@ impl OpcodeInfo {
     }
 }
 
+/// Information about a single instruction in a [`Bytecode`](crate::Bytecode).
+///
+/// See [`Bytecode::inst_infos`](crate::Bytecode::inst_infos).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InstInfo {
+    /// The instruction index.
+    pub inst: usize,
+    /// The program counter, meaning `code[pc]` is this instruction's opcode.
+    pub pc: usize,
+    /// The opcode byte.
+    pub opcode: u8,
+    /// The gas cost of the instruction, or `None` if it is not known statically.
+    ///
+    /// This is the case for opcodes with a dynamic gas cost, and for unknown or disabled opcodes.
+    pub static_gas: Option<u64>,
+    /// The number of stack elements popped by the instruction.
+    pub stack_in: u8,
+    /// The number of stack elements pushed by the instruction.
+    pub stack_out: u8,
+    /// Whether the instruction is unreachable.
+    pub is_dead_code: bool,
+    /// The program counter of the `JUMPDEST` that the `JUMP` or `JUMPI` jumps to, if it is known
+    /// statically and valid. Always `None` in EOF.
+    pub jump_target: Option<usize>,
+}
+
+/// A loop in a [`Bytecode`](crate::Bytecode), formed by static jumps back to a `JUMPDEST`.
+///
+/// See [`Bytecode::loops`](crate::Bytecode::loops).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LoopInfo {
+    /// The instruction index of the loop header `JUMPDEST`.
+    pub header_inst: usize,
+    /// The program counter of the loop header `JUMPDEST`.
+    pub header_pc: usize,
+    /// The program counter of the last jump back to the header.
+    pub back_edge_pc: usize,
+    /// The size of the loop body in bytes, from the header up to and including the last jump
+    /// back to it.
+    pub body_size: usize,
+}
+
+/// A section of a [`Bytecode`](crate::Bytecode).
+///
+/// See [`Bytecode::sections`](crate::Bytecode::sections).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SectionInfo {
+    /// The instruction index of the first instruction.
+    pub start_inst: usize,
+    /// The program counter of the first instruction.
+    pub start_pc: usize,
+    /// The number of instructions.
+    pub len: usize,
+    /// The sum of the static gas costs of the instructions, which is charged when entering the
+    /// section. Dynamic gas costs are charged separately.
+    pub static_gas: u64,
+    /// The stack height required to execute the section without underflowing.
+    pub stack_inputs: usize,
+    /// The maximum stack height growth relative to the stack height at section start.
+    pub stack_max_growth: usize,
+    /// The stack height difference between the end and the start of the section.
+    pub stack_diff: isize,
+}
+
 /// Returns the static info map for the given `SpecId`.
 #[allow(unused_parens)]
 pub const fn op_info_map(spec_id: SpecId) -> &'static [OpcodeInfo; 256] {
     spec_to_generic!(spec_id, (const { &make_map(<SPEC as revm_primitives::Spec>::SPEC_ID) }))
 }
 
+/// Returns `true` if the given opcode accesses the [`Host`](revm_interpreter::Host), either
+/// directly or by reading its environment.
+pub const fn uses_host(opcode: u8) -> bool {
+    matches!(
+        opcode,
+        op::BALANCE
+            | op::ORIGIN
+            | op::GASPRICE
+            | op::EXTCODESIZE
+            | op::EXTCODECOPY
+            | op::EXTCODEHASH
+            | op::BLOCKHASH
+            | op::COINBASE
+            | op::TIMESTAMP
+            | op::NUMBER
+            | op::DIFFICULTY
+            | op::GASLIMIT
+            | op::CHAINID
+            | op::SELFBALANCE
+            | op::BASEFEE
+            | op::BLOBHASH
+            | op::BLOBBASEFEE
+            | op::SLOAD
+            | op::SSTORE
+            | op::TLOAD
+            | op::TSTORE
+            | op::LOG0
+            | op::LOG1
+            | op::LOG2
+            | op::LOG3
+            | op::LOG4
+            | op::EOFCREATE
+            | op::CREATE
+            | op::CALL
+            | op::CALLCODE
+            | op::DELEGATECALL
+            | op::CREATE2
+            | op::EXTCALL
+            | op::EXTDELEGATECALL
+            | op::STATICCALL
+            | op::EXTSTATICCALL
+            | op::SELFDESTRUCT
+    )
+}
+
 #[allow(unused_mut)]
 const fn make_map(spec_id: SpecId) -> [OpcodeInfo; 256] {
     const DYNAMIC: u16 = OpcodeInfo::DYNAMIC;
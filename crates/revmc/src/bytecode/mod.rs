@@ -1,16 +1,20 @@
 //! Internal EVM bytecode and opcode representation.
 
+use crate::CustomOpcode;
 use bitvec::vec::BitVec;
 use either::Either;
 use revm_interpreter::opcode as op;
-use revm_primitives::{hex, Eof, SpecId};
-use revmc_backend::{eyre::ensure, Result};
+use revm_primitives::{hex, Eof, SpecId, EOF_MAGIC_BYTES, U256};
+use revmc_backend::{ensure, Result};
 use rustc_hash::FxHashMap;
-use std::{borrow::Cow, fmt};
+use std::{borrow::Cow, collections::BTreeMap, fmt};
 
 mod sections;
 use sections::{Section, SectionAnalysis};
 
+mod cache;
+pub use cache::AnalysisCache;
+
 mod info;
 pub use info::*;
 
@@ -21,6 +25,10 @@ pub use opcode::*;
 #[cfg(any(feature = "__fuzzing", test))]
 pub(crate) const TEST_SUSPEND: u8 = 0x25;
 
+/// Noop opcode calling `Builtin::TestStop`, used to test builtins with a custom success result.
+#[cfg(test)]
+pub(crate) const TEST_BUILTIN_SUCCESS: u8 = 0x26;
+
 // TODO: Use `indexvec`.
 /// An EVM instruction is a high level internal representation of an EVM opcode.
 ///
@@ -29,8 +37,13 @@ pub(crate) const TEST_SUSPEND: u8 = 0x25;
 /// Also known as `ic`, or instruction counter; not to be confused with SSA `inst`s.
 pub(crate) type Inst = usize;
 
-/// EVM bytecode.
-#[doc(hidden)] // Not public API.
+/// A set of opcodes, indexed by opcode.
+pub(crate) type OpcodeSet = bitvec::BitArr!(for 256, in u64);
+
+/// Analyzed EVM bytecode.
+///
+/// Created with [`EvmCompiler::parse`](crate::EvmCompiler::parse) or
+/// [`Bytecode::analyze_legacy`].
 pub struct Bytecode<'a> {
     /// The original bytecode slice.
     pub(crate) code: &'a [u8],
@@ -50,20 +63,20 @@ pub struct Bytecode<'a> {
     pc_to_inst: FxHashMap<u32, u32>,
     /// Mapping from EOF code section index to the list of instructions that call it.
     eof_called_by: Vec<Vec<Inst>>,
+    /// Simple counter loops, sorted by head instruction. Always empty in EOF.
+    counter_loops: Vec<CounterLoop>,
+    /// Function selector dispatches, sorted by start instruction. Always empty in EOF.
+    selector_switches: Vec<SelectorSwitch>,
+    /// The constants pushed by `CONST` instructions.
+    constants: Vec<U256>,
+    /// Whether to fold constants during analysis.
+    constant_folding: bool,
 }
 
 impl<'a> Bytecode<'a> {
     #[instrument(name = "new_bytecode", level = "debug", skip_all)]
-    pub(crate) fn new(mut code: &'a [u8], eof: Option<Cow<'a, Eof>>, spec_id: SpecId) -> Self {
-        if let Some(eof) = &eof {
-            code = unsafe {
-                std::slice::from_raw_parts(
-                    eof.body.code_section.first().unwrap().as_ptr(),
-                    eof.header.sum_code_sizes,
-                )
-            };
-        }
-
+    pub(crate) fn new(code: &'a [u8], eof: Option<Cow<'a, Eof>>, spec_id: SpecId) -> Self {
+        let code = Self::code_section(code, eof.as_deref());
         let is_eof = eof.is_some();
 
         let mut insts = Vec::with_capacity(code.len() + 8);
@@ -110,6 +123,10 @@ impl<'a> Bytecode<'a> {
             may_suspend: false,
             pc_to_inst,
             eof_called_by: vec![],
+            counter_loops: vec![],
+            selector_switches: vec![],
+            constants: vec![],
+            constant_folding: true,
         };
 
         // Pad code to ensure there is at least one diverging instruction.
@@ -121,6 +138,98 @@ impl<'a> Bytecode<'a> {
         bytecode
     }
 
+    /// Parses and analyzes legacy bytecode without a compiler, using the default opcode
+    /// configuration.
+    ///
+    /// This allows inspecting the analysis results, e.g. with [`inst_infos`](Self::inst_infos)
+    /// and [`sections`](Self::sections), without a codegen backend. EOF containers must be
+    /// parsed with [`EvmCompiler::parse`](crate::EvmCompiler::parse), which also validates them.
+    pub fn analyze_legacy(code: &'a [u8], spec_id: SpecId) -> Result<Self> {
+        ensure!(
+            !(spec_id.is_enabled_in(SpecId::PRAGUE_EOF) && code.starts_with(&EOF_MAGIC_BYTES)),
+            InvalidUsage,
+            "EOF bytecode must be parsed with `EvmCompiler::parse`"
+        );
+        let mut bytecode = Self::new(code, None, spec_id);
+        bytecode.analyze()?;
+        Ok(bytecode)
+    }
+
+    /// Disables [constant folding](Self::fold_constants) in the analysis.
+    pub(crate) fn disable_constant_folding(&mut self) {
+        self.constant_folding = false;
+    }
+
+    /// Marks all the instructions whose opcode is not in `allowed` as unknown.
+    ///
+    /// Must be called before [`analyze`](Self::analyze).
+    pub(crate) fn restrict_opcodes(&mut self, allowed: &OpcodeSet) {
+        for inst in 0..self.insts.len() {
+            // The implicit `STOP` at the end of the code is always allowed.
+            if !allowed[self.insts[inst].opcode as usize] && !self.is_padding(inst) {
+                self.insts[inst].flags |= InstFlags::UNKNOWN;
+            }
+        }
+    }
+
+    /// Overrides the base gas cost of all the instructions with the one in `op_infos`.
+    ///
+    /// Custom opcodes keep their registered gas cost.
+    ///
+    /// Must be called before [`analyze`](Self::analyze).
+    pub(crate) fn pin_gas_costs(&mut self, op_infos: &[OpcodeInfo; 256]) {
+        for inst in &mut self.insts {
+            if !inst.flags.contains(InstFlags::CUSTOM) {
+                inst.base_gas = op_infos[inst.opcode as usize].base_gas();
+            }
+        }
+    }
+
+    /// Marks all the unknown instructions whose opcode is registered in `custom` as custom
+    /// instructions, see [`EvmCompiler::register_opcode`](crate::EvmCompiler::register_opcode).
+    ///
+    /// Must be called before [`restrict_opcodes`](Self::restrict_opcodes) and
+    /// [`analyze`](Self::analyze).
+    pub(crate) fn set_custom_opcodes(&mut self, custom: &[Option<CustomOpcode>; 256]) {
+        for inst in &mut self.insts {
+            let Some(op) = &custom[inst.opcode as usize] else { continue };
+            if inst.flags.contains(InstFlags::UNKNOWN) {
+                inst.flags.remove(InstFlags::UNKNOWN);
+                inst.flags |= InstFlags::CUSTOM;
+                inst.base_gas = op.gas;
+                inst.data = u32::from_le_bytes([op.inputs, op.outputs, 0, 0]);
+            }
+        }
+
+        // Unknown opcodes were diverging when the code was padded in `new`.
+        if !self.is_eof() && !self.insts.last().unwrap().is_diverging(false) {
+            self.insts.push(InstData::new(op::STOP));
+        }
+    }
+
+    /// Returns `true` if the given instruction is the `STOP` that is added to the end of legacy
+    /// code that doesn't end with a diverging instruction, see [`new`](Self::new).
+    ///
+    /// Its `pc` is 0, as it is not part of the code.
+    fn is_padding(&self, inst: Inst) -> bool {
+        !self.is_eof()
+            && inst == self.insts.len() - 1
+            && if inst == 0 { self.code.is_empty() } else { self.insts[inst].pc == 0 }
+    }
+
+    /// Returns the code to analyze: either the given code, or all of the EOF code sections.
+    fn code_section(code: &'a [u8], eof: Option<&Eof>) -> &'a [u8] {
+        match eof {
+            Some(eof) => unsafe {
+                std::slice::from_raw_parts(
+                    eof.body.code_section.first().unwrap().as_ptr(),
+                    eof.header.sum_code_sizes,
+                )
+            },
+            None => code,
+        }
+    }
+
     /// Returns an iterator over the opcodes.
     #[inline]
     pub(crate) fn opcodes(&self) -> OpcodesIter<'a> {
@@ -183,16 +292,129 @@ impl<'a> Bytecode<'a> {
         self.insts.iter_mut().enumerate()
     }
 
+    /// Returns an iterator over information about all the instructions, including dead code.
+    ///
+    /// Legacy bytecode that does not end with a diverging instruction has an additional `STOP`
+    /// instruction at `pc == code.len()`.
+    pub fn inst_infos(&self) -> impl DoubleEndedIterator<Item = InstInfo> + ExactSizeIterator + '_ {
+        let op_infos = op_info_map(self.spec_id);
+        self.iter_all_insts().map(|(inst, data)| {
+            let info = op_infos[data.opcode as usize];
+            let is_static = !(info.is_unknown() || info.is_disabled() || info.is_dynamic());
+            let is_custom = data.flags.contains(InstFlags::CUSTOM);
+            let (stack_in, stack_out) = evm_stack_io(data);
+            let jump_target = (data.is_legacy_static_jump()
+                && !data.flags.contains(InstFlags::INVALID_JUMP))
+            .then(|| self.inst(data.data as Inst).pc as usize);
+            InstInfo {
+                inst,
+                pc: if self.is_padding(inst) { self.code.len() } else { data.pc as usize },
+                opcode: data.opcode,
+                static_gas: (is_static || is_custom).then_some(data.base_gas as u64),
+                stack_in,
+                stack_out,
+                is_dead_code: data.is_dead_code(),
+                jump_target,
+            }
+        })
+    }
+
+    /// Returns the sections of the bytecode, in order.
+    ///
+    /// A section is a sequence of instructions that is always executed as a whole once entered,
+    /// unless execution halts in it. Sections start at reachable `JUMPDEST`s and after instructions
+    /// that branch, may suspend execution, or require the remaining gas. Dead code is not part of
+    /// any section.
+    pub fn sections(&self) -> Vec<SectionInfo> {
+        let is_eof = self.is_eof();
+        let mut sections = Vec::<SectionInfo>::new();
+        let mut ended = true;
+        for (inst, data) in self.iter_insts() {
+            if ended || data.is_reachable_jumpdest(is_eof, self.has_dynamic_jumps()) {
+                sections.push(SectionInfo {
+                    start_inst: inst,
+                    start_pc: data.pc as usize,
+                    len: 0,
+                    static_gas: 0,
+                    stack_inputs: 0,
+                    stack_max_growth: 0,
+                    stack_diff: 0,
+                });
+            }
+            let section = sections.last_mut().unwrap();
+            let (inp, out) = evm_stack_io(data);
+            let required = inp as isize - section.stack_diff;
+            section.stack_inputs = section.stack_inputs.max(required.max(0) as usize);
+            section.stack_diff += out as isize - inp as isize;
+            section.stack_max_growth =
+                section.stack_max_growth.max(section.stack_diff.max(0) as usize);
+            section.static_gas += data.base_gas as u64;
+            section.len += 1;
+            ended = data.ends_section(is_eof, self.spec_id);
+        }
+        sections
+    }
+
+    /// Returns the loops in the bytecode, sorted by their header.
+    ///
+    /// A loop is found for every `JUMPDEST` that is the target of a static `JUMP` or `JUMPI` at
+    /// or after it. Loops formed only by dynamic jumps are not detected. Always empty in EOF.
+    pub fn loops(&self) -> Vec<LoopInfo> {
+        if self.is_eof() {
+            return Vec::new();
+        }
+        let mut back_edges = BTreeMap::<Inst, Inst>::new();
+        for (jump, data) in self.iter_insts() {
+            if !(data.is_legacy_jump()
+                && data.flags.contains(InstFlags::STATIC_JUMP)
+                && !data.flags.contains(InstFlags::INVALID_JUMP))
+            {
+                continue;
+            }
+            let header = data.data as Inst;
+            if header <= jump {
+                let last = back_edges.entry(header).or_insert(jump);
+                *last = (*last).max(jump);
+            }
+        }
+        back_edges
+            .into_iter()
+            .map(|(header, jump)| {
+                let header_pc = self.inst(header).pc as usize;
+                let back_edge_pc = self.inst(jump).pc as usize;
+                LoopInfo {
+                    header_inst: header,
+                    header_pc,
+                    back_edge_pc,
+                    body_size: back_edge_pc + 1 - header_pc,
+                }
+            })
+            .collect()
+    }
+
     /// Runs a list of analysis passes on the instructions.
     #[instrument(level = "debug", skip_all)]
     pub(crate) fn analyze(&mut self) -> Result<()> {
         if !self.is_eof() {
             self.static_jump_analysis();
-            // NOTE: `mark_dead_code` must run after `static_jump_analysis` as it can mark
-            // unreachable `JUMPDEST`s as dead code.
+            self.resolve_computed_jumps();
+            // NOTE: `mark_dead_code` must run after the jump analyses as it follows the static
+            // jumps to find the reachable instructions.
             self.mark_dead_code();
+            self.find_counter_loops();
+            // NOTE: `fold_constants` must run before the other fusions, as it only folds
+            // instructions without flags.
+            if self.constant_folding {
+                self.fold_constants();
+            }
+            self.find_selector_switches();
         }
 
+        self.fuse_eq_iszero();
+        self.fuse_push_pop();
+        self.fuse_dup1_mload();
+        self.fuse_swap1_pop();
+
         self.calc_may_suspend();
 
         if self.is_eof() {
@@ -270,6 +492,518 @@ impl<'a> Bytecode<'a> {
         }
     }
 
+    /// Resolve the remaining jumps whose target is a constant computed in the same block, such as
+    /// `PUSH2 tag DUP1 SWAP2 JUMP`.
+    ///
+    /// The constants are tracked through `DUP*`, `SWAP*`, `POP`, and the instructions that can be
+    /// folded into constants. Every `JUMPDEST` starts a new block with an unknown stack.
+    ///
+    /// Resolved jumps are marked as `STATIC_JUMP` and `COMPUTED_JUMP`, as their target is still
+    /// on the stack.
+    #[instrument(name = "cj", level = "debug", skip_all)]
+    fn resolve_computed_jumps(&mut self) {
+        debug_assert!(!self.is_eof());
+
+        if !self.has_dynamic_jumps {
+            return;
+        }
+
+        // The values on top of the stack since the start of the block, if known. The values below
+        // them are unknown.
+        let mut stack = Vec::<Option<U256>>::new();
+        for inst in 0..self.insts.len() {
+            let data = &self.insts[inst];
+            if data.is_jumpdest() {
+                stack.clear();
+                continue;
+            }
+            if data.is_legacy_jump() && !data.flags.contains(InstFlags::STATIC_JUMP) {
+                if let Some(&Some(target_pc)) = stack.last() {
+                    self.resolve_computed_jump(inst, target_pc);
+                }
+            }
+
+            let data = &self.insts[inst];
+            match data.opcode {
+                op::PUSH0..=op::PUSH32 => stack.push(Some(self.push_value(data))),
+                op::DUP1..=op::DUP16 => {
+                    let n = (data.opcode - op::DUP1 + 1) as usize;
+                    let value = stack.len().checked_sub(n).and_then(|i| stack[i]);
+                    stack.push(value);
+                }
+                op::SWAP1..=op::SWAP16 => {
+                    let n = (data.opcode - op::SWAP1 + 1) as usize;
+                    let missing = (n + 1).saturating_sub(stack.len());
+                    stack.splice(0..0, std::iter::repeat(None).take(missing));
+                    let len = stack.len();
+                    stack.swap(len - 1, len - 1 - n);
+                }
+                _ => {
+                    let (inp, out) = evm_stack_io(data);
+                    // The first argument is the top of the stack.
+                    let args = (0..inp)
+                        .map(|_| stack.pop().flatten())
+                        .collect::<Option<Vec<_>>>()
+                        .filter(|_| out == 1);
+                    let value = args.and_then(|args| fold_opcode(data.opcode, &args));
+                    stack.extend(std::iter::repeat(value).take(out as usize));
+                }
+            }
+        }
+
+        self.has_dynamic_jumps = self
+            .insts
+            .iter()
+            .any(|data| data.is_legacy_jump() && !data.flags.contains(InstFlags::STATIC_JUMP));
+    }
+
+    fn resolve_computed_jump(&mut self, jump_inst: Inst, target_pc: U256) {
+        self.insts[jump_inst].flags |= InstFlags::STATIC_JUMP | InstFlags::COMPUTED_JUMP;
+        let Some(target_pc) = usize::try_from(target_pc).ok().filter(|&pc| self.is_valid_jump(pc))
+        else {
+            trace!(jump_inst, %target_pc, "invalid computed jump target");
+            self.insts[jump_inst].flags |= InstFlags::INVALID_JUMP;
+            return;
+        };
+        let target = self.pc_to_inst(target_pc);
+        self.insts[target].data = 1;
+        trace!(jump_inst, target, "found computed jump");
+        self.insts[jump_inst].data = target as u32;
+    }
+
+    /// Finds simple counter loops that can be unrolled.
+    ///
+    /// These are loops in the form of
+    /// `PUSH<N> count JUMPDEST body PUSH1 1 SWAP1 SUB DUP1 PUSH<N> head JUMPI`, where `body` is
+    /// straight-line code that doesn't contain any other jumps or jump destinations, doesn't stop
+    /// or suspend execution, and leaves the counter unchanged on top of the stack. The `JUMPDEST`
+    /// must not be the target of any other jump, so the loop runs exactly `count` times unless
+    /// execution is halted.
+    #[instrument(name = "loops", level = "debug", skip_all)]
+    fn find_counter_loops(&mut self) {
+        debug_assert!(!self.is_eof());
+
+        // The loop head must only be entered from the `PUSH` of the counter and the loop itself.
+        if self.has_dynamic_jumps {
+            return;
+        }
+        let mut jumps_to = FxHashMap::<Inst, u32>::default();
+        for (_, data) in self.iter_insts() {
+            if data.is_legacy_static_jump() && !data.flags.contains(InstFlags::INVALID_JUMP) {
+                *jumps_to.entry(data.data as Inst).or_default() += 1;
+            }
+        }
+
+        let mut counter_loops = Vec::new();
+        for (jumpi, data) in self.iter_insts() {
+            if !(data.opcode == op::JUMPI
+                && data.flags.contains(InstFlags::STATIC_JUMP)
+                && !data.flags.intersects(InstFlags::INVALID_JUMP | InstFlags::COMPUTED_JUMP))
+            {
+                continue;
+            }
+            let head = data.data as Inst;
+            if head == 0 || head >= jumpi || jumps_to[&head] != 1 {
+                continue;
+            }
+
+            let push = self.inst(head - 1);
+            if !push.is_push() || push.is_dead_code() {
+                continue;
+            }
+            let Some(count) = self.get_imm_u32(push) else { continue };
+            if count == 0 {
+                continue;
+            }
+
+            // `jumpi - 1` is the `PUSH` of the jump target.
+            let body = &self.insts[head + 1..jumpi - 1];
+            let is_straight_line = body.iter().all(|data| {
+                (data.flags - InstFlags::SKIP_LOGIC).is_empty()
+                    && !data.is_jump(false)
+                    && !data.is_jumpdest()
+                    && !data.is_diverging(false)
+                    && !data.may_suspend(false)
+            });
+            if !is_straight_line {
+                continue;
+            }
+
+            // The body must end by decrementing the counter and duplicating it as the condition.
+            let Some((body, decrement)) = body.split_last_chunk::<4>() else { continue };
+            let [push1, swap1, sub, dup1] = decrement;
+            if !(push1.opcode == op::PUSH1
+                && self.get_imm_u32(push1) == Some(1)
+                && swap1.opcode == op::SWAP1
+                && sub.opcode == op::SUB
+                && dup1.opcode == op::DUP1)
+            {
+                continue;
+            }
+
+            // Track the depth of the counter from the top of the stack through the body, which
+            // may move it with `DUP`s and `SWAP`s, but must not consume it.
+            let mut depth = 0u32;
+            let keeps_counter = body.iter().all(|data| {
+                match data.opcode {
+                    op::DUP1..=op::DUP16 => depth += 1,
+                    op::SWAP1..=op::SWAP16 => {
+                        let n = (data.opcode - op::SWAP1 + 1) as u32;
+                        if depth == 0 {
+                            depth = n;
+                        } else if depth == n {
+                            depth = 0;
+                        }
+                    }
+                    opcode => {
+                        let (inp, out) = stack_io(opcode);
+                        if depth < inp as u32 {
+                            return false;
+                        }
+                        depth = depth - inp as u32 + out as u32;
+                    }
+                }
+                true
+            });
+            if !keeps_counter || depth != 0 {
+                continue;
+            }
+
+            trace!(head, jumpi, count, "found counter loop");
+            counter_loops.push(CounterLoop { head, jumpi, count });
+        }
+        self.counter_loops = counter_loops;
+    }
+
+    /// Returns the immediate of the given `PUSH` instruction as a `u32`, if it fits.
+    fn get_imm_u32(&self, data: &InstData) -> Option<u32> {
+        let imm = self.get_imm(data).unwrap_or_default();
+        if imm.len() > 4 {
+            return None;
+        }
+        let mut padded = [0; 4];
+        padded[4 - imm.len()..].copy_from_slice(imm);
+        Some(u32::from_be_bytes(padded))
+    }
+
+    /// Returns the simple counter loop starting at the given instruction, if any.
+    pub(crate) fn counter_loop_at(&self, head: Inst) -> Option<CounterLoop> {
+        let i = self.counter_loops.binary_search_by_key(&head, |l| l.head).ok()?;
+        Some(self.counter_loops[i])
+    }
+
+    /// Finds function selector dispatches, which are sequences of comparisons of the value on top
+    /// of the stack with constants in the form of `DUP1 PUSH<N> selector EQ PUSH<N> target JUMPI`,
+    /// as emitted by Solidity after loading the selector with `CALLDATALOAD` and `SHR`.
+    ///
+    /// Only sequences of at least [`SelectorSwitch::MIN_CASES`] comparisons are recorded. Any such
+    /// sequence can be lowered to a switch, but only the ones whose compared value is shown to be
+    /// the calldata selector, see [`is_calldata_selector_at`](Self::is_calldata_selector_at), are
+    /// used for the selector entry points.
+    #[instrument(name = "selectors", level = "debug", skip_all)]
+    fn find_selector_switches(&mut self) {
+        debug_assert!(!self.is_eof());
+
+        let mut selector_switches = Vec::new();
+        let mut inst = 0;
+        while inst < self.insts.len() {
+            let mut cases = Vec::new();
+            while let Some((op::EQ, selector, target)) =
+                self.comparison_at(inst + cases.len() * SelectorSwitch::CASE_LEN)
+            {
+                cases.push((selector, target));
+            }
+            if cases.len() < SelectorSwitch::MIN_CASES {
+                inst += 1;
+                continue;
+            }
+            let is_calldata = self.is_calldata_selector_at(inst);
+            trace!(inst, cases = cases.len(), is_calldata, "found selector switch");
+            let switch = SelectorSwitch { start: inst, cases, is_calldata };
+            inst = switch.end();
+            selector_switches.push(switch);
+        }
+        self.selector_switches = selector_switches;
+    }
+
+    /// Returns the comparison `DUP1 PUSH<N> value <EQ|LT|GT> PUSH<N> target JUMPI` starting at
+    /// the given instruction, if any, as its comparison opcode, value and target.
+    ///
+    /// The value on top of the stack is the same before and after the comparison, whether the
+    /// jump is taken or not.
+    fn comparison_at(&self, inst: Inst) -> Option<(u8, u32, Inst)> {
+        let case = self.insts.get(inst..inst + SelectorSwitch::CASE_LEN)?;
+        let [dup, push, cmp, target, jumpi] = case else { unreachable!() };
+        let is_comparison = dup.opcode == op::DUP1
+            && dup.flags.is_empty()
+            && dup.data == 0
+            && matches!(push.opcode, op::PUSH1..=op::PUSH4)
+            && push.flags.is_empty()
+            && matches!(cmp.opcode, op::EQ | op::LT | op::GT)
+            && cmp.flags.is_empty()
+            && cmp.data == 0
+            && target.is_push()
+            && target.flags == InstFlags::SKIP_LOGIC
+            && jumpi.opcode == op::JUMPI
+            && jumpi.flags == InstFlags::STATIC_JUMP;
+        is_comparison.then(|| (cmp.opcode, self.push_value(push).to::<u32>(), jumpi.data as Inst))
+    }
+
+    /// Returns `true` if the value on top of the stack before the given instruction is always the
+    /// function selector loaded from the calldata.
+    ///
+    /// This is the case if the instruction is reached only right after loading the selector, with
+    /// either `CALLDATALOAD(0) >> 224` or `CALLDATALOAD(0) / 2**224 & 0xffffffff`, or through
+    /// comparisons of it, such as the ones splitting the dispatch of Solidity contracts with many
+    /// functions into several sequences.
+    fn is_calldata_selector_at(&self, inst: Inst) -> bool {
+        let mut jumps_to = FxHashMap::<Inst, Vec<Inst>>::default();
+        for (jump, data) in self.iter_insts() {
+            if data.is_legacy_static_jump() && !data.flags.contains(InstFlags::INVALID_JUMP) {
+                jumps_to.entry(data.data as Inst).or_default().push(jump);
+            }
+        }
+
+        // All the predecessors of the visited instructions must either load the selector, or be
+        // comparisons or `JUMPDEST`s, which leave it on top of the stack, reached the same way.
+        let mut visited: BitVec = BitVec::repeat(false, self.insts.len());
+        let mut stack = vec![inst];
+        while let Some(inst) = stack.pop() {
+            if visited.replace(inst, true) {
+                continue;
+            }
+            // `JUMPDEST`s may also be reached from dynamic jumps.
+            if inst == 0 || (self.insts[inst].is_jumpdest() && self.has_dynamic_jumps) {
+                return false;
+            }
+
+            let prev = &self.insts[inst - 1];
+            if !(prev.opcode == op::JUMP || prev.is_diverging(false)) {
+                if self.loads_calldata_selector_before(inst) {
+                    // Fall through from the selector load.
+                } else if prev.is_jumpdest() {
+                    stack.push(inst - 1);
+                } else if inst >= SelectorSwitch::CASE_LEN
+                    && self.comparison_at(inst - SelectorSwitch::CASE_LEN).is_some()
+                {
+                    stack.push(inst - SelectorSwitch::CASE_LEN);
+                } else {
+                    return false;
+                }
+            }
+
+            for &jump in jumps_to.get(&inst).into_iter().flatten() {
+                let Some(start) = (jump + 1).checked_sub(SelectorSwitch::CASE_LEN) else {
+                    return false;
+                };
+                if self.comparison_at(start).is_none() {
+                    return false;
+                }
+                stack.push(start);
+            }
+        }
+        true
+    }
+
+    /// Returns `true` if the instructions right before the given one load the function selector
+    /// from the calldata.
+    fn loads_calldata_selector_before(&self, inst: Inst) -> bool {
+        let is = |data: &InstData, opcode: u8, value: Option<u64>| {
+            data.opcode == opcode
+                && data.flags.is_empty()
+                && value.map_or(true, |value| self.push_value(data) == U256::from(value))
+        };
+        let is_zero = |data: &InstData| is(data, op::PUSH0, None) || is(data, op::PUSH1, Some(0));
+        let insts = &self.insts[..inst];
+
+        // `PUSH0 CALLDATALOAD PUSH1 0xe0 SHR`
+        if let [.., zero, load, shift, shr] = insts {
+            if is_zero(zero)
+                && is(load, op::CALLDATALOAD, None)
+                && is(shift, op::PUSH1, Some(224))
+                && is(shr, op::SHR, None)
+            {
+                return true;
+            }
+        }
+
+        // `PUSH1 0 CALLDATALOAD PUSH29 0x0100..00 SWAP1 DIV PUSH4 0xffffffff AND`
+        if let [.., zero, load, divisor, swap, div, mask, and] = insts {
+            if is_zero(zero)
+                && is(load, op::CALLDATALOAD, None)
+                && divisor.opcode == op::PUSH29
+                && divisor.flags.is_empty()
+                && self.push_value(divisor) == U256::from(1) << 224
+                && is(swap, op::SWAP1, None)
+                && is(div, op::DIV, None)
+                && is(mask, op::PUSH4, Some(0xffffffff))
+                && is(and, op::AND, None)
+            {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Returns the selector switch starting at the given instruction, if any.
+    pub(crate) fn selector_switch_at(&self, start: Inst) -> Option<&SelectorSwitch> {
+        let i = self.selector_switches.binary_search_by_key(&start, |s| s.start).ok()?;
+        Some(&self.selector_switches[i])
+    }
+
+    /// Returns the selectors of all the selector switches on the calldata selector, sorted and
+    /// deduplicated.
+    pub(crate) fn selectors(&self) -> Vec<u32> {
+        let mut selectors = self
+            .selector_switches
+            .iter()
+            .filter(|s| s.is_calldata)
+            .flat_map(|s| s.cases.iter().map(|&(selector, _)| selector))
+            .collect::<Vec<_>>();
+        selectors.sort_unstable();
+        selectors.dedup();
+        selectors
+    }
+
+    /// Fold instructions whose operands are all constants pushed right before them, such as
+    /// `PUSH1 1 PUSH1 2 ADD`, into a single constant.
+    ///
+    /// The first instruction of the sequence is marked as `CONST` and pushes the result, and the
+    /// others are marked as `SKIP_LOGIC` so that they only contribute their gas cost. Folded
+    /// sequences can be operands themselves, so chains of constant arithmetic are folded entirely.
+    #[instrument(name = "fold", level = "debug", skip_all)]
+    fn fold_constants(&mut self) {
+        debug_assert!(!self.is_eof());
+
+        // The constants on top of the stack, and the first instruction of the sequence pushing
+        // each of them.
+        let mut operands = Vec::<(Inst, U256)>::new();
+        for inst in 0..self.insts.len() {
+            let data = &self.insts[inst];
+            if !data.flags.is_empty() {
+                operands.clear();
+                continue;
+            }
+            if data.is_push() {
+                operands.push((inst, self.push_value(data)));
+                continue;
+            }
+
+            let n = stack_io(data.opcode).0 as usize;
+            let Some(start) = operands.len().checked_sub(n).filter(|_| n > 0) else {
+                operands.clear();
+                continue;
+            };
+            // The first argument is the top of the stack.
+            let args = operands[start..].iter().rev().map(|&(_, value)| value).collect::<Vec<_>>();
+            let Some(value) = fold_opcode(data.opcode, &args) else {
+                operands.clear();
+                continue;
+            };
+
+            let first = operands[start].0;
+            trace!(first, inst, %value, "folding constant");
+            for data in &mut self.insts[first + 1..=inst] {
+                data.flags |= InstFlags::SKIP_LOGIC;
+            }
+            let first_data = &mut self.insts[first];
+            if first_data.flags.contains(InstFlags::CONST) {
+                self.constants[first_data.data as usize] = value;
+            } else {
+                first_data.flags |= InstFlags::CONST;
+                first_data.data = self.constants.len() as u32;
+                self.constants.push(value);
+            }
+            operands.truncate(start);
+            operands.push((first, value));
+        }
+    }
+
+    /// Fuse `EQ` immediately followed by `ISZERO` into a single "not equal" comparison.
+    ///
+    /// The `EQ` is marked with `data = 1`, and the `ISZERO` is marked as `SKIP_LOGIC` so that it
+    /// only contributes its gas cost.
+    #[instrument(name = "fuse_eq", level = "debug", skip_all)]
+    fn fuse_eq_iszero(&mut self) {
+        for eq_inst in 0..self.insts.len().saturating_sub(1) {
+            let eq = &self.insts[eq_inst];
+            let iszero = &self.insts[eq_inst + 1];
+            if !(eq.opcode == op::EQ && iszero.opcode == op::ISZERO)
+                || !eq.flags.is_empty()
+                || !iszero.flags.is_empty()
+            {
+                continue;
+            }
+            trace!(eq_inst, "fusing EQ ISZERO");
+            self.insts[eq_inst].data = 1;
+            self.insts[eq_inst + 1].flags |= InstFlags::SKIP_LOGIC;
+        }
+    }
+
+    /// Skip `PUSH<N>` immediately followed by `POP`.
+    ///
+    /// Both instructions are marked as `SKIP_LOGIC` so that they only contribute their gas cost.
+    /// Stack length checks are still performed as part of the section they belong to.
+    #[instrument(name = "fuse_pop", level = "debug", skip_all)]
+    fn fuse_push_pop(&mut self) {
+        for push_inst in 0..self.insts.len().saturating_sub(1) {
+            let push = &self.insts[push_inst];
+            let pop = &self.insts[push_inst + 1];
+            if !(push.is_push() && pop.opcode == op::POP)
+                || !push.flags.is_empty()
+                || !pop.flags.is_empty()
+            {
+                continue;
+            }
+            trace!(push_inst, "fusing PUSH POP");
+            self.insts[push_inst].flags |= InstFlags::SKIP_LOGIC;
+            self.insts[push_inst + 1].flags |= InstFlags::SKIP_LOGIC;
+        }
+    }
+
+    /// Fuse `DUP1` immediately followed by `MLOAD` into a load that keeps the offset on the stack.
+    ///
+    /// The `DUP1` is marked with `data = 1`, and the `MLOAD` is marked as `SKIP_LOGIC` so that it
+    /// only contributes its gas cost.
+    #[instrument(name = "fuse_mload", level = "debug", skip_all)]
+    fn fuse_dup1_mload(&mut self) {
+        for dup_inst in 0..self.insts.len().saturating_sub(1) {
+            let dup = &self.insts[dup_inst];
+            let mload = &self.insts[dup_inst + 1];
+            if !(dup.opcode == op::DUP1 && mload.opcode == op::MLOAD)
+                || !dup.flags.is_empty()
+                || !mload.flags.is_empty()
+            {
+                continue;
+            }
+            trace!(dup_inst, "fusing DUP1 MLOAD");
+            self.insts[dup_inst].data = 1;
+            self.insts[dup_inst + 1].flags |= InstFlags::SKIP_LOGIC;
+        }
+    }
+
+    /// Fuse `SWAP1` immediately followed by `POP` into a single removal of the second value.
+    ///
+    /// The `SWAP1` is marked as `SKIP_LOGIC` so that it only contributes its gas cost, and the
+    /// `POP` is marked with `data = 1`.
+    #[instrument(name = "fuse_swap", level = "debug", skip_all)]
+    fn fuse_swap1_pop(&mut self) {
+        for swap_inst in 0..self.insts.len().saturating_sub(1) {
+            let swap = &self.insts[swap_inst];
+            let pop = &self.insts[swap_inst + 1];
+            if !(swap.opcode == op::SWAP1 && pop.opcode == op::POP)
+                || !swap.flags.is_empty()
+                || !pop.flags.is_empty()
+            {
+                continue;
+            }
+            trace!(swap_inst, "fusing SWAP1 POP");
+            self.insts[swap_inst].flags |= InstFlags::SKIP_LOGIC;
+            self.insts[swap_inst + 1].data = 1;
+        }
+    }
+
     /// Mark `RJUMP*` targets with `EOF_JUMPDEST` flag.
     #[instrument(name = "eof_sj", level = "debug", skip_all)]
     fn eof_mark_jumpdests(&mut self) {
@@ -292,28 +1026,66 @@ impl<'a> Bytecode<'a> {
     /// unreachable code that we generate, but this is trivial for us to do and significantly speeds
     /// up code generation.
     ///
-    /// We can simply mark all instructions that are between diverging instructions and
-    /// `JUMPDEST`s.
+    /// Instructions are reachable from the first one by falling through, by static jumps, and, if
+    /// any dynamic jump is reachable, by jumping to any `JUMPDEST`. If no dynamic jump is
+    /// reachable, the bytecode is treated as not having any.
+    ///
+    /// EOF code is not pruned, as unreachable code is rejected by EOF validation.
     #[instrument(name = "dce", level = "debug", skip_all)]
     fn mark_dead_code(&mut self) {
         debug_assert!(!self.is_eof());
 
-        let mut iter = self.insts.iter_mut().enumerate();
-        while let Some((i, data)) = iter.next() {
-            if data.is_diverging(false) {
-                let mut end = i;
-                for (j, data) in &mut iter {
-                    end = j;
-                    if data.is_reachable_jumpdest(false, self.has_dynamic_jumps) {
-                        break;
-                    }
-                    data.flags |= InstFlags::DEAD_CODE;
+        let mut reachable = vec![false; self.insts.len()];
+        let mut has_dynamic_jumps = false;
+        let mut worklist = vec![0];
+        loop {
+            while let Some(inst) = worklist.pop() {
+                if inst >= self.insts.len() || reachable[inst] {
+                    continue;
                 }
-                let start = i + 1;
-                if end > start {
-                    debug!("found dead code: {start}..{end}");
+                reachable[inst] = true;
+
+                let data = &self.insts[inst];
+                if data.is_legacy_jump() {
+                    if !data.flags.contains(InstFlags::STATIC_JUMP) {
+                        has_dynamic_jumps = true;
+                    } else if !data.flags.contains(InstFlags::INVALID_JUMP) {
+                        worklist.push(data.data as Inst);
+                    }
+                    if data.opcode == op::JUMPI {
+                        worklist.push(inst + 1);
+                    }
+                } else if !data.is_diverging(false) {
+                    worklist.push(inst + 1);
                 }
             }
+
+            // Any `JUMPDEST` can be the target of a dynamic jump.
+            let before = worklist.len();
+            if has_dynamic_jumps {
+                worklist.extend(
+                    (0..self.insts.len())
+                        .filter(|&inst| self.insts[inst].is_jumpdest() && !reachable[inst]),
+                );
+            }
+            if worklist.len() == before {
+                break;
+            }
+        }
+
+        let mut dead = 0usize;
+        for (data, reachable) in self.insts.iter_mut().zip(reachable) {
+            if !reachable {
+                data.flags |= InstFlags::DEAD_CODE;
+                dead += 1;
+            }
+        }
+        if dead > 0 {
+            debug!(dead, "found dead code");
+        }
+        if self.has_dynamic_jumps && !has_dynamic_jumps {
+            debug!("all dynamic jumps are dead code");
+            self.has_dynamic_jumps = false;
         }
     }
 
@@ -404,7 +1176,7 @@ impl<'a> Bytecode<'a> {
         // TODO: Is this actually reachable?
         // If so, we should remove this error and handle this case properly by making all `CALLF`
         // reachable.
-        ensure!(i < MAX_ITERATIONS, "`calc_eof_called_by` did not converge");
+        ensure!(i < MAX_ITERATIONS, InvalidBytecode, "`calc_eof_called_by` did not converge");
         self.eof_called_by = eof_called_by;
         Ok(())
     }
@@ -428,13 +1200,31 @@ impl<'a> Bytecode<'a> {
         self.code.get(start..start + imm_len)
     }
 
+    /// Returns the value pushed by the given `PUSH*` instruction.
+    pub(crate) fn push_value(&self, data: &InstData) -> U256 {
+        if data.flags.contains(InstFlags::CONST) {
+            return self.constants[data.data as usize];
+        }
+        // NOTE: This can be None if the bytecode is invalid.
+        self.get_imm(data).map(U256::from_be_slice).unwrap_or_default()
+    }
+
+    /// Returns the program counters of all the valid jump destinations, in ascending order.
+    ///
+    /// These are all the `JUMPDEST` opcodes that are not part of `PUSH` immediate data, including
+    /// unreachable ones. Always empty in EOF, which has no dynamic jumps.
+    pub fn jump_dests(&self) -> impl Iterator<Item = u32> + '_ {
+        self.jumpdests.iter_ones().map(|pc| pc as u32)
+    }
+
     /// Returns `true` if the given program counter is a valid jump destination.
     fn is_valid_jump(&self, pc: usize) -> bool {
         self.jumpdests.get(pc).as_deref().copied() == Some(true)
     }
 
-    /// Returns `true` if the bytecode has dynamic jumps.
-    pub(crate) fn has_dynamic_jumps(&self) -> bool {
+    /// Returns `true` if the bytecode has dynamic jumps, meaning jumps whose target is not known
+    /// statically.
+    pub fn has_dynamic_jumps(&self) -> bool {
         self.has_dynamic_jumps
     }
 
@@ -443,6 +1233,79 @@ impl<'a> Bytecode<'a> {
         self.may_suspend
     }
 
+    /// Returns the highest memory offset accessed by the bytecode, if it can be determined
+    /// statically.
+    ///
+    /// This is the case if every memory access is an `MLOAD`, `MSTORE`, `MSTORE8`, `RETURN` or
+    /// `REVERT` whose memory operands are pushed by the immediately preceding `PUSH` instructions,
+    /// and no other instruction reads or writes memory.
+    ///
+    /// This is never the case in EOF, where the memory instructions can be the targets of relative
+    /// jumps, and can therefore be reached without executing the preceding instructions.
+    pub(crate) fn static_memory_bound(&self) -> Option<usize> {
+        if self.may_suspend() || self.is_eof() {
+            return None;
+        }
+
+        // Returns the immediate of the `PUSH` instruction `n` instructions before `inst`.
+        let push_imm = |inst: Inst, n: usize| -> Option<usize> {
+            let data = self.insts.get(inst.checked_sub(n)?)?;
+            if !data.is_push() || !data.flags.is_empty() {
+                return None;
+            }
+            let imm = self.get_imm(data).unwrap_or(&[]);
+            let (high, low) = imm.split_at(imm.len().saturating_sub(4));
+            if high.iter().any(|&b| b != 0) {
+                return None;
+            }
+            let mut padded = [0; 4];
+            padded[4 - low.len()..].copy_from_slice(low);
+            Some(u32::from_be_bytes(padded) as usize)
+        };
+
+        let mut bound = 0;
+        for (inst, data) in self.iter_insts() {
+            // Custom opcodes may access memory.
+            if data.flags.contains(InstFlags::CUSTOM) {
+                return None;
+            }
+            let end = match data.opcode {
+                op::MLOAD | op::MSTORE => push_imm(inst, 1)? + 32,
+                op::MSTORE8 => push_imm(inst, 1)? + 1,
+                op::RETURN | op::REVERT => {
+                    let offset = push_imm(inst, 1)?;
+                    match push_imm(inst, 2)? {
+                        0 => 0,
+                        len => offset + len,
+                    }
+                }
+                op::KECCAK256
+                | op::CALLDATACOPY
+                | op::CODECOPY
+                | op::EXTCODECOPY
+                | op::RETURNDATACOPY
+                | op::MSIZE
+                | op::MCOPY
+                | op::LOG0..=op::LOG4
+                | op::DATACOPY
+                | op::CREATE
+                | op::CREATE2
+                | op::EOFCREATE
+                | op::RETURNCONTRACT
+                | op::CALL
+                | op::CALLCODE
+                | op::DELEGATECALL
+                | op::STATICCALL
+                | op::EXTCALL
+                | op::EXTDELEGATECALL
+                | op::EXTSTATICCALL => return None,
+                _ => continue,
+            };
+            bound = bound.max(end);
+        }
+        Some(bound)
+    }
+
     /// Returns `true` if the bytecode is EOF.
     pub(crate) fn is_eof(&self) -> bool {
         self.eof.is_some()
@@ -603,10 +1466,52 @@ impl fmt::Debug for Bytecode<'_> {
             .field("spec_id", &self.spec_id)
             .field("has_dynamic_jumps", &self.has_dynamic_jumps)
             .field("may_suspend", &self.may_suspend)
+            .field("constants", &self.constants)
             .finish()
     }
 }
 
+/// A function selector dispatch. See [`Bytecode::find_selector_switches`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SelectorSwitch {
+    /// The `DUP1` of the first comparison.
+    pub(crate) start: Inst,
+    /// The selector and the target `JUMPDEST` of every comparison, in order.
+    pub(crate) cases: Vec<(u32, Inst)>,
+    /// Whether the compared value is shown to be the calldata selector. Only these switches are
+    /// used for the selector entry points.
+    pub(crate) is_calldata: bool,
+}
+
+impl SelectorSwitch {
+    /// The number of instructions of a comparison.
+    pub(crate) const CASE_LEN: usize = 5;
+    /// The minimum number of comparisons of a selector switch.
+    pub(crate) const MIN_CASES: usize = 4;
+
+    /// Returns the first instruction of the given comparison.
+    pub(crate) fn case_start(&self, case: usize) -> Inst {
+        self.start + case * Self::CASE_LEN
+    }
+
+    /// Returns the instruction after the last comparison.
+    pub(crate) fn end(&self) -> Inst {
+        self.case_start(self.cases.len())
+    }
+}
+
+/// A simple counter loop. See [`Bytecode::find_counter_loops`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct CounterLoop {
+    /// The loop head `JUMPDEST`.
+    pub(crate) head: Inst,
+    /// The `JUMPI` back to `head`.
+    pub(crate) jumpi: Inst,
+    /// The initial value of the loop counter, pushed right before `head`, which is the number of
+    /// iterations.
+    pub(crate) count: u32,
+}
+
 /// A single instruction in the bytecode.
 #[derive(Clone, Default)]
 pub(crate) struct InstData {
@@ -622,6 +1527,11 @@ pub(crate) struct InstData {
     /// - if the instruction has immediate data, this is a packed offset+length into the bytecode;
     /// - `JUMP{,I} && STATIC_JUMP in kind`: the jump target, `Instr`;
     /// - `JUMPDEST`: `1` if the jump destination is reachable, `0` otherwise;
+    /// - `EQ`: `1` if the next instruction is a fused `ISZERO`, `0` otherwise;
+    /// - `DUP1`: `1` if the next instruction is a fused `MLOAD`, `0` otherwise;
+    /// - `POP`: `1` if the previous instruction is a fused `SWAP1`, `0` otherwise;
+    /// - `CUSTOM in kind`: the number of inputs and outputs in the first and second bytes;
+    /// - `PUSH* && CONST in kind`: the index of the folded constant in `Bytecode::constants`;
     /// - otherwise: no meaning.
     pub(crate) data: u32,
     /// The program counter, meaning `code[pc]` is this instruction's opcode.
@@ -673,8 +1583,17 @@ impl InstData {
     /// Returns the number of input and output stack elements of this instruction.
     #[inline]
     pub(crate) fn stack_io(&self) -> (u8, u8) {
+        if self.flags.contains(InstFlags::CUSTOM) {
+            let [inp, out, ..] = self.data.to_le_bytes();
+            return (inp, out);
+        }
+        // The fused `SWAP1` is skipped, so the `POP` removes the second value instead.
+        if self.opcode == op::POP && self.data == 1 {
+            return (2, 1);
+        }
         let (mut inp, out) = stack_io(self.opcode);
         if self.is_legacy_static_jump()
+            && !self.flags.contains(InstFlags::COMPUTED_JUMP)
             && !(self.opcode == op::JUMPI && self.flags.contains(InstFlags::INVALID_JUMP))
         {
             inp -= 1;
@@ -764,6 +1683,17 @@ impl InstData {
             || (self.opcode == op::SSTORE && spec_id.is_enabled_in(SpecId::ISTANBUL))
     }
 
+    /// Returns `true` if this instruction ends a section, starting a new one on the next
+    /// instruction.
+    ///
+    /// This is the case for instructions that require `gasleft`, branch, or may suspend execution.
+    #[inline]
+    pub(crate) fn ends_section(&self, is_eof: bool, spec_id: SpecId) -> bool {
+        (!is_eof && self.requires_gasleft(spec_id))
+            || self.may_suspend(is_eof)
+            || self.is_branching(is_eof)
+    }
+
     /// Returns `true` if we know that this instruction will branch or stop execution.
     #[inline]
     pub(crate) fn is_branching(&self, is_eof: bool) -> bool {
@@ -774,7 +1704,7 @@ impl InstData {
     #[inline]
     pub(crate) fn is_diverging(&self, is_eof: bool) -> bool {
         #[cfg(test)]
-        if self.opcode == TEST_SUSPEND {
+        if self.opcode == TEST_SUSPEND || self.opcode == TEST_BUILTIN_SUCCESS {
             return false;
         }
 
@@ -816,7 +1746,7 @@ impl InstData {
 bitflags::bitflags! {
     /// [`InstrData`] flags.
     #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
-    pub(crate) struct InstFlags: u8 {
+    pub(crate) struct InstFlags: u16 {
         /// The `JUMP`/`JUMPI` target is known at compile time.
         /// This is implied for other jump instructions which are always static.
         const STATIC_JUMP = 1 << 0;
@@ -840,6 +1770,55 @@ bitflags::bitflags! {
         const SKIP_LOGIC = 1 << 6;
         /// Don't generate any code.
         const DEAD_CODE = 1 << 7;
+
+        /// The instruction is a custom opcode registered with
+        /// [`EvmCompiler::register_opcode`](crate::EvmCompiler::register_opcode).
+        const CUSTOM = 1 << 8;
+        /// The `PUSH*` instruction pushes a constant folded from the instructions following it,
+        /// which are all marked as `SKIP_LOGIC`.
+        const CONST = 1 << 9;
+        /// The target of the `STATIC_JUMP` is computed by the instructions before it, rather than
+        /// pushed by the previous one, so it is still on the stack.
+        const COMPUTED_JUMP = 1 << 10;
+    }
+}
+
+/// Returns the result of `opcode` with the given arguments, the first being the top of the stack,
+/// if it can be folded into a constant.
+///
+/// Only pure opcodes with a static gas cost can be folded.
+fn fold_opcode(opcode: u8, args: &[U256]) -> Option<U256> {
+    let bool_word = |b: bool| U256::from(b as u8);
+    Some(match (opcode, args) {
+        (op::ADD, &[a, b]) => a.wrapping_add(b),
+        (op::MUL, &[a, b]) => a.wrapping_mul(b),
+        (op::SUB, &[a, b]) => a.wrapping_sub(b),
+        (op::DIV, &[a, b]) => a.checked_div(b).unwrap_or_default(),
+        (op::MOD, &[a, b]) => a.checked_rem(b).unwrap_or_default(),
+        (op::LT, &[a, b]) => bool_word(a < b),
+        (op::GT, &[a, b]) => bool_word(a > b),
+        (op::EQ, &[a, b]) => bool_word(a == b),
+        (op::ISZERO, &[a]) => bool_word(a.is_zero()),
+        (op::AND, &[a, b]) => a & b,
+        (op::OR, &[a, b]) => a | b,
+        (op::XOR, &[a, b]) => a ^ b,
+        (op::NOT, &[a]) => !a,
+        (op::BYTE, &[i, x]) if i < U256::from(32) => U256::from(x.byte(31 - i.to::<usize>())),
+        (op::BYTE, &[_, _]) => U256::ZERO,
+        (op::SHL, &[shift, value]) if shift < U256::from(256) => value << shift.to::<usize>(),
+        (op::SHR, &[shift, value]) if shift < U256::from(256) => value >> shift.to::<usize>(),
+        (op::SHL | op::SHR, &[_, _]) => U256::ZERO,
+        _ => return None,
+    })
+}
+
+/// Returns the number of stack elements popped and pushed by the instruction when executed, which
+/// does not account for the instructions that are combined by the analysis.
+fn evm_stack_io(data: &InstData) -> (u8, u8) {
+    if data.flags.contains(InstFlags::CUSTOM) {
+        data.stack_io()
+    } else {
+        stack_io(data.opcode)
     }
 }
 
@@ -864,7 +1843,321 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_suspend_is_free() {
+    fn test_opcodes_are_free() {
         assert_eq!(op::OPCODE_INFO_JUMPTABLE[TEST_SUSPEND as usize], None);
+        assert_eq!(op::OPCODE_INFO_JUMPTABLE[TEST_BUILTIN_SUCCESS as usize], None);
+    }
+
+    #[test]
+    fn inst_infos() {
+        let code = &[op::PUSH1, 1, op::PUSH1, 2, op::ADD, op::PUSH0, op::SSTORE];
+        let mut bytecode = Bytecode::new(code, None, SpecId::CANCUN);
+        bytecode.analyze().unwrap();
+        let info = |inst, pc, opcode, static_gas, stack_in, stack_out| InstInfo {
+            inst,
+            pc,
+            opcode,
+            static_gas,
+            stack_in,
+            stack_out,
+            is_dead_code: false,
+            jump_target: None,
+        };
+        assert_eq!(
+            bytecode.inst_infos().collect::<Vec<_>>(),
+            [
+                info(0, 0, op::PUSH1, Some(3), 0, 1),
+                info(1, 2, op::PUSH1, Some(3), 0, 1),
+                info(2, 4, op::ADD, Some(3), 2, 1),
+                info(3, 5, op::PUSH0, Some(2), 0, 1),
+                info(4, 6, op::SSTORE, None, 2, 0),
+                info(5, 7, op::STOP, Some(0), 0, 0),
+            ]
+        );
+
+        // Only the added `STOP` is reported at the end of the code.
+        let pcs = |code: &[u8]| {
+            let bytecode = Bytecode::analyze_legacy(code, SpecId::CANCUN).unwrap();
+            bytecode.inst_infos().map(|info| info.pc).collect::<Vec<_>>()
+        };
+        assert_eq!(pcs(&[]), [0]);
+        assert_eq!(pcs(&[op::STOP]), [0]);
+        assert_eq!(pcs(&[op::STOP, op::ADD]), [0, 1, 2]);
+    }
+
+    #[test]
+    fn sections() {
+        let code = &[
+            op::PUSH1,
+            4,
+            op::JUMP,
+            op::INVALID,
+            op::JUMPDEST,
+            op::PUSH0,
+            op::GAS,
+            op::ADD,
+            op::STOP,
+        ];
+        let bytecode = Bytecode::analyze_legacy(code, SpecId::CANCUN).unwrap();
+        let infos = bytecode.inst_infos().collect::<Vec<_>>();
+        assert_eq!(infos[1].jump_target, Some(4));
+        assert!(infos[2].is_dead_code);
+        assert!(infos.iter().filter(|info| info.inst != 2).all(|info| !info.is_dead_code));
+
+        let section =
+            |start_inst, start_pc, len, static_gas, stack_inputs, stack_max_growth, stack_diff| {
+                SectionInfo {
+                    start_inst,
+                    start_pc,
+                    len,
+                    static_gas,
+                    stack_inputs,
+                    stack_max_growth,
+                    stack_diff,
+                }
+            };
+        assert_eq!(
+            bytecode.sections(),
+            [
+                section(0, 0, 2, 3 + 8, 0, 1, 0),
+                section(3, 4, 3, 1 + 2 + 2, 0, 2, 2),
+                section(6, 7, 2, 3, 2, 0, -1)
+            ]
+        );
+    }
+
+    #[test]
+    fn counter_loops() {
+        let counter_loop = |body: &[u8], decrement: &[u8], extra: &[u8]| -> Vec<u8> {
+            let mut code = vec![op::PUSH0, op::PUSH1, 4, op::JUMPDEST];
+            code.extend_from_slice(body);
+            code.extend_from_slice(decrement);
+            code.extend_from_slice(&[op::DUP1, op::PUSH1, 3, op::JUMPI]);
+            code.extend_from_slice(extra);
+            code
+        };
+        let find = |code: &[u8]| {
+            let bytecode = Bytecode::analyze_legacy(code, SpecId::CANCUN).unwrap();
+            bytecode.counter_loops.iter().map(|l| (l.head, l.count)).collect::<Vec<_>>()
+        };
+        let acc = &[op::SWAP1, op::PUSH1, 3, op::ADD, op::SWAP1];
+        let decrement = &[op::PUSH1, 1, op::SWAP1, op::SUB];
+
+        assert_eq!(find(&counter_loop(acc, decrement, &[])), [(2, 4)]);
+        // The counter is not decremented by one.
+        assert_eq!(find(&counter_loop(acc, &[op::PUSH1, 2, op::SWAP1, op::SUB], &[])), []);
+        // The counter is consumed by the body.
+        assert_eq!(find(&counter_loop(&[op::POP, op::PUSH1, 4], decrement, &[])), []);
+        // The counter is moved and not put back on top of the stack.
+        assert_eq!(find(&counter_loop(&[op::SWAP1], decrement, &[])), []);
+        // The head is the target of another jump.
+        assert_eq!(find(&counter_loop(acc, decrement, &[op::PUSH1, 3, op::JUMP])), []);
+    }
+
+    #[test]
+    fn fold_constants() {
+        let code =
+            &[op::PUSH1, 1, op::PUSH1, 1, op::PUSH1, 0xa0, op::SHL, op::SUB, op::CALLER, op::AND];
+        let bytecode = Bytecode::analyze_legacy(code, SpecId::CANCUN).unwrap();
+        let first = bytecode.inst(0);
+        assert!(first.flags.contains(InstFlags::CONST));
+        assert_eq!(bytecode.push_value(first), (U256::from(1) << 160) - U256::from(1));
+        assert!((1..=4).all(|inst| bytecode.inst(inst).flags.contains(InstFlags::SKIP_LOGIC)));
+        assert!(bytecode.inst(5).flags.is_empty());
+        assert!(bytecode.inst(6).flags.is_empty());
+
+        // Division by zero is folded, but instructions with side effects are not.
+        let code = &[op::PUSH0, op::PUSH1, 1, op::DIV, op::PUSH0, op::MSTORE];
+        let bytecode = Bytecode::analyze_legacy(code, SpecId::CANCUN).unwrap();
+        assert_eq!(bytecode.push_value(bytecode.inst(0)), U256::ZERO);
+        assert!(bytecode.inst(3).flags.is_empty());
+        assert!(!bytecode.inst(4).flags.contains(InstFlags::SKIP_LOGIC));
+
+        let mut bytecode = Bytecode::new(&code[..], None, SpecId::CANCUN);
+        bytecode.disable_constant_folding();
+        bytecode.analyze().unwrap();
+        assert!(bytecode.iter_insts().all(|(_, data)| !data.flags.contains(InstFlags::CONST)));
+    }
+
+    #[test]
+    fn dead_code() {
+        let dead_code = |code: &[u8]| {
+            let bytecode = Bytecode::analyze_legacy(code, SpecId::CANCUN).unwrap();
+            let dead = bytecode.inst_infos().filter(|info| info.is_dead_code).map(|info| info.inst);
+            (dead.collect::<Vec<_>>(), bytecode.has_dynamic_jumps())
+        };
+
+        // Code after a static jump, including a `JUMPDEST` that is never jumped to.
+        let code = &[op::PUSH1, 6, op::JUMP, op::JUMPDEST, op::PUSH0, op::STOP, op::JUMPDEST];
+        assert_eq!(dead_code(code), (vec![2, 3, 4], false));
+        // A `JUMPDEST` only jumped to from dead code.
+        let code = &[op::STOP, op::PUSH1, 4, op::JUMP, op::JUMPDEST];
+        assert_eq!(dead_code(code), (vec![1, 2, 3, 4], false));
+        // Dynamic jumps in dead code.
+        let code = &[op::STOP, op::CALLDATASIZE, op::JUMP, op::JUMPDEST, op::STOP];
+        assert_eq!(dead_code(code), (vec![1, 2, 3, 4], false));
+        // Any `JUMPDEST` can be reached by a reachable dynamic jump.
+        let code = &[op::CALLDATASIZE, op::JUMP, op::STOP, op::JUMPDEST, op::STOP];
+        assert_eq!(dead_code(code), (vec![2], true));
+    }
+
+    #[test]
+    fn computed_jumps() {
+        let code = &[
+            op::PUSH1,
+            9,
+            op::PUSH1,
+            42,
+            op::SWAP1,
+            op::DUP1,
+            op::POP,
+            op::JUMP,
+            op::INVALID,
+            op::JUMPDEST,
+            op::PUSH1,
+            7,
+            op::PUSH1,
+            7,
+            op::ADD,
+            op::JUMP,
+        ];
+        let bytecode = Bytecode::analyze_legacy(code, SpecId::CANCUN).unwrap();
+        let jump = bytecode.inst(5);
+        assert!(jump.flags.contains(InstFlags::STATIC_JUMP | InstFlags::COMPUTED_JUMP));
+        assert_eq!(jump.data, 7);
+        assert_eq!(jump.stack_io(), (1, 0));
+        let jump = bytecode.inst(11);
+        assert!(jump.flags.contains(InstFlags::COMPUTED_JUMP | InstFlags::INVALID_JUMP));
+        assert!(!bytecode.has_dynamic_jumps());
+
+        // The stack is unknown at the start of a block.
+        let code = &[op::PUSH1, 3, op::JUMPDEST, op::JUMP];
+        let bytecode = Bytecode::analyze_legacy(code, SpecId::CANCUN).unwrap();
+        assert!(bytecode.has_dynamic_jumps());
+    }
+
+    #[test]
+    fn selector_switches() {
+        let mut code = vec![op::PUSH0, op::CALLDATALOAD, op::PUSH1, 0xe0, op::SHR];
+        for (selector, target) in [(1u8, 48u8), (2, 49), (1, 50), (3, 51)] {
+            code.extend([op::DUP1, op::PUSH4, 0, 0, 0, selector, op::EQ, op::PUSH1, target]);
+            code.push(op::JUMPI);
+        }
+        code.extend([op::PUSH0, op::DUP1, op::REVERT]);
+        code.extend([op::JUMPDEST; 4]);
+        let bytecode = Bytecode::analyze_legacy(&code, SpecId::CANCUN).unwrap();
+        let s = bytecode.selector_switch_at(4).unwrap();
+        assert_eq!(s.cases, [(1, 27), (2, 28), (1, 29), (3, 30)]);
+        assert_eq!(s.end(), 24);
+        assert!(s.is_calldata);
+        assert_eq!(bytecode.selectors(), [1, 2, 3]);
+
+        // Comparisons of other values are not used for the entry points.
+        code[1] = op::CALLVALUE;
+        let bytecode = Bytecode::analyze_legacy(&code, SpecId::CANCUN).unwrap();
+        assert!(!bytecode.selector_switch_at(4).unwrap().is_calldata);
+        assert!(bytecode.selectors().is_empty());
+        code[1] = op::CALLDATALOAD;
+
+        // Shorter sequences are translated as usual.
+        code[5 + 3 * 10 + 6] = op::LT;
+        let bytecode = Bytecode::analyze_legacy(&code, SpecId::CANCUN).unwrap();
+        assert_eq!(bytecode.selector_switch_at(4), None);
+    }
+
+    #[test]
+    fn selector_switches_split() {
+        // The dispatch split in two by `selector > 4`, with the given code between the two
+        // sequences of comparisons.
+        let split_code = |between: &[u8]| {
+            let mut code = vec![op::PUSH0, op::CALLDATALOAD, op::PUSH1, 0xe0, op::SHR];
+            code.extend([op::DUP1, op::PUSH4, 0, 0, 0, 4, op::GT, op::PUSH1, 0, op::JUMPI]);
+            let cases = |code: &mut Vec<u8>, selectors: [u8; 4]| {
+                for selector in selectors {
+                    code.extend([op::DUP1, op::PUSH4, 0, 0, 0, selector, op::EQ, op::PUSH1, 0]);
+                    code.push(op::JUMPI);
+                }
+            };
+            cases(&mut code, [1, 2, 3, 4]);
+            code.extend(between);
+            code[13] = code.len() as u8;
+            code.push(op::JUMPDEST);
+            cases(&mut code, [5, 6, 7, 8]);
+            code.extend([op::PUSH0, op::DUP1, op::REVERT]);
+            let dest = code.len() as u8;
+            code.push(op::JUMPDEST);
+            for i in 0..8 {
+                code[15 + i * 10 + 8 + if i < 4 { 0 } else { between.len() + 1 }] = dest;
+            }
+            code
+        };
+
+        let code = split_code(&[op::PUSH0, op::DUP1, op::REVERT]);
+        let bytecode = Bytecode::analyze_legacy(&code, SpecId::CANCUN).unwrap();
+        assert!(bytecode.selector_switch_at(9).unwrap().is_calldata);
+        assert!(bytecode.selector_switch_at(33).unwrap().is_calldata);
+        assert_eq!(bytecode.selectors(), [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        // The second sequence can also be reached with another value on top of the stack.
+        let code = split_code(&[op::PUSH0, op::PUSH1, 59, op::JUMP]);
+        let bytecode = Bytecode::analyze_legacy(&code, SpecId::CANCUN).unwrap();
+        assert!(bytecode.selector_switch_at(9).unwrap().is_calldata);
+        assert!(!bytecode.selector_switch_at(33).unwrap().is_calldata);
+        assert_eq!(bytecode.selectors(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn section_stack_checks() {
+        let stack_checks = |code: &[u8], inst| {
+            let mut bytecode = Bytecode::new(code, None, SpecId::CANCUN);
+            bytecode.analyze().unwrap();
+            let section = bytecode.inst(inst).section;
+            (section.inputs, section.max_growth)
+        };
+
+        // The section after `GAS` starts with 3 items.
+        assert_eq!(stack_checks(&[op::PUSH0, op::PUSH0, op::GAS, op::POP, op::ADD], 3), (0, 0));
+        assert_eq!(
+            stack_checks(&[op::PUSH0, op::PUSH0, op::GAS, op::POP, op::ADD, op::ADD], 3),
+            (4, 0)
+        );
+        // The section after `GAS` starts with at most 1023 items.
+        let code = &[op::PUSH0, op::PUSH0, op::POP, op::POP, op::GAS, op::PUSH0];
+        assert_eq!(stack_checks(code, 5), (0, 0));
+        let code = &[op::PUSH0, op::PUSH0, op::POP, op::POP, op::GAS, op::PUSH0, op::PUSH0];
+        assert_eq!(stack_checks(code, 5), (0, 2));
+        // The pushed target of a static `JUMPI` is not on the stack.
+        let code = &[op::PUSH0, op::PUSH0, op::PUSH1, 7, op::JUMPI, op::POP, op::POP, op::JUMPDEST];
+        assert_eq!(stack_checks(code, 4), (2, 0));
+        // Nothing is known about the section after an unconditional jump.
+        let code = &[op::PUSH0, op::PUSH1, 4, op::JUMP, op::JUMPDEST, op::POP];
+        assert_eq!(stack_checks(code, 3), (1, 0));
+    }
+
+    #[test]
+    fn static_memory_bound() {
+        let bound = |code: &[u8]| {
+            let mut bytecode = Bytecode::new(code, None, SpecId::CANCUN);
+            bytecode.analyze().unwrap();
+            bytecode.static_memory_bound()
+        };
+
+        assert_eq!(bound(&[op::STOP]), Some(0));
+        assert_eq!(bound(&[op::PUSH1, 0x40, op::MLOAD]), Some(0x60));
+        assert_eq!(bound(&[op::PUSH0, op::PUSH1, 0x40, op::MSTORE8]), Some(0x41));
+        assert_eq!(bound(&[op::PUSH1, 0x20, op::PUSH2, 0x01, 0x00, op::RETURN]), Some(0x120));
+        assert_eq!(bound(&[op::PUSH0, op::PUSH2, 0x01, 0x00, op::REVERT]), Some(0));
+        // Dynamic offset.
+        assert_eq!(bound(&[op::PUSH0, op::CALLDATALOAD, op::MLOAD]), None);
+        assert_eq!(bound(&[op::PUSH5, 1, 0, 0, 0, 0, op::MLOAD]), None);
+        // Other memory accesses.
+        assert_eq!(bound(&[op::MSIZE]), None);
+        assert_eq!(bound(&[op::PUSH0, op::PUSH0, op::KECCAK256]), None);
+
+        // EOF.
+        let eof = crate::tests::eof_sections_unchecked(&[&[op::PUSH1, 0x40, op::MLOAD, op::STOP]]);
+        let mut bytecode = Bytecode::new(&eof.raw, Some(Cow::Borrowed(&eof)), SpecId::PRAGUE_EOF);
+        bytecode.analyze().unwrap();
+        assert_eq!(bytecode.static_memory_bound(), None);
     }
 }
@@ -1,5 +1,8 @@
-use super::Bytecode;
+use super::{Bytecode, InstFlags};
 use core::fmt;
+use revm_interpreter::opcode as op;
+
+const STACK_CAP: i32 = 1024;
 
 // TODO: Separate gas sections from stack length sections.
 // E.g. `GAS` should stop only a gas section because it requires `gasleft`, and execution will
@@ -15,8 +18,12 @@ pub(crate) struct Section {
     /// The total base gas cost of all instructions in the section.
     pub(crate) gas_cost: u32,
     /// The stack height required to execute the section.
+    ///
+    /// `0` if the stack height at section start is known to be sufficient.
     pub(crate) inputs: u16,
     /// The maximum stack height growth relative to the stack height at section start.
+    ///
+    /// `0` if the stack height at section start is known to be low enough.
     pub(crate) max_growth: i16,
 }
 
@@ -48,9 +55,14 @@ pub(crate) struct SectionAnalysis {
     inputs: i32,
     diff: i32,
     max_growth: i32,
+    /// The stack height difference at runtime, which excludes `SKIP_LOGIC` instructions.
+    len_diff: i32,
 
     gas_cost: u64,
     start_inst: usize,
+    /// The minimum and maximum stack height at section start, if the section is only entered from
+    /// the previous one.
+    entry_len: Option<(i32, i32)>,
 }
 
 impl SectionAnalysis {
@@ -70,18 +82,22 @@ impl SectionAnalysis {
         self.inputs = self.inputs.max(inp as i32 - self.diff);
         self.diff += stack_diff;
         self.max_growth = self.max_growth.max(self.diff);
+        if !data.flags.contains(InstFlags::SKIP_LOGIC) {
+            self.len_diff += stack_diff;
+        }
 
         self.gas_cost += data.base_gas as u64;
 
-        // Instructions that require `gasleft` and branching instructions end a section, starting a
-        // new one on the next instruction, if any.
-        if (!is_eof && data.requires_gasleft(bytecode.spec_id))
-            || data.may_suspend(is_eof)
-            || data.is_branching(is_eof)
-        {
+        // Start a new section on the next instruction, if any.
+        if data.ends_section(is_eof, bytecode.spec_id) {
+            // Execution continues in the next section only by falling through, unless it is a
+            // reachable `JUMPDEST`, in which case it is reset again above.
+            let falls_through = !is_eof && !data.is_diverging(is_eof) && data.opcode != op::JUMP;
+            let exit_len = falls_through.then(|| self.exit_len());
             let next = inst + 1;
             self.save_to(bytecode, next);
             self.reset(next);
+            self.entry_len = exit_len;
         }
     }
 
@@ -130,12 +146,32 @@ impl SectionAnalysis {
         *self = Self { start_inst: inst, ..Default::default() };
     }
 
+    /// Returns the minimum and maximum stack height after executing the current section.
+    ///
+    /// The stack height is checked at section start, so it is at least `inputs` and at most
+    /// `STACK_CAP - max_growth` if execution gets past the check.
+    fn exit_len(&self) -> (i32, i32) {
+        let (min, max) = self.entry_len.unwrap_or((0, STACK_CAP));
+        let diff = self.len_diff;
+        (min.max(self.inputs) + diff, max.min(STACK_CAP - self.max_growth) + diff)
+    }
+
     /// Returns the current section.
     fn section(&self) -> Section {
+        let (mut inputs, mut max_growth) = (self.inputs, self.max_growth);
+        // Elide the stack checks that cannot fail.
+        if let Some((min, max)) = self.entry_len {
+            if min >= inputs {
+                inputs = 0;
+            }
+            if max <= STACK_CAP - max_growth {
+                max_growth = 0;
+            }
+        }
         Section {
             gas_cost: self.gas_cost.try_into().unwrap_or(u32::MAX),
-            inputs: self.inputs.try_into().unwrap_or(u16::MAX),
-            max_growth: self.max_growth.try_into().unwrap_or(i16::MAX),
+            inputs: inputs.try_into().unwrap_or(u16::MAX),
+            max_growth: max_growth.try_into().unwrap_or(i16::MAX),
         }
     }
 }
@@ -0,0 +1,216 @@
+use crate::{code_hash_symbol_name, Backend, EvmCompiler, EvmCompilerFn, Result};
+use revm_primitives::{keccak256, SpecId, B256};
+use rustc_hash::FxHashMap;
+use std::{fmt, sync::Arc};
+
+/// An in-memory cache of JIT-compiled functions with least-recently-used eviction.
+///
+/// Functions are keyed by `keccak256(bytecode)` and the [`SpecId`], and are compiled on first use.
+/// Once the cache is full, the least recently used function is evicted and its machine code is
+/// freed with [`EvmCompiler::free_function`], which unlike [`EvmCompiler::clear`] leaves the other
+/// functions untouched.
+///
+/// Every lookup returns a [`CachedFn`] handle that keeps the function alive: the machine code of an
+/// evicted function is only freed once all of its handles are dropped, at the next call to
+/// [`get_or_compile`](Self::get_or_compile) or [`free_unused`](Self::free_unused).
+///
+/// Note that every miss compiles the function in a new module, as the previous one was already
+/// finalized. Modules are only released when the compiler is [cleared](EvmCompiler::clear), so
+/// eviction does not free their IR, nor any machine code that the backend cannot free for a single
+/// function, and the memory used by the compiler keeps growing with the number of misses. To bound
+/// it, drop the cache and all of its handles, and then clear the compiler.
+///
+/// The cache must always be used with the same compiler, which must not be
+/// [cleared](EvmCompiler::clear) while the cache is in use.
+pub struct CompiledFnCache<B: Backend> {
+    capacity: usize,
+    entries: FxHashMap<(B256, SpecId), Entry<B::FuncId>>,
+    evicted: Vec<Entry<B::FuncId>>,
+    clock: u64,
+    next_name: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl<B: Backend> fmt::Debug for CompiledFnCache<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompiledFnCache")
+            .field("capacity", &self.capacity)
+            .field("len", &self.entries.len())
+            .field("evicted", &self.evicted.len())
+            .field("hits", &self.hits)
+            .field("misses", &self.misses)
+            .finish()
+    }
+}
+
+struct Entry<Id> {
+    id: Id,
+    handle: CachedFn,
+    last_used: u64,
+}
+
+impl<B: Backend> CompiledFnCache<B> {
+    /// Creates a new cache that holds at most `capacity` functions.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is `0`.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "`capacity` must be greater than 0");
+        Self {
+            capacity,
+            entries: FxHashMap::default(),
+            evicted: Vec::new(),
+            clock: 0,
+            next_name: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Returns the maximum number of cached functions.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of cached functions.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the number of evicted functions that have not been freed yet, as they are still
+    /// referenced by a [`CachedFn`].
+    pub fn num_evicted(&self) -> usize {
+        self.evicted.len()
+    }
+
+    /// Returns the number of cache hits.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Returns the number of cache misses.
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Returns the function compiled for `bytecode`, either from the cache or by JIT-compiling it
+    /// with `compiler`, evicting the least recently used function if the cache is full.
+    ///
+    /// A miss adds a new module to `compiler`, which is kept until it is cleared, see the
+    /// [type-level documentation](Self).
+    ///
+    /// # Safety
+    ///
+    /// See [`EvmCompiler::jit`] and [`free_unused`](Self::free_unused).
+    pub unsafe fn get_or_compile(
+        &mut self,
+        compiler: &mut EvmCompiler<B>,
+        bytecode: &[u8],
+        spec_id: SpecId,
+    ) -> Result<CachedFn> {
+        self.clock += 1;
+        let key = (keccak256(bytecode), spec_id);
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.last_used = self.clock;
+            self.hits += 1;
+            return Ok(entry.handle.clone());
+        }
+        self.misses += 1;
+
+        if self.entries.len() >= self.capacity {
+            let lru = self.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(k, _)| *k);
+            if let Some(lru) = lru {
+                self.evict(&lru);
+            }
+        }
+        unsafe { self.free_unused(compiler)? };
+
+        // Functions are named after their key, so that they can be attributed e.g. in profiles.
+        // Freed functions may still be present in the module, so names are never reused.
+        let mut name = format!("{}_{spec_id:?}", code_hash_symbol_name(&key.0));
+        if !compiler.backend().function_name_is_unique(&name) {
+            name = format!("{name}_{}", self.next_name);
+            self.next_name += 1;
+        }
+        let id = compiler.translate(&name, bytecode, spec_id)?;
+        let f = unsafe { compiler.jit_function(id)? };
+        let handle = CachedFn { f, name: name.into(), refs: Arc::new(()) };
+        self.entries.insert(key, Entry { id, handle: handle.clone(), last_used: self.clock });
+        Ok(handle)
+    }
+
+    /// Removes the function compiled for the bytecode with the given code hash and `spec_id`
+    /// from the cache, if any.
+    ///
+    /// The function is freed once it is no longer referenced, see
+    /// [`free_unused`](Self::free_unused). Returns `true` if the function was cached.
+    pub fn invalidate(&mut self, code_hash: &B256, spec_id: SpecId) -> bool {
+        self.evict(&(*code_hash, spec_id))
+    }
+
+    /// Frees the machine code of the evicted functions that are no longer referenced by any
+    /// [`CachedFn`].
+    ///
+    /// # Safety
+    ///
+    /// See [`EvmCompiler::free_function`]. The functions returned by [`CachedFn::get`] must not be
+    /// called after all the handles they were obtained from are dropped.
+    pub unsafe fn free_unused(&mut self, compiler: &mut EvmCompiler<B>) -> Result<()> {
+        let mut i = 0;
+        while i < self.evicted.len() {
+            if Arc::strong_count(&self.evicted[i].handle.refs) == 1 {
+                let entry = self.evicted.swap_remove(i);
+                unsafe { compiler.free_function(entry.id)? };
+            } else {
+                i += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn evict(&mut self, key: &(B256, SpecId)) -> bool {
+        match self.entries.remove(key) {
+            Some(entry) => {
+                trace!(code_hash=%key.0, spec_id=?key.1, "evicting function");
+                self.evicted.push(entry);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A handle to a function in a [`CompiledFnCache`].
+///
+/// The function is not freed while any clone of its handle is alive.
+#[derive(Clone, Debug)]
+pub struct CachedFn {
+    f: EvmCompilerFn,
+    name: Arc<str>,
+    refs: Arc<()>,
+}
+
+impl CachedFn {
+    /// Returns the compiled function.
+    ///
+    /// The function must not be called after this handle and all of its clones are dropped.
+    pub fn get(&self) -> EvmCompilerFn {
+        self.f
+    }
+
+    /// Returns the symbol name of the compiled function.
+    ///
+    /// This is the [`code_hash_symbol_name`] of the bytecode followed by the [`SpecId`], e.g.
+    /// `revmc_<hash>_CANCUN`, with an additional unique suffix if the function was compiled again
+    /// after being evicted.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
@@ -1,35 +1,41 @@
 //! EVM bytecode compiler implementation.
 
-use crate::{Backend, Builder, Bytecode, EvmCompilerFn, EvmContext, EvmStack, Result};
-use revm_interpreter::{Contract, Gas};
-use revm_primitives::{Bytes, Env, Eof, SpecId, EOF_MAGIC_BYTES};
+use crate::{
+    bundle_paths, bytecode::OpcodeSet, code_hash_symbol_name, write_bundle_manifest, AnalysisCache,
+    Backend, Builder, BundleContract, Bytecode, EvmCompilerFn, EvmContext, EvmStack, EvmWord,
+    Linker, Opcode, OpcodeInfo, Result,
+};
+use revm_interpreter::{Contract, Gas, Host, InstructionResult};
+use revm_primitives::{hex, keccak256, Address, Bytes, Env, Eof, SpecId, B256, EOF_MAGIC_BYTES};
 use revmc_backend::{
-    eyre::{ensure, eyre},
-    Attribute, FunctionAttributeLocation, Linkage, OptimizationLevel,
+    ensure, Attribute, CallingConvention, FunctionAttributeLocation, Linkage, OptimizationLevel,
+    RevmcError,
 };
 use revmc_builtins::Builtins;
 use revmc_context::RawEvmCompilerFn;
+use rustc_hash::{FxHashMap, FxHashSet};
 use std::{
     borrow::Cow,
-    fs,
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs,
     io::{self, Write},
     mem,
     path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
-// TODO: Somehow have a config to tell the backend to assume that stack stores are unobservable,
-// making it eliminate redundant stores for values outside the stack length when optimized away.
-// E.g. `PUSH0 POP` gets fully optimized away, but the `store i256 0, ptr %stack` will still get
-// emitted.
-// Use this when `stack` is passed in arguments.
-
 // TODO: Get rid of `cfg!(target_endian)` calls.
 
 // TODO: Test on big-endian hardware.
 // It probably doesn't work when loading Rust U256 into native endianness.
 
 mod translate;
-use translate::{FcxConfig, FunctionCx};
+use translate::{CommentSink, FcxConfig, FunctionCx};
+
+mod verify;
+pub use verify::EvmContextSnapshot;
+#[cfg(any(test, feature = "__fuzzing"))]
+pub(crate) use verify::{MemDisplay, Outcome};
 
 /// EVM bytecode compiler.
 ///
@@ -42,6 +48,9 @@ use translate::{FcxConfig, FunctionCx};
 /// Performing either of these operations finalizes the module, and no more functions can be added
 /// afterwards until [`clear`] is called, which will reset the module to its initial state.
 ///
+/// Translation is deterministic: translating the same bytecode with the same configuration in a
+/// fresh module always produces identical IR, which makes IR dumps suitable for diffing.
+///
 /// [`translate`]: EvmCompiler::translate
 /// [`write_object`]: EvmCompiler::write_object
 /// [`jit_function`]: EvmCompiler::jit_function
@@ -53,6 +62,16 @@ pub struct EvmCompiler<B: Backend> {
     out_dir: Option<PathBuf>,
     config: FcxConfig,
     builtins: Builtins<B>,
+    analysis_cache: Option<AnalysisCache>,
+    precompile_addresses: FxHashSet<Address>,
+    deny_precompiles: bool,
+    allowed_opcodes: Option<OpcodeSet>,
+    deprecated_opcodes: Option<OpcodeSet>,
+    op_infos: Option<Box<[OpcodeInfo; 256]>>,
+    custom_opcodes: Option<Box<[Option<CustomOpcode>; 256]>>,
+    comment_sink: Option<Box<CommentSink>>,
+    /// Functions translated with a calling convention other than C.
+    non_c_functions: FxHashSet<B::FuncId>,
 
     dump_assembly: bool,
     dump_unopt_assembly: bool,
@@ -69,6 +88,15 @@ impl<B: Backend> EvmCompiler<B> {
             out_dir: None,
             config: FcxConfig::default(),
             builtins: Builtins::new(),
+            analysis_cache: None,
+            precompile_addresses: FxHashSet::default(),
+            deny_precompiles: false,
+            allowed_opcodes: None,
+            deprecated_opcodes: None,
+            op_infos: None,
+            custom_opcodes: None,
+            comment_sink: None,
+            non_c_functions: FxHashSet::default(),
             dump_assembly: true,
             dump_unopt_assembly: false,
             finalized: false,
@@ -82,6 +110,18 @@ impl<B: Backend> EvmCompiler<B> {
         self.name = Some(name);
     }
 
+    /// Returns a reference to the backend.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Returns a mutable reference to the backend.
+    ///
+    /// Note that modifying the backend's module directly may invalidate the compiler's state.
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
     fn is_aot(&self) -> bool {
         self.backend.is_aot()
     }
@@ -97,13 +137,34 @@ impl<B: Backend> EvmCompiler<B> {
 
     /// Dumps intermediate outputs and other debug info to the given directory after compilation.
     ///
+    /// This includes a `<name>.json` manifest for every translated function, which contains the
+    /// bytecode, the [`SpecId`], analysis information, translation timings, an opcode histogram,
+    /// and the paths to the module's IR and assembly files.
+    ///
     /// Disables dumping if `output_dir` is `None`.
     pub fn set_dump_to(&mut self, output_dir: Option<PathBuf>) {
         self.backend.set_is_dumping(output_dir.is_some());
-        self.config.comments = output_dir.is_some();
+        self.config.comments = output_dir.is_some() || self.comment_sink.is_some();
         self.out_dir = output_dir;
     }
 
+    /// Sets a callback that receives the comments generated for each instruction, or `None` to
+    /// remove it.
+    ///
+    /// The callback is called with the index of the instruction (see [`InstInfo::inst`]) and the
+    /// comment, regardless of whether [dumping](Self::set_dump_to) is enabled. The first comment
+    /// for every translated instruction is its disassembly, e.g. `PUSH1 0x01`, followed by any
+    /// comments describing the generated code, e.g. `fail OutOfGas`. This can be used to build
+    /// custom source maps.
+    ///
+    /// Defaults to `None`.
+    ///
+    /// [`InstInfo::inst`]: crate::InstInfo::inst
+    pub fn set_comment_sink(&mut self, sink: Option<Box<dyn FnMut(usize, &str)>>) {
+        self.config.comments = self.out_dir.is_some() || sink.is_some();
+        self.comment_sink = sink;
+    }
+
     /// Dumps assembly to the output directory.
     ///
     /// This can be quite slow.
@@ -178,6 +239,18 @@ impl<B: Backend> EvmCompiler<B> {
         self.config.local_stack = yes;
     }
 
+    /// Sets whether to mark the lifetime of the locally allocated stack with
+    /// `llvm.lifetime.start`/`llvm.lifetime.end`, which lets the optimizer reuse its native stack
+    /// space.
+    ///
+    /// Has no effect if [`local_stack`](Self::local_stack) is disabled or if the backend does not
+    /// support lifetime markers.
+    ///
+    /// Defaults to `false`.
+    pub fn local_stack_lifetimes(&mut self, yes: bool) {
+        self.config.local_stack_lifetimes = yes;
+    }
+
     /// Sets whether to treat the stack length as observable outside the function.
     ///
     /// This also implies that the length is loaded in the beginning of the function, meaning
@@ -193,6 +266,26 @@ impl<B: Backend> EvmCompiler<B> {
         self.config.inspect_stack_length = yes;
     }
 
+    /// Sets whether to fold constant arithmetic, such as `PUSH1 1 PUSH1 2 ADD`, into a single
+    /// constant when analyzing the bytecode.
+    ///
+    /// Defaults to `true`.
+    pub fn constant_folding(&mut self, yes: bool) {
+        self.config.constant_folding = yes;
+    }
+
+    /// Sets whether to assume that the stack slots above the stack length are unobservable.
+    ///
+    /// This allows not storing the values that are popped before the stack can be observed, so
+    /// that e.g. `PUSH0 POP` does not write to the stack at all. Pushed values are kept in
+    /// registers and only stored to the stack before it is handed to a builtin, before jumping,
+    /// and before returning or suspending.
+    ///
+    /// Defaults to `true`.
+    pub fn elide_dead_stack_stores(&mut self, yes: bool) {
+        self.config.elide_dead_stack_stores = yes;
+    }
+
     /// Sets whether to enable stack bound checks.
     ///
     /// Ignored for EOF bytecodes, as they are assumed to be correct.
@@ -211,6 +304,45 @@ impl<B: Backend> EvmCompiler<B> {
         self.config.stack_bound_checks = yes;
     }
 
+    /// Sets the results returned on stack underflow and overflow.
+    ///
+    /// This allows embedders to map stack errors to their own results. Both results must be
+    /// errors, as the compiled function stops executing when returning them.
+    ///
+    /// Defaults to [`StackUnderflow`] and [`StackOverflow`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if either result is not an error.
+    ///
+    /// [`StackUnderflow`]: crate::interpreter::InstructionResult::StackUnderflow
+    /// [`StackOverflow`]: crate::interpreter::InstructionResult::StackOverflow
+    pub fn set_stack_error_results(
+        &mut self,
+        underflow: InstructionResult,
+        overflow: InstructionResult,
+    ) {
+        assert!(underflow.is_error(), "stack underflow result must be an error: {underflow:?}");
+        assert!(overflow.is_error(), "stack overflow result must be an error: {overflow:?}");
+        self.config.stack_underflow = underflow;
+        self.config.stack_overflow = overflow;
+    }
+
+    /// Sets whether to assert that every stack access is within the stack bounds.
+    ///
+    /// This checks the computed index of every stack slot against the stack capacity, which is
+    /// finer-grained than the [stack bound checks](Self::stack_bound_checks) performed once per
+    /// instruction, and is meant for catching bugs in the generated code. The slots accessed by
+    /// `DUP` and `SWAP` are additionally checked to be below the current stack length. Failures
+    /// call the panic handler (see [`set_panic_handler`](Self::set_panic_handler)).
+    ///
+    /// Has no effect if debug assertions are disabled.
+    ///
+    /// Defaults to `false`.
+    pub fn stack_access_checks(&mut self, yes: bool) {
+        self.config.stack_access_checks = yes;
+    }
+
     /// Sets whether to track gas costs.
     ///
     /// Disabling this will greatly improves compilation speed and performance, at the cost of not
@@ -226,20 +358,431 @@ impl<B: Backend> EvmCompiler<B> {
         self.config.gas_metering = yes;
     }
 
+    /// Sets whether to charge gas in the same order as the interpreter.
+    ///
+    /// By default, the static gas of all the instructions in a straight-line section of code is
+    /// charged at once at the start of the section, so running out of gas midway reports a
+    /// different amount of gas spent than the interpreter. With this enabled, the static gas of
+    /// each instruction is charged right before it is executed, followed by its dynamic gas, and
+    /// the remaining gas is left untouched by the failing charge, so that the gas spent on
+    /// [`OutOfGas`] matches the interpreter exactly. This includes instructions such as
+    /// `KECCAK256` for which the interpreter charges the static and dynamic gas at once.
+    ///
+    /// Note that stack length checks are still performed once per section.
+    ///
+    /// Has no effect if gas metering is disabled.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// [`OutOfGas`]: crate::interpreter::InstructionResult::OutOfGas
+    pub fn interpreter_exact_gas(&mut self, yes: bool) {
+        self.config.interpreter_exact_gas = yes;
+    }
+
+    /// Sets whether to clamp the remaining gas at zero instead of failing with [`OutOfGas`].
+    ///
+    /// When enabled, the gas checks emitted by the compiler saturate rather than trap, so
+    /// execution continues once the gas is exhausted and the gas spent at the end is equal to the
+    /// gas limit. This is useful for profiling or tracing bytecode regardless of its gas usage.
+    ///
+    /// Note that dynamic gas charged by builtins, such as memory expansion, still fails with
+    /// [`OutOfGas`].
+    ///
+    /// Has no effect if gas metering is disabled.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// [`OutOfGas`]: crate::interpreter::InstructionResult::OutOfGas
+    pub fn saturating_gas(&mut self, yes: bool) {
+        self.config.saturating_gas = yes;
+    }
+
+    /// Sets whether the host may re-enter compiled functions while one is executing.
+    ///
+    /// `CALL`, `CREATE` and similar instructions suspend the compiled function and return to the
+    /// caller, so the compiled functions never recurse through them, and are marked as
+    /// `NoRecurse` along with the builtins they call. A host which executes bytecode from within
+    /// one of its methods, e.g. `sload`, however re-enters the compiled functions through the
+    /// builtins, in which case this must be enabled so that the optimizer does not rely on the
+    /// absence of recursion.
+    ///
+    /// Only affects functions and builtins translated after this is set.
+    ///
+    /// Defaults to `false`.
+    pub fn reentrant_host(&mut self, yes: bool) {
+        self.config.reentrant_host = yes;
+        self.builtins.set_reentrant(yes);
+    }
+
+    /// Sets whether to assert that the remaining gas never exceeds the gas limit after every gas
+    /// deduction.
+    ///
+    /// Gas should only ever decrease during execution, so a failure indicates a gas accounting bug,
+    /// and calls the panic handler (see [`set_panic_handler`](Self::set_panic_handler)).
+    ///
+    /// Has no effect if debug assertions or gas metering are disabled.
+    ///
+    /// Defaults to `false`.
+    pub fn gas_invariant_checks(&mut self, yes: bool) {
+        self.config.gas_invariant_checks = yes;
+    }
+
+    /// Sets whether to move the input validation debug assertions out of the compiled functions.
+    ///
+    /// When enabled, the checks are instead only emitted in a separate function that can be
+    /// built with [`translate_validation`](Self::translate_validation) and called once ahead of
+    /// the hot function, which has the same signature.
+    ///
+    /// Has no effect if debug assertions are disabled.
+    ///
+    /// Defaults to `false`.
+    pub fn split_validation(&mut self, yes: bool) {
+        self.config.split_validation = yes;
+    }
+
+    /// Sets a custom handler for panics in the compiled functions.
+    ///
+    /// Panics can only be triggered by failed debug assertions, such as null argument pointers.
+    /// By default, these call into a builtin that panics with the given message.
+    ///
+    /// Unlike the builtin, the custom handler may return, in which case the compiled function
+    /// returns [`FatalExternalError`] instead of continuing execution.
+    ///
+    /// The handler is referenced by the `__revmc_panic_handler` symbol, and is shared by all the
+    /// functions in the same module, so it must not be changed until the module is
+    /// [cleared](Self::clear). In AOT mode, the symbol must be provided when linking.
+    ///
+    /// Defaults to `None`.
+    ///
+    /// [`FatalExternalError`]: crate::interpreter::InstructionResult::FatalExternalError
+    pub fn set_panic_handler(&mut self, handler: Option<PanicHandler>) {
+        self.config.panic_handler = handler;
+    }
+
+    /// Sets the calling convention of the compiled functions.
+    ///
+    /// Functions with a calling convention other than [`CallingConvention::C`] cannot be called
+    /// through [`EvmCompilerFn`], so [`jit_function`](Self::jit_function) rejects them. They must
+    /// instead be called from other compiled code that uses the same calling convention at the
+    /// call site.
+    ///
+    /// Defaults to [`CallingConvention::C`].
+    pub fn calling_convention(&mut self, calling_convention: CallingConvention) {
+        self.config.calling_convention = calling_convention;
+    }
+
+    /// Sets the budget for unrolling small loops, or `None` to disable unrolling.
+    ///
+    /// Only simple counter loops are unrolled: a `JUMPDEST` preceded by a constant loop counter,
+    /// followed by straight-line code that decrements the counter with `PUSH1 1 SWAP1 SUB`, and a
+    /// `JUMPI` back to the `JUMPDEST` on the decremented counter. The loop must be the only jump to
+    /// the `JUMPDEST`, and the code must not have any dynamic jumps, so that the number of
+    /// iterations is the initial counter. The loop body is translated once per iteration as
+    /// straight-line code, with every copy falling through to the next one.
+    ///
+    /// Defaults to `None`.
+    pub fn unroll_small_loops(&mut self, budget: Option<UnrollBudget>) {
+        self.config.unroll_small_loops = budget;
+    }
+
+    /// Sets how dynamic jumps are dispatched to their `JUMPDEST`.
+    ///
+    /// Only affects bytecode with jumps whose target is not known statically.
+    ///
+    /// Defaults to [`JumpDispatch::Switch`].
+    pub fn jump_dispatch(&mut self, dispatch: JumpDispatch) {
+        self.config.jump_dispatch = dispatch;
+    }
+
+    /// Sets whether to lower the bitwise `AND`, `OR` and `XOR` instructions through `<4 x i64>`
+    /// vector operations instead of 256-bit integer operations.
+    ///
+    /// This is experimental, and is meant for evaluating SIMD lowering of 256-bit operations.
+    ///
+    /// Has no effect if the backend does not support vector types.
+    ///
+    /// Defaults to `false`.
+    pub fn vector_bitwise(&mut self, yes: bool) {
+        self.config.vector_bitwise = yes;
+    }
+
+    /// Sets whether to expand the memory once at function entry if its size is statically known.
+    ///
+    /// This applies only if every memory access is an `MLOAD`, `MSTORE`, `MSTORE8`, `RETURN` or
+    /// `REVERT` with constant operands. The memory is then expanded to the highest accessed offset
+    /// before executing any instruction, and memory operations do not check for expansion.
+    ///
+    /// The total gas charged is the same as the interpreter's as long as the highest offset is
+    /// accessed, since the memory expansion cost only depends on the final memory size. However,
+    /// all of it is charged up front: execution that halts before reaching that access will be
+    /// charged more, and running out of gas for the expansion happens before any instruction is
+    /// executed.
+    ///
+    /// Defaults to `false`.
+    pub fn presize_memory(&mut self, yes: bool) {
+        self.config.presize_memory = yes;
+    }
+
+    /// Sets whether to emit stack maps at builtin call sites.
+    ///
+    /// Each call to a builtin is preceded by an `llvm.experimental.stackmap` record with the
+    /// program counter of the instruction as its ID, keeping the stack, stack length, gas, and
+    /// context pointers live. The records are emitted in the `.llvm_stackmaps` section of the
+    /// object file, and allow embedders to precisely walk or relocate compiled frames.
+    ///
+    /// Has no effect if the backend does not support stack maps.
+    ///
+    /// Defaults to `false`.
+    #[cfg(feature = "stackmaps")]
+    pub fn stackmaps(&mut self, yes: bool) {
+        self.config.stackmaps = yes;
+    }
+
+    /// Sets the bytecode analysis cache, returning the previous one.
+    ///
+    /// When set, the analysis of bytecode that was already seen with the same [`SpecId`] is
+    /// reused instead of being recomputed. The cache is kept across calls to
+    /// [`clear`](Self::clear).
+    ///
+    /// Defaults to `None`.
+    pub fn set_analysis_cache(&mut self, cache: Option<AnalysisCache>) -> Option<AnalysisCache> {
+        mem::replace(&mut self.analysis_cache, cache)
+    }
+
+    /// Returns the bytecode analysis cache, if any.
+    pub fn analysis_cache(&self) -> Option<&AnalysisCache> {
+        self.analysis_cache.as_ref()
+    }
+
+    /// Sets the addresses of the known precompiled contracts.
+    ///
+    /// Precompiles have no EVM bytecode and should be dispatched to directly, so translating
+    /// bytecode for one of these addresses with [`translate_for`](Self::translate_for) is a logic
+    /// error in the caller. See [`deny_precompiles`](Self::deny_precompiles).
+    ///
+    /// Defaults to no addresses.
+    pub fn set_precompile_addresses(&mut self, addresses: impl IntoIterator<Item = Address>) {
+        self.precompile_addresses = addresses.into_iter().collect();
+    }
+
+    /// Returns `true` if the given address is a known precompile address.
+    ///
+    /// See [`set_precompile_addresses`](Self::set_precompile_addresses).
+    pub fn is_precompile(&self, address: &Address) -> bool {
+        self.precompile_addresses.contains(address)
+    }
+
+    /// Sets whether translating bytecode for a precompile address returns an error instead of
+    /// logging a warning.
+    ///
+    /// Defaults to `false`.
+    pub fn deny_precompiles(&mut self, yes: bool) {
+        self.deny_precompiles = yes;
+    }
+
+    /// Sets the opcodes that are allowed to be compiled, or `None` to allow all of them.
+    ///
+    /// This is an application-level policy for VMs that implement a subset of the EVM: any opcode
+    /// not in the list is compiled as an unknown opcode, returning [`OpcodeNotFound`] at runtime.
+    /// It is applied on top of the [`SpecId`] gating, so an opcode must also be enabled in the
+    /// spec to be executed.
+    ///
+    /// Executing past the end of legacy bytecode is an implicit `STOP`, and is always allowed.
+    ///
+    /// Defaults to `None`.
+    ///
+    /// [`OpcodeNotFound`]: crate::interpreter::InstructionResult::OpcodeNotFound
+    pub fn set_allowed_opcodes(&mut self, opcodes: Option<&[u8]>) {
+        self.allowed_opcodes = opcodes.map(opcode_set);
+    }
+
+    /// Sets the opcodes that are deprecated, or `None` to not check for any.
+    ///
+    /// Unlike [`set_allowed_opcodes`](Self::set_allowed_opcodes), which compiles disallowed
+    /// opcodes to a runtime failure, parsing bytecode that contains any of these opcodes returns
+    /// an [`InvalidBytecode`](RevmcError::InvalidBytecode) error listing them. This allows
+    /// rejecting contracts that use e.g. `SELFDESTRUCT` or `CALLCODE` before deploying them.
+    ///
+    /// Only reachable instructions are checked, so data such as the Solidity metadata at the end
+    /// of the code is ignored.
+    ///
+    /// Defaults to `None`.
+    pub fn set_deprecated_opcodes(&mut self, opcodes: Option<&[u8]>) {
+        self.deprecated_opcodes = opcodes.map(opcode_set);
+    }
+
+    /// Sets a pinned opcode info table to take the static gas costs from, or `None` to use the
+    /// table of the [`SpecId`] being compiled for, see [`op_info_map`].
+    ///
+    /// This freezes the gas semantics of the compiled code independently of the version of
+    /// `revm` in the dependency tree, e.g. by storing a snapshot of `*op_info_map(spec_id)`.
+    /// Only the [base gas costs](OpcodeInfo::base_gas) are taken from the table; whether an
+    /// opcode is known and enabled is still determined by the [`SpecId`].
+    ///
+    /// Defaults to `None`.
+    ///
+    /// [`op_info_map`]: crate::op_info_map
+    pub fn set_opcode_infos(&mut self, op_infos: Option<[OpcodeInfo; 256]>) {
+        self.op_infos = op_infos.map(Box::new);
+    }
+
+    /// Registers a custom opcode, implemented by calling `op.handler`.
+    ///
+    /// This allows compiling bytecode for chains that extend the instruction set. The opcode must
+    /// not be defined in any [`SpecId`], and has no immediate data. It is subject to
+    /// [`set_allowed_opcodes`](Self::set_allowed_opcodes) like any other opcode.
+    ///
+    /// Compiled functions reference the handler by the `__revmc_custom_opcode_XX` symbol, where
+    /// `XX` is the opcode in lowercase hex, so it must not be changed until the module is
+    /// [cleared](Self::clear). In AOT mode, the symbol must be provided when linking.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `opcode` is a known opcode.
+    pub fn register_opcode(&mut self, opcode: u8, op: CustomOpcode) {
+        assert!(
+            revm_interpreter::OPCODE_INFO_JUMPTABLE[opcode as usize].is_none(),
+            "cannot override known opcode {opcode:#04x}"
+        );
+        let custom_opcodes = self.custom_opcodes.get_or_insert_with(|| Box::new([None; 256]));
+        custom_opcodes[opcode as usize] = Some(op);
+    }
+
+    /// Translates the given EVM bytecode of the contract at `address` into an internal function.
+    ///
+    /// Same as [`translate`](Self::translate), but first checks that `address` is not a known
+    /// precompile address. See [`set_precompile_addresses`](Self::set_precompile_addresses).
+    pub fn translate_for<'a>(
+        &mut self,
+        address: Address,
+        name: &str,
+        input: impl Into<EvmCompilerInput<'a>>,
+        spec_id: SpecId,
+    ) -> Result<B::FuncId> {
+        if self.is_precompile(&address) {
+            ensure!(
+                !self.deny_precompiles,
+                InvalidUsage,
+                "cannot compile bytecode for precompile {address}"
+            );
+            warn!(%address, name, "compiling bytecode for a precompile address");
+        }
+        self.translate(name, input, spec_id)
+    }
+
     /// Translates the given EVM bytecode into an internal function.
     ///
     /// NOTE: `name` must be unique for each function, as it is used as the name of the final
-    /// symbol.
+    /// symbol. It must consist of ASCII alphanumerics, `_`, `-`, `.` and `$`, and must not start
+    /// with a digit or `-`, so that the symbol can be referenced by linkers and `dlsym` as is.
+    ///
+    /// In JIT mode, functions can still be translated after others were compiled with
+    /// [`jit_function`](Self::jit_function), in which case they are put in a new module. This
+    /// allows compiling functions on demand over the lifetime of the compiler.
     pub fn translate<'a>(
         &mut self,
         name: &str,
         input: impl Into<EvmCompilerInput<'a>>,
         spec_id: SpecId,
     ) -> Result<B::FuncId> {
-        ensure!(cfg!(target_endian = "little"), "only little-endian is supported");
-        ensure!(!self.finalized, "cannot compile more functions after finalizing the module");
+        self.translate_checked(name, input.into(), spec_id, |_| Ok(()))
+    }
+
+    /// Translates one entry point per external function of the given EVM bytecode, which skips
+    /// the function selector comparisons.
+    ///
+    /// The external functions are the targets of the selector dispatch emitted by Solidity, which
+    /// is a sequence of `DUP1 PUSH4 selector EQ PUSH2 target JUMPI` comparisons. Only sequences
+    /// of at least four comparisons are recognized, so no entry points are returned for contracts
+    /// with fewer external functions, and only if the compared value is shown to be the selector
+    /// loaded from the calldata, so other sequences of comparisons are never skipped. Every entry
+    /// point executes the bytecode as usual, including the code before the dispatch, such as
+    /// the `CALLVALUE` and `CALLDATASIZE` checks, except that the comparisons after the first
+    /// one of the sequence containing its selector are replaced with a direct branch to the
+    /// function. The gas of the skipped comparisons is still charged. The caller is responsible
+    /// for only calling an entry point with calldata starting with its selector.
+    ///
+    /// The comparisons are not skipped with [exact interpreter gas](Self::interpreter_exact_gas).
+    ///
+    /// The entry points have the same signature as the function generated by
+    /// [`translate`](Self::translate), are named `{name}_{selector:08x}`, and are returned with
+    /// their selector, sorted by selector.
+    pub fn translate_selector_entries<'a>(
+        &mut self,
+        name: &str,
+        input: impl Into<EvmCompilerInput<'a>>,
+        spec_id: SpecId,
+    ) -> Result<Vec<(u32, B::FuncId)>> {
+        ensure!(cfg!(target_endian = "little"), Unsupported, "only little-endian is supported");
+        self.prepare_translate()?;
         let bytecode = self.parse(input.into(), spec_id)?;
-        self.translate_inner(name, &bytecode)
+        let selectors = bytecode.selectors();
+        let mut entries = Vec::with_capacity(selectors.len());
+        for selector in selectors {
+            self.config.selector_entry = Some(selector);
+            let id = self.translate_inner(&format!("{name}_{selector:08x}"), &bytecode);
+            self.config.selector_entry = None;
+            entries.push((selector, id?));
+        }
+        Ok(entries)
+    }
+
+    /// Translates all the given contracts, keyed by code hash, into functions of the same module.
+    ///
+    /// Every function is named after its code hash with [`code_hash_symbol_name`], and the
+    /// returned IDs are in the same order as `contracts`. Contracts with the same code hash are
+    /// only translated once.
+    ///
+    /// All the functions share the declarations of the builtins and the string constants, and
+    /// are optimized together when the module is finalized, which allows e.g. merging identical
+    /// code across contracts. This is the preferred way of compiling bundles of related contracts
+    /// ahead of time, see [`compile_to_cdylib`](Self::compile_to_cdylib).
+    pub fn translate_many(
+        &mut self,
+        contracts: &[(B256, &[u8])],
+        spec_id: SpecId,
+    ) -> Result<Vec<B::FuncId>> {
+        let mut translated = FxHashMap::<B256, B::FuncId>::default();
+        let mut ids = Vec::with_capacity(contracts.len());
+        for (code_hash, bytecode) in contracts {
+            let id = match translated.get(code_hash) {
+                Some(&id) => id,
+                None => {
+                    let id =
+                        self.translate(&code_hash_symbol_name(code_hash), *bytecode, spec_id)?;
+                    translated.insert(*code_hash, id);
+                    id
+                }
+            };
+            ids.push(id);
+        }
+        Ok(ids)
+    }
+
+    /// Translates the input validation function of the given EVM bytecode.
+    ///
+    /// The resulting function has the same signature as the one generated by
+    /// [`translate`](Self::translate) with the same configuration, and panics if any of the
+    /// arguments are invalid, returning [`InstructionResult::Continue`] otherwise.
+    ///
+    /// See [`split_validation`](Self::split_validation) for more information.
+    ///
+    /// [`InstructionResult::Continue`]: crate::interpreter::InstructionResult::Continue
+    pub fn translate_validation<'a>(
+        &mut self,
+        name: &str,
+        input: impl Into<EvmCompilerInput<'a>>,
+        spec_id: SpecId,
+    ) -> Result<B::FuncId> {
+        self.prepare_translate()?;
+        self.check_name(name)?;
+        let bytecode = self.parse(input.into(), spec_id)?;
+        let (bcx, id) = Self::make_builder(&mut self.backend, &self.config, name, Linkage::Public)?;
+        if self.config.calling_convention != CallingConvention::C {
+            self.non_c_functions.insert(id);
+        }
+        translate::translate_validation(bcx, self.config, &mut self.builtins, &bytecode);
+        Ok(id)
     }
 
     /// (JIT) Compiles the given EVM bytecode into a JIT function.
@@ -260,6 +803,100 @@ impl<B: Backend> EvmCompiler<B> {
         unsafe { self.jit_function(id) }
     }
 
+    /// (JIT) Compiles the given EVM bytecode into both an unoptimized and an optimized JIT
+    /// function.
+    ///
+    /// The functions are named `{name}_unopt` and `{name}_opt`, and are returned in this order.
+    /// The unoptimized function is translated with the same configuration, but is excluded from
+    /// all optimizations regardless of the [optimization level](Self::set_opt_level).
+    ///
+    /// This is useful for differential testing, e.g. by executing both functions and comparing
+    /// their results.
+    ///
+    /// # Safety
+    ///
+    /// See [`jit`](Self::jit).
+    pub unsafe fn jit_both<'a>(
+        &mut self,
+        name: &str,
+        bytecode: impl Into<EvmCompilerInput<'a>>,
+        spec_id: SpecId,
+    ) -> Result<(EvmCompilerFn, EvmCompilerFn)> {
+        ensure!(self.is_jit(), InvalidUsage, "cannot JIT functions during AOT compilation");
+        self.prepare_translate()?;
+        let bytecode = self.parse(bytecode.into(), spec_id)?;
+        self.config.optimize_none = true;
+        let unopt = self.translate_inner(&format!("{name}_unopt"), &bytecode);
+        self.config.optimize_none = false;
+        let unopt = unopt?;
+        let opt = self.translate_inner(&format!("{name}_opt"), &bytecode)?;
+        unsafe { Ok((self.jit_function(unopt)?, self.jit_function(opt)?)) }
+    }
+
+    /// (JIT) Compiles the given EVM bytecode into a JIT function, and verifies it against the
+    /// interpreter.
+    ///
+    /// Every sample is executed by both the compiled function and the `revm` interpreter, each
+    /// on a separate clone of the sample's host, and their results, gas, memory and next action
+    /// are compared. The stacks are also compared if
+    /// [stack length inspection](Self::inspect_stack_length) is enabled and the stack is not
+    /// [allocated locally](Self::local_stack).
+    ///
+    /// Returns a [`Verification`](RevmcError::Verification) error on the first divergence.
+    ///
+    /// # Safety
+    ///
+    /// See [`jit`](Self::jit).
+    pub unsafe fn jit_verified<H: Host + Clone + 'static>(
+        &mut self,
+        name: &str,
+        bytecode: &[u8],
+        spec_id: SpecId,
+        samples: &[EvmContextSnapshot<H>],
+    ) -> Result<EvmCompilerFn> {
+        let f = unsafe { self.jit(name, bytecode, spec_id) }?;
+        let compare_stack = self.config.inspect_stack_length && !self.config.local_stack;
+        for (i, sample) in samples.iter().enumerate() {
+            if let Err(msg) = sample.verify(bytecode, spec_id, f, compare_stack) {
+                return Err(RevmcError::Verification(format!("`{name}`, sample {i}: {msg}")).into());
+            }
+        }
+        Ok(f)
+    }
+
+    /// (JIT) Compiles the given pure EVM bytecode into a JIT function.
+    ///
+    /// Same as [`jit`](Self::jit), but returns an [`InvalidBytecode`](RevmcError::InvalidBytecode)
+    /// error listing the offending opcodes if the bytecode contains any reachable instruction that
+    /// [uses the host](crate::uses_host), including the ones that only read its environment.
+    /// The host of the resulting function is then never accessed.
+    ///
+    /// # Safety
+    ///
+    /// See [`jit`](Self::jit).
+    pub unsafe fn jit_pure<'a>(
+        &mut self,
+        name: &str,
+        bytecode: impl Into<EvmCompilerInput<'a>>,
+        spec_id: SpecId,
+    ) -> Result<EvmCompilerFn> {
+        let id = self.translate_checked(name, bytecode.into(), spec_id, |bytecode| {
+            let found = bytecode
+                .iter_insts()
+                .filter(|(_, data)| crate::uses_host(data.opcode))
+                .map(|(_, data)| data.opcode)
+                .collect::<BTreeSet<_>>();
+            ensure!(
+                found.is_empty(),
+                InvalidBytecode,
+                "bytecode is not pure, it uses the host in: {}",
+                opcode_names(&found)
+            );
+            Ok(())
+        })?;
+        unsafe { self.jit_function(id) }
+    }
+
     /// (JIT) Finalizes the module and JITs the given function.
     ///
     /// # Safety
@@ -267,7 +904,12 @@ impl<B: Backend> EvmCompiler<B> {
     /// The returned function pointer is owned by the module, and must not be called after the
     /// module is cleared or the function is freed.
     pub unsafe fn jit_function(&mut self, id: B::FuncId) -> Result<EvmCompilerFn> {
-        ensure!(self.is_jit(), "cannot JIT functions during AOT compilation");
+        ensure!(self.is_jit(), InvalidUsage, "cannot JIT functions during AOT compilation");
+        ensure!(
+            !self.non_c_functions.contains(&id),
+            InvalidUsage,
+            "cannot call functions with a non-C calling convention through `EvmCompilerFn`"
+        );
         self.finalize()?;
         let addr = self.backend.jit_function(id)?;
         debug_assert!(addr != 0);
@@ -285,11 +927,73 @@ impl<B: Backend> EvmCompiler<B> {
 
     /// (AOT) Finalizes the module and writes the compiled object to the given writer.
     pub fn write_object<W: io::Write>(&mut self, w: W) -> Result<()> {
-        ensure!(self.is_aot(), "cannot write AOT object during JIT compilation");
+        ensure!(self.is_aot(), InvalidUsage, "cannot write AOT object during JIT compilation");
         self.finalize()?;
         self.backend.write_object(w)
     }
 
+    /// (AOT) Translates all the given contracts and links them into a shared library at `out`.
+    ///
+    /// Each item is a `(name, input, spec_id)` tuple, see [`translate`](Self::translate). Every
+    /// contract is exported from the library as a symbol with the given `name` and the
+    /// [`EvmCompilerFn`] ABI, and can be loaded with `dlopen` and `dlsym`, e.g. using
+    /// `libloading`.
+    ///
+    /// The object file is written next to `out` and linked with the default [`Linker`], which
+    /// invokes `cc -shared -fuse-ld=lld` (`$CC` is respected if set). Calls to builtins are left as
+    /// undefined `__revmc_builtin_*` symbols, which are resolved by the dynamic loader when the
+    /// library is loaded. This means that the loading executable must link `revmc-builtins` and
+    /// export these symbols, e.g. by calling `revmc_build::emit()` in its build script.
+    pub fn compile_to_cdylib<'a, 'n>(
+        &mut self,
+        items: impl IntoIterator<Item = (&'n str, EvmCompilerInput<'a>, SpecId)>,
+        out: &Path,
+    ) -> Result<()> {
+        for (name, input, spec_id) in items {
+            self.translate(name, input, spec_id)?;
+        }
+        self.link_cdylib(out)
+    }
+
+    /// (AOT) Translates all the given contracts and writes them as a bundle to the directory
+    /// `out`, which is created if it does not exist.
+    ///
+    /// Each item is the address of a contract and its bytecode. A bundle consists of a shared
+    /// library, linked like in [`compile_to_cdylib`](Self::compile_to_cdylib) and exporting the
+    /// functions as in [`translate_many`](Self::translate_many), and of a manifest that links the
+    /// address of every contract to its code hash and `spec_id`. This allows shipping related
+    /// contracts, such as all the contracts of a protocol, together.
+    ///
+    /// The bundle is loaded with [`EvmCompilerBundle::open`](crate::EvmCompilerBundle::open).
+    pub fn compile_bundle(
+        &mut self,
+        contracts: &[(Address, &[u8])],
+        spec_id: SpecId,
+        out: &Path,
+    ) -> Result<()> {
+        let hashed = contracts.iter().map(|&(_, code)| (keccak256(code), code)).collect::<Vec<_>>();
+        self.translate_many(&hashed, spec_id)?;
+
+        fs::create_dir_all(out)?;
+        let (library, manifest) = bundle_paths(out);
+        self.link_cdylib(&library)?;
+        let contracts = contracts
+            .iter()
+            .zip(&hashed)
+            .map(|(&(address, _), &(code_hash, _))| BundleContract { address, code_hash, spec_id })
+            .collect::<Vec<_>>();
+        write_bundle_manifest(&manifest, &contracts)
+    }
+
+    /// (AOT) Writes the compiled object next to `out` and links it into a shared library at `out`.
+    fn link_cdylib(&mut self, out: &Path) -> Result<()> {
+        let obj = out.with_extension("o");
+        self.write_object_to_file(&obj)?;
+        let linked = Linker::new().link(out, [&obj]);
+        let _ = fs::remove_file(&obj);
+        linked.map_err(|e| RevmcError::Link(format!("{}: {e}", out.display())).into())
+    }
+
     /// (JIT) Frees the memory associated with a single function.
     ///
     /// Note that this will not reset the state of the internal module even if all functions are
@@ -306,8 +1010,7 @@ impl<B: Backend> EvmCompiler<B> {
         self.backend.free_function(id)
     }
 
-    /// Frees all functions and resets the state of the internal module, allowing for new functions
-    /// to be compiled.
+    /// Frees all functions and resets the state of the internal modules.
     ///
     /// # Safety
     ///
@@ -316,12 +1019,46 @@ impl<B: Backend> EvmCompiler<B> {
     /// none of the `fn` pointers are called afterwards.
     pub unsafe fn clear(&mut self) -> Result<()> {
         self.builtins.clear();
+        self.non_c_functions.clear();
         self.finalized = false;
         self.backend.free_all_functions()
     }
 
-    /// Parses the given EVM bytecode. Not public API.
-    #[doc(hidden)] // Not public API.
+    /// Returns the key of the function compiled for the bytecode with the given code hash and
+    /// `spec_id` with the current configuration, see [`LibraryCache`](crate::LibraryCache).
+    #[cfg(feature = "loader")]
+    pub(crate) fn library_cache_key(
+        &self,
+        code_hash: &revm_primitives::B256,
+        spec_id: SpecId,
+    ) -> Result<revm_primitives::B256> {
+        ensure!(self.is_aot(), InvalidUsage, "cannot cache libraries during JIT compilation");
+        ensure!(
+            self.config.panic_handler.is_none(),
+            InvalidUsage,
+            "cannot cache functions that call a custom panic handler"
+        );
+        ensure!(
+            self.custom_opcodes.is_none(),
+            InvalidUsage,
+            "cannot cache functions that call custom opcode handlers"
+        );
+        let fingerprint = format!(
+            "{} {} {code_hash} {spec_id:?} {:?} {:?} {:?} {:?} {:?}",
+            env!("CARGO_PKG_VERSION"),
+            std::any::type_name::<B>(),
+            self.opt_level(),
+            self.config,
+            self.allowed_opcodes,
+            self.deprecated_opcodes,
+            self.op_infos,
+        );
+        Ok(revm_primitives::keccak256(fingerprint))
+    }
+
+    /// Parses and analyzes the given EVM bytecode.
+    ///
+    /// See [`Bytecode::inst_infos`] for inspecting the result.
     pub fn parse<'a>(
         &mut self,
         input: EvmCompilerInput<'a>,
@@ -333,7 +1070,9 @@ impl<B: Backend> EvmCompiler<B> {
             EvmCompilerInput::Code(code) => {
                 bytecode = code;
                 if spec_id.is_enabled_in(SpecId::PRAGUE_EOF) && code.starts_with(&EOF_MAGIC_BYTES) {
-                    eof = Some(Cow::Owned(Eof::decode(Bytes::copy_from_slice(code))?));
+                    let decoded = Eof::decode(Bytes::copy_from_slice(code))
+                        .map_err(|e| RevmcError::InvalidBytecode(e.to_string()))?;
+                    eof = Some(Cow::Owned(decoded));
                 } else {
                     eof = None;
                 }
@@ -347,8 +1086,47 @@ impl<B: Backend> EvmCompiler<B> {
             self.do_validate_eof(eof)?;
         }
 
-        let mut bytecode = Bytecode::new(bytecode, eof, spec_id);
-        bytecode.analyze()?;
+        let bytecode = match &mut self.analysis_cache {
+            Some(cache) => cache.get_or_analyze(
+                bytecode,
+                eof,
+                spec_id,
+                self.allowed_opcodes.as_ref(),
+                self.op_infos.as_deref(),
+                self.custom_opcodes.as_deref(),
+                self.config.constant_folding,
+            )?,
+            None => {
+                let mut bytecode = Bytecode::new(bytecode, eof, spec_id);
+                if let Some(custom) = &self.custom_opcodes {
+                    bytecode.set_custom_opcodes(custom);
+                }
+                if let Some(allowed) = &self.allowed_opcodes {
+                    bytecode.restrict_opcodes(allowed);
+                }
+                if let Some(op_infos) = &self.op_infos {
+                    bytecode.pin_gas_costs(op_infos);
+                }
+                if !self.config.constant_folding {
+                    bytecode.disable_constant_folding();
+                }
+                bytecode.analyze()?;
+                bytecode
+            }
+        };
+        if let Some(deprecated) = &self.deprecated_opcodes {
+            let found = bytecode
+                .iter_insts()
+                .filter(|(_, data)| deprecated[data.opcode as usize])
+                .map(|(_, data)| data.opcode)
+                .collect::<BTreeSet<_>>();
+            ensure!(
+                found.is_empty(),
+                InvalidBytecode,
+                "bytecode contains deprecated opcodes: {}",
+                opcode_names(&found)
+            );
+        }
         if let Some(dump_dir) = &self.dump_dir() {
             Self::dump_bytecode(dump_dir, &bytecode)?;
         }
@@ -359,23 +1137,96 @@ impl<B: Backend> EvmCompiler<B> {
         if !self.config.validate_eof {
             return Ok(());
         }
-        revm_interpreter::analysis::validate_eof_inner(eof, None).map_err(|e| match e {
-            revm_interpreter::analysis::EofError::Decode(e) => e.into(),
-            revm_interpreter::analysis::EofError::Validation(e) => {
-                eyre!("validation error: {e:?}")
+        let e = match revm_interpreter::analysis::validate_eof_inner(eof, None) {
+            Ok(()) => return Ok(()),
+            Err(revm_interpreter::analysis::EofError::Decode(e)) => e.to_string(),
+            Err(revm_interpreter::analysis::EofError::Validation(e)) => {
+                format!("validation error: {e:?}")
             }
-        })
+        };
+        Err(RevmcError::InvalidBytecode(e).into())
+    }
+
+    /// Parses the bytecode and checks it with `check` before translating it.
+    fn translate_checked(
+        &mut self,
+        name: &str,
+        input: EvmCompilerInput<'_>,
+        spec_id: SpecId,
+        check: impl FnOnce(&Bytecode<'_>) -> Result<()>,
+    ) -> Result<B::FuncId> {
+        ensure!(cfg!(target_endian = "little"), Unsupported, "only little-endian is supported");
+        self.prepare_translate()?;
+        let bytecode = self.parse(input, spec_id)?;
+        check(&bytecode)?;
+        self.translate_inner(name, &bytecode)
     }
 
     #[instrument(name = "translate", level = "debug", skip_all)]
     fn translate_inner(&mut self, name: &str, bytecode: &Bytecode<'_>) -> Result<B::FuncId> {
-        ensure!(self.backend.function_name_is_unique(name), "function name `{name}` is not unique");
+        self.check_name(name)?;
+        if enabled!(tracing::Level::DEBUG) {
+            for l in bytecode.loops() {
+                debug!(name, header_pc = l.header_pc, body_size = l.body_size, "found loop");
+            }
+        }
         let linkage = Linkage::Public;
+        let start = Instant::now();
         let (bcx, id) = Self::make_builder(&mut self.backend, &self.config, name, linkage)?;
-        FunctionCx::translate(bcx, self.config, &mut self.builtins, bytecode)?;
+        if self.config.calling_convention != CallingConvention::C {
+            self.non_c_functions.insert(id);
+        }
+        let comment_sink = self.comment_sink.as_deref_mut();
+        FunctionCx::translate(
+            bcx,
+            self.config,
+            &mut self.builtins,
+            comment_sink,
+            self.custom_opcodes.as_deref(),
+            bytecode,
+        )?;
+        let translate_time = start.elapsed();
+        if let Some(dump_dir) = &self.dump_dir() {
+            self.dump_manifest(dump_dir, name, bytecode, translate_time)?;
+        }
         Ok(id)
     }
 
+    /// Checks that `name` can be used as the symbol name of a new function.
+    fn check_name(&self, name: &str) -> Result<()> {
+        ensure!(
+            is_valid_symbol_name(name),
+            InvalidUsage,
+            "function name `{name}` is not a valid symbol name"
+        );
+        ensure!(
+            self.backend.function_name_is_unique(name),
+            InvalidUsage,
+            "function name `{name}` is not unique"
+        );
+        Ok(())
+    }
+
+    /// Prepares the module for translating a new function.
+    ///
+    /// In JIT mode, a new module is started if the current one was already finalized, keeping the
+    /// functions compiled from it valid.
+    fn prepare_translate(&mut self) -> Result<()> {
+        if !self.finalized {
+            return Ok(());
+        }
+        ensure!(
+            self.is_jit(),
+            InvalidUsage,
+            "cannot compile more functions after finalizing the module"
+        );
+        self.backend.new_module()?;
+        // The builtins are declared in every module.
+        self.builtins.clear();
+        self.finalized = false;
+        Ok(())
+    }
+
     #[instrument(level = "debug", skip_all)]
     fn finalize(&mut self) -> Result<()> {
         if self.finalized {
@@ -451,12 +1302,19 @@ impl<B: Backend> EvmCompiler<B> {
 
         // Function attributes.
         let function_attributes = default_attrs::for_fn()
+            // Calls suspend the function instead of recursing, but the host may still re-enter.
+            .chain((!config.reentrant_host).then_some(Attribute::NoRecurse))
             .chain(config.frame_pointers.then_some(Attribute::AllFramePointers))
+            .chain(config.optimize_none.then_some(Attribute::OptimizeNone))
+            .chain(config.optimize_none.then_some(Attribute::NoInline))
             // We can unwind in panics, which are present only in debug assertions.
             .chain((!config.debug_assertions).then_some(Attribute::NoUnwind));
         for attr in function_attributes {
             bcx.add_function_attribute(None, attr, FunctionAttributeLocation::Function);
         }
+        if config.calling_convention != CallingConvention::C {
+            bcx.set_calling_convention(None, config.calling_convention);
+        }
 
         // Pointer argument attributes.
         if !config.debug_assertions {
@@ -513,6 +1371,68 @@ impl<B: Backend> EvmCompiler<B> {
         Ok(())
     }
 
+    fn dump_manifest(
+        &self,
+        dump_dir: &Path,
+        name: &str,
+        bytecode: &Bytecode<'_>,
+        translate_time: Duration,
+    ) -> Result<()> {
+        let mut histogram = BTreeMap::<String, usize>::new();
+        for (_, data) in bytecode.iter_all_insts() {
+            let opcode = Opcode { opcode: data.opcode, immediate: None };
+            *histogram.entry(opcode.to_string()).or_default() += 1;
+        }
+
+        let ir_extension = self.backend.ir_extension();
+        let mut files = vec![
+            ("unopt_ir", dump_dir.join("unopt").with_extension(ir_extension)),
+            ("opt_ir", dump_dir.join("opt").with_extension(ir_extension)),
+        ];
+        if self.dump_assembly {
+            if self.dump_unopt_assembly {
+                files.push(("unopt_asm", dump_dir.join("unopt.s")));
+            }
+            files.push(("opt_asm", dump_dir.join("opt.s")));
+        }
+
+        let file = fs::File::create(dump_dir.join(format!("{name}.json")))?;
+        let w = &mut io::BufWriter::new(file);
+        writeln!(w, "{{")?;
+        writeln!(w, "  \"name\": {},", JsonStr(name))?;
+        writeln!(w, "  \"spec_id\": \"{:?}\",", bytecode.spec_id)?;
+        writeln!(w, "  \"bytecode\": \"{}\",", hex::encode_prefixed(bytecode.code))?;
+        writeln!(w, "  \"opt_level\": \"{:?}\",", self.opt_level())?;
+        writeln!(w, "  \"info\": {{")?;
+        writeln!(w, "    \"is_eof\": {},", bytecode.is_eof())?;
+        writeln!(w, "    \"instructions\": {},", bytecode.iter_all_insts().len())?;
+        writeln!(w, "    \"has_dynamic_jumps\": {},", bytecode.has_dynamic_jumps())?;
+        writeln!(w, "    \"may_suspend\": {},", bytecode.may_suspend())?;
+        match bytecode.static_memory_bound() {
+            Some(bound) => writeln!(w, "    \"static_memory_bound\": {bound}")?,
+            None => writeln!(w, "    \"static_memory_bound\": null")?,
+        }
+        writeln!(w, "  }},")?;
+        writeln!(w, "  \"timings\": {{")?;
+        writeln!(w, "    \"translate_ns\": {}", translate_time.as_nanos())?;
+        writeln!(w, "  }},")?;
+        writeln!(w, "  \"opcodes\": {{")?;
+        for (i, (opcode, count)) in histogram.iter().enumerate() {
+            let comma = if i + 1 < histogram.len() { "," } else { "" };
+            writeln!(w, "    {}: {count}{comma}", JsonStr(opcode))?;
+        }
+        writeln!(w, "  }},")?;
+        writeln!(w, "  \"files\": {{")?;
+        for (i, (key, path)) in files.iter().enumerate() {
+            let comma = if i + 1 < files.len() { "," } else { "" };
+            writeln!(w, "    \"{key}\": {}{comma}", JsonStr(&path.display().to_string()))?;
+        }
+        writeln!(w, "  }}")?;
+        writeln!(w, "}}")?;
+        w.flush()?;
+        Ok(())
+    }
+
     fn dump_dir(&self) -> Option<PathBuf> {
         let mut dump_dir = self.out_dir.clone()?;
         if let Some(name) = &self.name {
@@ -525,6 +1445,90 @@ impl<B: Backend> EvmCompiler<B> {
     }
 }
 
+/// Formats a string as a JSON string literal.
+struct JsonStr<'a>(&'a str);
+
+impl fmt::Display for JsonStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use fmt::Write;
+
+        f.write_char('"')?;
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+        f.write_char('"')
+    }
+}
+
+/// A custom panic handler for compiled functions.
+///
+/// Receives the panic message as a UTF-8 string. See [`EvmCompiler::set_panic_handler`].
+pub type PanicHandler = unsafe extern "C" fn(msg: *const u8, len: usize);
+
+/// A custom opcode. See [`EvmCompiler::register_opcode`].
+#[derive(Clone, Copy, Debug)]
+pub struct CustomOpcode {
+    /// The number of stack items popped by the opcode.
+    pub inputs: u8,
+    /// The number of stack items pushed by the opcode.
+    pub outputs: u8,
+    /// The static gas cost, charged before calling the handler.
+    pub gas: u16,
+    /// The function implementing the opcode.
+    pub handler: CustomOpcodeFn,
+    /// Additional function attributes of the handler, e.g. [`Attribute::Cold`] for opcodes that
+    /// are rarely executed.
+    ///
+    /// The handler is always assumed to return and to not unwind, and to not re-enter the
+    /// compiled functions unless [`reentrant_host`](EvmCompiler::reentrant_host) is enabled.
+    pub attrs: &'static [Attribute],
+}
+
+/// The function implementing a [`CustomOpcode`].
+///
+/// `sp` points to the deepest of the inputs, so the top of the stack is at
+/// `sp.add(inputs - 1)`, and the outputs must be written starting at `sp`, with the last one
+/// becoming the new top of the stack. Any dynamic gas must be charged through `ecx.gas`.
+///
+/// Returning anything other than [`InstructionResult::Continue`] stops execution with that
+/// result.
+pub type CustomOpcodeFn =
+    unsafe extern "C" fn(ecx: &mut EvmContext<'_>, sp: *mut EvmWord) -> InstructionResult;
+
+/// Budget for unrolling small loops. See [`EvmCompiler::unroll_small_loops`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnrollBudget {
+    /// The maximum number of iterations to unroll.
+    pub max_iterations: u32,
+    /// The maximum number of instructions of the unrolled loop, across all iterations.
+    pub max_insts: u32,
+}
+
+impl Default for UnrollBudget {
+    fn default() -> Self {
+        Self { max_iterations: 16, max_insts: 256 }
+    }
+}
+
+/// How dynamic jumps are dispatched. See [`EvmCompiler::jump_dispatch`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JumpDispatch {
+    /// A single `switch` over all the `JUMPDEST`s, which is split into a `switch` over chunks of
+    /// 256 program counters followed by a `switch` within the chunk for large contracts.
+    #[default]
+    Switch,
+    /// A binary search over the sorted `JUMPDEST`s, ending in `switch`es of at most 16 targets.
+    ///
+    /// This produces less code than [`Switch`](Self::Switch) for contracts with thousands of
+    /// `JUMPDEST`s, at the cost of a few more branches per dynamic jump.
+    BinarySearch,
+}
+
 /// [`EvmCompiler`] input.
 #[allow(missing_debug_implementations)]
 pub enum EvmCompilerInput<'a> {
@@ -558,17 +1562,39 @@ impl<'a> From<&'a Eof> for EvmCompilerInput<'a> {
     }
 }
 
+/// Returns `true` if `name` is a valid symbol name. See [`EvmCompiler::translate`].
+fn is_valid_symbol_name(name: &str) -> bool {
+    let mut bytes = name.bytes();
+    bytes.next().is_some_and(|b| b.is_ascii_alphabetic() || matches!(b, b'_' | b'.' | b'$'))
+        && bytes.all(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'-' | b'.' | b'$'))
+}
+
+fn opcode_set(opcodes: &[u8]) -> OpcodeSet {
+    let mut set = OpcodeSet::ZERO;
+    for &opcode in opcodes {
+        set.set(opcode as usize, true);
+    }
+    set
+}
+
+fn opcode_names(opcodes: &BTreeSet<u8>) -> String {
+    opcodes
+        .iter()
+        .map(|&opcode| Opcode { opcode, immediate: None }.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 #[allow(dead_code)]
 mod default_attrs {
     use revmc_backend::Attribute;
 
     pub(crate) fn for_fn() -> impl Iterator<Item = Attribute> {
+        // NOTE: Not `Speculatable`, as these functions call builtins which write memory.
         [
             Attribute::WillReturn,      // Always returns.
             Attribute::NoSync,          // No thread synchronization.
             Attribute::NativeTargetCpu, // Optimization.
-            Attribute::Speculatable,    // No undefined behavior.
-            Attribute::NoRecurse,       // Revm is not recursive.
         ]
         .into_iter()
     }
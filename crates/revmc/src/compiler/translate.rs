@@ -1,23 +1,38 @@
 //! EVM to IR translation.
 
-use super::default_attrs;
+use super::{default_attrs, CustomOpcode, JumpDispatch, PanicHandler, UnrollBudget};
 use crate::{
-    Backend, Builder, Bytecode, EvmContext, Inst, InstData, InstFlags, IntCC, Result, I256_MIN,
+    Backend, Builder, Bytecode, CounterLoop, EvmContext, Inst, InstData, InstFlags, IntCC, Result,
+    SelectorSwitch, I256_MIN,
 };
 use revm_interpreter::{
     opcode as op, Contract, FunctionReturnFrame, FunctionStack, InstructionResult,
     OPCODE_INFO_JUMPTABLE,
 };
-use revm_primitives::{BlockEnv, CfgEnv, Env, Eof, SpecId, TxEnv, U256};
+use revm_primitives::{Address, BlockEnv, CfgEnv, Env, Eof, SpecId, TxEnv, U256};
 use revmc_backend::{
-    eyre::ensure, Attribute, BackendTypes, FunctionAttributeLocation, Pointer, TypeMethods,
+    ensure, Attribute, BackendTypes, CallingConvention, FunctionAttributeLocation, Pointer,
+    TypeMethods,
 };
 use revmc_builtins::{Builtin, Builtins, CallKind, CreateKind, ExtCallKind, EXTCALL_LIGHT_FAILURE};
 use std::{fmt::Write, mem, sync::atomic::AtomicPtr};
 
 const STACK_CAP: usize = 1024;
+/// The size of the locally allocated stack in bytes.
+const STACK_SIZE: u64 = STACK_CAP as u64 * 32;
+/// The maximum number of `JUMPDEST`s to dispatch to with a single `switch` in the dynamic jump
+/// table. Above this, the dispatch is split into chunks of `1 << JUMP_TABLE_CHUNK_BITS` pcs.
+const MAX_JUMP_TABLE_LEN: usize = 512;
+const JUMP_TABLE_CHUNK_BITS: u32 = 8;
+/// The maximum number of targets of the `switch`es at the end of a binary search jump table.
+const JUMP_TABLE_LEAF_LEN: usize = 16;
 // const WORD_SIZE: usize = 32;
 
+/// A callback receiving `(inst, comment)` pairs. See [`EvmCompiler::set_comment_sink`].
+///
+/// [`EvmCompiler::set_comment_sink`]: crate::EvmCompiler::set_comment_sink
+pub(super) type CommentSink = dyn FnMut(Inst, &str);
+
 #[derive(Clone, Copy, Debug)]
 pub(super) struct FcxConfig {
     pub(super) comments: bool,
@@ -26,9 +41,29 @@ pub(super) struct FcxConfig {
     pub(super) validate_eof: bool,
 
     pub(super) local_stack: bool,
+    pub(super) local_stack_lifetimes: bool,
     pub(super) inspect_stack_length: bool,
+    pub(super) elide_dead_stack_stores: bool,
+    pub(super) constant_folding: bool,
     pub(super) stack_bound_checks: bool,
+    pub(super) stack_access_checks: bool,
     pub(super) gas_metering: bool,
+    pub(super) gas_invariant_checks: bool,
+    pub(super) split_validation: bool,
+    pub(super) panic_handler: Option<PanicHandler>,
+    pub(super) calling_convention: CallingConvention,
+    pub(super) unroll_small_loops: Option<UnrollBudget>,
+    pub(super) jump_dispatch: JumpDispatch,
+    pub(super) stackmaps: bool,
+    pub(super) presize_memory: bool,
+    pub(super) optimize_none: bool,
+    pub(super) selector_entry: Option<u32>,
+    pub(super) interpreter_exact_gas: bool,
+    pub(super) vector_bitwise: bool,
+    pub(super) saturating_gas: bool,
+    pub(super) stack_underflow: InstructionResult,
+    pub(super) stack_overflow: InstructionResult,
+    pub(super) reentrant_host: bool,
 }
 
 impl Default for FcxConfig {
@@ -39,9 +74,29 @@ impl Default for FcxConfig {
             frame_pointers: cfg!(debug_assertions),
             validate_eof: true,
             local_stack: false,
+            local_stack_lifetimes: false,
             inspect_stack_length: false,
+            elide_dead_stack_stores: true,
+            constant_folding: true,
             stack_bound_checks: true,
+            stack_access_checks: false,
             gas_metering: true,
+            gas_invariant_checks: false,
+            split_validation: false,
+            calling_convention: CallingConvention::C,
+            unroll_small_loops: None,
+            jump_dispatch: JumpDispatch::Switch,
+            stackmaps: false,
+            presize_memory: false,
+            optimize_none: false,
+            selector_entry: None,
+            interpreter_exact_gas: false,
+            vector_bitwise: false,
+            saturating_gas: false,
+            stack_underflow: InstructionResult::StackUnderflow,
+            stack_overflow: InstructionResult::StackOverflow,
+            reentrant_host: false,
+            panic_handler: None,
         }
     }
 }
@@ -53,6 +108,103 @@ type Incoming<B> = Vec<(<B as BackendTypes>::Value, <B as BackendTypes>::BasicBl
 #[allow(dead_code)]
 type SwitchTargets<B> = Vec<(u64, <B as BackendTypes>::BasicBlock)>;
 
+/// Stack slots whose values are known, so that they don't have to be loaded again.
+///
+/// Slots are indexed relative to the stack length at the start of the run of instructions that
+/// the cache is valid for. The cache has to be cleared when the stack may be written through a
+/// pointer handed out to a builtin, or when an instruction may be entered from anywhere other than
+/// the previous one.
+///
+/// If stores are deferred, pushed values are only stored to the stack once it may be observed, so
+/// slots that are popped before then are never stored at all. The same goes for the stack length,
+/// which is only stored once at the end of the run.
+struct StackCache<B: Backend> {
+    /// The index of the stack length before the current instruction.
+    depth: i32,
+    /// The index of the stack length after the current instruction.
+    next_depth: i32,
+    /// The known values by index, and whether they have yet to be stored to the stack.
+    values: Vec<(i32, B::Value, bool)>,
+    /// Whether a pointer to the stack was created in the current instruction.
+    escaped: bool,
+    /// Whether the stores of the current instruction can be deferred.
+    defer_stores: bool,
+    /// Whether the store of the stack length of the current instruction can be deferred.
+    defer_len: bool,
+    /// Whether the stack length has yet to be stored.
+    len_pending: bool,
+    /// The comparison results among the known values, as the pushed word and the original `i1`.
+    conditions: Vec<(B::Value, B::Value)>,
+}
+
+impl<B: Backend> StackCache<B> {
+    fn new() -> Self {
+        Self {
+            depth: 0,
+            next_depth: 0,
+            values: Vec::new(),
+            escaped: false,
+            defer_stores: false,
+            defer_len: false,
+            len_pending: false,
+            conditions: Vec::new(),
+        }
+    }
+
+    /// Starts a new instruction, keeping the known values if `continues` is `true`.
+    ///
+    /// `next_continues` is whether the next instruction continues from this one, in which case
+    /// the stack length store can be deferred, as well as the stack stores if `defer_stores` is
+    /// `true`.
+    fn start_inst(&mut self, continues: bool, next_continues: bool, defer_stores: bool) {
+        if continues && !self.escaped {
+            self.depth = self.next_depth;
+            // Slots above the stack length are always written before being read again, so their
+            // pending stores can be dropped.
+            let depth = self.depth;
+            self.values.retain(|&(index, _, _)| index < depth);
+        } else {
+            debug_assert!(
+                self.values.iter().all(|&(_, _, pending)| !pending) && !self.len_pending,
+                "dropped pending stack stores"
+            );
+            self.depth = 0;
+            self.values.clear();
+            self.conditions.clear();
+        }
+        self.next_depth = self.depth;
+        self.escaped = false;
+        self.defer_stores = next_continues && defer_stores;
+        self.defer_len = next_continues;
+    }
+
+    /// Returns `true` if a store to the stack can be deferred at this point.
+    fn can_defer_store(&self) -> bool {
+        self.defer_stores && !self.escaped
+    }
+
+    /// Returns `true` if the store of the stack length can be deferred at this point.
+    fn can_defer_len(&self) -> bool {
+        self.defer_len && !self.escaped
+    }
+
+    fn get(&self, index: i32) -> Option<B::Value> {
+        self.values.iter().find(|&&(i, _, _)| i == index).map(|&(_, value, _)| value)
+    }
+
+    fn set(&mut self, index: i32, value: B::Value, pending: bool) {
+        match self.values.iter_mut().find(|(i, _, _)| *i == index) {
+            Some((_, v, p)) => (*v, *p) = (value, pending),
+            None => self.values.push((index, value, pending)),
+        }
+    }
+
+    /// Returns the `i1` that `word` was extended from, if it is the result of a comparison.
+    fn condition(&self, word: B::Value) -> Option<B::Value> {
+        self.conditions.iter().find(|&&(w, _)| w == word).map(|&(_, cond)| cond)
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 enum ResumeKind {
     /// Use `indirectbr`.
@@ -91,11 +243,20 @@ pub(super) struct FunctionCx<'a, B: Backend> {
     ecx: B::Value,
     /// Stack length before the current instruction.
     len_before: B::Value,
+    /// Stack length after the current instruction.
+    len_after: B::Value,
     /// Stack length offset for the current instruction, used for push/pop.
     len_offset: i8,
+    /// The known values of the stack slots.
+    stack_cache: StackCache<B>,
+    /// Whether the memory is expanded to its static bound at function entry, in which case memory
+    /// operations do not need to check for expansion.
+    memory_presized: bool,
 
     /// The bytecode being translated.
     bytecode: &'a Bytecode<'a>,
+    /// The registered custom opcodes.
+    custom_opcodes: Option<&'a [Option<CustomOpcode>; 256]>,
     /// All entry blocks for each instruction.
     inst_entries: Vec<B::BasicBlock>,
     /// The current instruction being translated.
@@ -127,6 +288,8 @@ pub(super) struct FunctionCx<'a, B: Backend> {
 
     /// Builtins.
     builtins: &'a mut Builtins<B>,
+    /// Receives all the comments added to instructions, if any.
+    comment_sink: Option<&'a mut CommentSink>,
 }
 
 impl<'a, B: Backend> FunctionCx<'a, B> {
@@ -189,6 +352,8 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
         mut bcx: B::Builder<'a>,
         config: FcxConfig,
         builtins: &'a mut Builtins<B>,
+        comment_sink: Option<&'a mut CommentSink>,
+        custom_opcodes: Option<&'a [Option<CustomOpcode>; 256]>,
         bytecode: &'a Bytecode<'a>,
     ) -> Result<()> {
         let entry_block = bcx.current_block().unwrap();
@@ -211,7 +376,13 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
 
         let sp_arg = bcx.fn_param(1);
         let stack = if config.local_stack {
-            bcx.new_stack_slot(word_type, "stack.addr")
+            let stack_type = bcx.type_array(word_type, STACK_CAP as u32);
+            let stack = bcx.new_stack_slot(stack_type, "stack.addr");
+            if config.local_stack_lifetimes {
+                let ptr = stack.addr(&mut bcx);
+                bcx.lifetime_start(ptr, STACK_SIZE);
+            }
+            stack
         } else {
             Pointer::new_address(word_type, sp_arg)
         };
@@ -238,6 +409,9 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
             .collect();
         assert!(!inst_entries.is_empty(), "translating empty bytecode");
 
+        let memory_bound =
+            if config.presize_memory { bytecode.static_memory_bound() } else { None };
+
         let dynamic_jump_table = bcx.create_block("dynamic_jump_table");
         let suspend_block = bcx.create_block("suspend");
         let failure_block = bcx.create_block("failure");
@@ -258,10 +432,14 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
             contract,
             ecx,
             len_before: bcx.iconst(isize_type, 0),
+            len_after: bcx.iconst(isize_type, 0),
             len_offset: 0,
+            stack_cache: StackCache::new(),
+            memory_presized: memory_bound.is_some(),
             bcx,
 
             bytecode,
+            custom_opcodes,
             inst_entries,
             current_inst: usize::MAX,
 
@@ -279,38 +457,16 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
             suspend_block,
 
             builtins,
+            comment_sink,
         };
 
         // We store the stack length if requested or necessary due to the bytecode.
         let stack_length_observable = config.inspect_stack_length || bytecode.may_suspend();
 
         // Add debug assertions for the parameters.
-        if config.debug_assertions {
-            fx.pointer_panic_with_bool(
-                config.gas_metering,
-                gas_ptr,
-                "gas pointer",
-                "gas metering is enabled",
-            );
-            fx.pointer_panic_with_bool(
-                !config.local_stack,
-                sp_arg,
-                "stack pointer",
-                "local stack is disabled",
-            );
-            fx.pointer_panic_with_bool(
-                stack_length_observable,
-                stack_len_arg,
-                "stack length pointer",
-                if config.inspect_stack_length {
-                    "stack length inspection is enabled"
-                } else {
-                    "bytecode suspends execution"
-                },
-            );
-            fx.pointer_panic_with_bool(true, env, "env pointer", "");
-            fx.pointer_panic_with_bool(true, contract, "contract pointer", "");
-            fx.pointer_panic_with_bool(true, ecx, "EVM context pointer", "");
+        // These are emitted in a separate function if `split_validation` is enabled.
+        if config.debug_assertions && !config.split_validation {
+            build_param_assertions(&mut fx.bcx, fx.builtins, &config, bytecode);
         }
 
         // The bytecode is guaranteed to have at least one instruction.
@@ -320,7 +476,28 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
         fx.bcx.br(post_entry_block);
 
         // Translate individual instructions into their respective blocks.
-        for (inst, _) in bytecode.iter_insts() {
+        let mut insts = bytecode.iter_insts();
+        while let Some((inst, _)) = insts.next() {
+            if let Some(l) = fx.unrollable_loop_at(inst) {
+                fx.translate_unrolled_loop(l)?;
+                // Skip the rest of the loop.
+                for (inst, _) in insts.by_ref() {
+                    if inst == l.jumpi {
+                        break;
+                    }
+                }
+                continue;
+            }
+            if let Some(s) = fx.selector_switch_at(inst) {
+                fx.translate_selector_switch(s)?;
+                // Skip the rest of the comparisons.
+                for (inst, _) in insts.by_ref() {
+                    if inst == s.end() - 1 {
+                        break;
+                    }
+                }
+                continue;
+            }
             fx.translate_inst(inst)?;
         }
 
@@ -344,8 +521,14 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
 
             // fx.bcx.switch_to_block(target);
             // let index = fx.bcx.ireduce(i32_type, index);
-            fx.add_invalid_jump();
-            fx.bcx.switch(index, return_block, &targets, true);
+            if config.jump_dispatch == JumpDispatch::BinarySearch {
+                fx.build_binary_search_jump_table(index, return_block, &targets);
+            } else if targets.len() > MAX_JUMP_TABLE_LEN {
+                fx.build_chunked_jump_table(index, return_block, &targets);
+            } else {
+                fx.add_invalid_jump();
+                fx.bcx.switch(index, return_block, &targets, true);
+            }
         } else {
             // No dynamic jumps.
             debug_assert!(fx.incoming_dynamic_jumps.is_empty());
@@ -356,14 +539,22 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
         // Finalize the suspend and resume blocks. Must come before the return block.
         // Also here is where the stack length is initialized.
         let load_len_at_start = |fx: &mut Self| {
-            // Loaded from args only for the config.
             if config.inspect_stack_length {
+                // Loaded from args only for the config.
                 let stack_len = fx.bcx.load(fx.isize_type, stack_len_arg, "stack_len");
                 fx.stack_len.store(&mut fx.bcx, stack_len);
             } else {
                 fx.stack_len.store_imm(&mut fx.bcx, 0);
             }
         };
+        // Expand the memory once at entry, charging all of the expansion gas up front.
+        // The memory is empty at this point since the bytecode cannot suspend if it has a bound.
+        let presize_memory = |fx: &mut Self| {
+            if let Some(bound) = memory_bound.filter(|&bound| bound > 0) {
+                let bound = fx.bcx.iconst(fx.isize_type, bound as i64);
+                fx.call_fallible_builtin(Builtin::ResizeMemory, &[fx.ecx, bound]);
+            }
+        };
         let generate_resume = bytecode.may_suspend();
         if generate_resume {
             let get_ecx_resume_at_ptr = |fx: &mut Self| {
@@ -397,6 +588,7 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
 
                 fx.bcx.switch_to_block(no_resume_block);
                 load_len_at_start(&mut fx);
+                presize_memory(&mut fx);
                 fx.bcx.br(first_inst_block);
 
                 // Dispatch to the resume block.
@@ -439,6 +631,7 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
 
             fx.bcx.switch_to_block(post_entry_block);
             load_len_at_start(&mut fx);
+            presize_memory(&mut fx);
             fx.bcx.br(first_inst_block);
 
             fx.bcx.switch_to_block(resume_block);
@@ -464,6 +657,11 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
             if stack_length_observable {
                 fx.save_stack_len();
             }
+            // All returns go through this block, so this ends the lifetime on every path.
+            if config.local_stack && config.local_stack_lifetimes {
+                let ptr = fx.stack.addr(&mut fx.bcx);
+                fx.bcx.lifetime_end(ptr, STACK_SIZE);
+            }
             fx.bcx.ret(&[return_value]);
         } else {
             fx.bcx.unreachable();
@@ -474,20 +672,190 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
         Ok(())
     }
 
+    /// Returns the counter loop starting at `inst` if it should be unrolled.
+    fn unrollable_loop_at(&self, inst: Inst) -> Option<CounterLoop> {
+        let budget = self.config.unroll_small_loops?;
+        let l = self.bytecode.counter_loop_at(inst)?;
+        let len = (l.jumpi - l.head + 1) as u64;
+        let fits = l.count > 1
+            && l.count <= budget.max_iterations
+            && len * l.count as u64 <= budget.max_insts as u64;
+        fits.then_some(l)
+    }
+
+    /// Translates a counter loop with its instructions duplicated once per iteration.
+    ///
+    /// The first copy uses the original instruction blocks. As the number of iterations is known,
+    /// the `JUMPI` of every copy falls through to the head of the next one, and the last one to
+    /// the instruction after the loop.
+    #[instrument(level = "debug", skip_all, fields(head = l.head, count = l.count))]
+    fn translate_unrolled_loop(&mut self, l: CounterLoop) -> Result<()> {
+        let range = l.head..=l.jumpi;
+        let original = self.inst_entries[range.clone()].to_vec();
+        let mut after = *original.last().unwrap();
+        let mut copies = Vec::with_capacity(l.count as usize - 1);
+        for i in 1..l.count {
+            let mut blocks = Vec::with_capacity(original.len());
+            for inst in range.clone() {
+                let name = self.bytecode.op_block_name(inst, &format!("unroll{i}"));
+                after = self.bcx.create_block_after(after, &name);
+                blocks.push(after);
+            }
+            copies.push(blocks);
+        }
+
+        let exit = self.inst_entries[l.jumpi + 1];
+        let mut first = Vec::new();
+        for i in 0..l.count as usize {
+            let blocks = if i == 0 { &original } else { &copies[i - 1] };
+            self.inst_entries[range.clone()].copy_from_slice(blocks);
+            for inst in l.head..l.jumpi {
+                self.translate_inst(inst)?;
+            }
+            // Both branches of the `JUMPI` continue at the next copy.
+            let next = copies.get(i).map_or(exit, |blocks| blocks[0]);
+            self.inst_entries[l.head] = next;
+            self.inst_entries[l.jumpi + 1] = next;
+            self.translate_inst(l.jumpi)?;
+            if i == 0 {
+                first = self.inst_entries[range.clone()].to_vec();
+                first[0] = original[0];
+            }
+        }
+        self.inst_entries[range].copy_from_slice(&first);
+        self.inst_entries[l.jumpi + 1] = exit;
+
+        Ok(())
+    }
+
+    /// Returns the selector switch starting at `inst` if it should be translated as a `switch`.
+    fn selector_switch_at(&self, inst: Inst) -> Option<&'a SelectorSwitch> {
+        // The gas of the comparisons is charged at once, which the interpreter would not do.
+        if self.config.interpreter_exact_gas {
+            return None;
+        }
+        self.bytecode.selector_switch_at(inst)
+    }
+
+    /// Translates a selector switch as a `switch` on the selector.
+    ///
+    /// The first comparison is translated as usual, and falls through to the `switch`, which
+    /// replaces the others. Their gas is charged at once, up to the comparison that is taken, or
+    /// all of it if none is. Their stack checks are the same as the first one's, as the stack is
+    /// left unchanged when a comparison falls through.
+    ///
+    /// In a selector entry point, the `switch` is replaced with a direct branch to the target of
+    /// the entry's selector, if it is one of the cases.
+    #[instrument(level = "debug", skip_all, fields(start = s.start, cases = s.cases.len()))]
+    fn translate_selector_switch(&mut self, s: &SelectorSwitch) -> Result<()> {
+        let second = s.case_start(1);
+        for inst in s.start..second {
+            self.translate_inst(inst)?;
+        }
+
+        let mut gas = 0;
+        let mut targets = Vec::with_capacity(s.cases.len() - 1);
+        for (i, &(selector, target)) in s.cases.iter().enumerate().skip(1) {
+            gas += self.bytecode.inst(s.case_start(i)).section.gas_cost as u64;
+            // Only the first comparison with a given selector can be taken.
+            if s.cases[..i].iter().all(|&(prev, _)| prev != selector) {
+                targets.push((selector as u64, target, gas));
+            }
+        }
+
+        self.current_inst = second;
+        self.bcx.switch_to_block(self.inst_entries[second]);
+        let entry =
+            self.config.selector_entry.filter(|_| s.is_calldata).and_then(|entry| {
+                targets.iter().find(|&&(selector, _, _)| selector == entry as u64)
+            });
+        if let Some(&(_, target, gas)) = entry {
+            self.gas_cost_imm(gas);
+            self.bcx.br(self.inst_entries[target]);
+        } else {
+            self.build_selector_switch(s, &targets, gas);
+        }
+
+        // The other comparisons are never entered.
+        for inst in second + 1..s.end() {
+            self.bcx.switch_to_block(self.inst_entries[inst]);
+            self.bcx.unreachable();
+        }
+
+        Ok(())
+    }
+
+    /// Builds the `switch` of [`translate_selector_switch`](Self::translate_selector_switch) on
+    /// the selector on top of the stack, to the given targets with the gas charged for each.
+    fn build_selector_switch(
+        &mut self,
+        s: &SelectorSwitch,
+        targets: &[(u64, Inst, u64)],
+        gas: u64,
+    ) {
+        let len = self.stack_len.load(&mut self.bcx, "stack_len");
+        let sp = self.sp_from_top(len, 1);
+        let selector = self.load_word(sp, "selector");
+
+        let mut after = self.current_block();
+        let mut cases = Vec::with_capacity(targets.len());
+        for &(selector, _, _) in targets {
+            after = self.create_block_after(after, &format!("selector.{selector:08x}"));
+            cases.push((selector, after));
+        }
+        let default = self.create_block_after(after, "selector.default");
+        self.bcx.switch(selector, default, &cases, false);
+
+        for (&(_, target, gas), &(_, block)) in targets.iter().zip(&cases) {
+            self.bcx.switch_to_block(block);
+            self.gas_cost_imm(gas);
+            self.bcx.br(self.inst_entries[target]);
+        }
+        self.bcx.switch_to_block(default);
+        self.gas_cost_imm(gas);
+        self.bcx.br(self.inst_entries[s.end()]);
+    }
+
     #[instrument(level = "debug", skip_all, fields(inst = %self.bytecode.inst(inst).to_op()))]
     fn translate_inst(&mut self, inst: Inst) -> Result<()> {
-        self.current_inst = inst;
+        let prev_inst = mem::replace(&mut self.current_inst, inst);
         let data = self.bytecode.inst(inst);
         let opcode = data.opcode;
         let entry_block = self.inst_entries[inst];
         self.bcx.switch_to_block(entry_block);
 
+        if let Some(sink) = &mut self.comment_sink {
+            sink(inst, &data.to_op_in(self.bytecode).to_string());
+        }
+
         let is_eof = self.bytecode.is_eof();
         let is_eof_enabled = self.bytecode.spec_id.is_enabled_in(SpecId::PRAGUE_EOF);
         if is_eof {
-            ensure!(is_eof_enabled, "EOF bytecode in non-EOF spec");
+            ensure!(is_eof_enabled, InvalidBytecode, "EOF bytecode in non-EOF spec");
         }
 
+        // Known stack values can only be reused if this instruction is only entered from the
+        // previous one.
+        let continues = !is_eof
+            && inst.checked_sub(1) == Some(prev_inst)
+            && !data.is_reachable_jumpdest(is_eof, self.bytecode.has_dynamic_jumps())
+            && {
+                let prev = self.bytecode.inst(prev_inst);
+                !prev.is_branching(is_eof) && !prev.may_suspend(is_eof)
+            };
+        // Stores can only be deferred if the next instruction continues from this one, as the
+        // stack may be observed otherwise.
+        let next_continues = !is_eof
+            && !data.is_branching(is_eof)
+            && !data.may_suspend(is_eof)
+            && inst + 1 < self.inst_entries.len()
+            && {
+                let next = self.bytecode.inst(inst + 1);
+                !next.is_dead_code()
+                    && !next.is_reachable_jumpdest(is_eof, self.bytecode.has_dynamic_jumps())
+            };
+        self.stack_cache.start_inst(continues, next_continues, self.config.elide_dead_stack_stores);
+
         // self.call_printf(format_printf!("{}\n", self.op_block_name("")), &[]);
 
         let branch_to_next_opcode = |this: &mut Self| {
@@ -539,12 +907,20 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
             self.suspend();
             goto_return!(no_branch);
         }
+        #[cfg(test)]
+        if opcode == crate::TEST_BUILTIN_SUCCESS {
+            let success = Builtin::TestStop.success();
+            let result = self.bcx.iconst(self.i8_type, success as i64);
+            self.call_fallible_builtin(Builtin::TestStop, &[result]);
+            goto_return!();
+        }
 
         // This is a compile error because it should've been validated as per EOF.
         if is_eof_enabled && is_eof {
             if let Some(info) = OPCODE_INFO_JUMPTABLE[opcode as usize] {
                 ensure!(
                     !info.is_disabled_in_eof(),
+                    InvalidBytecode,
                     "disabled opcode in EOF bytecode: {}",
                     data.to_op_in(self.bytecode),
                 );
@@ -568,26 +944,44 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
             goto_return!(fail InstructionResult::NotActivated);
         }
         if data.flags.contains(InstFlags::UNKNOWN) {
-            ensure!(!is_eof, "Unknown opcode in EOF bytecode: {data:?}");
+            ensure!(!is_eof, InvalidBytecode, "Unknown opcode in EOF bytecode: {data:?}");
             goto_return!(fail InstructionResult::OpcodeNotFound);
         }
 
         if is_eof {
             if let Some(info) = OPCODE_INFO_JUMPTABLE[opcode as usize] {
-                ensure!(!info.is_disabled_in_eof(), "Disabled opcode in EOF bytecode: {data:?}");
+                ensure!(
+                    !info.is_disabled_in_eof(),
+                    InvalidBytecode,
+                    "Disabled opcode in EOF bytecode: {data:?}"
+                );
             }
         }
 
-        // Pay static gas for the current section.
-        self.gas_cost_imm(data.section.gas_cost as u64);
-
-        if data.flags.contains(InstFlags::SKIP_LOGIC) {
-            goto_return!("skipped");
+        // Pay static gas for the current section, or only for this instruction if replicating the
+        // interpreter's charging order.
+        if self.config.interpreter_exact_gas {
+            self.gas_cost_imm(data.base_gas as u64);
+        } else {
+            self.gas_cost_imm(data.section.gas_cost as u64);
         }
 
         // Reset the stack length offset for this instruction.
         self.len_offset = 0;
-        self.len_before = self.stack_len.load(&mut self.bcx, "stack_len");
+        // The stack length is only loaded at the start of a run of instructions, as it is not
+        // stored until its end.
+        self.len_before = if continues {
+            self.len_after
+        } else {
+            self.stack_len.load(&mut self.bcx, "stack_len")
+        };
+        self.len_after = self.len_before;
+        if !self.stack_cache.defer_stores {
+            self.flush_stack_stores();
+        }
+        if !self.stack_cache.defer_len {
+            self.flush_stack_len();
+        }
 
         // Check stack length for the current section.
         // Skip doing this for EOF bytecode, as it is done at deploy time.
@@ -596,7 +990,7 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
             let diff = data.section.max_growth as i64;
 
             if diff > revmc_context::EvmStack::CAPACITY as i64 {
-                goto_return!(fail InstructionResult::StackOverflow);
+                goto_return!(fail self.config.stack_overflow);
             }
 
             let underflow = |this: &mut Self| {
@@ -619,23 +1013,26 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
                 let overflow = overflow(self);
                 let cond = self.bcx.bitor(underflow, overflow);
                 let ret = {
-                    let under =
-                        self.bcx.iconst(self.i8_type, InstructionResult::StackUnderflow as i64);
-                    let over =
-                        self.bcx.iconst(self.i8_type, InstructionResult::StackOverflow as i64);
+                    let under = self.bcx.iconst(self.i8_type, self.config.stack_underflow as i64);
+                    let over = self.bcx.iconst(self.i8_type, self.config.stack_overflow as i64);
                     self.bcx.select(underflow, under, over)
                 };
                 let target = self.build_check_inner(true, cond, ret);
                 self.bcx.switch_to_block(target);
             } else if may_underflow {
                 let cond = underflow(self);
-                self.build_check(cond, InstructionResult::StackUnderflow);
+                self.build_check(cond, self.config.stack_underflow);
             } else if may_overflow {
                 let cond = overflow(self);
-                self.build_check(cond, InstructionResult::StackOverflow);
+                self.build_check(cond, self.config.stack_overflow);
             }
         }
 
+        // Skipped instructions still have to check the stack length of the section they start.
+        if data.flags.contains(InstFlags::SKIP_LOGIC) {
+            goto_return!("skipped");
+        }
+
         // Update the stack length for this instruction.
         {
             let (inp, out) = data.stack_io();
@@ -648,11 +1045,23 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
                 if data.may_suspend(is_eof) {
                     diff -= 1;
                 }
-                let len_changed = self.bcx.iadd_imm(self.len_before, diff);
-                self.stack_len.store(&mut self.bcx, len_changed);
+                self.len_after = self.bcx.iadd_imm(self.len_before, diff);
+                self.stack_cache.len_pending = true;
+                if !self.stack_cache.can_defer_len() {
+                    self.flush_stack_len();
+                }
+                self.stack_cache.next_depth += diff as i32;
             }
         }
 
+        if data.flags.contains(InstFlags::CUSTOM) {
+            let sp = self.sp_after_inputs();
+            let function = self.custom_opcode_function(opcode);
+            let ret = self.bcx.call(function, &[self.ecx, sp]).unwrap();
+            self.build_check_instruction_result(ret);
+            goto_return!();
+        }
+
         // Macro utils.
         macro_rules! unop {
             ($op:ident) => {{
@@ -668,6 +1077,22 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
                 let r = self.bcx.$op(a, b);
                 self.push(r);
             }};
+            // Lowered through `<4 x i64>` if enabled and supported by the backend.
+            (@vector $op:ident) => {{
+                let [a, b] = self.popn();
+                let i64_type = self.bcx.type_int(64);
+                let vector_type =
+                    self.config.vector_bitwise.then(|| self.bcx.type_vector(i64_type, 4)).flatten();
+                let r = if let Some(vector_type) = vector_type {
+                    let a = self.bcx.bitcast(vector_type, a);
+                    let b = self.bcx.bitcast(vector_type, b);
+                    let r = self.bcx.$op(a, b);
+                    self.bcx.bitcast(self.word_type, r)
+                } else {
+                    self.bcx.$op(a, b)
+                };
+                self.push(r);
+            }};
             (@shift $op:ident, | $value:ident, $shift:ident | $default:expr) => {{
                 let [$shift, $value] = self.popn();
                 let r = self.bcx.$op($value, $shift);
@@ -677,11 +1102,14 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
                 self.push(r);
             }};
             (@if_not_zero $op:ident) => {{
-                // TODO: `select` might not have the same semantics in all backends.
+                // Both values of `select` are evaluated, so the division by zero must be avoided
+                // by dividing by one instead.
                 let [a, b] = self.popn();
                 let b_is_zero = self.bcx.icmp_imm(IntCC::Equal, b, 0);
                 let zero = self.bcx.iconst_256(U256::ZERO);
-                let op_result = self.bcx.$op(a, b);
+                let one = self.bcx.iconst_256(U256::from(1));
+                let divisor = self.bcx.select(b_is_zero, one, b);
+                let op_result = self.bcx.$op(a, divisor);
                 let r = self.bcx.select(b_is_zero, zero, op_result);
                 self.push(r);
             }};
@@ -693,22 +1121,22 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
                 self.get_field(self.$field, 0 $(+ mem::offset_of!($paths, $spec))*, stringify!($field.$($spec).*.addr))
             };
             // Gets and loads the pointer to a field.
-            // The value is loaded as a native-endian 256-bit integer.
-            // `@[endian]` is the endianness of the value. If native, omit it.
-            ($field:ident; @load $(@[endian = $endian:tt])? $ty:expr, $($paths:path),*; $($spec:tt).*) => {{
+            // The value is loaded as a native-endian integer.
+            // `@[endian]` is the `Endian` of the value in memory. If native, omit it.
+            ($field:ident; @load $(@[endian = $endian:ident])? $ty:expr, $($paths:path),*; $($spec:tt).*) => {{
                 let ptr = field!($field; @get $($paths),*; $($spec).*);
                 #[allow(unused_mut)]
                 let mut value = self.bcx.load($ty, ptr, stringify!($field.$($spec).*));
                 $(
-                    if !cfg!(target_endian = $endian) {
+                    if Endian::$endian.needs_bswap() {
                         value = self.bcx.bswap(value);
                     }
                 )?
                 value
             }};
             // Gets, loads, extends (if necessary), and pushes the value of a field to the stack.
-            // `@[endian]` is the endianness of the value. If native, omit it.
-            ($field:ident; @push $(@[endian = $endian:tt])? $ty:expr, $($rest:tt)*) => {{
+            // `@[endian]` is the `Endian` of the value in memory. If native, omit it.
+            ($field:ident; @push $(@[endian = $endian:ident])? $ty:expr, $($rest:tt)*) => {{
                 let mut value = field!($field; @load $(@[endian = $endian])? $ty, $($rest)*);
                 if self.bcx.type_bit_width($ty) < 256 {
                     value = self.bcx.zext(self.word_type, value);
@@ -744,14 +1172,28 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
                             let b_is_neg1 = bcx.icmp_imm(IntCC::Equal, b, -1);
                             bcx.bitand(a_is_min, b_is_neg1)
                         };
-                        let sdiv_result = bcx.sdiv(a, b);
-                        bcx.select(is_weird_sdiv_edge_case, min, sdiv_result)
+                        // `sdiv(I256_MIN, -1)` overflows, but `sdiv(I256_MIN, 1)` is the same as
+                        // the EVM result.
+                        let one = bcx.iconst_256(U256::from(1));
+                        let divisor = bcx.select(is_weird_sdiv_edge_case, one, b);
+                        bcx.sdiv(a, divisor)
                     },
                 );
                 self.push(r);
             }
             op::MOD => binop!(@if_not_zero urem),
-            op::SMOD => binop!(@if_not_zero srem),
+            op::SMOD => {
+                let [a, b] = self.popn();
+                // `srem(a, 0)` is undefined and `srem(I256_MIN, -1)` overflows, but the result of
+                // both is zero, the same as `srem(a, 1)`.
+                let b_is_zero = self.bcx.icmp_imm(IntCC::Equal, b, 0);
+                let b_is_neg1 = self.bcx.icmp_imm(IntCC::Equal, b, -1);
+                let use_one = self.bcx.bitor(b_is_zero, b_is_neg1);
+                let one = self.bcx.iconst_256(U256::from(1));
+                let divisor = self.bcx.select(use_one, one, b);
+                let r = self.bcx.srem(a, divisor);
+                self.push(r);
+            }
             op::ADDMOD => {
                 let sp = self.sp_after_inputs();
                 let _ = self.call_builtin(Builtin::AddMod, &[sp]);
@@ -777,24 +1219,27 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
                     op::GT => IntCC::UnsignedGreaterThan,
                     op::SLT => IntCC::SignedLessThan,
                     op::SGT => IntCC::SignedGreaterThan,
+                    // `EQ ISZERO` fused into `NE`; the `ISZERO` is skipped.
+                    op::EQ if data.data == 1 => IntCC::NotEqual,
                     op::EQ => IntCC::Equal,
                     _ => unreachable!(),
                 };
 
                 let [a, b] = self.popn();
                 let r = self.bcx.icmp(cond, a, b);
-                let r = self.bcx.zext(self.word_type, r);
-                self.push(r);
+                self.push_condition(r);
             }
             op::ISZERO => {
                 let a = self.pop();
-                let r = self.bcx.icmp_imm(IntCC::Equal, a, 0);
-                let r = self.bcx.zext(self.word_type, r);
-                self.push(r);
+                let r = match self.stack_cache.condition(a) {
+                    Some(cond) => self.bcx.bitnot(cond),
+                    None => self.bcx.icmp_imm(IntCC::Equal, a, 0),
+                };
+                self.push_condition(r);
             }
-            op::AND => binop!(bitand),
-            op::OR => binop!(bitor),
-            op::XOR => binop!(bitxor),
+            op::AND => binop!(@vector bitand),
+            op::OR => binop!(@vector bitor),
+            op::XOR => binop!(@vector bitxor),
             op::NOT => unop!(bitnot),
             op::BYTE => {
                 let [index, value] = self.popn();
@@ -816,21 +1261,17 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
             }
 
             op::ADDRESS => {
-                contract_field!(@push @[endian = "big"] self.address_type, Contract; target_address)
-            }
-            op::BALANCE => {
-                let sp = self.sp_after_inputs();
-                let spec_id = self.const_spec_id();
-                self.call_fallible_builtin(Builtin::Balance, &[self.ecx, sp, spec_id]);
+                contract_field!(@push @[endian = Big] self.address_type, Contract; target_address)
             }
+            op::BALANCE => self.account_query(Builtin::Balance, Builtin::BalanceConst),
             op::ORIGIN => {
-                env_field!(@push @[endian = "big"] self.address_type, Env, TxEnv; tx.caller)
+                env_field!(@push @[endian = Big] self.address_type, Env, TxEnv; tx.caller)
             }
             op::CALLER => {
-                contract_field!(@push @[endian = "big"] self.address_type, Contract; caller)
+                contract_field!(@push @[endian = Big] self.address_type, Contract; caller)
             }
             op::CALLVALUE => {
-                contract_field!(@push @[endian = "little"] self.word_type, Contract; call_value)
+                contract_field!(@push @[endian = Little] self.word_type, Contract; call_value)
             }
             op::CALLDATALOAD => {
                 let index = self.pop();
@@ -858,11 +1299,7 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
                 let sp = self.sp_after_inputs();
                 let _ = self.call_builtin(Builtin::GasPrice, &[self.ecx, sp]);
             }
-            op::EXTCODESIZE => {
-                let sp = self.sp_after_inputs();
-                let spec_id = self.const_spec_id();
-                self.call_fallible_builtin(Builtin::ExtCodeSize, &[self.ecx, sp, spec_id]);
-            }
+            op::EXTCODESIZE => self.account_query(Builtin::ExtCodeSize, Builtin::ExtCodeSizeConst),
             op::EXTCODECOPY => {
                 let sp = self.sp_after_inputs();
                 let spec_id = self.const_spec_id();
@@ -875,23 +1312,19 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
                 let sp = self.sp_after_inputs();
                 self.call_fallible_builtin(Builtin::ReturnDataCopy, &[self.ecx, sp]);
             }
-            op::EXTCODEHASH => {
-                let sp = self.sp_after_inputs();
-                let spec_id = self.const_spec_id();
-                self.call_fallible_builtin(Builtin::ExtCodeHash, &[self.ecx, sp, spec_id]);
-            }
+            op::EXTCODEHASH => self.account_query(Builtin::ExtCodeHash, Builtin::ExtCodeHashConst),
             op::BLOCKHASH => {
                 let sp = self.sp_after_inputs();
                 self.call_fallible_builtin(Builtin::BlockHash, &[self.ecx, sp]);
             }
             op::COINBASE => {
-                env_field!(@push @[endian = "big"] self.address_type, Env, BlockEnv; block.coinbase)
+                env_field!(@push @[endian = Big] self.address_type, Env, BlockEnv; block.coinbase)
             }
             op::TIMESTAMP => {
-                env_field!(@push @[endian = "little"] self.word_type, Env, BlockEnv; block.timestamp)
+                env_field!(@push @[endian = Little] self.word_type, Env, BlockEnv; block.timestamp)
             }
             op::NUMBER => {
-                env_field!(@push @[endian = "little"] self.word_type, Env, BlockEnv; block.number)
+                env_field!(@push @[endian = Little] self.word_type, Env, BlockEnv; block.number)
             }
             op::DIFFICULTY => {
                 let slot = self.sp_at_top();
@@ -899,7 +1332,7 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
                 let _ = self.call_builtin(Builtin::Difficulty, &[self.ecx, slot, spec_id]);
             }
             op::GASLIMIT => {
-                env_field!(@push @[endian = "little"] self.word_type, Env, BlockEnv; block.gas_limit)
+                env_field!(@push @[endian = Little] self.word_type, Env, BlockEnv; block.gas_limit)
             }
             op::CHAINID => env_field!(@push self.bcx.type_int(64), Env, CfgEnv; cfg.chain_id),
             op::SELFBALANCE => {
@@ -907,18 +1340,22 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
                 self.call_fallible_builtin(Builtin::SelfBalance, &[self.ecx, slot]);
             }
             op::BASEFEE => {
-                env_field!(@push @[endian = "little"] self.word_type, Env, BlockEnv; block.basefee)
+                env_field!(@push @[endian = Little] self.word_type, Env, BlockEnv; block.basefee)
             }
             op::BLOBHASH => {
                 let sp = self.sp_after_inputs();
                 let _ = self.call_builtin(Builtin::BlobHash, &[self.ecx, sp]);
             }
             op::BLOBBASEFEE => {
-                let len = self.len_before();
-                let slot = self.sp_at(len);
+                let slot = self.sp_at_top();
                 let _ = self.call_builtin(Builtin::BlobBaseFee, &[self.ecx, slot]);
             }
 
+            op::POP if data.data == 1 => {
+                // Fused with the previous `SWAP1`.
+                let [a, _] = self.popn();
+                self.push(a);
+            }
             op::POP => { /* Already handled in stack_io */ }
             op::MLOAD => {
                 let offset = self.pop();
@@ -957,6 +1394,10 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
                         self.len_offset -= 1;
                         self.return_block.unwrap()
                     } else if data.flags.contains(InstFlags::STATIC_JUMP) {
+                        if data.flags.contains(InstFlags::COMPUTED_JUMP) {
+                            // The target is known, but we still need to account for the stack.
+                            self.len_offset -= 1;
+                        }
                         let target_inst = data.data as usize;
                         debug_assert_eq!(
                             *self.bytecode.inst(target_inst),
@@ -975,12 +1416,21 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
 
                     if opcode == op::JUMPI {
                         let cond_word = self.pop();
-                        let cond = self.bcx.icmp_imm(IntCC::NotEqual, cond_word, 0);
+                        // Branch on the result of a previous comparison directly.
+                        let cond = match self.stack_cache.condition(cond_word) {
+                            Some(cond) => cond,
+                            None => self.bcx.icmp_imm(IntCC::NotEqual, cond_word, 0),
+                        };
                         let next = self.inst_entries[inst + 1];
                         if target == self.return_block.unwrap() {
                             self.add_invalid_jump();
                         }
-                        self.bcx.brif(cond, target, next);
+                        if target == next {
+                            // Both branches continue at the same block, as in unrolled loops.
+                            self.bcx.br(next);
+                        } else {
+                            self.bcx.brif(cond, target, next);
+                        }
                     } else {
                         self.bcx.br(target);
                     }
@@ -994,7 +1444,8 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
                 self.push(pc);
             }
             op::MSIZE => {
-                let msize = self.call_builtin(Builtin::Msize, &[self.ecx]).unwrap();
+                let memory_ptr = self.load_memory_ptr(self.ecx);
+                let msize = self.load_memory_len(memory_ptr);
                 let msize = self.bcx.zext(self.word_type, msize);
                 self.push(msize);
             }
@@ -1019,18 +1470,18 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
                 self.call_fallible_builtin(Builtin::Mcopy, &[self.ecx, sp]);
             }
 
-            op::PUSH0 => {
-                let value = self.bcx.iconst_256(U256::ZERO);
-                self.push(value);
-            }
-            op::PUSH1..=op::PUSH32 => {
-                // NOTE: This can be None if the bytecode is invalid.
-                let imm = self.bytecode.get_imm(data);
-                let value = imm.map(U256::from_be_slice).unwrap_or_default();
+            op::PUSH0..=op::PUSH32 => {
+                let value = self.bytecode.push_value(data);
                 let value = self.bcx.iconst_256(value);
                 self.push(value);
             }
 
+            op::DUP1 if data.data == 1 => {
+                // Fused with the next `MLOAD`.
+                let offset = self.peek(1);
+                let value = self.call_mload(offset);
+                self.push(value);
+            }
             op::DUP1..=op::DUP16 => self.dup((opcode - op::DUP1 + 1) as usize),
 
             op::SWAP1..=op::SWAP16 => self.swap((opcode - op::SWAP1 + 1) as usize),
@@ -1213,17 +1664,29 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
     fn pushn(&mut self, values: &[B::Value]) {
         let len_start = self.len_before();
         for &value in values {
-            let len = if self.len_offset != 0 {
-                self.bcx.iadd_imm(len_start, self.len_offset as i64)
-            } else {
-                len_start
-            };
+            let deferred = self.stack_cache.can_defer_store();
+            self.stack_cache.set(self.stack_cache.depth + self.len_offset as i32, value, deferred);
+            if !deferred {
+                let len = if self.len_offset != 0 {
+                    self.bcx.iadd_imm(len_start, self.len_offset as i64)
+                } else {
+                    len_start
+                };
+                let sp = self.sp_at(len);
+                self.bcx.store(value, sp);
+            }
             self.len_offset += 1;
-            let sp = self.sp_at(len);
-            self.bcx.store(value, sp);
         }
     }
 
+    /// Pushes the result of a comparison onto the stack, remembering the original `i1` so that it
+    /// can be used directly by `ISZERO` and `JUMPI`.
+    fn push_condition(&mut self, cond: B::Value) {
+        let word = self.bcx.zext(self.word_type, cond);
+        self.stack_cache.conditions.push((word, cond));
+        self.push(word);
+    }
+
     /// Removes the topmost element from the stack and returns it.
     fn pop(&mut self) -> B::Value {
         self.popn::<1>()[0]
@@ -1238,6 +1701,11 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
         let len_start = self.len_before();
         std::array::from_fn(|i| {
             self.len_offset -= 1;
+            if let Some(value) =
+                self.stack_cache.get(self.stack_cache.depth + self.len_offset as i32)
+            {
+                return value;
+            }
             let len = if self.len_offset != 0 {
                 self.bcx.iadd_imm(len_start, self.len_offset as i64)
             } else {
@@ -1252,13 +1720,24 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
     /// Duplicates the `n`th value from the top of the stack.
     /// `n` cannot be `0`.
     fn dup(&mut self, n: usize) {
-        debug_assert_ne!(n, 0);
-        let len = self.len_before();
-        let sp = self.sp_from_top(len, n);
-        let value = self.load_word(sp, &format!("dup{n}"));
+        let value = self.peek(n);
         self.push(value);
     }
 
+    /// Returns the `n`th value from the top of the stack without removing it.
+    /// `n` cannot be `0`.
+    fn peek(&mut self, n: usize) -> B::Value {
+        debug_assert_ne!(n, 0);
+        match self.stack_cache.get(self.stack_cache.depth - n as i32) {
+            Some(value) => value,
+            None => {
+                let len = self.len_before();
+                let sp = self.sp_from_top(len, n);
+                self.load_word(sp, &format!("dup{n}"))
+            }
+        }
+    }
+
     /// Swaps the topmost value with the `n`th value from the top.
     /// `n` cannot be `0`.
     fn swap(&mut self, n: usize) {
@@ -1271,15 +1750,71 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
     fn exchange(&mut self, n: usize, m: usize) {
         debug_assert_ne!(m, 0);
         let len = self.len_before();
+        let a_index = self.stack_cache.depth - (n + 1) as i32;
+        let b_index = self.stack_cache.depth - (n + m + 1) as i32;
         // Load a.
         let a_sp = self.sp_from_top(len, n + 1);
-        let a = self.load_word(a_sp, "swap.a");
+        let a = match self.stack_cache.get(a_index) {
+            Some(a) => a,
+            None => self.load_word(a_sp, "swap.a"),
+        };
         // Load b.
         let b_sp = self.sp_from_top(len, n + m + 1);
-        let b = self.load_word(b_sp, "swap.b");
+        let b = match self.stack_cache.get(b_index) {
+            Some(b) => b,
+            None => self.load_word(b_sp, "swap.b"),
+        };
         // Store.
-        self.bcx.store(a, b_sp);
-        self.bcx.store(b, a_sp);
+        let deferred = self.stack_cache.can_defer_store();
+        if !deferred {
+            self.bcx.store(a, b_sp);
+            self.bcx.store(b, a_sp);
+        }
+        self.stack_cache.set(b_index, a, deferred);
+        self.stack_cache.set(a_index, b, deferred);
+    }
+
+    /// `BALANCE`, `EXTCODESIZE` or `EXTCODEHASH` instruction.
+    ///
+    /// If the address was pushed by the previous instruction, it is passed to `const_builtin` as
+    /// an immediate instead of being read back from the stack.
+    fn account_query(&mut self, builtin: Builtin, const_builtin: Builtin) {
+        let sp = self.sp_after_inputs();
+        let spec_id = self.const_spec_id();
+        let Some(address) = self.const_address_operand() else {
+            self.call_fallible_builtin(builtin, &[self.ecx, sp, spec_id]);
+            return;
+        };
+        let (hi, mid, lo) = revmc_builtins::split_address(&address);
+        let i32_type = self.bcx.type_int(32);
+        let i64_type = self.bcx.type_int(64);
+        let hi = self.bcx.uconst(i32_type, hi as u64);
+        let mid = self.bcx.uconst(i64_type, mid);
+        let lo = self.bcx.uconst(i64_type, lo);
+        if self.config.comments {
+            self.add_comment(&format!("address {address}"));
+        }
+        self.call_fallible_builtin(const_builtin, &[self.ecx, sp, hi, mid, lo, spec_id]);
+    }
+
+    /// Returns the address on top of the stack if it was pushed by the previous instruction.
+    ///
+    /// The previous instruction is always executed right before the current one, as only
+    /// `JUMPDEST`s can be jumped to. In EOF, the current instruction can also be the target of an
+    /// `RJUMP*`, in which case the operand is unknown.
+    fn const_address_operand(&self) -> Option<Address> {
+        if self.bytecode.inst(self.current_inst).flags.contains(InstFlags::EOF_JUMPDEST) {
+            return None;
+        }
+        let prev = self.current_inst.checked_sub(1)?;
+        let data = self.bytecode.inst(prev);
+        if !matches!(data.opcode, op::PUSH0..=op::PUSH32)
+            || data.flags.contains(InstFlags::SKIP_LOGIC)
+        {
+            return None;
+        }
+        let value = self.bytecode.push_value(data);
+        Some(Address::from_word(value.to_be_bytes().into()))
     }
 
     /// `RETURN` or `REVERT` instruction.
@@ -1349,7 +1884,7 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
             max_len = self.bcx.iadd_imm(max_len, max_height as i64);
         }
         let cond = self.bcx.icmp_imm(IntCC::UnsignedGreaterThan, max_len, STACK_CAP as i64);
-        self.build_check(cond, InstructionResult::StackOverflow);
+        self.build_check(cond, self.config.stack_overflow);
 
         // Push the return address to the function stack.
         let next_block = self.inst_entries[self.current_inst + 1];
@@ -1447,6 +1982,14 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
         self.gas_remaining.load(&mut self.bcx, "gas.remaining")
     }
 
+    /// Loads the gas limit.
+    fn load_gas_limit(&mut self) -> B::Value {
+        let gas_ptr = self.bcx.fn_param(0);
+        let ptr = self.get_field(gas_ptr, mem::offset_of!(pf::Gas, limit), "gas.limit.addr");
+        let i64_type = self.bcx.type_int(64);
+        self.bcx.load(i64_type, ptr, "gas.limit")
+    }
+
     /// Stores the gas used.
     fn store_gas_remaining(&mut self, value: B::Value) {
         self.gas_remaining.store(&mut self.bcx, value);
@@ -1466,6 +2009,7 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
 
     /// Returns the stack pointer at the top (`&stack[stack.len]`).
     fn sp_at_top(&mut self) -> B::Value {
+        self.escape_stack();
         let len = self.len_before();
         self.sp_at(len)
     }
@@ -1473,6 +2017,7 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
     /// Returns the stack pointer after the input has been popped
     /// (`&stack[stack.len - op.input()]`).
     fn sp_after_inputs(&mut self) -> B::Value {
+        self.escape_stack();
         let mut len = self.len_before();
         let (inputs, _) = self.current_inst().stack_io();
         if inputs > 0 {
@@ -1481,8 +2026,46 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
         self.sp_at(len)
     }
 
+    /// Stores the deferred stack values and invalidates the known ones after the current
+    /// instruction, as the stack is about to be accessed through a pointer.
+    fn escape_stack(&mut self) {
+        self.flush_stack_stores();
+        self.flush_stack_len();
+        self.stack_cache.escaped = true;
+    }
+
+    /// Stores the stack length if its store was deferred.
+    fn flush_stack_len(&mut self) {
+        if mem::take(&mut self.stack_cache.len_pending) {
+            self.stack_len.store(&mut self.bcx, self.len_after);
+        }
+    }
+
+    /// Stores the stack values whose stores were deferred.
+    fn flush_stack_stores(&mut self) {
+        for i in 0..self.stack_cache.values.len() {
+            let (index, value, pending) = self.stack_cache.values[i];
+            if !pending {
+                continue;
+            }
+            let offset = index - self.stack_cache.depth;
+            let len = if offset != 0 {
+                self.bcx.iadd_imm(self.len_before, offset as i64)
+            } else {
+                self.len_before
+            };
+            let sp = self.sp_at(len);
+            self.bcx.store(value, sp);
+            self.stack_cache.values[i].2 = false;
+        }
+    }
+
     /// Returns the stack pointer at `len` (`&stack[len]`).
     fn sp_at(&mut self, len: B::Value) -> B::Value {
+        if self.config.debug_assertions && self.config.stack_access_checks {
+            let cond = self.bcx.icmp_imm(IntCC::UnsignedGreaterThan, len, STACK_CAP as i64);
+            self.build_assertion(cond, "stack index out of bounds");
+        }
         let ptr = self.stack.addr(&mut self.bcx);
         self.bcx.gep(self.word_type, ptr, &[len], "sp")
     }
@@ -1490,8 +2073,13 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
     /// Returns the stack pointer at `len` from the top (`&stack[CAPACITY - len]`).
     fn sp_from_top(&mut self, len: B::Value, n: usize) -> B::Value {
         debug_assert_ne!(n, 0);
-        let len = self.bcx.isub_imm(len, n as i64);
-        self.sp_at(len)
+        let index = self.bcx.isub_imm(len, n as i64);
+        if self.config.debug_assertions && self.config.stack_access_checks {
+            // Also catches `n > len`, since the index wraps around.
+            let cond = self.bcx.icmp(IntCC::UnsignedGreaterThanOrEqual, index, len);
+            self.build_assertion(cond, "stack index from the top out of bounds");
+        }
+        self.sp_at(index)
     }
 
     /// Builds a gas cost deduction for an immediate value.
@@ -1512,17 +2100,29 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
         // Modified from `Gas::record_cost`.
         // This can overflow the gas counters, which has to be adjusted for after the call.
         let gas_remaining = self.load_gas_remaining();
-        let (res, overflow) = self.bcx.usub_overflow(gas_remaining, cost);
-        if self.bytecode.is_small() {
+        let (mut res, overflow) = self.bcx.usub_overflow(gas_remaining, cost);
+        if self.config.saturating_gas {
+            // Clamp the remaining gas at zero and keep executing.
+            let zero = self.bcx.iconst(self.isize_type, 0);
+            res = self.bcx.select(overflow, zero, res);
+            self.store_gas_remaining(res);
+        } else if self.bytecode.is_small() && !self.config.interpreter_exact_gas {
             // Storing the result before the check significantly increases time spent in
             // `llvm::MemoryDependenceResults::getNonLocalPointerDependency`, but it might produce
             // slightly better code.
+            // The remaining gas is left wrapped around on failure.
             self.store_gas_remaining(res);
             self.build_check(overflow, InstructionResult::OutOfGas);
         } else {
             self.build_check(overflow, InstructionResult::OutOfGas);
             self.store_gas_remaining(res);
         }
+
+        if self.config.debug_assertions && self.config.gas_invariant_checks {
+            let gas_limit = self.load_gas_limit();
+            let cond = self.bcx.icmp(IntCC::UnsignedGreaterThan, res, gas_limit);
+            self.build_assertion(cond, "gas remaining exceeds gas limit");
+        }
     }
 
     /*
@@ -1536,7 +2136,12 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
 
     /// Builds a check, failing if `ret` is not `InstructionResult::Continue`.
     fn build_check_instruction_result(&mut self, ret: B::Value) {
-        let failure = self.bcx.icmp_imm(IntCC::NotEqual, ret, InstructionResult::Continue as i64);
+        self.build_check_instruction_result_with(ret, InstructionResult::Continue);
+    }
+
+    /// Builds a check, failing if `ret` is not `success`.
+    fn build_check_instruction_result_with(&mut self, ret: B::Value, success: InstructionResult) {
+        let failure = self.bcx.icmp_imm(IntCC::NotEqual, ret, success as i64);
         let target = self.build_check_inner(true, failure, ret);
         self.bcx.switch_to_block(target);
     }
@@ -1634,23 +2239,93 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
         ));
     }
 
-    // Pointer must not be null if `must_be_set` is true.
-    fn pointer_panic_with_bool(
+    /// Builds a two-level dispatch to the given `(pc, block)` targets, sorted by `pc`: first on the
+    /// high bits of `index`, and then on its low [`JUMP_TABLE_CHUNK_BITS`] bits.
+    ///
+    /// Jumps to `default` with an invalid jump result if `index` is not one of the targets.
+    fn build_chunked_jump_table(
         &mut self,
-        must_be_set: bool,
-        ptr: B::Value,
-        name: &str,
-        extra: &str,
+        index: B::Value,
+        default: B::BasicBlock,
+        targets: &[(u64, B::BasicBlock)],
     ) {
-        if !must_be_set {
-            return;
+        let mask = (1 << JUMP_TABLE_CHUNK_BITS) - 1;
+        let max_pc = targets.last().expect("no targets").0;
+        let current = self.current_block();
+        let in_bounds = self.bcx.create_block_after(current, "dynamic_jump_table.hi");
+        let overflow = self.bcx.icmp_imm(IntCC::UnsignedGreaterThan, index, max_pc as i64);
+        self.add_invalid_jump();
+        self.bcx.brif_cold(overflow, default, in_bounds, true);
+
+        self.bcx.switch_to_block(in_bounds);
+        // `pc` fits in 32 bits.
+        let index = self.bcx.ireduce(self.isize_type, index);
+        let shift = self.bcx.iconst(self.isize_type, JUMP_TABLE_CHUNK_BITS as i64);
+        let high = self.bcx.ushr(index, shift);
+        let low = self.bcx.bitand_imm(index, mask as i64);
+
+        let mut chunks = Vec::new();
+        let mut after = in_bounds;
+        for chunk in
+            targets.chunk_by(|a, b| a.0 >> JUMP_TABLE_CHUNK_BITS == b.0 >> JUMP_TABLE_CHUNK_BITS)
+        {
+            let hi = chunk[0].0 >> JUMP_TABLE_CHUNK_BITS;
+            let block = self.bcx.create_block_after(after, &format!("dynamic_jump_table.lo{hi}"));
+            chunks.push((hi, block, chunk));
+            after = block;
         }
-        let panic_cond = self.bcx.is_null(ptr);
-        let mut msg = format!("revmc panic: {name} must not be null");
-        if !extra.is_empty() {
-            write!(msg, " ({extra})").unwrap();
+        let outer = chunks.iter().map(|&(hi, block, _)| (hi, block)).collect::<Vec<_>>();
+        self.add_invalid_jump();
+        self.bcx.switch(high, default, &outer, true);
+
+        for (_, block, chunk) in chunks {
+            self.bcx.switch_to_block(block);
+            let inner = chunk.iter().map(|&(pc, target)| (pc & mask, target)).collect::<Vec<_>>();
+            self.add_invalid_jump();
+            self.bcx.switch(low, default, &inner, true);
+        }
+    }
+
+    /// Builds a binary search over the given `(pc, block)` targets, sorted by `pc`, ending in
+    /// `switch`es of at most [`JUMP_TABLE_LEAF_LEN`] targets.
+    ///
+    /// Jumps to `default` with an invalid jump result if `index` is not one of the targets.
+    fn build_binary_search_jump_table(
+        &mut self,
+        index: B::Value,
+        default: B::BasicBlock,
+        targets: &[(u64, B::BasicBlock)],
+    ) {
+        let max_pc = targets.last().expect("no targets").0;
+        let current = self.current_block();
+        let in_bounds = self.bcx.create_block_after(current, "dynamic_jump_table.search");
+        let overflow = self.bcx.icmp_imm(IntCC::UnsignedGreaterThan, index, max_pc as i64);
+        self.add_invalid_jump();
+        self.bcx.brif_cold(overflow, default, in_bounds, true);
+
+        self.bcx.switch_to_block(in_bounds);
+        // `pc` fits in 32 bits.
+        let index = self.bcx.ireduce(self.isize_type, index);
+
+        let mut ranges = vec![(in_bounds, targets)];
+        while let Some((block, targets)) = ranges.pop() {
+            self.bcx.switch_to_block(block);
+            if targets.len() <= JUMP_TABLE_LEAF_LEN {
+                self.add_invalid_jump();
+                self.bcx.switch(index, default, targets, true);
+                continue;
+            }
+            let (lo, hi) = targets.split_at(targets.len() / 2);
+            let pivot = hi[0].0;
+            let lo_block =
+                self.bcx.create_block_after(block, &format!("dynamic_jump_table.lt{pivot}"));
+            let hi_block =
+                self.bcx.create_block_after(lo_block, &format!("dynamic_jump_table.ge{pivot}"));
+            let cond = self.bcx.icmp_imm(IntCC::UnsignedLessThan, index, pivot as i64);
+            self.bcx.brif(cond, lo_block, hi_block);
+            ranges.push((hi_block, hi));
+            ranges.push((lo_block, lo));
         }
-        self.build_assertion(panic_cond, &msg);
     }
 
     fn build_assertion(&mut self, cond: B::Value, msg: &str) {
@@ -1666,13 +2341,11 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
         self.bcx.switch_to_block(target);
     }
 
-    /// Build a call to the panic builtin.
+    /// Build a call to the panic builtin, or to the custom panic handler if set.
     fn call_panic(&mut self, msg: &str) {
-        let function = self.builtin_function(Builtin::Panic);
-        let ptr = self.bcx.str_const(msg);
-        let len = self.bcx.iconst(self.isize_type, msg.len() as i64);
-        let _ = self.bcx.call(function, &[ptr, len]);
-        self.bcx.unreachable();
+        if let Some(ret) = build_panic(&mut self.bcx, self.builtins, &self.config, msg) {
+            self.build_return(ret);
+        }
     }
 
     #[allow(dead_code)]
@@ -1684,16 +2357,72 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
         let _ = self.bcx.call(printf, &args);
     }
 
+    /// Returns the function implementing the given custom opcode, declaring it if necessary.
+    ///
+    /// `fn(ecx: ptr, sp: ptr) -> InstructionResult`
+    fn custom_opcode_function(&mut self, opcode: u8) -> B::Function {
+        let op = self
+            .custom_opcodes
+            .and_then(|custom| custom[opcode as usize])
+            .expect("custom instruction without a registered opcode");
+        let name = format!("__revmc_custom_opcode_{opcode:02x}");
+        let params = [self.ptr_type, self.ptr_type];
+        let ret = Some(self.i8_type);
+        let address = op.handler as usize;
+        self.builtins.get_extern(&mut self.bcx, &name, &params, ret, address, op.attrs)
+    }
+
     /// Build a call to a builtin that returns an [`InstructionResult`].
+    ///
+    /// Fails if the result is not the builtin's [success](Builtin::success) value.
     fn call_fallible_builtin(&mut self, builtin: Builtin, args: &[B::Value]) {
         let ret = self.call_builtin(builtin, args).expect("builtin does not return a value");
-        self.build_check_instruction_result(ret);
+        if self.config.interpreter_exact_gas {
+            self.refund_combined_gas(builtin, ret);
+        }
+        self.build_check_instruction_result_with(ret, builtin.success());
+    }
+
+    /// Refunds the static gas of the current instruction if `builtin` ran out of gas, for
+    /// instructions whose static and dynamic gas are charged at once by the interpreter.
+    ///
+    /// The interpreter doesn't charge anything in this case, but the static gas has already been
+    /// paid before calling the builtin.
+    fn refund_combined_gas(&mut self, builtin: Builtin, ret: B::Value) {
+        let is_combined = matches!(
+            builtin,
+            Builtin::Exp
+                | Builtin::Keccak256
+                | Builtin::CallDataCopy
+                | Builtin::CodeCopy
+                | Builtin::ReturnDataCopy
+                | Builtin::Mcopy
+                | Builtin::Log
+                | Builtin::DataCopy
+        );
+        if !self.config.gas_metering || !is_combined {
+            return;
+        }
+        let static_gas = self.current_inst().base_gas;
+        if static_gas == 0 {
+            return;
+        }
+        let is_oog = self.bcx.icmp_imm(IntCC::Equal, ret, InstructionResult::OutOfGas as i64);
+        let refund = self.bcx.iconst(self.isize_type, static_gas as i64);
+        let zero = self.bcx.iconst(self.isize_type, 0);
+        let refund = self.bcx.select(is_oog, refund, zero);
+        let gas_remaining = self.load_gas_remaining();
+        let gas_remaining = self.bcx.iadd(gas_remaining, refund);
+        self.store_gas_remaining(gas_remaining);
     }
 
     /// Build a call to a builtin.
     #[must_use]
     fn call_builtin(&mut self, builtin: Builtin, args: &[B::Value]) -> Option<B::Value> {
         let function = self.builtin_function(builtin);
+        if self.config.stackmaps {
+            self.build_stackmap();
+        }
         // self.call_printf(
         //     format_printf!("{} - calling {}\n", self.op_block_name(""), builtin.name()),
         //     &[],
@@ -1701,6 +2430,24 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
         self.bcx.call(function, args)
     }
 
+    /// Records a stack map for the current instruction, identified by its program counter.
+    ///
+    /// The live values are the pointers to the stack, stack length and remaining gas, followed by
+    /// the environment, contract and EVM context pointers.
+    fn build_stackmap(&mut self) {
+        self.escape_stack();
+        let id = self.current_inst().pc as u64;
+        let live = [
+            self.stack.addr(&mut self.bcx),
+            self.stack_len.addr(&mut self.bcx),
+            self.gas_remaining.addr(&mut self.bcx),
+            self.env,
+            self.contract,
+            self.ecx,
+        ];
+        self.bcx.stackmap(id, &live);
+    }
+
     /// Gets the function for the given builtin.
     fn builtin_function(&mut self, builtin: Builtin) -> B::Function {
         self.builtins.get(builtin, &mut self.bcx)
@@ -1712,6 +2459,12 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
             return;
         }
         self.bcx.add_comment_to_current_inst(comment);
+        // Comments outside of instructions, e.g. in the entry block, are not forwarded.
+        if let Some(sink) = &mut self.comment_sink {
+            if self.current_inst != usize::MAX {
+                sink(self.current_inst, comment);
+            }
+        }
     }
 
     /// Returns the current instruction.
@@ -1751,6 +2504,118 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
     }
 }
 
+/// Translates the validation function of the given bytecode.
+///
+/// This function has the same signature as the main function, and only performs the input
+/// validation that is otherwise done at the start of the main function, returning
+/// [`InstructionResult::Continue`] if all checks pass.
+pub(super) fn translate_validation<B: Backend>(
+    mut bcx: B::Builder<'_>,
+    config: FcxConfig,
+    builtins: &mut Builtins<B>,
+    bytecode: &Bytecode<'_>,
+) {
+    build_param_assertions(&mut bcx, builtins, &config, bytecode);
+    let i8_type = bcx.type_int(8);
+    let ret = bcx.iconst(i8_type, InstructionResult::Continue as i64);
+    bcx.ret(&[ret]);
+    bcx.seal_all_blocks();
+}
+
+/// Builds the debug assertions for the function parameters.
+///
+/// Each pointer must not be null if it's required by the configuration or the bytecode.
+fn build_param_assertions<B: Backend>(
+    bcx: &mut B::Builder<'_>,
+    builtins: &mut Builtins<B>,
+    config: &FcxConfig,
+    bytecode: &Bytecode<'_>,
+) {
+    let stack_length_observable = config.inspect_stack_length || bytecode.may_suspend();
+    let params = [
+        (config.gas_metering, "gas pointer", "gas metering is enabled"),
+        (!config.local_stack, "stack pointer", "local stack is disabled"),
+        (
+            stack_length_observable,
+            "stack length pointer",
+            if config.inspect_stack_length {
+                "stack length inspection is enabled"
+            } else {
+                "bytecode suspends execution"
+            },
+        ),
+        (true, "env pointer", ""),
+        (true, "contract pointer", ""),
+        (true, "EVM context pointer", ""),
+    ];
+    for (i, (must_be_set, name, extra)) in params.into_iter().enumerate() {
+        if !must_be_set {
+            continue;
+        }
+
+        let ptr = bcx.fn_param(i);
+        let panic_cond = bcx.is_null(ptr);
+        let current = bcx.current_block().unwrap();
+        let failure = bcx.create_block_after(current, "entry.panic");
+        let target = bcx.create_block_after(failure, "entry.contd");
+        bcx.brif(panic_cond, failure, target);
+
+        bcx.switch_to_block(failure);
+        let mut msg = format!("revmc panic: {name} must not be null");
+        if !extra.is_empty() {
+            write!(msg, " ({extra})").unwrap();
+        }
+        if let Some(ret) = build_panic(bcx, builtins, config, &msg) {
+            // Nothing was read from the arguments yet, so there is nothing to write back.
+            bcx.ret(&[ret]);
+        }
+
+        bcx.switch_to_block(target);
+    }
+}
+
+/// Builds a call to the panic builtin, or to the custom panic handler if set.
+///
+/// The custom handler is allowed to return, in which case this returns the
+/// [`InstructionResult::FatalExternalError`] value that the function must return, and the current
+/// block is left for the caller to terminate.
+fn build_panic<B: Backend>(
+    bcx: &mut B::Builder<'_>,
+    builtins: &mut Builtins<B>,
+    config: &FcxConfig,
+    msg: &str,
+) -> Option<B::Value> {
+    const PANIC_HANDLER_NAME: &str = "__revmc_panic_handler";
+
+    let ptr = bcx.str_const(msg);
+    let isize_type = bcx.type_ptr_sized_int();
+    let len = bcx.iconst(isize_type, msg.len() as i64);
+    let Some(handler) = config.panic_handler else {
+        let function = builtins.get(Builtin::Panic, bcx);
+        let _ = bcx.call(function, &[ptr, len]);
+        bcx.unreachable();
+        return None;
+    };
+
+    let function = bcx.get_function(PANIC_HANDLER_NAME).unwrap_or_else(|| {
+        let ptr_type = bcx.type_ptr();
+        let address = handler as usize;
+        let linkage = revmc_backend::Linkage::Import;
+        let f = bcx.add_function(
+            PANIC_HANDLER_NAME,
+            &[ptr_type, isize_type],
+            None,
+            Some(address),
+            linkage,
+        );
+        bcx.add_function_attribute(Some(f), Attribute::Cold, FunctionAttributeLocation::Function);
+        f
+    });
+    let _ = bcx.call(function, &[ptr, len]);
+    let i8_type = bcx.type_int(8);
+    Some(bcx.iconst(i8_type, InstructionResult::FatalExternalError as i64))
+}
+
 /// IR builtins.
 impl<'a, B: Backend> FunctionCx<'a, B> {
     fn call_byte(&mut self, index: B::Value, value: B::Value) -> B::Value {
@@ -1891,7 +2756,7 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
                 let tmp_addr = tmp.addr(bcx);
                 bcx.memcpy(tmp_addr, calldata, slice_len);
                 let mut value = tmp.load(bcx, "calldata.i256");
-                if cfg!(target_endian = "little") {
+                if Endian::Big.needs_bswap() {
                     value = bcx.bswap(value);
                 }
                 value
@@ -1927,6 +2792,8 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
             MemOpKind::Store => self.word_type,
             MemOpKind::Store8 => self.i8_type,
         };
+        // Memory operations in presized functions are built separately as they are not checked.
+        let name = &if self.memory_presized { format!("{name}_presized") } else { name.into() };
         let ret = self
             .call_ir_builtin(
                 name,
@@ -1975,60 +2842,48 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
         let value = self.bcx.fn_param(1);
         let ecx = self.bcx.fn_param(2);
 
-        let memory_ptr = {
-            let memory_ptr_ptr =
-                self.get_field(ecx, mem::offset_of!(EvmContext<'_>, memory), "ecx.memory.addr");
-            self.bcx.load(self.ptr_type, memory_ptr_ptr, "ecx.memory")
-        };
+        let memory_ptr = self.load_memory_ptr(ecx);
 
         let memory_buffer_offset = mem::offset_of!(pf::SharedMemory, buffer);
-        let len_ptr = self.get_field(
-            memory_ptr,
-            memory_buffer_offset + mem::offset_of!(pf::Vec<u8>, len),
-            "ecx.memory.len.addr",
-        );
-        let sm_len = self.bcx.load(self.isize_type, len_ptr, "ecx.memory.len");
-
-        // `memory.len() = memory.buffer.len() - memory.last_checkpoint`
-        // `new_size = offset + len`
-        // `if new_size > memory.len() { resize_memory(new_size) }`
-        let last_checkpoint = {
-            let ptr = self.get_field(
-                memory_ptr,
-                mem::offset_of!(pf::SharedMemory, last_checkpoint),
-                "ecx.memory.last_checkpoint.addr",
-            );
-            self.bcx.load(self.isize_type, ptr, "ecx.memory.last_checkpoint")
-        };
-        let buffer_len = self.bcx.isub(sm_len, last_checkpoint);
-        let max_isize = ((1u128 << self.bcx.type_bit_width(self.isize_type)) - 1u128) as u64;
-        let max_isize_u256 = self.bcx.iconst_256(U256::from(max_isize));
-        let max_isize = self.bcx.uconst(self.isize_type, max_isize);
-        let offset_too_big = self.bcx.icmp(IntCC::UnsignedGreaterThan, offset, max_isize_u256);
-        let offset = self.bcx.ireduce(self.isize_type, offset);
-        let (new_size, new_size_overflow) = {
-            let slot_size = match kind {
-                MemOpKind::Load | MemOpKind::Store => 32,
-                MemOpKind::Store8 => 1,
+        let last_checkpoint = self.load_memory_last_checkpoint(memory_ptr);
+        let offset = if self.memory_presized {
+            // All accesses are within the memory that was expanded at function entry.
+            self.bcx.ireduce(self.isize_type, offset)
+        } else {
+            // `new_size = offset + len`
+            // `if new_size > memory.len() { resize_memory(new_size) }`
+            let buffer_len = self.load_memory_len(memory_ptr);
+            let max_isize = ((1u128 << self.bcx.type_bit_width(self.isize_type)) - 1u128) as u64;
+            let max_isize_u256 = self.bcx.iconst_256(U256::from(max_isize));
+            let max_isize = self.bcx.uconst(self.isize_type, max_isize);
+            let offset_too_big = self.bcx.icmp(IntCC::UnsignedGreaterThan, offset, max_isize_u256);
+            let offset = self.bcx.ireduce(self.isize_type, offset);
+            let (new_size, new_size_overflow) = {
+                let slot_size = match kind {
+                    MemOpKind::Load | MemOpKind::Store => 32,
+                    MemOpKind::Store8 => 1,
+                };
+                let slot_size = self.bcx.iconst(self.isize_type, slot_size as i64);
+                self.bcx.uadd_overflow(offset, slot_size)
             };
-            let slot_size = self.bcx.iconst(self.isize_type, slot_size as i64);
-            self.bcx.uadd_overflow(offset, slot_size)
-        };
-        let new_size_overflow = self.bcx.bitor(offset_too_big, new_size_overflow);
-        let new_size = self.bcx.select(new_size_overflow, max_isize, new_size);
-        let cond = self.bcx.icmp(IntCC::UnsignedGreaterThan, new_size, buffer_len);
+            let new_size_overflow = self.bcx.bitor(offset_too_big, new_size_overflow);
+            let new_size = self.bcx.select(new_size_overflow, max_isize, new_size);
+            let cond = self.bcx.icmp(IntCC::UnsignedGreaterThan, new_size, buffer_len);
 
-        let resize = self.bcx.create_block("resize");
-        let cont = self.bcx.create_block("contd");
-        self.bcx.brif_cold(cond, resize, cont, true);
+            let resize = self.bcx.create_block("resize");
+            let cont = self.bcx.create_block("contd");
+            self.bcx.brif_cold(cond, resize, cont, true);
 
-        self.bcx.switch_to_block(resize);
-        self.call_fallible_builtin(Builtin::ResizeMemory, &[ecx, new_size]);
-        self.bcx.br(cont);
+            self.bcx.switch_to_block(resize);
+            self.call_fallible_builtin(Builtin::ResizeMemory, &[ecx, new_size]);
+            self.bcx.br(cont);
+
+            self.bcx.switch_to_block(cont);
+            offset
+        };
 
         // `ecx.memory.buffer[last_checkpoint + offset..]`
         // Implemented as `ecx.memory.buffer[last_checkpoint..][offset..]`
-        self.bcx.switch_to_block(cont);
         let shared_buffer_ptr = {
             let ptr = self.get_field(
                 memory_ptr,
@@ -2048,11 +2903,11 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
             MemOpKind::Load => {
                 let loaded = self.bcx.load(self.word_type, slot, "slot.value");
                 let loaded =
-                    if cfg!(target_endian = "little") { self.bcx.bswap(loaded) } else { loaded };
+                    if Endian::Big.needs_bswap() { self.bcx.bswap(loaded) } else { loaded };
                 self.bcx.store(loaded, value);
             }
             MemOpKind::Store | MemOpKind::Store8 => {
-                let value = if matches!(kind, MemOpKind::Store) && cfg!(target_endian = "little") {
+                let value = if matches!(kind, MemOpKind::Store) && Endian::Big.needs_bswap() {
                     self.bcx.bswap(value)
                 } else {
                     value
@@ -2065,6 +2920,35 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
         self.bcx.ret(&[cont]);
     }
 
+    /// Loads `ecx.memory`.
+    fn load_memory_ptr(&mut self, ecx: B::Value) -> B::Value {
+        let memory_ptr_ptr =
+            self.get_field(ecx, mem::offset_of!(EvmContext<'_>, memory), "ecx.memory.addr");
+        self.bcx.load(self.ptr_type, memory_ptr_ptr, "ecx.memory")
+    }
+
+    /// Loads `memory.last_checkpoint`.
+    fn load_memory_last_checkpoint(&mut self, memory_ptr: B::Value) -> B::Value {
+        let ptr = self.get_field(
+            memory_ptr,
+            mem::offset_of!(pf::SharedMemory, last_checkpoint),
+            "ecx.memory.last_checkpoint.addr",
+        );
+        self.bcx.load(self.isize_type, ptr, "ecx.memory.last_checkpoint")
+    }
+
+    /// Loads `memory.len() = memory.buffer.len() - memory.last_checkpoint`.
+    fn load_memory_len(&mut self, memory_ptr: B::Value) -> B::Value {
+        let len_ptr = self.get_field(
+            memory_ptr,
+            mem::offset_of!(pf::SharedMemory, buffer) + mem::offset_of!(pf::Vec<u8>, len),
+            "ecx.memory.len.addr",
+        );
+        let sm_len = self.bcx.load(self.isize_type, len_ptr, "ecx.memory.len");
+        let last_checkpoint = self.load_memory_last_checkpoint(memory_ptr);
+        self.bcx.isub(sm_len, last_checkpoint)
+    }
+
     fn call_func_stack_push(&mut self, pc: B::Value, new_idx: usize) {
         let new_idx = self.bcx.iconst(self.isize_type, new_idx as i64);
         self.call_fallible_builtin(Builtin::FuncStackPush, &[self.ecx, pc, new_idx]);
@@ -2100,7 +2984,7 @@ impl<'a, B: Backend> FunctionCx<'a, B> {
         let old_len = self.bcx.load(self.isize_type, len_ptr, "ecx.func_stack.return_stack.len");
         let len = self.bcx.iadd_imm(old_len, 1);
         let cond = self.bcx.icmp_imm(IntCC::UnsignedGreaterThan, len, STACK_CAP as i64);
-        self.build_check(cond, InstructionResult::StackOverflow);
+        self.build_check(cond, self.config.stack_overflow);
 
         // Grow the capacity if needed.
         let cap = {
@@ -2275,6 +3159,36 @@ enum MemOpKind {
     Store8,
 }
 
+/// The byte order of a value in memory.
+///
+/// All values are operated on as native-endian integers, so values that are not already
+/// native-endian must be byte-swapped after being loaded and before being stored. This is the only
+/// place where this should be decided:
+/// - EVM memory, calldata, and byte arrays like [`Address`](revm_primitives::Address) and
+///   [`B256`](revm_primitives::B256) are big-endian, regardless of the host;
+/// - [`U256`] is stored as an array of `u64` limbs from least to most significant, each of which is
+///   native-endian. On little-endian hosts this is a little-endian 256-bit integer. Big-endian
+///   hosts are not supported, see [`EvmCompiler::translate`](super::EvmCompiler::translate);
+/// - primitive integers like `u64` and `usize` are native-endian.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Endian {
+    /// Big-endian bytes.
+    Big,
+    /// Little-endian bytes; only used for [`U256`].
+    Little,
+}
+
+impl Endian {
+    /// Returns `true` if a value with this byte order must be byte-swapped to become native-endian,
+    /// and vice versa.
+    const fn needs_bswap(self) -> bool {
+        match self {
+            Self::Big => cfg!(target_endian = "little"),
+            Self::Little => cfg!(target_endian = "big"),
+        }
+    }
+}
+
 // HACK: Need these structs' fields to be public for `offset_of!`.
 // `pf == private_fields`.
 #[allow(dead_code)]
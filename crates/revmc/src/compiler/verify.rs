@@ -0,0 +1,211 @@
+//! Verification of compiled functions against the interpreter.
+
+use crate::{EvmCompilerFn, EvmContext};
+use revm_interpreter::{
+    analysis::to_analysed, opcode as op, Contract, Host, InstructionResult, Interpreter,
+    InterpreterAction,
+};
+use revm_primitives::{spec_to_generic, Address, Bytecode, Bytes, SpecId, U256};
+use std::fmt;
+
+/// A sample execution context used to verify a compiled function against the interpreter.
+///
+/// See [`EvmCompiler::jit_verified`](crate::EvmCompiler::jit_verified).
+#[derive(Clone, Debug)]
+pub struct EvmContextSnapshot<H> {
+    /// The call data.
+    pub input: Bytes,
+    /// The address of the contract being executed.
+    pub target_address: Address,
+    /// The caller of the contract.
+    pub caller: Address,
+    /// The value sent to the contract.
+    pub call_value: U256,
+    /// The gas limit.
+    pub gas_limit: u64,
+    /// The return data of the previous call.
+    pub return_data: Bytes,
+    /// Whether the context is static.
+    pub is_static: bool,
+    /// The host. Every execution is done on a separate clone of it.
+    pub host: H,
+}
+
+impl<H> EvmContextSnapshot<H> {
+    /// Creates a new snapshot with the given host and gas limit, and empty call data, return
+    /// data, and value.
+    pub fn new(host: H, gas_limit: u64) -> Self {
+        Self {
+            input: Bytes::new(),
+            target_address: Address::ZERO,
+            caller: Address::ZERO,
+            call_value: U256::ZERO,
+            gas_limit,
+            return_data: Bytes::new(),
+            is_static: false,
+            host,
+        }
+    }
+}
+
+impl<H: Host + Clone + 'static> EvmContextSnapshot<H> {
+    /// Executes `bytecode` with both the interpreter and `f`, returning a description of the
+    /// first difference between the two, if any.
+    ///
+    /// The stacks are only compared if `compare_stack` is `true`.
+    pub(crate) fn verify(
+        &self,
+        bytecode: &[u8],
+        spec_id: SpecId,
+        f: EvmCompilerFn,
+        compare_stack: bool,
+    ) -> Result<(), String> {
+        let contract = self.contract(bytecode);
+
+        let mut interpreter = self.interpreter(contract.clone());
+        let table = spec_to_generic!(spec_id, op::make_instruction_table::<H, SPEC>());
+        let memory = interpreter.take_memory();
+        let expected_action = interpreter.run(memory, &table, &mut self.host.clone());
+
+        let mut compiled = self.interpreter(contract);
+        let mut host = self.host.clone();
+        let (mut ecx, stack, stack_len) =
+            EvmContext::from_interpreter_with_stack(&mut compiled, &mut host);
+        let actual = unsafe { f.call(Some(stack), Some(stack_len), &mut ecx) };
+
+        let actual = Outcome {
+            result: actual,
+            stack: compare_stack.then_some(compiled.stack.data().as_slice()),
+            memory: compiled.shared_memory.context_memory(),
+            gas_spent: compiled.gas.spent(),
+            gas_refunded: Some(compiled.gas.refunded()),
+            next_action: (!compiled.next_action.is_none()).then_some(&compiled.next_action),
+        };
+        let expected = Outcome {
+            result: interpreter.instruction_result,
+            stack: compare_stack.then_some(interpreter.stack.data().as_slice()),
+            memory: interpreter.shared_memory.context_memory(),
+            gas_spent: interpreter.gas.spent(),
+            gas_refunded: Some(interpreter.gas.refunded()),
+            next_action: Some(&expected_action),
+        };
+        actual.compare(&expected)
+    }
+
+    /// Returns the contract executing `bytecode` in this context.
+    pub(crate) fn contract(&self, bytecode: &[u8]) -> Contract {
+        Contract {
+            input: self.input.clone(),
+            bytecode: to_analysed(Bytecode::new_raw(Bytes::copy_from_slice(bytecode))),
+            hash: None,
+            bytecode_address: None,
+            target_address: self.target_address,
+            caller: self.caller,
+            call_value: self.call_value,
+        }
+    }
+
+    /// Returns a new interpreter executing `contract` in this context.
+    pub(crate) fn interpreter(&self, contract: Contract) -> Interpreter {
+        let mut interpreter = Interpreter::new(contract, self.gas_limit, self.is_static);
+        interpreter.return_data_buffer = self.return_data.clone();
+        interpreter
+    }
+}
+
+/// The observable outcome of an execution, see [`Outcome::compare`].
+#[derive(Debug)]
+pub(crate) struct Outcome<'a> {
+    /// The instruction result.
+    pub(crate) result: InstructionResult,
+    /// The stack, if it is compared.
+    pub(crate) stack: Option<&'a [U256]>,
+    /// The memory of the current context.
+    pub(crate) memory: &'a [u8],
+    /// The spent gas.
+    pub(crate) gas_spent: u64,
+    /// The refunded gas, if it is compared.
+    pub(crate) gas_refunded: Option<i64>,
+    /// The next action, or `None` if there is none.
+    pub(crate) next_action: Option<&'a InterpreterAction>,
+}
+
+impl Outcome<'_> {
+    /// Compares the outcome of a compiled function with the `expected` one, returning a
+    /// description of the first difference between the two, if any.
+    pub(crate) fn compare(&self, expected: &Self) -> Result<(), String> {
+        // The stack is checked per section, so a stack overflow or underflow can be reported
+        // before other errors. Any out of gas error is equivalent, and `InvalidOperand` is
+        // sometimes skipped.
+        let is_equivalent_error = matches!(
+            self.result,
+            InstructionResult::StackOverflow
+                | InstructionResult::StackUnderflow
+                | InstructionResult::OutOfGas
+                | InstructionResult::MemoryOOG
+                | InstructionResult::InvalidOperandOOG
+        ) && expected.result.is_error();
+        if self.result != expected.result && !is_equivalent_error {
+            return Err(format!("result mismatch: {:?} != {:?}", self.result, expected.result));
+        }
+
+        // On halt all the available gas is consumed, so the resulting stack, memory and gas do
+        // not matter.
+        if !self.result.is_error() {
+            if let (Some(stack), Some(expected)) = (self.stack, expected.stack) {
+                if stack != expected {
+                    return Err(format!("stack mismatch: {stack:?} != {expected:?}"));
+                }
+            }
+            if self.memory != expected.memory {
+                return Err(format!(
+                    "memory mismatch: {:?} != {:?}",
+                    MemDisplay(self.memory),
+                    MemDisplay(expected.memory)
+                ));
+            }
+            if self.gas_spent != expected.gas_spent {
+                return Err(format!(
+                    "gas mismatch: spent {} != {}",
+                    self.gas_spent, expected.gas_spent
+                ));
+            }
+            if let (Some(refunded), Some(expected)) = (self.gas_refunded, expected.gas_refunded) {
+                if refunded != expected {
+                    return Err(format!("gas mismatch: refunded {refunded} != {expected}"));
+                }
+            }
+        }
+
+        match (self.next_action, expected.next_action) {
+            (None, None) => {}
+            // The interpreter returns this when there is no next action.
+            (None, Some(InterpreterAction::Return { result }))
+            | (Some(InterpreterAction::Return { result }), None)
+                if result.output.is_empty() => {}
+            (
+                Some(InterpreterAction::Return { result }),
+                Some(InterpreterAction::Return { result: expected }),
+            ) if result.result == expected.result
+                && result.output == expected.output
+                && result.gas.spent() == expected.gas.spent() => {}
+            (Some(action), Some(expected)) if action == expected => {}
+            (action, expected) => {
+                return Err(format!("next action mismatch: {action:?} != {expected:?}"))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats memory as a list of 32-byte words.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct MemDisplay<'a>(pub(crate) &'a [u8]);
+
+impl fmt::Debug for MemDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let chunks = self.0.chunks(32).map(revm_primitives::hex::encode_prefixed);
+        f.debug_list().entries(chunks).finish()
+    }
+}
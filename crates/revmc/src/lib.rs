@@ -12,12 +12,27 @@ use alloy_primitives as _;
 mod bytecode;
 pub use bytecode::*;
 
+mod cache;
+pub use cache::{CachedFn, CompiledFnCache};
+
 mod compiler;
-pub use compiler::{EvmCompiler, EvmCompilerInput};
+pub use compiler::{
+    CustomOpcode, CustomOpcodeFn, EvmCompiler, EvmCompilerInput, EvmContextSnapshot, JumpDispatch,
+    PanicHandler, UnrollBudget,
+};
+
+mod library;
+pub use library::*;
 
 mod linker;
 pub use linker::Linker;
 
+mod tiered;
+pub use tiered::{HotnessPolicy, OnCompiled, TieredCompiler, TieredWorker};
+
+#[cfg(feature = "bench")]
+pub mod bench;
+
 /// Internal tests and testing utilities. Not public API.
 #[cfg(any(test, feature = "__fuzzing"))]
 pub mod tests;
@@ -0,0 +1,283 @@
+use crate::Result;
+#[cfg(feature = "loader")]
+use crate::{Backend, EvmCompiler, EvmCompilerFn, EvmCompilerFnInfo, EvmCompilerFns, RevmcError};
+#[cfg(feature = "loader")]
+use revm_primitives::keccak256;
+use revm_primitives::{hex, Address, SpecId, B256};
+#[cfg(feature = "loader")]
+use rustc_hash::FxHashMap;
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The name of the shared library of a bundle, without the extension.
+const BUNDLE_LIBRARY: &str = "contracts";
+/// The name of the manifest of a bundle.
+const BUNDLE_MANIFEST: &str = "manifest.txt";
+/// The first line of a bundle manifest. Bundles are only loaded by the same version of this crate.
+const BUNDLE_HEADER: &str = concat!("revmc-bundle ", env!("CARGO_PKG_VERSION"));
+
+/// Returns the name of the symbol that the function compiled for the bytecode with the given
+/// code hash is exported as, which is `revmc_` followed by the hex-encoded hash.
+///
+/// Use this as the function name in [`compile_to_cdylib`](crate::EvmCompiler::compile_to_cdylib)
+/// to be able to look up the functions by code hash once the library is loaded.
+pub fn code_hash_symbol_name(code_hash: &B256) -> String {
+    format!("revmc_{}", hex::encode(code_hash))
+}
+
+/// A shared library of compiled functions loaded at runtime.
+///
+/// This allows shipping contracts that were compiled ahead of time with
+/// [`compile_to_cdylib`](crate::EvmCompiler::compile_to_cdylib) and executing them without
+/// initializing a codegen backend.
+///
+/// The library is unloaded when this is dropped, so the functions returned by it must not be
+/// called afterwards.
+#[cfg(feature = "loader")]
+#[derive(Debug)]
+pub struct EvmCompilerLibrary {
+    lib: libloading::Library,
+}
+
+#[cfg(feature = "loader")]
+impl EvmCompilerLibrary {
+    /// Loads the shared library at `path`.
+    ///
+    /// The same rules as in [`compile_to_cdylib`](crate::EvmCompiler::compile_to_cdylib) apply
+    /// for resolving the builtins called by the functions.
+    ///
+    /// # Safety
+    ///
+    /// Loading a library runs its initialization routines. The library must have been compiled
+    /// with a compatible version of this crate, as the exported symbols are assumed to have the
+    /// [`EvmCompilerFn`](crate::EvmCompilerFn) ABI.
+    pub unsafe fn open(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let path = path.as_ref();
+        match unsafe { libloading::Library::new(path) } {
+            Ok(lib) => Ok(Self { lib }),
+            Err(e) => Err(RevmcError::Link(format!("{}: {e}", path.display())).into()),
+        }
+    }
+
+    /// Returns the function exported as `name`, if any.
+    pub fn get(&self, name: &str) -> Option<EvmCompilerFn> {
+        let f = unsafe { self.lib.get::<EvmCompilerFn>(name.as_bytes()) };
+        f.ok().map(|f| *f)
+    }
+
+    /// Returns the function compiled for the bytecode with the given code hash, if any.
+    ///
+    /// See [`code_hash_symbol_name`].
+    pub fn get_by_code_hash(&self, code_hash: &B256) -> Option<EvmCompilerFn> {
+        self.get(&code_hash_symbol_name(code_hash))
+    }
+}
+
+/// A disk-backed cache of compiled functions.
+///
+/// Every function is compiled into its own shared library in the cache directory, keyed by
+/// `keccak256(bytecode)`, the [`SpecId`], and a fingerprint of the compiler's configuration and
+/// the version of this crate. The libraries are kept across restarts, so only the first
+/// compilation of a contract pays for the codegen backend.
+///
+/// The fingerprint does not include the configuration of the backend itself, such as the target,
+/// so a cache directory must not be shared between differently configured backends.
+#[cfg(feature = "loader")]
+#[derive(Debug)]
+pub struct LibraryCache {
+    dir: PathBuf,
+    libraries: FxHashMap<B256, EvmCompilerLibrary>,
+    hits: u64,
+    misses: u64,
+}
+
+#[cfg(feature = "loader")]
+impl LibraryCache {
+    /// Creates a new cache in the given directory, which is created if it does not exist.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into(), libraries: FxHashMap::default(), hits: 0, misses: 0 }
+    }
+
+    /// Returns the cache directory.
+    pub fn dir(&self) -> &std::path::Path {
+        &self.dir
+    }
+
+    /// Returns the number of functions that were found in the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Returns the number of functions that had to be compiled.
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Returns the function compiled for `bytecode`, either by loading it from the cache directory
+    /// or by compiling it with `compiler` and then storing the result.
+    ///
+    /// `compiler` must be in AOT mode and is [cleared](EvmCompiler::clear) after compiling.
+    /// Returns an [`InvalidUsage`](RevmcError::InvalidUsage) error if a
+    /// [panic handler](EvmCompiler::set_panic_handler) is set, as its address is not stable across
+    /// restarts.
+    ///
+    /// # Safety
+    ///
+    /// See [`EvmCompilerLibrary::open`]. The returned function must not be called after the cache
+    /// is dropped.
+    pub unsafe fn get_or_compile<B: Backend>(
+        &mut self,
+        compiler: &mut EvmCompiler<B>,
+        bytecode: &[u8],
+        spec_id: SpecId,
+    ) -> Result<EvmCompilerFn> {
+        let code_hash = keccak256(bytecode);
+        let key = compiler.library_cache_key(&code_hash, spec_id)?;
+        if let Some(lib) = self.libraries.get(&key) {
+            self.hits += 1;
+            return Ok(lib.get_by_code_hash(&code_hash).expect("cached library without function"));
+        }
+
+        let key_hex = hex::encode(key);
+        let path = self.dir.join(&key_hex).with_extension(std::env::consts::DLL_EXTENSION);
+        if path.exists() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+            fs::create_dir_all(&self.dir)?;
+            // Link to a temporary file first so that a partially written library is never loaded,
+            // e.g. by another process using the same directory.
+            let tmp = self.dir.join(format!("{key_hex}.{}.tmp", std::process::id()));
+            let name = code_hash_symbol_name(&code_hash);
+            let compiled =
+                compiler.compile_to_cdylib([(&name[..], bytecode.into(), spec_id)], &tmp);
+            unsafe { compiler.clear()? };
+            compiled?;
+            fs::rename(&tmp, &path)?;
+        }
+
+        let lib = unsafe { EvmCompilerLibrary::open(&path)? };
+        let Some(f) = lib.get_by_code_hash(&code_hash) else {
+            return Err(RevmcError::Link(format!("{}: missing function", path.display())).into());
+        };
+        self.libraries.insert(key, lib);
+        Ok(f)
+    }
+}
+
+/// A contract in a bundle. See [`compile_bundle`](crate::EvmCompiler::compile_bundle).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BundleContract {
+    /// The address of the contract.
+    pub address: Address,
+    /// The code hash of the contract, which its function is exported by, see
+    /// [`code_hash_symbol_name`].
+    pub code_hash: B256,
+    /// The `SpecId` the contract was compiled for.
+    pub spec_id: SpecId,
+}
+
+impl BundleContract {
+    fn parse(line: &str) -> Option<Self> {
+        let [address, code_hash, spec_id] = line.split(' ').collect::<Vec<_>>()[..] else {
+            return None;
+        };
+        Some(Self {
+            address: address.parse().ok()?,
+            code_hash: code_hash.parse().ok()?,
+            spec_id: SpecId::try_from_u8(spec_id.parse().ok()?)?,
+        })
+    }
+}
+
+/// Returns the paths of the shared library and of the manifest of the bundle in `dir`.
+pub(crate) fn bundle_paths(dir: &Path) -> (PathBuf, PathBuf) {
+    let library = dir.join(BUNDLE_LIBRARY).with_extension(std::env::consts::DLL_EXTENSION);
+    (library, dir.join(BUNDLE_MANIFEST))
+}
+
+/// Writes a bundle manifest listing the given contracts, one per line.
+pub(crate) fn write_bundle_manifest(path: &Path, contracts: &[BundleContract]) -> Result<()> {
+    let mut manifest = format!("{BUNDLE_HEADER}\n");
+    for c in contracts {
+        let address = hex::encode_prefixed(c.address);
+        let code_hash = hex::encode_prefixed(c.code_hash);
+        writeln!(manifest, "{address} {code_hash} {}", c.spec_id as u8).unwrap();
+    }
+    fs::write(path, manifest)?;
+    Ok(())
+}
+
+/// A bundle of contracts compiled ahead of time, loaded at runtime.
+///
+/// A bundle is produced by [`compile_bundle`](crate::EvmCompiler::compile_bundle), and contains
+/// the functions of all of its contracts in a single shared library, which is unloaded when this
+/// is dropped.
+#[cfg(feature = "loader")]
+#[derive(Debug)]
+pub struct EvmCompilerBundle {
+    lib: EvmCompilerLibrary,
+    contracts: Vec<BundleContract>,
+}
+
+#[cfg(feature = "loader")]
+impl EvmCompilerBundle {
+    /// Loads the bundle in the directory `dir`.
+    ///
+    /// Returns a [`Link`](RevmcError::Link) error if the bundle was produced by a different
+    /// version of this crate, or if the function of any of its contracts is missing.
+    ///
+    /// # Safety
+    ///
+    /// See [`EvmCompilerLibrary::open`].
+    pub unsafe fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let (library, manifest) = bundle_paths(dir.as_ref());
+        let invalid = |msg: String| RevmcError::Link(format!("{}: {msg}", manifest.display()));
+        let contents = fs::read_to_string(&manifest)?;
+        let mut lines = contents.lines();
+        if lines.next() != Some(BUNDLE_HEADER) {
+            return Err(invalid("incompatible bundle version".into()).into());
+        }
+        let contracts = lines
+            .map(|line| BundleContract::parse(line).ok_or_else(|| invalid(format!("`{line}`"))))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let lib = unsafe { EvmCompilerLibrary::open(&library)? };
+        if let Some(c) = contracts.iter().find(|c| lib.get_by_code_hash(&c.code_hash).is_none()) {
+            return Err(invalid(format!("missing function for {}", c.address)).into());
+        }
+        Ok(Self { lib, contracts })
+    }
+
+    /// Returns the contracts of the bundle.
+    pub fn contracts(&self) -> &[BundleContract] {
+        &self.contracts
+    }
+
+    /// Returns the function of the contract at `address`, if any.
+    pub fn get(&self, address: &Address) -> Option<EvmCompilerFnInfo> {
+        let c = self.contracts.iter().find(|c| c.address == *address)?;
+        self.info(c)
+    }
+
+    /// Inserts the functions of all the contracts into `fns`, keyed by code hash and `SpecId`.
+    ///
+    /// # Safety
+    ///
+    /// The functions must be removed from `fns` before the bundle is dropped. See
+    /// [`EvmCompilerFns::insert`].
+    pub unsafe fn register(&self, fns: &EvmCompilerFns) {
+        for c in &self.contracts {
+            let info = self.info(c).expect("checked when opening");
+            unsafe { fns.insert(c.code_hash, info) };
+        }
+    }
+
+    fn info(&self, c: &BundleContract) -> Option<EvmCompilerFnInfo> {
+        let f = self.lib.get_by_code_hash(&c.code_hash)?;
+        Some(EvmCompilerFnInfo { f, spec_id: c.spec_id })
+    }
+}
@@ -89,7 +89,9 @@ impl Linker {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use revm_primitives::SpecId;
+    use crate::tests::with_evm_context;
+    use revm_interpreter::{opcode as op, InstructionResult};
+    use revm_primitives::{SpecId, U256};
 
     #[test]
     fn basic() {
@@ -130,6 +132,180 @@ mod tests {
         assert!(n > 0, "no C compiler found");
     }
 
+    #[test]
+    fn cdylib() {
+        if !command_v("cc") || !command_v("ld.lld") {
+            eprintln!("skipping: no linker found");
+            return;
+        }
+
+        let tmp = tempfile::tempdir().expect("could not create temp dir");
+        let so = tmp.path().join("contracts.so");
+
+        let add: &[u8] = &[op::PUSH1, 0x42, op::PUSH1, 0x69, op::ADD, op::STOP];
+        let empty: &[u8] = &[];
+        // Calls the memory expansion and `KECCAK256` builtins, which are resolved when loading.
+        let keccak: &[u8] = &[op::PUSH1, 0x20, op::PUSH0, op::KECCAK256, op::STOP];
+
+        let cx = crate::llvm::inkwell::context::Context::create();
+        let opt_level = revmc_backend::OptimizationLevel::Aggressive;
+        let backend = crate::EvmLlvmBackend::new(&cx, true, opt_level).unwrap();
+        let mut compiler = crate::EvmCompiler::new(backend);
+        let items = [
+            ("cdylib_add", add.into(), SpecId::CANCUN),
+            ("cdylib_empty", empty.into(), SpecId::CANCUN),
+            ("cdylib_keccak", keccak.into(), SpecId::CANCUN),
+        ];
+        if let Err(e) = compiler.compile_to_cdylib(items, &so) {
+            panic!("failed to compile: {e}");
+        }
+        assert!(so.exists());
+
+        let lib = unsafe { libloading::Library::new(&so) }.expect("failed to load library");
+        let load = |name: &str| -> crate::EvmCompilerFn {
+            *unsafe { lib.get::<crate::EvmCompilerFn>(name.as_bytes()) }.expect("missing symbol")
+        };
+
+        let f = load("cdylib_add");
+        with_evm_context(add, |ecx, stack, stack_len| {
+            let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+            assert_eq!(r, InstructionResult::Stop);
+            assert_eq!(*stack_len, 1);
+            assert_eq!(stack.as_slice()[0].to_u256(), U256::from(0x42 + 0x69));
+        });
+
+        let f = load("cdylib_empty");
+        with_evm_context(empty, |ecx, stack, stack_len| {
+            let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+            assert_eq!(r, InstructionResult::Stop);
+            assert_eq!(*stack_len, 0);
+        });
+
+        let f = load("cdylib_keccak");
+        with_evm_context(keccak, |ecx, stack, stack_len| {
+            let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+            assert_eq!(r, InstructionResult::Stop);
+            assert_eq!(*stack_len, 1);
+            let expected = revm_primitives::keccak256([0; 32]);
+            assert_eq!(stack.as_slice()[0].to_u256(), U256::from_be_bytes(expected.0));
+        });
+    }
+
+    #[cfg(feature = "loader")]
+    #[test]
+    fn cdylib_by_code_hash() {
+        if !command_v("cc") || !command_v("ld.lld") {
+            eprintln!("skipping: no linker found");
+            return;
+        }
+
+        let tmp = tempfile::tempdir().expect("could not create temp dir");
+        let so = tmp.path().join("contracts.so");
+
+        let add: &[u8] = &[op::PUSH1, 0x42, op::PUSH1, 0x69, op::ADD, op::STOP];
+        let hash = revm_primitives::keccak256(add);
+        let name = crate::code_hash_symbol_name(&hash);
+
+        let cx = crate::llvm::inkwell::context::Context::create();
+        let opt_level = revmc_backend::OptimizationLevel::Aggressive;
+        let backend = crate::EvmLlvmBackend::new(&cx, true, opt_level).unwrap();
+        let mut compiler = crate::EvmCompiler::new(backend);
+        if let Err(e) = compiler.compile_to_cdylib([(&name[..], add.into(), SpecId::CANCUN)], &so) {
+            panic!("failed to compile: {e}");
+        }
+
+        let lib = unsafe { crate::EvmCompilerLibrary::open(&so) }.expect("failed to load library");
+        assert!(lib.get_by_code_hash(&revm_primitives::B256::ZERO).is_none());
+        let f = lib.get_by_code_hash(&hash).expect("missing symbol");
+        with_evm_context(add, |ecx, stack, stack_len| {
+            let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+            assert_eq!(r, InstructionResult::Stop);
+            assert_eq!(*stack_len, 1);
+            assert_eq!(stack.as_slice()[0].to_u256(), U256::from(0x42 + 0x69));
+        });
+    }
+
+    #[cfg(feature = "loader")]
+    #[test]
+    fn library_cache() {
+        if !command_v("cc") || !command_v("ld.lld") {
+            eprintln!("skipping: no linker found");
+            return;
+        }
+
+        let tmp = tempfile::tempdir().expect("could not create temp dir");
+        let add: &[u8] = &[op::PUSH1, 0x42, op::PUSH1, 0x69, op::ADD, op::STOP];
+
+        let cx = crate::llvm::inkwell::context::Context::create();
+        let opt_level = revmc_backend::OptimizationLevel::Aggressive;
+        let backend = crate::EvmLlvmBackend::new(&cx, true, opt_level).unwrap();
+        let mut compiler = crate::EvmCompiler::new(backend);
+        let get = |compiler: &mut crate::EvmCompiler<_>| {
+            // A new cache for every call, as after a restart.
+            let mut cache = crate::LibraryCache::new(tmp.path());
+            let f = unsafe { cache.get_or_compile(compiler, add, SpecId::CANCUN) }.unwrap();
+            with_evm_context(add, |ecx, stack, stack_len| {
+                let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+                assert_eq!(r, InstructionResult::Stop);
+                assert_eq!(*stack_len, 1);
+                assert_eq!(stack.as_slice()[0].to_u256(), U256::from(0x42 + 0x69));
+            });
+            (cache.hits(), cache.misses())
+        };
+
+        assert_eq!(get(&mut compiler), (0, 1));
+        assert_eq!(get(&mut compiler), (1, 0));
+        // A different configuration is compiled separately.
+        compiler.gas_metering(false);
+        assert_eq!(get(&mut compiler), (0, 1));
+        compiler.gas_metering(true);
+        assert_eq!(get(&mut compiler), (1, 0));
+    }
+
+    #[cfg(feature = "loader")]
+    #[test]
+    fn bundle() {
+        if !command_v("cc") || !command_v("ld.lld") {
+            eprintln!("skipping: no linker found");
+            return;
+        }
+
+        let tmp = tempfile::tempdir().expect("could not create temp dir");
+        let add: &[u8] = &[op::PUSH1, 0x42, op::PUSH1, 0x69, op::ADD, op::STOP];
+        let empty: &[u8] = &[];
+        let [a, b, c] = [1, 2, 3].map(revm_primitives::Address::repeat_byte);
+        // Contracts with the same code share their function.
+        let contracts = [(a, add), (b, empty), (c, add)];
+
+        let cx = crate::llvm::inkwell::context::Context::create();
+        let opt_level = revmc_backend::OptimizationLevel::Aggressive;
+        let backend = crate::EvmLlvmBackend::new(&cx, true, opt_level).unwrap();
+        let mut compiler = crate::EvmCompiler::new(backend);
+        if let Err(e) = compiler.compile_bundle(&contracts, SpecId::CANCUN, tmp.path()) {
+            panic!("failed to compile: {e}");
+        }
+
+        let bundle = unsafe { crate::EvmCompilerBundle::open(tmp.path()) }.expect("failed to load");
+        assert_eq!(bundle.contracts().len(), 3);
+        assert_eq!(bundle.contracts()[1].code_hash, revm_primitives::keccak256(empty));
+        assert!(bundle.get(&revm_primitives::Address::ZERO).is_none());
+        assert_eq!(bundle.get(&a), bundle.get(&c));
+        let info = bundle.get(&a).expect("missing contract");
+        assert_eq!(info.spec_id, SpecId::CANCUN);
+        with_evm_context(add, |ecx, stack, stack_len| {
+            let r = unsafe { info.f.call(Some(stack), Some(stack_len), ecx) };
+            assert_eq!(r, InstructionResult::Stop);
+            assert_eq!(*stack_len, 1);
+            assert_eq!(stack.as_slice()[0].to_u256(), U256::from(0x42 + 0x69));
+        });
+
+        let fns = crate::EvmCompilerFns::new();
+        unsafe { bundle.register(&fns) };
+        assert_eq!(fns.len(), 2);
+        assert_eq!(fns.get(&revm_primitives::keccak256(add), info.spec_id), Some(info));
+        fns.clear();
+    }
+
     fn command_v(cmd: &str) -> bool {
         let Ok(output) = std::process::Command::new(cmd).arg("--version").output() else {
             return false;
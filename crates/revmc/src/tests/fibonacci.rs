@@ -14,6 +14,11 @@ macro_rules! fibonacci_tests {
 }
 
 fibonacci_tests!(0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 20, 30, 40, 50, 100, 1000);
+matrix_tests!(jit_both = |jit| run_fibonacci_both_test(jit));
+matrix_tests!(loops = |jit| run_fibonacci_loops_test(jit));
+matrix_tests!(jump_dests = |jit| run_fibonacci_jump_dests_test(jit));
+#[cfg(feature = "bench")]
+matrix_tests!(compare_throughput = |jit| run_fibonacci_throughput_test(jit));
 
 fn run_fibonacci_test<B: Backend>(compiler: &mut EvmCompiler<B>, input: u16, dynamic: bool) {
     let code = mk_fibonacci_code(input, dynamic);
@@ -35,6 +40,67 @@ fn run_fibonacci_test<B: Backend>(compiler: &mut EvmCompiler<B>, input: u16, dyn
     });
 }
 
+fn run_fibonacci_both_test<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    let code = mk_fibonacci_code(0, true);
+
+    unsafe { compiler.clear() }.unwrap();
+    compiler.inspect_stack_length(true);
+    let (unopt, opt) = unsafe { compiler.jit_both("fib", &code, DEF_SPEC) }.unwrap();
+
+    for input in [0, 1, 2, 10, 100, 1000] {
+        let run = |f: crate::EvmCompilerFn| {
+            with_evm_context(&code, |ecx, stack, stack_len| {
+                stack.as_mut_slice()[0] = U256::from(input).into();
+                *stack_len = 1;
+                let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+                (r, stack.as_slice()[..*stack_len].to_vec(), ecx.gas_report())
+            })
+        };
+        let unopt = run(unopt);
+        assert_eq!(unopt.0, InstructionResult::Stop);
+        assert_eq!(unopt.1[0].to_u256(), fibonacci_rust(input + 1));
+        assert_eq!(unopt, run(opt), "input {input}");
+    }
+}
+
+fn run_fibonacci_loops_test<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    for dynamic in [false, true] {
+        let code = mk_fibonacci_code(10, dynamic);
+        let bytecode = compiler.parse(code.as_slice().into(), DEF_SPEC).unwrap();
+        // `MAINLOOP` is at pc 7, and is jumped back to from the `JUMP` at pc 27.
+        let loops = bytecode.loops();
+        assert_eq!(loops.len(), 1, "{loops:?}");
+        assert_eq!(loops[0].header_pc, 7);
+        assert_eq!(loops[0].back_edge_pc, 27);
+        assert_eq!(loops[0].body_size, 21);
+    }
+}
+
+fn run_fibonacci_jump_dests_test<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    // `MAINLOOP` and `CLEANUP`, plus the `JUMPDEST`s at the start of the dynamic code.
+    for (dynamic, expected) in [(false, &[7, 28][..]), (true, &[0, 1, 2, 7, 28][..])] {
+        let code = mk_fibonacci_code(10, dynamic);
+        let bytecode = compiler.parse(code.as_slice().into(), DEF_SPEC).unwrap();
+        assert_eq!(bytecode.jump_dests().collect::<Vec<_>>(), expected, "dynamic: {dynamic}");
+    }
+}
+
+#[cfg(feature = "bench")]
+fn run_fibonacci_throughput_test<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    use super::TestHost;
+    use crate::{bench::compare_throughput, EvmContextSnapshot};
+
+    let code = mk_fibonacci_code(100, false);
+    unsafe { compiler.clear() }.unwrap();
+    let f = unsafe { compiler.jit("fib", &code, DEF_SPEC) }.unwrap();
+    let input = EvmContextSnapshot::new(TestHost::new(), 1_000_000);
+    let throughput = unsafe { compare_throughput(f, &code, DEF_SPEC, &input, 10) };
+    assert_eq!(throughput.jit_result, InstructionResult::Stop, "{throughput:?}");
+    assert_eq!(throughput.interp_result, InstructionResult::Stop, "{throughput:?}");
+    assert!(throughput.jit_ns_per_call > 0.0, "{throughput:?}");
+    assert!(throughput.interp_ns_per_call > 0.0, "{throughput:?}");
+}
+
 fn mk_fibonacci_code(input: u16, dynamic: bool) -> Vec<u8> {
     if dynamic {
         [&[op::JUMPDEST; 3][..], FIBONACCI_CODE].concat()
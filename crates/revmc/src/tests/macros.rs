@@ -57,24 +57,50 @@ macro_rules! build_push32 {
 }
 
 macro_rules! tests {
-    ($($group:ident { $($t:tt)* })*) => { uint! {
+    ($($group:ident $(@also($variant:ident = |$c:ident| $setup:expr))? { $($t:tt)* })*) => { uint! {
         $(
-            mod $group {
-                use super::*;
-                #[allow(unused_imports)]
-                use similar_asserts::assert_eq;
-
-                tests!(@cases $($t)*);
-            }
+            tests!(@group $group ($($variant = |$c| $setup)?) { $($t)* });
         )*
     }};
 
+    (@group $group:ident () { $($t:tt)* }) => {
+        mod $group {
+            use super::*;
+            #[allow(unused_imports)]
+            use similar_asserts::assert_eq;
+
+            tests!(@cases $($t)*);
+        }
+    };
+
+    // Also runs all the cases of the group with `$setup` applied to the compiler.
+    (@group $group:ident ($variant:ident = |$c:ident| $setup:expr) { $($t:tt)* }) => {
+        tests!(@group $group () { $($t)* });
+
+        mod $variant {
+            use super::*;
+            #[allow(unused_imports)]
+            use similar_asserts::assert_eq;
+
+            tests!(@cases_with |$c| $setup; $($t)*);
+        }
+    };
+
     (@cases $( $name:ident($($t:tt)*) ),* $(,)?) => {
         $(
             matrix_tests!($name = |jit| run_test_case(tests!(@case $($t)*), jit));
         )*
     };
 
+    (@cases_with |$c:ident| $setup:expr; $( $name:ident($($t:tt)*) ),* $(,)?) => {
+        $(
+            matrix_tests!($name = |$c| {
+                $setup;
+                run_test_case(tests!(@case $($t)*), $c)
+            });
+        )*
+    };
+
     (@case @raw { $($fields:tt)* }) => { &TestCase { $($fields)* ..Default::default() } };
 
     (@case $op:expr $(, $args:expr)* $(,)? => $($ret:expr),* $(,)? $(; op_gas($op_gas:expr))?) => {
@@ -1,9 +1,51 @@
-use super::with_evm_context;
-use crate::{Backend, EvmCompiler};
-use revm_interpreter::InstructionResult;
-use revm_primitives::SpecId;
+use super::{
+    capture_panics, eof, error_kind, take_panic_message, with_evm_context, TestHost, OTHER_ADDR,
+};
+use crate::{
+    AnalysisCache, Backend, EvmCompiler, EvmCompilerArgs, EvmContextSnapshot, GasReport,
+    JumpDispatch, RevmcError, UnrollBudget,
+};
+use revm_interpreter::{opcode as op, Gas, Host, InstructionResult};
+use revm_primitives::{hex, keccak256, spec_to_generic, Address, SpecId};
 
 matrix_tests!(translate_then_compile);
+matrix_tests!(gas_report);
+matrix_tests!(analysis_cache);
+matrix_tests!(call_args);
+matrix_tests!(split_validation);
+matrix_tests!(panic_handler);
+matrix_tests!(gas_invariant_checks);
+matrix_tests!(precompile_addresses);
+matrix_tests!(stack_access_checks);
+matrix_tests!(dup_swap_access_checks);
+matrix_tests!(unroll_small_loops);
+matrix_tests!(presize_memory);
+matrix_tests!(error_kinds);
+matrix_tests!(allowed_opcodes);
+matrix_tests!(chainid_width);
+matrix_tests!(dump_manifest);
+matrix_tests!(large_jump_table);
+matrix_tests!(interpreter_exact_gas);
+matrix_tests!(local_stack_lifetimes);
+matrix_tests!(pinned_opcode_infos);
+matrix_tests!(jit_verified);
+matrix_tests!(comment_sink);
+matrix_tests!(deprecated_opcodes);
+matrix_tests!(saturating_gas);
+matrix_tests!(stack_error_results);
+matrix_tests!(revert_range);
+matrix_tests!(reentrant_host);
+matrix_tests!(jit_pure);
+matrix_tests!(create_resume);
+matrix_tests!(compiled_fn_cache);
+matrix_tests!(custom_opcodes);
+matrix_tests!(builtin_success);
+matrix_tests!(elide_dead_stack_stores);
+matrix_tests!(selector_entries);
+matrix_tests!(translate_many);
+matrix_tests!(jit_after_finalize);
+#[cfg(feature = "revm")]
+matrix_tests!(mid_frame_swap);
 
 // Also tests multiple functions in the same module.
 fn translate_then_compile<B: Backend>(compiler: &mut EvmCompiler<B>) {
@@ -22,3 +64,1634 @@ fn translate_then_compile<B: Backend>(compiler: &mut EvmCompiler<B>) {
         assert_eq!(r, InstructionResult::Stop);
     });
 }
+
+fn gas_report<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    // Clears slot 69, which is set in the test host, generating a refund.
+    let bytecode: &[u8] = &[op::PUSH0, op::PUSH1, 69, op::SSTORE];
+    let spec_id = SpecId::CANCUN;
+    let f = unsafe { compiler.jit("gas_report", bytecode, spec_id) }.unwrap();
+    with_evm_context(bytecode, |ecx, stack, stack_len| {
+        let table = spec_to_generic!(spec_id, op::make_instruction_table::<_, SPEC>());
+        let mut interpreter = ecx.to_interpreter(Default::default());
+        let memory = interpreter.take_memory();
+        interpreter.run(memory, &table, &mut TestHost::new());
+        assert_eq!(interpreter.instruction_result, InstructionResult::Stop);
+        let expected = GasReport::from(interpreter.gas);
+
+        let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::Stop);
+        let report = ecx.gas_report();
+        assert_eq!(report, expected);
+        assert!(report.gas_refunded > 0, "{report:?}");
+        assert_eq!(report.gas_remaining + report.gas_spent, ecx.gas.limit());
+    });
+}
+
+fn analysis_cache<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    let bytecode: &[u8] = &[op::PUSH1, 3, op::JUMP, op::JUMPDEST, op::PUSH1, 0x69, op::STOP];
+    let spec_id = SpecId::CANCUN;
+    compiler.set_analysis_cache(Some(AnalysisCache::new()));
+
+    compiler.translate("cached1", bytecode, spec_id).unwrap();
+    let cache = compiler.analysis_cache().unwrap();
+    assert_eq!((cache.len(), cache.hits(), cache.misses()), (1, 0, 1));
+
+    let id = compiler.translate("cached2", bytecode, spec_id).unwrap();
+    let cache = compiler.analysis_cache().unwrap();
+    assert_eq!((cache.len(), cache.hits(), cache.misses()), (1, 1, 1));
+
+    // Different spec, different analysis.
+    compiler.translate("cached3", bytecode, SpecId::LONDON).unwrap();
+    let cache = compiler.analysis_cache().unwrap();
+    assert_eq!((cache.len(), cache.hits(), cache.misses()), (2, 1, 2));
+
+    let f = unsafe { compiler.jit_function(id) }.unwrap();
+    with_evm_context(bytecode, |ecx, stack, stack_len| {
+        let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::Stop);
+        assert_eq!(*stack_len, 1);
+        assert_eq!(stack.as_slice()[0].to_u256(), revm_primitives::U256::from(0x69));
+    });
+}
+
+fn call_args<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    let bytecode: &[u8] = &[op::PUSH1, 0x42, op::PUSH1, 0x69, op::ADD, op::GAS, op::STOP];
+    let f = unsafe { compiler.jit("call_args", bytecode, SpecId::CANCUN) }.unwrap();
+
+    let positional = with_evm_context(bytecode, |ecx, stack, stack_len| {
+        let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+        (r, stack.as_slice()[..*stack_len].to_vec(), ecx.gas_report())
+    });
+    let named = with_evm_context(bytecode, |ecx, stack, stack_len| {
+        let args = EvmCompilerArgs::new(Some(stack), Some(stack_len), ecx);
+        let r = unsafe { f.call_args(args) };
+        (r, stack.as_slice()[..*stack_len].to_vec(), ecx.gas_report())
+    });
+    assert_eq!(positional.0, InstructionResult::Stop);
+    assert_eq!(positional.1.len(), 2);
+    assert_eq!(positional, named);
+}
+
+fn split_validation<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    let bytecode: &[u8] = &[op::STOP];
+    compiler.debug_assertions(true);
+    compiler.split_validation(true);
+    let hot = compiler.translate("hot", bytecode, SpecId::CANCUN).unwrap();
+    let validate = compiler.translate_validation("validate", bytecode, SpecId::CANCUN).unwrap();
+    let hot = unsafe { compiler.jit_function(hot) }.unwrap();
+    let validate = unsafe { compiler.jit_function(validate) }.unwrap();
+    with_evm_context(bytecode, |ecx, stack, stack_len| {
+        let r = unsafe { validate.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::Continue);
+
+        // The stack pointer is required, but not checked in the hot function.
+        let r = unsafe { hot.call(None, None, ecx) };
+        assert_eq!(r, InstructionResult::Stop);
+    });
+}
+
+fn panic_handler<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    let bytecode: &[u8] = &[op::STOP];
+    compiler.debug_assertions(true);
+    capture_panics(compiler);
+    let f = unsafe { compiler.jit("panic_handler", bytecode, SpecId::CANCUN) }.unwrap();
+    with_evm_context(bytecode, |ecx, _stack, _stack_len| {
+        // The stack pointer is required.
+        let r = unsafe { f.call(None, None, ecx) };
+        assert_eq!(r, InstructionResult::FatalExternalError);
+    });
+    let msg = take_panic_message();
+    assert!(msg.contains("stack pointer must not be null"), "{msg}");
+
+    // Panics in the body return through the return block, which writes back the stack length.
+    let bytecode: &[u8] = &[op::PUSH0, op::PUSH0, op::JUMPDEST, op::DUP3, op::STOP];
+    compiler.inspect_stack_length(true);
+    unsafe { compiler.stack_bound_checks(false) };
+    compiler.stack_access_checks(true);
+    let f = unsafe { compiler.jit("panic_handler_body", bytecode, SpecId::CANCUN) }.unwrap();
+    with_evm_context(bytecode, |ecx, stack, stack_len| {
+        let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::FatalExternalError);
+        assert_eq!(*stack_len, 2);
+    });
+    let msg = take_panic_message();
+    assert!(msg.contains("stack index out of bounds"), "{msg}");
+}
+
+fn stack_access_checks<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    let bytecode: &[u8] = &[op::PUSH0, op::PUSH1, 1, op::ADD, op::STOP];
+    compiler.debug_assertions(true);
+    compiler.inspect_stack_length(true);
+    // Don't catch the bad stack length before the stack is accessed.
+    unsafe { compiler.stack_bound_checks(false) };
+    compiler.stack_access_checks(true);
+    capture_panics(compiler);
+    let f = unsafe { compiler.jit("stack_access_checks", bytecode, SpecId::CANCUN) }.unwrap();
+
+    with_evm_context(bytecode, |ecx, stack, stack_len| {
+        let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::Stop);
+        assert_eq!(*stack_len, 1);
+    });
+
+    // Start with a stack length past the end of the stack.
+    with_evm_context(bytecode, |ecx, stack, stack_len| {
+        *stack_len = 2000;
+        let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::FatalExternalError);
+    });
+    let msg = take_panic_message();
+    assert!(msg.contains("stack index out of bounds"), "{msg}");
+}
+
+fn dup_swap_access_checks<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    let bytecode: &[u8] = &[op::DUP2, op::SWAP2, op::STOP];
+    compiler.debug_assertions(true);
+    compiler.inspect_stack_length(true);
+    // Don't catch the stack underflow before the stack is accessed.
+    unsafe { compiler.stack_bound_checks(false) };
+    compiler.stack_access_checks(true);
+    capture_panics(compiler);
+    let f = unsafe { compiler.jit("dup_swap_access_checks", bytecode, SpecId::CANCUN) }.unwrap();
+
+    with_evm_context(bytecode, |ecx, stack, stack_len| {
+        stack.as_mut_slice()[0] = revm_primitives::U256::from(1).into();
+        stack.as_mut_slice()[1] = revm_primitives::U256::from(2).into();
+        *stack_len = 2;
+        let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::Stop);
+        assert_eq!(*stack_len, 3);
+        let stack = stack.as_slice()[..3].iter().map(|x| x.to_u256()).collect::<Vec<_>>();
+        assert_eq!(stack, [1, 2, 1].map(revm_primitives::U256::from));
+    });
+
+    // The index of the `DUP2` operand does not match the stack length.
+    with_evm_context(bytecode, |ecx, stack, stack_len| {
+        *stack_len = 1;
+        let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::FatalExternalError);
+    });
+    let msg = take_panic_message();
+    assert!(msg.contains("stack index from the top out of bounds"), "{msg}");
+}
+
+fn unroll_small_loops<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    // `acc = 0; for (i = count; i != 0; i -= step) acc += 3;`
+    #[rustfmt::skip]
+    let counter_loop = |count: u8, step: u8| -> Vec<u8> {
+        vec![
+            op::PUSH0,
+            op::PUSH1, count,
+            op::JUMPDEST,
+            op::SWAP1, op::PUSH1, 3, op::ADD, op::SWAP1,
+            op::PUSH1, step, op::SWAP1, op::SUB,
+            op::DUP1, op::PUSH1, 3, op::JUMPI,
+            op::STOP,
+        ]
+    };
+    // The loop is unrolled, isn't unrolled as it is not decremented by one, and exceeds the budget.
+    let cases = [(5, 1, 15), (6, 2, 9), (200, 1, 600)];
+
+    for (i, &(count, step, expected)) in cases.iter().enumerate() {
+        let bytecode = counter_loop(count, step);
+        compiler.unroll_small_loops(None);
+        let looped = compiler.translate(&format!("looped{i}"), &bytecode, SpecId::CANCUN).unwrap();
+        compiler.unroll_small_loops(Some(UnrollBudget::default()));
+        let unrolled =
+            compiler.translate(&format!("unrolled{i}"), &bytecode, SpecId::CANCUN).unwrap();
+        let looped = unsafe { compiler.jit_function(looped) }.unwrap();
+        let unrolled = unsafe { compiler.jit_function(unrolled) }.unwrap();
+
+        let run = |f: crate::EvmCompilerFn| {
+            with_evm_context(&bytecode, |ecx, stack, stack_len| {
+                let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+                let stack =
+                    stack.as_slice()[..*stack_len].iter().map(|x| x.to_u256()).collect::<Vec<_>>();
+                (r, stack, ecx.gas_report())
+            })
+        };
+        let looped = run(looped);
+        assert_eq!(looped.0, InstructionResult::Stop);
+        assert_eq!(looped.1, [revm_primitives::U256::from(expected), revm_primitives::U256::ZERO]);
+        assert_eq!(looped, run(unrolled), "count={count} step={step}");
+        unsafe { compiler.clear() }.unwrap();
+    }
+}
+
+fn presize_memory<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    // The `MSTORE` is the target of the `RJUMPI`, which skips replacing its offset with 0.
+    #[rustfmt::skip]
+    let eof_code = eof(&[
+        op::PUSH1, 0x42, op::PUSH2, 0xff, 0xff, op::PUSH1, 0x01, op::RJUMPI, 0x00, 0x03,
+        op::POP, op::PUSH1, 0x00,
+        op::MSTORE,
+        op::STOP,
+    ]);
+    #[rustfmt::skip]
+    let bytecodes: [(&[u8], SpecId); 4] = [
+        (&[
+            op::PUSH1, 0x42, op::PUSH1, 0x20, op::MSTORE,
+            op::PUSH1, 0x69, op::PUSH2, 0x01, 0x00, op::MSTORE8,
+            op::PUSH1, 0x20, op::MLOAD,
+            op::PUSH1, 0x40, op::PUSH1, 0x00, op::RETURN,
+        ], SpecId::CANCUN),
+        (&[op::PUSH1, 0x80, op::MLOAD, op::PUSH0, op::PUSH0, op::REVERT], SpecId::CANCUN),
+        // Not statically bounded.
+        (&[op::PUSH0, op::CALLDATALOAD, op::MLOAD, op::MSIZE, op::STOP], SpecId::CANCUN),
+        (&eof_code, SpecId::PRAGUE_EOF),
+    ];
+
+    for (i, &(bytecode, spec_id)) in bytecodes.iter().enumerate() {
+        compiler.presize_memory(false);
+        let progressive =
+            compiler.translate(&format!("progressive{i}"), bytecode, spec_id).unwrap();
+        compiler.presize_memory(true);
+        let presized = compiler.translate(&format!("presized{i}"), bytecode, spec_id).unwrap();
+        let progressive = unsafe { compiler.jit_function(progressive) }.unwrap();
+        let presized = unsafe { compiler.jit_function(presized) }.unwrap();
+
+        let run = |f: crate::EvmCompilerFn| {
+            with_evm_context(bytecode, |ecx, stack, stack_len| {
+                let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+                let stack =
+                    stack.as_slice()[..*stack_len].iter().map(|x| x.to_u256()).collect::<Vec<_>>();
+                (r, stack, ecx.memory.context_memory().to_vec(), ecx.gas_report())
+            })
+        };
+        let progressive = run(progressive);
+        assert!(!progressive.2.is_empty());
+        assert_eq!(progressive, run(presized), "bytecode {i}");
+        unsafe { compiler.clear() }.unwrap();
+    }
+}
+
+fn error_kinds<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    let bytecode: &[u8] = &[op::STOP];
+
+    compiler.translate("same", bytecode, SpecId::CANCUN).unwrap();
+    match error_kind(compiler.translate("same", bytecode, SpecId::CANCUN).unwrap_err()) {
+        RevmcError::InvalidUsage(msg) => assert!(msg.contains("is not unique"), "{msg}"),
+        e => panic!("unexpected error: {e:?}"),
+    }
+
+    for name in ["", "1st", "-x", "with space", "transfer@v1", "tr\u{e4}nsfer"] {
+        match error_kind(compiler.translate(name, bytecode, SpecId::CANCUN).unwrap_err()) {
+            RevmcError::InvalidUsage(msg) => {
+                assert!(msg.contains("is not a valid symbol name"), "{msg}")
+            }
+            e => panic!("unexpected error: {e:?}"),
+        }
+    }
+    compiler.translate("weth_transfer_shanghai", bytecode, SpecId::SHANGHAI).unwrap();
+    compiler.translate("counter-eof.v2", bytecode, SpecId::CANCUN).unwrap();
+
+    let e = compiler.write_object(std::io::sink()).unwrap_err();
+    assert!(matches!(error_kind(e), RevmcError::InvalidUsage(_)));
+}
+
+#[cfg(feature = "llvm")]
+#[test]
+fn llvm_split_validation() {
+    crate::llvm::with_llvm_context(|cx| {
+        let opt_level = crate::OptimizationLevel::None;
+        let backend = crate::EvmLlvmBackend::new(cx, false, opt_level).unwrap();
+        let mut compiler = EvmCompiler::new(backend);
+        compiler.debug_assertions(true);
+        let bytecode: &[u8] = &[op::STOP];
+        let calls_panic = |compiler: &EvmCompiler<_>, name: &str| {
+            let f = compiler.backend().get_function_value(name).unwrap();
+            f.print_to_string().to_string().contains("@__revmc_builtin_panic(")
+        };
+
+        compiler.translate("checked", bytecode, SpecId::CANCUN).unwrap();
+        assert!(calls_panic(&compiler, "checked"));
+
+        compiler.split_validation(true);
+        compiler.translate("hot", bytecode, SpecId::CANCUN).unwrap();
+        compiler.translate_validation("validate", bytecode, SpecId::CANCUN).unwrap();
+        assert!(!calls_panic(&compiler, "hot"));
+        assert!(calls_panic(&compiler, "validate"));
+    });
+}
+
+#[cfg(feature = "llvm")]
+#[test]
+fn llvm_deterministic_ir() {
+    let bytecodes: [&[u8]; 3] = [
+        // Dynamic jumps.
+        &[
+            op::PUSH0,
+            op::CALLDATALOAD,
+            op::JUMP,
+            op::JUMPDEST,
+            op::PUSH1,
+            1,
+            op::PUSH1,
+            3,
+            op::JUMPI,
+            op::JUMPDEST,
+            op::STOP,
+        ],
+        // Builtins and failure blocks.
+        &[op::PUSH0, op::SLOAD, op::PUSH1, 0x20, op::MLOAD, op::ADD, op::PUSH0, op::SSTORE],
+        &[op::PUSH1, 6, op::JUMP, op::INVALID, op::JUMPDEST, op::JUMPDEST, op::GAS, op::STOP],
+    ];
+    let tmp = tempfile::tempdir().expect("could not create temp dir");
+    let dump = |i: usize| {
+        crate::llvm::with_llvm_context(|cx| {
+            let opt_level = crate::OptimizationLevel::None;
+            let backend = crate::EvmLlvmBackend::new(cx, false, opt_level).unwrap();
+            let mut compiler = EvmCompiler::new(backend);
+            compiler.debug_assertions(true);
+            for (j, bytecode) in bytecodes.iter().enumerate() {
+                compiler.translate(&format!("test{j}"), *bytecode, SpecId::CANCUN).unwrap();
+            }
+            let path = tmp.path().join(format!("{i}.ll"));
+            compiler.backend_mut().dump_ir(&path).unwrap();
+            std::fs::read_to_string(path).unwrap()
+        })
+    };
+    let first = dump(0);
+    assert!(first.contains("test2"));
+    for i in 1..4 {
+        similar_asserts::assert_eq!(first, dump(i));
+    }
+}
+
+#[cfg(feature = "llvm")]
+#[test]
+fn llvm_calling_convention() {
+    use crate::{Builder, CallingConvention, Linkage, TypeMethods};
+
+    crate::llvm::with_llvm_context(|cx| {
+        let opt_level = crate::OptimizationLevel::Aggressive;
+        let backend = crate::EvmLlvmBackend::new(cx, false, opt_level).unwrap();
+        let mut compiler = EvmCompiler::new(backend);
+        compiler.calling_convention(CallingConvention::Fast);
+        let bytecode: &[u8] = &[op::PUSH1, 0x42, op::PUSH1, 0x69, op::ADD, op::STOP];
+        let fast = compiler.translate("fast", bytecode, SpecId::CANCUN).unwrap();
+        let inner = compiler.backend().get_function_value("fast").unwrap();
+        assert_eq!(inner.get_call_conventions(), 8);
+
+        // Build a C wrapper that calls the `fastcc` function.
+        let backend = compiler.backend_mut();
+        let ptr = backend.type_ptr();
+        let i8 = backend.type_int(8);
+        let (mut bcx, wrapper) = backend
+            .build_function("fast_wrapper", Some(i8), &[ptr; 6], &[""; 6], Linkage::Public)
+            .unwrap();
+        let f = bcx.get_function("fast").unwrap();
+        let args = (0..6).map(|i| bcx.fn_param(i)).collect::<Vec<_>>();
+        let r = bcx.call(f, &args).unwrap();
+        bcx.ret(&[r]);
+        drop(bcx);
+        let ir = compiler.backend().get_function_value("fast_wrapper").unwrap().print_to_string();
+        assert!(ir.to_string().contains("call fastcc"), "{ir}");
+
+        let f = unsafe { compiler.jit_function(wrapper) }.unwrap();
+        with_evm_context(bytecode, |ecx, stack, stack_len| {
+            let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+            assert_eq!(r, InstructionResult::Stop);
+            assert_eq!(*stack_len, 1);
+            assert_eq!(stack.as_slice()[0].to_u256(), revm_primitives::U256::from(0x42 + 0x69));
+        });
+
+        // The `fastcc` function itself cannot be called through `EvmCompilerFn`.
+        assert!(unsafe { compiler.jit_function(fast) }.is_err());
+    });
+}
+
+#[cfg(all(feature = "llvm", feature = "stackmaps"))]
+#[test]
+fn llvm_stackmaps() {
+    let bytecode: &[u8] = &[op::PUSH1, 0x69, op::PUSH0, op::SSTORE, op::PUSH0, op::SLOAD, op::STOP];
+
+    crate::llvm::with_llvm_context(|cx| {
+        let opt_level = crate::OptimizationLevel::Aggressive;
+        let backend = crate::EvmLlvmBackend::new(cx, false, opt_level).unwrap();
+        let mut compiler = EvmCompiler::new(backend);
+        compiler.stackmaps(true);
+        let id = compiler.translate("stackmaps", bytecode, SpecId::CANCUN).unwrap();
+        let f = compiler.backend().get_function_value("stackmaps").unwrap();
+        let ir = f.print_to_string().to_string();
+        assert!(ir.contains("@llvm.experimental.stackmap(i64 3,"), "{ir}");
+        assert!(ir.contains("@llvm.experimental.stackmap(i64 5,"), "{ir}");
+
+        let f = unsafe { compiler.jit_function(id) }.unwrap();
+        with_evm_context(bytecode, |ecx, stack, stack_len| {
+            let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+            assert_eq!(r, InstructionResult::Stop);
+            assert_eq!(*stack_len, 1);
+            assert_eq!(stack.as_slice()[0].to_u256(), revm_primitives::U256::from(0x69));
+        });
+    });
+
+    crate::llvm::with_llvm_context(|cx| {
+        let opt_level = crate::OptimizationLevel::Aggressive;
+        let backend = crate::EvmLlvmBackend::new(cx, true, opt_level).unwrap();
+        let mut compiler = EvmCompiler::new(backend);
+        compiler.stackmaps(true);
+        compiler.translate("stackmaps", bytecode, SpecId::CANCUN).unwrap();
+        let mut object = Vec::new();
+        compiler.write_object(&mut object).unwrap();
+        let section: &[u8] =
+            if cfg!(target_vendor = "apple") { b"__llvm_stackmaps" } else { b".llvm_stackmaps" };
+        assert!(object.windows(section.len()).any(|w| w == section), "missing stackmap section");
+    });
+}
+
+#[cfg(feature = "llvm")]
+#[test]
+fn llvm_push_pop() {
+    crate::llvm::with_llvm_context(|cx| {
+        let opt_level = crate::OptimizationLevel::None;
+        let backend = crate::EvmLlvmBackend::new(cx, false, opt_level).unwrap();
+        let mut compiler = EvmCompiler::new(backend);
+        let bytecode: &[u8] = &[op::PUSH1, 0x42, op::POP, op::STOP];
+        let id = compiler.translate("push_pop", bytecode, SpecId::CANCUN).unwrap();
+        let ir = compiler.backend().get_function_value("push_pop").unwrap().print_to_string();
+        assert!(!ir.to_string().contains("store i256 66"), "{ir}");
+
+        let f = unsafe { compiler.jit_function(id) }.unwrap();
+        with_evm_context(bytecode, |ecx, stack, stack_len| {
+            let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+            assert_eq!(r, InstructionResult::Stop);
+            assert_eq!(*stack_len, 0);
+            assert_eq!(ecx.gas.spent(), 3 + 2);
+        });
+    });
+}
+
+#[cfg(feature = "llvm")]
+#[test]
+fn llvm_get_function_value() {
+    crate::llvm::with_llvm_context(|cx| {
+        let opt_level = crate::OptimizationLevel::None;
+        let backend = crate::EvmLlvmBackend::new(cx, false, opt_level).unwrap();
+        let mut compiler = EvmCompiler::new(backend);
+        compiler.translate("fn_value", &[op::STOP][..], SpecId::CANCUN).unwrap();
+
+        let f = compiler.backend().get_function_value("fn_value").unwrap();
+        assert_eq!(f.get_name().to_str(), Ok("fn_value"));
+        assert_eq!(f.count_params(), 6);
+        assert!(compiler.backend().get_function_value("does_not_exist").is_none());
+    });
+}
+
+#[cfg(feature = "llvm")]
+#[test]
+fn llvm_link_bitcode() {
+    use crate::llvm::inkwell::AddressSpace;
+
+    crate::llvm::with_llvm_context(|cx| {
+        // A stand-in for the builtins' bitcode, with an `ADDMOD` that always returns `0x42`.
+        let bitcode = {
+            let module = cx.create_module("builtins");
+            let ptr = cx.ptr_type(AddressSpace::default());
+            let fn_type = cx.void_type().fn_type(&[ptr.into()], false);
+            let f = module.add_function("__revmc_builtin_addmod", fn_type, None);
+            let bcx = cx.create_builder();
+            bcx.position_at_end(cx.append_basic_block(f, "entry"));
+            let sp = f.get_first_param().unwrap().into_pointer_value();
+            bcx.build_store(sp, cx.custom_width_int_type(256).const_int(0x42, false)).unwrap();
+            bcx.build_return(None).unwrap();
+            module.write_bitcode_to_memory().as_slice().to_vec()
+        };
+
+        let opt_level = crate::OptimizationLevel::Aggressive;
+        let backend = crate::EvmLlvmBackend::new(cx, false, opt_level).unwrap();
+        let mut compiler = EvmCompiler::new(backend);
+        compiler.backend_mut().add_bitcode(&bitcode);
+        let bytecode: &[u8] = &[op::PUSH1, 3, op::PUSH1, 2, op::PUSH1, 1, op::ADDMOD, op::STOP];
+        let f = unsafe { compiler.jit("link_bitcode", bytecode, SpecId::CANCUN) }.unwrap();
+
+        let ir = compiler.backend().get_function_value("link_bitcode").unwrap().print_to_string();
+        assert!(!ir.to_string().contains("@__revmc_builtin_addmod("), "{ir}");
+        with_evm_context(bytecode, |ecx, stack, stack_len| {
+            let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+            assert_eq!(r, InstructionResult::Stop);
+            assert_eq!(*stack_len, 1);
+            assert_eq!(stack.as_slice()[0].to_u256(), revm_primitives::U256::from(0x42));
+        });
+    });
+}
+
+/// Links the real builtins' bitcode, which can be produced with
+/// `cargo rustc -p revmc-builtins --release -- --emit=llvm-bc`.
+#[cfg(feature = "llvm")]
+#[test]
+#[ignore = "requires the path to the builtins' bitcode in `REVMC_BUILTINS_BITCODE`"]
+fn llvm_link_builtins_bitcode() {
+    let path = std::env::var("REVMC_BUILTINS_BITCODE").expect("`REVMC_BUILTINS_BITCODE` not set");
+    let bitcode = std::fs::read(path).unwrap();
+    #[rustfmt::skip]
+    let bytecode: &[u8] = &[
+        op::PUSH1, 5, op::PUSH1, 4, op::PUSH1, 10, op::ADDMOD,
+        op::PUSH1, 3, op::PUSH1, 2, op::EXP,
+        op::STOP,
+    ];
+
+    crate::llvm::with_llvm_context(|cx| {
+        let opt_level = crate::OptimizationLevel::Aggressive;
+        let backend = crate::EvmLlvmBackend::new(cx, false, opt_level).unwrap();
+        let mut compiler = EvmCompiler::new(backend);
+        compiler.backend_mut().add_bitcode(&bitcode);
+        let f = unsafe { compiler.jit("builtins_bitcode", bytecode, SpecId::CANCUN) }.unwrap();
+        let ir = compiler.backend().get_function_value("builtins_bitcode").unwrap();
+        let ir = ir.print_to_string().to_string();
+        assert!(!ir.contains("@__revmc_builtin_addmod("), "{ir}");
+        with_evm_context(bytecode, |ecx, stack, stack_len| {
+            let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+            assert_eq!(r, InstructionResult::Stop);
+            assert_eq!(*stack_len, 2);
+            assert_eq!(stack.as_slice()[0].to_u256(), revm_primitives::U256::from(4));
+            assert_eq!(stack.as_slice()[1].to_u256(), revm_primitives::U256::from(8));
+            assert_eq!(ecx.gas.spent(), 3 * 3 + 8 + 3 * 2 + 10 + 50);
+        });
+
+        // A new module links the bitcode again, once.
+        let f = unsafe { compiler.jit("builtins_bitcode2", bytecode, SpecId::CANCUN) }.unwrap();
+        with_evm_context(bytecode, |ecx, stack, stack_len| {
+            let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+            assert_eq!(r, InstructionResult::Stop);
+            assert_eq!(stack.as_slice()[1].to_u256(), revm_primitives::U256::from(8));
+        });
+    });
+
+    // Optimizing the module again does not link the bitcode twice.
+    crate::llvm::with_llvm_context(|cx| {
+        let opt_level = crate::OptimizationLevel::None;
+        let backend = crate::EvmLlvmBackend::new(cx, true, opt_level).unwrap();
+        let mut compiler = EvmCompiler::new(backend);
+        compiler.backend_mut().add_bitcode(&bitcode);
+        compiler.translate("builtins_bitcode", bytecode, SpecId::CANCUN).unwrap();
+        compiler.backend_mut().optimize_module().unwrap();
+        compiler.write_object(std::io::sink()).unwrap();
+        let backend = compiler.backend();
+        let addmod = backend.get_function_value("__revmc_builtin_addmod").unwrap();
+        assert!(addmod.count_basic_blocks() > 0);
+        assert!(backend.get_function_value("__revmc_builtin_addmod.1").is_none());
+    });
+}
+
+#[cfg(feature = "llvm")]
+#[test]
+#[ignore = "requires LLVM to be built with the RISC-V target"]
+fn llvm_riscv64() {
+    let bytecode: &[u8] = &[op::PUSH0, op::SLOAD, op::PUSH1, 1, op::ADD, op::PUSH0, op::SSTORE];
+    crate::llvm::with_llvm_context(|cx| {
+        let opt_level = crate::OptimizationLevel::Aggressive;
+        let target = crate::Target::triple("riscv64gc-unknown-linux-gnu");
+        if cfg!(not(target_arch = "riscv64")) {
+            let e = crate::EvmLlvmBackend::new_for_target(cx, false, opt_level, &target).err();
+            assert!(matches!(error_kind(e.unwrap()), RevmcError::Unsupported(_)));
+        }
+        let backend = crate::EvmLlvmBackend::new_for_target(cx, true, opt_level, &target).unwrap();
+        let mut compiler = EvmCompiler::new(backend);
+        compiler.translate("riscv64", bytecode, SpecId::CANCUN).unwrap();
+        let mut object = Vec::new();
+        compiler.write_object(&mut object).unwrap();
+
+        // ELF64, little-endian, `EM_RISCV`, double-float ABI.
+        assert_eq!(&object[..6], b"\x7fELF\x02\x01");
+        assert_eq!(u16::from_le_bytes([object[18], object[19]]), 243);
+        let e_flags = u32::from_le_bytes(object[48..52].try_into().unwrap());
+        assert_eq!(e_flags & 0x6, 0x4, "{e_flags:#x}");
+        // The builtins are resolved when linking.
+        let symbol = b"__revmc_builtin_sload";
+        assert!(object.windows(symbol.len()).any(|w| w == symbol), "missing builtin symbol");
+    });
+}
+
+#[cfg(feature = "llvm")]
+#[test]
+fn llvm_data_layout() {
+    crate::llvm::with_llvm_context(|cx| {
+        let opt_level = crate::OptimizationLevel::None;
+        let backend = crate::EvmLlvmBackend::new(cx, false, opt_level).unwrap();
+        let mut compiler = EvmCompiler::new(backend);
+        let default = compiler.backend().data_layout();
+        assert!(!default.is_empty());
+        // Same as the default, with a different natural stack alignment.
+        let stack_align = if default.contains("-S64") { "S32" } else { "S64" };
+        let data_layout = default
+            .split('-')
+            .filter(|spec| !spec.starts_with('S'))
+            .chain([stack_align])
+            .collect::<Vec<_>>()
+            .join("-");
+        assert_ne!(data_layout, default);
+
+        let backend = compiler.backend_mut();
+        backend.set_data_layout(&data_layout).unwrap();
+        assert_eq!(backend.data_layout(), data_layout);
+        let other = if cfg!(target_pointer_width = "64") { "e-p:32:32" } else { "e-p:64:64" };
+        let e = backend.set_data_layout(other).unwrap_err().to_string();
+        assert!(e.contains("data layout pointer size"), "{e}");
+        assert_eq!(backend.data_layout(), data_layout);
+
+        let bytecode: &[u8] = &[op::PUSH1, 0x42, op::PUSH1, 0x69, op::ADD, op::STOP];
+        let f = unsafe { compiler.jit("data_layout", bytecode, SpecId::CANCUN) }.unwrap();
+        with_evm_context(bytecode, |ecx, stack, stack_len| {
+            let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+            assert_eq!(r, InstructionResult::Stop);
+            assert_eq!(stack.as_slice()[0].to_u256(), revm_primitives::U256::from(0x42 + 0x69));
+        });
+
+        // The data layout is kept for new modules.
+        unsafe { compiler.clear() }.unwrap();
+        assert_eq!(compiler.backend().data_layout(), data_layout);
+    });
+}
+
+#[cfg(feature = "llvm")]
+#[test]
+fn llvm_const_account_query() {
+    crate::llvm::with_llvm_context(|cx| {
+        let opt_level = crate::OptimizationLevel::None;
+        let backend = crate::EvmLlvmBackend::new(cx, false, opt_level).unwrap();
+        let mut compiler = EvmCompiler::new(backend);
+        let mut bytecode = vec![op::PUSH20];
+        bytecode.extend_from_slice(OTHER_ADDR.as_slice());
+        bytecode.push(op::EXTCODESIZE);
+        compiler.translate("const_account_query", &bytecode[..], SpecId::CANCUN).unwrap();
+
+        let f = compiler.backend().get_function_value("const_account_query").unwrap();
+        let ir = f.print_to_string().to_string();
+        let call = ir.lines().find(|l| l.contains("@__revmc_builtin_extcodesize_const(")).unwrap();
+        assert!(call.contains("i32 1768515945, i64 7595718147998050665, i64 7595718147998050665"));
+        assert!(!ir.contains("@__revmc_builtin_extcodesize("), "{ir}");
+    });
+}
+
+#[cfg(feature = "optimism")]
+#[test]
+fn llvm_optimism_specs() {
+    crate::tests::with_llvm_backend_jit(crate::OptimizationLevel::Aggressive, |compiler| {
+        #[rustfmt::skip]
+        let bytecode: &[u8] = &[
+            // Cancun opcodes, enabled in Ecotone.
+            op::PUSH1, 0x42, op::PUSH0, op::TSTORE, op::PUSH0, op::TLOAD,
+            op::PUSH0, op::MSTORE, op::PUSH1, 0x20, op::PUSH0, op::PUSH1, 0x20, op::MCOPY,
+            op::PUSH1, 0x40, op::PUSH0, op::RETURN,
+        ];
+        let samples = [EvmContextSnapshot::new(TestHost::new(), 100_000)];
+        for spec_id in [SpecId::ECOTONE, SpecId::FJORD] {
+            let name = format!("{spec_id:?}");
+            unsafe { compiler.jit_verified(&name, bytecode, spec_id, &samples) }.unwrap();
+        }
+    });
+}
+
+#[cfg(feature = "cranelift")]
+#[test]
+fn cranelift_select() {
+    use crate::{Builder, IntCC, Linkage, TypeMethods};
+
+    let opt_level = crate::OptimizationLevel::Aggressive;
+    let mut backend = crate::EvmCraneliftBackend::new(false, opt_level);
+    let i64 = backend.type_int(64);
+    // `b == 0 ? 0 : a / b`, like `DIV`. Both values of `select` are evaluated, so the division is
+    // by one instead of zero.
+    let (mut bcx, id) = backend
+        .build_function("udiv_or_zero", Some(i64), &[i64, i64], &["a", "b"], Linkage::Public)
+        .unwrap();
+    let a = bcx.fn_param(0);
+    let b = bcx.fn_param(1);
+    let b_is_zero = bcx.icmp_imm(IntCC::Equal, b, 0);
+    let zero = bcx.iconst(i64, 0);
+    let one = bcx.iconst(i64, 1);
+    let divisor = bcx.select(b_is_zero, one, b);
+    let quotient = bcx.udiv(a, divisor);
+    let r = bcx.select(b_is_zero, zero, quotient);
+    bcx.ret(&[r]);
+    bcx.seal_all_blocks();
+    drop(bcx);
+    backend.optimize_module().unwrap();
+
+    let addr = backend.jit_function(id).unwrap();
+    let f = unsafe { std::mem::transmute::<usize, extern "C" fn(u64, u64) -> u64>(addr) };
+    assert_eq!(f(7, 2), 3);
+    assert_eq!(f(7, 0), 0);
+    assert_eq!(f(u64::MAX, 1), u64::MAX);
+    assert_eq!(f(u64::MAX, 0), 0);
+}
+
+#[cfg(feature = "cranelift")]
+#[test]
+fn cranelift_sparse_br_table() {
+    use crate::{Builder, Linkage, TypeMethods};
+
+    let opt_level = crate::OptimizationLevel::Aggressive;
+    let mut backend = crate::EvmCraneliftBackend::new(false, opt_level);
+    let i64 = backend.type_int(64);
+    // Returns `i + 1` for the `i`th target, and `0` for any other value.
+    let values = (0..16).map(|i| 100 + 3 * i).collect::<Vec<u64>>();
+    let (mut bcx, id) = backend
+        .build_function("sparse_switch", Some(i64), &[i64], &["value"], Linkage::Public)
+        .unwrap();
+    let index = bcx.fn_param(0);
+    let default = bcx.create_block("default");
+    let targets =
+        values.iter().map(|&value| (value, bcx.create_block("target"))).collect::<Vec<_>>();
+    bcx.switch(index, default, &targets, false);
+    bcx.switch_to_block(default);
+    let zero = bcx.iconst(i64, 0);
+    bcx.ret(&[zero]);
+    for (i, &(_, block)) in targets.iter().enumerate() {
+        bcx.switch_to_block(block);
+        let r = bcx.iconst(i64, i as i64 + 1);
+        bcx.ret(&[r]);
+    }
+    bcx.seal_all_blocks();
+    drop(bcx);
+    backend.optimize_module().unwrap();
+
+    let addr = backend.jit_function(id).unwrap();
+    let f = unsafe { std::mem::transmute::<usize, extern "C" fn(u64) -> u64>(addr) };
+    // Valid targets.
+    for (i, &value) in values.iter().enumerate() {
+        assert_eq!(f(value), i as u64 + 1, "{value}");
+    }
+    // Invalid targets in the range of the table.
+    for value in [101, 102, 104, 144] {
+        assert_eq!(f(value), 0, "{value}");
+    }
+    // Out of the range of the table.
+    for value in [0, 99, 146, 1 << 16, u64::MAX] {
+        assert_eq!(f(value), 0, "{value}");
+    }
+}
+
+fn gas_invariant_checks<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    let bytecode: &[u8] = &[op::PUSH0, op::PUSH1, 1, op::ADD, op::POP, op::GAS, op::STOP];
+    compiler.debug_assertions(true);
+    compiler.gas_invariant_checks(true);
+    capture_panics(compiler);
+    let f = unsafe { compiler.jit("gas_invariant_checks", bytecode, SpecId::CANCUN) }.unwrap();
+
+    // Normal execution never trips the check, which would return `FatalExternalError`.
+    with_evm_context(bytecode, |ecx, stack, stack_len| {
+        let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::Stop);
+    });
+
+    // Corrupt the gas so that the remaining gas is greater than the limit.
+    with_evm_context(bytecode, |ecx, stack, stack_len| {
+        ecx.gas.erase_cost(1000);
+        let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::FatalExternalError);
+    });
+    let msg = take_panic_message();
+    assert!(msg.contains("gas remaining exceeds gas limit"), "{msg}");
+}
+
+fn precompile_addresses<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    let ecrecover = Address::with_last_byte(1);
+    let bytecode: &[u8] = &[op::STOP];
+    assert!(!compiler.is_precompile(&ecrecover));
+    compiler.set_precompile_addresses((1..=10).map(Address::with_last_byte));
+    assert!(compiler.is_precompile(&ecrecover));
+    assert!(!compiler.is_precompile(&OTHER_ADDR));
+
+    // Only warns by default.
+    compiler.translate_for(ecrecover, "precompile_warn", bytecode, SpecId::CANCUN).unwrap();
+    compiler.translate_for(OTHER_ADDR, "not_precompile", bytecode, SpecId::CANCUN).unwrap();
+
+    compiler.deny_precompiles(true);
+    let err = compiler
+        .translate_for(ecrecover, "precompile_deny", bytecode, SpecId::CANCUN)
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("precompile"), "{err}");
+    compiler.translate_for(OTHER_ADDR, "not_precompile2", bytecode, SpecId::CANCUN).unwrap();
+}
+
+fn allowed_opcodes<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    let add: &[u8] = &[op::PUSH1, 1, op::PUSH1, 2, op::ADD];
+    let sstore: &[u8] = &[op::PUSH1, 1, op::PUSH0, op::SSTORE];
+    let arithmetic = (op::ADD..=op::SIGNEXTEND).chain(op::PUSH0..=op::PUSH32);
+    compiler.set_allowed_opcodes(Some(&arithmetic.collect::<Vec<_>>()));
+    let add_id = compiler.translate("allowed_add", add, SpecId::CANCUN).unwrap();
+    let sstore_id = compiler.translate("allowed_sstore", sstore, SpecId::CANCUN).unwrap();
+    // Spec gating still applies.
+    let push0_id = compiler.translate("allowed_push0", sstore, SpecId::LONDON).unwrap();
+    let f_add = unsafe { compiler.jit_function(add_id) }.unwrap();
+    let f_sstore = unsafe { compiler.jit_function(sstore_id) }.unwrap();
+    let f_push0 = unsafe { compiler.jit_function(push0_id) }.unwrap();
+
+    with_evm_context(add, |ecx, stack, stack_len| {
+        let r = unsafe { f_add.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::Stop);
+        assert_eq!(stack.as_slice()[0].to_u256(), revm_primitives::U256::from(3));
+    });
+    with_evm_context(sstore, |ecx, stack, stack_len| {
+        let r = unsafe { f_sstore.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::OpcodeNotFound);
+    });
+    with_evm_context(sstore, |ecx, stack, stack_len| {
+        let r = unsafe { f_push0.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::NotActivated);
+    });
+}
+
+fn chainid_width<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    let bytecode: &[u8] = &[op::CHAINID];
+    let f = unsafe { compiler.jit("chainid_width", bytecode, SpecId::CANCUN) }.unwrap();
+
+    // `chain_id` is a native-endian `u64` which must be zero-extended, not sign-extended.
+    for chain_id in [u64::MAX, u64::MAX - 1, 1 << 63, 0x0102030405060708, 0] {
+        with_evm_context(bytecode, |ecx, stack, stack_len| {
+            ecx.host.env_mut().cfg.chain_id = chain_id;
+            let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+            assert_eq!(r, InstructionResult::Stop);
+            let expected = revm_primitives::U256::from(chain_id);
+            assert_eq!(stack.as_slice()[0].to_u256(), expected, "{chain_id:#x}");
+        });
+    }
+}
+
+fn dump_manifest<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    let dir = tempfile::tempdir().unwrap();
+    compiler.set_dump_to(Some(dir.path().to_path_buf()));
+    let bytecode: &[u8] = &[op::PUSH1, 1, op::PUSH1, 2, op::ADD, op::PUSH0, op::MSTORE];
+    let id = compiler.translate("manifest", bytecode, SpecId::CANCUN).unwrap();
+    let _ = unsafe { compiler.jit_function(id) }.unwrap();
+
+    let manifest = std::fs::read_to_string(dir.path().join("manifest.json")).unwrap();
+    for key in [
+        "\"name\": \"manifest\"",
+        "\"spec_id\": \"CANCUN\"",
+        "\"bytecode\": \"0x60016002015f52\"",
+        "\"opt_level\"",
+        "\"instructions\": 6",
+        "\"static_memory_bound\": 32",
+        "\"translate_ns\"",
+        "\"PUSH1\": 2",
+        "\"ADD\": 1",
+        "\"opt_ir\"",
+    ] {
+        assert!(manifest.contains(key), "missing {key} in manifest:\n{manifest}");
+    }
+    assert!(dir.path().join("opt").with_extension(compiler.backend().ir_extension()).exists());
+}
+
+fn large_jump_table<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    // Two groups of `JUMPDEST`s separated by more than one jump table chunk with no targets.
+    let mut bytecode = vec![op::JUMP];
+    bytecode.extend([op::JUMPDEST; 300]);
+    for _ in 0..16 {
+        bytecode.push(op::PUSH32);
+        bytecode.extend([0; 32]);
+        bytecode.push(op::POP);
+    }
+    let second = bytecode.len();
+    bytecode.extend([op::JUMPDEST; 300]);
+    let end = bytecode.len();
+    bytecode.extend([op::GAS, op::STOP]);
+
+    compiler.inspect_stack_length(true);
+
+    // `JUMP`, then the remaining `JUMPDEST`s and `PUSH32 POP`s, then `GAS`.
+    let gas = |pc: usize| {
+        let executed = if pc < second { 301 - pc + 16 * (3 + 2) + 300 } else { end - pc };
+        8 + executed as u64 + 2
+    };
+    let valid = [1, 150, 300, second, second + 150, end - 1];
+    let invalid = [0, 301, 600, second - 1, end, 1 << 16];
+    for dispatch in [JumpDispatch::Switch, JumpDispatch::BinarySearch] {
+        compiler.jump_dispatch(dispatch);
+        let name = format!("large_jump_table_{dispatch:?}");
+        let f = unsafe { compiler.jit(&name, &bytecode, SpecId::CANCUN) }.unwrap();
+        let targets =
+            valid.map(|pc| (pc, Some(gas(pc)))).into_iter().chain(invalid.map(|pc| (pc, None)));
+        for (pc, expected_gas) in targets {
+            with_evm_context(&bytecode, |ecx, stack, stack_len| {
+                stack.as_mut_slice()[0] = revm_primitives::U256::from(pc).into();
+                *stack_len = 1;
+                let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+                match expected_gas {
+                    Some(gas) => {
+                        assert_eq!(r, InstructionResult::Stop, "{dispatch:?} pc {pc}");
+                        assert_eq!(ecx.gas_report().gas_spent, gas, "{dispatch:?} pc {pc}");
+                    }
+                    None => assert_eq!(r, InstructionResult::InvalidJump, "{dispatch:?} pc {pc}"),
+                }
+            });
+        }
+    }
+}
+
+fn interpreter_exact_gas<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    #[rustfmt::skip]
+    let bytecodes: [&[u8]; 3] = [
+        &[op::PUSH1, 1, op::PUSH1, 2, op::ADD, op::PUSH1, 3, op::MUL, op::STOP],
+        // Runs out of gas in the memory expansion.
+        &[op::PUSH1, 1, op::PUSH1, 0x40, op::MSTORE, op::PUSH0, op::MLOAD, op::STOP],
+        // Runs out of gas in builtins which charge the static gas together with the dynamic gas.
+        &[op::PUSH1, 0x20, op::PUSH0, op::KECCAK256, op::PUSH1, 0xff, op::SWAP1, op::EXP, op::STOP],
+    ];
+    let spec_id = SpecId::CANCUN;
+    compiler.interpreter_exact_gas(true);
+    let ids = bytecodes.map(|bytecode| {
+        let name = format!("interpreter_exact_gas_{}", hex::encode(bytecode));
+        compiler.translate(&name, bytecode, spec_id).unwrap()
+    });
+    let fs = ids.map(|id| unsafe { compiler.jit_function(id) }.unwrap());
+
+    for (bytecode, f) in bytecodes.iter().zip(fs) {
+        for limit in 0..150 {
+            with_evm_context(bytecode, |ecx, stack, stack_len| {
+                *ecx.gas = Gas::new(limit);
+                let table = spec_to_generic!(spec_id, op::make_instruction_table::<_, SPEC>());
+                let mut interpreter = ecx.to_interpreter(Default::default());
+                let memory = interpreter.take_memory();
+                interpreter.run(memory, &table, &mut TestHost::new());
+
+                let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+                let msg = format!("bytecode {}, limit {limit}", hex::encode(bytecode));
+                assert_eq!(r, interpreter.instruction_result, "{msg}");
+                assert_eq!(ecx.gas.spent(), interpreter.gas.spent(), "{msg}");
+            });
+        }
+    }
+}
+
+fn local_stack_lifetimes<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    let dir = tempfile::tempdir().unwrap();
+    compiler.set_dump_to(Some(dir.path().to_path_buf()));
+    compiler.local_stack(true);
+    compiler.local_stack_lifetimes(true);
+
+    // Sums 1..=100 using 100 stack slots, then stores the result at memory offset 0.
+    let mut bytecode = Vec::new();
+    for i in 1..=100 {
+        bytecode.extend([op::PUSH1, i]);
+    }
+    bytecode.extend([op::ADD; 99]);
+    bytecode.extend([op::PUSH0, op::MSTORE, op::STOP]);
+
+    let f = unsafe { compiler.jit("local_stack_lifetimes", &bytecode, SpecId::CANCUN) }.unwrap();
+    // The unoptimized IR is dumped when finalizing the module.
+    let ir_path = dir.path().join("unopt").with_extension(compiler.backend().ir_extension());
+    let ir = std::fs::read_to_string(ir_path).unwrap();
+    assert!(ir.contains("llvm.lifetime.start"), "missing lifetime start:\n{ir}");
+    assert!(ir.contains("llvm.lifetime.end"), "missing lifetime end:\n{ir}");
+
+    with_evm_context(&bytecode, |ecx, _stack, _stack_len| {
+        let r = unsafe { f.call(None, None, ecx) };
+        assert_eq!(r, InstructionResult::Stop);
+        let expected = revm_primitives::U256::from(5050).to_be_bytes::<32>();
+        assert_eq!(&ecx.memory.context_memory()[..32], &expected[..]);
+    });
+}
+
+fn pinned_opcode_infos<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    let bytecode: &[u8] = &[op::PUSH1, 1, op::PUSH1, 2, op::ADD, op::STOP];
+    let spec_id = SpecId::CANCUN;
+    // Also checks that analyses with different tables are cached separately.
+    compiler.set_analysis_cache(Some(AnalysisCache::new()));
+
+    let mut op_infos = *crate::op_info_map(spec_id);
+    op_infos[op::ADD as usize].set_gas(10);
+
+    let default = compiler.translate("default", bytecode, spec_id).unwrap();
+    compiler.set_opcode_infos(Some(op_infos));
+    let pinned = compiler.translate("pinned", bytecode, spec_id).unwrap();
+    let default = unsafe { compiler.jit_function(default) }.unwrap();
+    let pinned = unsafe { compiler.jit_function(pinned) }.unwrap();
+
+    for (f, expected_gas) in [(default, 3 + 3 + 3), (pinned, 3 + 3 + 10)] {
+        with_evm_context(bytecode, |ecx, stack, stack_len| {
+            let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+            assert_eq!(r, InstructionResult::Stop);
+            assert_eq!(ecx.gas_report().gas_spent, expected_gas);
+        });
+    }
+    assert_eq!(compiler.analysis_cache().unwrap().misses(), 2);
+}
+
+fn jit_verified<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    #[rustfmt::skip]
+    let bytecode: &[u8] = &[
+        op::PUSH0, op::CALLDATALOAD, op::PUSH1, 2, op::ADD,
+        op::PUSH0, op::MSTORE, op::PUSH1, 0x20, op::PUSH0, op::RETURN,
+    ];
+    let spec_id = SpecId::CANCUN;
+    let mut with_input = EvmContextSnapshot::new(TestHost::new(), 100_000);
+    with_input.input = revm_primitives::U256::from(40).to_be_bytes_vec().into();
+    let samples = [
+        EvmContextSnapshot::new(TestHost::new(), 100_000),
+        with_input,
+        // Runs out of gas.
+        EvmContextSnapshot::new(TestHost::new(), 10),
+    ];
+
+    compiler.inspect_stack_length(true);
+    unsafe { compiler.jit_verified("verified", bytecode, spec_id, &samples) }.unwrap();
+    unsafe { compiler.clear() }.unwrap();
+
+    // Miscompile `ADD` by charging the wrong amount of gas for it.
+    let mut op_infos = *crate::op_info_map(spec_id);
+    op_infos[op::ADD as usize].set_gas(10);
+    compiler.set_opcode_infos(Some(op_infos));
+    let e = unsafe { compiler.jit_verified("miscompiled", bytecode, spec_id, &samples) };
+    match error_kind(e.unwrap_err()) {
+        RevmcError::Verification(msg) => {
+            assert!(msg.contains("`miscompiled`, sample 0: gas mismatch"), "{msg}")
+        }
+        e => panic!("unexpected error: {e:?}"),
+    }
+}
+
+fn comment_sink<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    use std::{cell::RefCell, rc::Rc};
+
+    // Comments are generated even if dumping is disabled.
+    compiler.set_dump_to(None);
+    let comments = Rc::new(RefCell::new(Vec::<(usize, String)>::new()));
+    let sink = comments.clone();
+    compiler.set_comment_sink(Some(Box::new(move |inst, comment| {
+        sink.borrow_mut().push((inst, comment.to_string()))
+    })));
+
+    let bytecode: &[u8] = &[op::PUSH1, 1, op::PUSH1, 2, op::ADD, op::STOP];
+    compiler.translate("comment_sink", bytecode, SpecId::CANCUN).unwrap();
+
+    let comments = comments.borrow();
+    let first = |inst: usize| comments.iter().find(|(i, _)| *i == inst).map(|(_, c)| c.as_str());
+    assert_eq!(first(0), Some("PUSH1 0x01"), "{comments:?}");
+    assert_eq!(first(1), Some("PUSH1 0x02"), "{comments:?}");
+    assert_eq!(first(2), Some("ADD"), "{comments:?}");
+    assert_eq!(first(3), Some("STOP"), "{comments:?}");
+    // The section's gas check is in the first instruction.
+    assert!(comments.contains(&(0, "check OutOfGas".to_string())), "{comments:?}");
+    assert!(comments.windows(2).all(|w| w[0].0 <= w[1].0), "{comments:?}");
+}
+
+fn deprecated_opcodes<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    compiler.set_deprecated_opcodes(Some(&[op::SELFDESTRUCT, op::CALLCODE]));
+
+    let selfdestruct: &[u8] = &[op::PUSH0, op::SELFDESTRUCT];
+    match error_kind(compiler.translate("selfdestruct", selfdestruct, SpecId::CANCUN).unwrap_err())
+    {
+        RevmcError::InvalidBytecode(msg) => {
+            assert_eq!(msg, "bytecode contains deprecated opcodes: SELFDESTRUCT")
+        }
+        e => panic!("unexpected error: {e:?}"),
+    }
+
+    // Unreachable code is not checked.
+    let dead: &[u8] = &[op::STOP, op::SELFDESTRUCT];
+    compiler.translate("dead", dead, SpecId::CANCUN).unwrap();
+    let add: &[u8] = &[op::PUSH1, 1, op::PUSH1, 2, op::ADD];
+    compiler.translate("add", add, SpecId::CANCUN).unwrap();
+
+    compiler.set_deprecated_opcodes(None);
+    compiler.translate("selfdestruct", selfdestruct, SpecId::CANCUN).unwrap();
+}
+
+fn saturating_gas<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    // Costs 15 gas.
+    let bytecode: &[u8] = &[op::PUSH1, 1, op::PUSH1, 2, op::ADD, op::PUSH1, 3, op::ADD, op::STOP];
+    compiler.inspect_stack_length(true);
+    compiler.saturating_gas(true);
+    let f = unsafe { compiler.jit("saturating_gas", bytecode, SpecId::CANCUN) }.unwrap();
+
+    with_evm_context(bytecode, |ecx, stack, stack_len| {
+        *ecx.gas = Gas::new(10);
+        let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::Stop);
+        assert_eq!(ecx.gas.remaining(), 0);
+        assert_eq!(ecx.gas.spent(), 10);
+        assert_eq!(*stack_len, 1);
+        assert_eq!(stack.as_slice()[0].to_u256(), revm_primitives::U256::from(6));
+    });
+}
+
+fn stack_error_results<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    let underflow = InstructionResult::FatalExternalError;
+    let overflow = InstructionResult::CallTooDeep;
+    compiler.inspect_stack_length(true);
+    compiler.set_stack_error_results(underflow, overflow);
+    let bytecodes: [&[u8]; 3] = [&[op::ADD], &[op::PUSH0], &[op::DUP1]];
+    let ids = bytecodes.map(|bytecode| {
+        let name = format!("stack_error_results_{}", hex::encode(bytecode));
+        compiler.translate(&name, bytecode, SpecId::CANCUN).unwrap()
+    });
+    let [add, push0, dup1] = ids.map(|id| unsafe { compiler.jit_function(id) }.unwrap());
+
+    let cases = [
+        (add, bytecodes[0], 1, underflow),
+        (push0, bytecodes[1], 1024, overflow),
+        (dup1, bytecodes[2], 0, underflow),
+        (dup1, bytecodes[2], 1024, overflow),
+        (dup1, bytecodes[2], 1, InstructionResult::Stop),
+    ];
+    for (f, bytecode, len, expected) in cases {
+        with_evm_context(bytecode, |ecx, stack, stack_len| {
+            *stack_len = len;
+            let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+            assert_eq!(r, expected, "{}, len {len}", hex::encode(bytecode));
+        });
+    }
+}
+
+fn revert_range<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    #[rustfmt::skip]
+    let bytecode: &[u8] = &[
+        op::PUSH4, 0xde, 0xad, 0xbe, 0xef, op::PUSH0, op::MSTORE,
+        op::PUSH1, 4, op::PUSH1, 28, op::REVERT,
+    ];
+    let f = unsafe { compiler.jit("revert_range", bytecode, SpecId::CANCUN) }.unwrap();
+
+    with_evm_context(bytecode, |ecx, stack, stack_len| {
+        assert_eq!(ecx.return_range, None);
+        let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::Revert);
+        let range = ecx.return_range.clone().unwrap();
+        assert_eq!(range, 28..32);
+        assert_eq!(&ecx.memory.context_memory()[range], &[0xde, 0xad, 0xbe, 0xef]);
+        let revm_interpreter::InterpreterAction::Return { result } = &*ecx.next_action else {
+            panic!("unexpected next action: {:?}", ecx.next_action);
+        };
+        assert_eq!(result.output[..], [0xde, 0xad, 0xbe, 0xef]);
+    });
+}
+
+fn reentrant_host<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    use revm_interpreter::{
+        AccountLoad, Eip7702CodeLoad, SStoreResult, SelfDestructResult, StateLoad,
+    };
+    use revm_primitives::{Bytes, Env, Log, B256, U256};
+
+    /// Executes the compiled function again from `SLOAD`, up to `MAX_DEPTH` times.
+    struct ReentrantHost {
+        inner: TestHost,
+        f: crate::EvmCompilerFn,
+        bytecode: &'static [u8],
+        depth: usize,
+    }
+
+    const MAX_DEPTH: usize = 3;
+
+    fn new_interpreter(bytecode: &[u8]) -> revm_interpreter::Interpreter {
+        let contract = revm_interpreter::Contract {
+            input: Bytes::new(),
+            bytecode: revm_interpreter::analysis::to_analysed(revm_primitives::Bytecode::new_raw(
+                Bytes::copy_from_slice(bytecode),
+            )),
+            hash: None,
+            bytecode_address: None,
+            target_address: Address::ZERO,
+            caller: Address::ZERO,
+            call_value: U256::ZERO,
+        };
+        revm_interpreter::Interpreter::new(contract, 100_000, false)
+    }
+
+    impl Host for ReentrantHost {
+        fn env(&self) -> &Env {
+            self.inner.env()
+        }
+        fn env_mut(&mut self) -> &mut Env {
+            self.inner.env_mut()
+        }
+        fn load_account_delegated(&mut self, address: Address) -> Option<AccountLoad> {
+            self.inner.load_account_delegated(address)
+        }
+        fn block_hash(&mut self, number: u64) -> Option<B256> {
+            self.inner.block_hash(number)
+        }
+        fn balance(&mut self, address: Address) -> Option<StateLoad<U256>> {
+            self.inner.balance(address)
+        }
+        fn code(&mut self, address: Address) -> Option<Eip7702CodeLoad<Bytes>> {
+            self.inner.code(address)
+        }
+        fn code_hash(&mut self, address: Address) -> Option<Eip7702CodeLoad<B256>> {
+            self.inner.code_hash(address)
+        }
+        fn sload(&mut self, address: Address, index: U256) -> Option<StateLoad<U256>> {
+            if self.depth < MAX_DEPTH {
+                self.depth += 1;
+                let f = self.f;
+                let mut interpreter = new_interpreter(self.bytecode);
+                let (mut ecx, stack, stack_len) =
+                    crate::EvmContext::from_interpreter_with_stack(&mut interpreter, self);
+                let r = unsafe { f.call(Some(stack), Some(stack_len), &mut ecx) };
+                assert_eq!(r, InstructionResult::Stop);
+            }
+            self.inner.sload(address, index)
+        }
+        fn sstore(
+            &mut self,
+            address: Address,
+            index: U256,
+            value: U256,
+        ) -> Option<StateLoad<SStoreResult>> {
+            self.inner.sstore(address, index, value)
+        }
+        fn tload(&mut self, address: Address, index: U256) -> U256 {
+            self.inner.tload(address, index)
+        }
+        fn tstore(&mut self, address: Address, index: U256, value: U256) {
+            self.inner.tstore(address, index, value)
+        }
+        fn log(&mut self, log: Log) {
+            self.inner.log(log)
+        }
+        fn selfdestruct(
+            &mut self,
+            address: Address,
+            target: Address,
+        ) -> Option<StateLoad<SelfDestructResult>> {
+            self.inner.selfdestruct(address, target)
+        }
+    }
+
+    // `sstore(0, sload(0) + 1)`, where the `SLOAD` re-enters the function.
+    let bytecode: &'static [u8] =
+        &[op::PUSH0, op::SLOAD, op::PUSH1, 1, op::ADD, op::PUSH0, op::SSTORE, op::STOP];
+    let dir = tempfile::tempdir().unwrap();
+    compiler.set_dump_to(Some(dir.path().to_path_buf()));
+    compiler.reentrant_host(true);
+    let f = unsafe { compiler.jit("reentrant_host", bytecode, SpecId::CANCUN) }.unwrap();
+
+    // `NoRecurse` is not emitted for the function nor the builtins.
+    if compiler.backend().ir_extension() == "ll" {
+        let path = dir.path().join("unopt.ll");
+        let ir = std::fs::read_to_string(path).unwrap();
+        assert!(!ir.contains("norecurse"), "{ir}");
+    }
+
+    let mut interpreter = new_interpreter(bytecode);
+    let mut host = ReentrantHost { inner: TestHost::new(), f, bytecode, depth: 0 };
+    let (mut ecx, stack, stack_len) =
+        crate::EvmContext::from_interpreter_with_stack(&mut interpreter, &mut host);
+    let r = unsafe { f.call(Some(stack), Some(stack_len), &mut ecx) };
+    assert_eq!(r, InstructionResult::Stop);
+    // Every execution increments the slot after the nested ones have returned.
+    assert_eq!(host.depth, MAX_DEPTH);
+    assert_eq!(host.inner.storage[&U256::ZERO], U256::from(1 + MAX_DEPTH + 1));
+}
+
+fn jit_pure<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    let sload: &[u8] = &[op::PUSH0, op::SLOAD, op::CHAINID, op::ADD];
+    match error_kind(unsafe { compiler.jit_pure("sload", sload, SpecId::CANCUN) }.unwrap_err()) {
+        RevmcError::InvalidBytecode(msg) => {
+            assert_eq!(msg, "bytecode is not pure, it uses the host in: CHAINID, SLOAD")
+        }
+        e => panic!("unexpected error: {e:?}"),
+    }
+
+    let add: &[u8] = &[op::PUSH1, 1, op::PUSH1, 2, op::ADD, op::STOP, op::SLOAD];
+    compiler.inspect_stack_length(true);
+    let f = unsafe { compiler.jit_pure("add", add, SpecId::CANCUN) }.unwrap();
+    with_evm_context(add, |ecx, stack, stack_len| {
+        let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::Stop);
+        assert_eq!(*stack_len, 1);
+        assert_eq!(stack.as_slice()[0].to_u256(), revm_primitives::U256::from(3));
+    });
+}
+
+fn create_resume<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    use revm_interpreter::{CreateOutcome, InterpreterAction, InterpreterResult};
+    use revm_primitives::{Bytes, U256};
+
+    // `sstore(0, create(0, 0, 0))`.
+    let bytecode: &[u8] =
+        &[op::PUSH0, op::PUSH0, op::PUSH0, op::CREATE, op::PUSH0, op::SSTORE, op::STOP];
+    let f = unsafe { compiler.jit("create_resume", bytecode, SpecId::CANCUN) }.unwrap();
+
+    let snapshot = EvmContextSnapshot::new(TestHost::new(), 100_000);
+    let mut interpreter = snapshot.interpreter(snapshot.contract(bytecode));
+    let mut host = snapshot.host;
+
+    // Suspends at `CREATE`, ...
+    let action = unsafe { f.call_with_interpreter(&mut interpreter, &mut host) };
+    assert!(matches!(action, InterpreterAction::Create { .. }), "{action:?}");
+    assert_eq!(interpreter.instruction_result, InstructionResult::CallOrCreate);
+
+    // ... and resumes with the created address pushed by the interpreter.
+    let result = InterpreterResult::new(InstructionResult::Return, Bytes::new(), Gas::new(0));
+    interpreter.insert_create_outcome(CreateOutcome::new(result, Some(OTHER_ADDR)));
+    let action = unsafe { f.call_with_interpreter(&mut interpreter, &mut host) };
+    assert_eq!(interpreter.instruction_result, InstructionResult::Stop, "{action:?}");
+    assert_eq!(host.storage[&U256::ZERO], U256::from_be_bytes(OTHER_ADDR.into_word().0));
+}
+
+fn compiled_fn_cache<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    let codes: [&[u8]; 3] =
+        [&[op::PUSH1, 1, op::STOP], &[op::PUSH1, 2, op::STOP], &[op::PUSH1, 3, op::STOP]];
+    compiler.inspect_stack_length(true);
+    let mut cache = crate::CompiledFnCache::new(2);
+    let get = |compiler: &mut EvmCompiler<B>, cache: &mut crate::CompiledFnCache<B>, i: usize| {
+        let f = unsafe { cache.get_or_compile(compiler, codes[i], SpecId::CANCUN) }.unwrap();
+        with_evm_context(codes[i], |ecx, stack, stack_len| {
+            let r = unsafe { f.get().call(Some(stack), Some(stack_len), ecx) };
+            assert_eq!(r, InstructionResult::Stop);
+            assert_eq!(*stack_len, 1);
+            assert_eq!(stack.as_slice()[0].to_u256(), revm_primitives::U256::from(i + 1));
+        });
+        f
+    };
+
+    let f0 = get(compiler, &mut cache, 0);
+    let name = crate::code_hash_symbol_name(&revm_primitives::keccak256(codes[0]));
+    assert_eq!(f0.name(), format!("{name}_CANCUN"));
+    drop(f0);
+    let f1 = get(compiler, &mut cache, 1);
+    drop(get(compiler, &mut cache, 0));
+    assert_eq!((cache.hits(), cache.misses(), cache.len()), (1, 2, 2));
+
+    // Evicts the least recently used function, which is still referenced.
+    drop(get(compiler, &mut cache, 2));
+    assert_eq!((cache.hits(), cache.misses(), cache.len()), (1, 3, 2));
+    assert_eq!(cache.num_evicted(), 1);
+    with_evm_context(codes[1], |ecx, stack, stack_len| {
+        let r = unsafe { f1.get().call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::Stop);
+    });
+
+    let name = f1.name().to_string();
+    drop(f1);
+    unsafe { cache.free_unused(compiler) }.unwrap();
+    assert_eq!(cache.num_evicted(), 0);
+
+    // Recompiled after being evicted, with a different name.
+    let f1 = get(compiler, &mut cache, 1);
+    assert!(f1.name().starts_with(&name) && f1.name() != name, "{}", f1.name());
+    drop(f1);
+    assert_eq!((cache.hits(), cache.misses(), cache.len()), (1, 4, 2));
+    assert!(cache.invalidate(&revm_primitives::keccak256(codes[1]), SpecId::CANCUN));
+    assert_eq!(cache.len(), 1);
+    unsafe { cache.free_unused(compiler) }.unwrap();
+    assert_eq!(cache.num_evicted(), 0);
+}
+
+fn custom_opcodes<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    use revm_primitives::U256;
+
+    /// `a, b -> a * 10 + b`, charging 5 more gas.
+    unsafe extern "C" fn handler(
+        ecx: &mut crate::EvmContext<'_>,
+        sp: *mut crate::EvmWord,
+    ) -> InstructionResult {
+        if !ecx.gas.record_cost(5) {
+            return InstructionResult::OutOfGas;
+        }
+        let (b, a) = unsafe { ((*sp).to_u256(), (*sp.add(1)).to_u256()) };
+        unsafe { *sp = crate::EvmWord::from_u256(a * U256::from(10) + b) };
+        InstructionResult::Continue
+    }
+
+    const OP: u8 = 0x0c;
+    let attrs = &[crate::Attribute::Cold];
+    let custom = crate::CustomOpcode { inputs: 2, outputs: 1, gas: 7, handler, attrs };
+    compiler.register_opcode(OP, custom);
+    compiler.inspect_stack_length(true);
+
+    // Also checks that the code is padded with `STOP` after the custom opcode.
+    let bytecode: &[u8] = &[op::PUSH1, 3, op::PUSH1, 4, OP];
+    let infos = compiler.parse(bytecode.into(), SpecId::CANCUN).unwrap();
+    let info = infos.inst_infos().nth(2).unwrap();
+    assert_eq!((info.stack_in, info.stack_out, info.static_gas), (2, 1, Some(7)));
+
+    let f = unsafe { compiler.jit("custom_opcodes", bytecode, SpecId::CANCUN) }.unwrap();
+    with_evm_context(bytecode, |ecx, stack, stack_len| {
+        let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::Stop);
+        assert_eq!(*stack_len, 1);
+        assert_eq!(stack.as_slice()[0].to_u256(), U256::from(43));
+        assert_eq!(ecx.gas.spent(), 3 + 3 + 7 + 5);
+    });
+
+    compiler.set_allowed_opcodes(Some(&[op::PUSH1]));
+    let f = unsafe { compiler.jit("custom_opcodes_disallowed", bytecode, SpecId::CANCUN) }.unwrap();
+    with_evm_context(bytecode, |ecx, stack, stack_len| {
+        let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::OpcodeNotFound);
+    });
+}
+
+// The test builtin returns `Stop`, which is its success result, so execution continues after it.
+fn builtin_success<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    use revm_primitives::U256;
+
+    compiler.inspect_stack_length(true);
+    let bytecode: &[u8] =
+        &[op::PUSH1, 0x42, crate::TEST_BUILTIN_SUCCESS, op::PUSH1, 0x69, op::STOP];
+    let f = unsafe { compiler.jit("builtin_success", bytecode, SpecId::CANCUN) }.unwrap();
+    with_evm_context(bytecode, |ecx, stack, stack_len| {
+        let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::Stop);
+        assert_eq!(*stack_len, 2);
+        assert_eq!(stack.as_slice()[0].to_u256(), U256::from(0x42));
+        assert_eq!(stack.as_slice()[1].to_u256(), U256::from(0x69));
+    });
+}
+
+fn elide_dead_stack_stores<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    let dir = tempfile::tempdir().unwrap();
+    compiler.set_dump_to(Some(dir.path().to_path_buf()));
+    compiler.inspect_stack_length(true);
+
+    // `0x42` and `0x43` are popped before the stack is observed at `STOP`.
+    let bytecode: &[u8] = &[
+        op::PUSH1,
+        0x42,
+        op::DUP1,
+        op::ADD,
+        op::PUSH1,
+        0x43,
+        op::PUSH1,
+        0x44,
+        op::SWAP1,
+        op::POP,
+        op::STOP,
+    ];
+    let f = unsafe { compiler.jit("elide_dead_stack_stores", bytecode, SpecId::CANCUN) }.unwrap();
+    let ir_path = dir.path().join("unopt").with_extension(compiler.backend().ir_extension());
+    let ir = std::fs::read_to_string(ir_path).unwrap();
+    assert!(!ir.contains("store i256 66"), "{ir}");
+    assert!(!ir.contains("store i256 67"), "{ir}");
+
+    with_evm_context(bytecode, |ecx, stack, stack_len| {
+        let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::Stop);
+        assert_eq!(*stack_len, 2);
+        assert_eq!(stack.as_slice()[0].to_u256(), revm_primitives::U256::from(0x84));
+        assert_eq!(stack.as_slice()[1].to_u256(), revm_primitives::U256::from(0x44));
+        assert_eq!(ecx.gas.spent(), 3 + 3 + 3 + 3 + 3 + 3 + 2);
+    });
+}
+
+fn selector_entries<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    // WETH9, compiled by solc 0.4, dispatches its 11 external functions in a single sequence.
+    let bytecode = hex::decode(include_str!("../../../../data/weth.rt.hex").trim()).unwrap();
+    compiler.inspect_stack_length(true);
+    let entries = compiler
+        .translate_selector_entries("selector_entries", &bytecode[..], SpecId::CANCUN)
+        .unwrap();
+    let selectors = entries.iter().map(|&(selector, _)| selector).collect::<Vec<_>>();
+    assert_eq!(selectors.len(), 11);
+    // `transfer(address,uint256)`, and the first and last comparisons.
+    for selector in [0xa9059cbb, 0x06fdde03, 0xdd62ed3e] {
+        assert!(selectors.contains(&selector), "{selector:08x}");
+    }
+    for (selector, id) in entries {
+        let f = unsafe { compiler.jit_function(id) }.unwrap();
+        let mut sample = EvmContextSnapshot::new(TestHost::new(), 100_000);
+        let arg = revm_primitives::U256::from(0x69).to_be_bytes::<32>();
+        sample.input = [&selector.to_be_bytes()[..], &arg, &arg, &arg].concat().into();
+        if let Err(e) = sample.verify(&bytecode, SpecId::CANCUN, f, true) {
+            panic!("{selector:08x}: {e}");
+        }
+    }
+}
+
+fn translate_many<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    let add: &[u8] = &[op::PUSH1, 1, op::PUSH1, 2, op::ADD];
+    let invalid: &[u8] = &[op::PUSH0, op::INVALID];
+    let contracts = [add, invalid, add].map(|code| (keccak256(code), code));
+    let ids = compiler.translate_many(&contracts, SpecId::CANCUN).unwrap();
+    assert_eq!(ids.len(), 3);
+    assert_eq!(ids[0], ids[2]);
+    assert_ne!(ids[0], ids[1]);
+
+    let expected = [InstructionResult::Stop, InstructionResult::InvalidFEOpcode];
+    for ((id, (_, code)), expected) in ids.into_iter().zip(contracts).zip(expected.repeat(2)) {
+        let f = unsafe { compiler.jit_function(id) }.unwrap();
+        with_evm_context(code, |ecx, stack, stack_len| {
+            let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+            assert_eq!(r, expected);
+        });
+    }
+}
+
+fn jit_after_finalize<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    compiler.inspect_stack_length(true);
+    let bytecode = |i: u16| -> Vec<u8> {
+        let [hi, lo] = i.to_be_bytes();
+        vec![op::PUSH2, hi, lo, op::STOP]
+    };
+    let run = |f: crate::EvmCompilerFn, i: u16| {
+        with_evm_context(&bytecode(i), |ecx, stack, stack_len| {
+            let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+            assert_eq!(r, InstructionResult::Stop);
+            assert_eq!(*stack_len, 1);
+            assert_eq!(stack.as_slice()[0].to_u256(), revm_primitives::U256::from(i));
+        });
+    };
+
+    // Every function is compiled after the previous ones were finalized.
+    let mut fns = Vec::new();
+    for i in 0..300 {
+        let f = unsafe { compiler.jit(&format!("f{i}"), &bytecode(i), SpecId::CANCUN) }.unwrap();
+        run(f, i);
+        fns.push(f);
+    }
+    // The functions of the previous modules are still valid.
+    for (i, f) in fns.into_iter().enumerate() {
+        run(f, i as u16);
+    }
+    // Names are unique across modules.
+    assert!(unsafe { compiler.jit("f0", &bytecode(0), SpecId::CANCUN) }.is_err());
+}
+
+// Replaces the function of a caller while it is suspended at a `CALL`, which must keep being
+// resumed by the function or the interpreter that started executing it.
+#[cfg(feature = "revm")]
+fn mid_frame_swap<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    use crate::{EvmCompilerFn, EvmCompilerFnInfo, EvmCompilerFnLookup, EvmCompilerFns};
+    use revm::{
+        db::{CacheDB, EmptyDB},
+        primitives::{AccountInfo, Bytecode, Bytes, TxKind, B256, U256},
+    };
+
+    const SPEC_ID: SpecId = SpecId::CANCUN;
+
+    /// Replaces the function of the caller with `replacement` when the callee starts.
+    struct SwapOnCall {
+        fns: EvmCompilerFns,
+        caller_hash: B256,
+        callee_hash: B256,
+        replacement: Option<EvmCompilerFnInfo>,
+    }
+
+    // SAFETY: Only the function compiled from the caller is inserted.
+    unsafe impl EvmCompilerFnLookup for SwapOnCall {
+        fn get_function(&self, bytecode_hash: B256, spec_id: SpecId) -> Option<EvmCompilerFn> {
+            if bytecode_hash == self.callee_hash {
+                self.fns.remove(&self.caller_hash, SPEC_ID);
+                if let Some(info) = self.replacement {
+                    unsafe { self.fns.insert(self.caller_hash, info) };
+                }
+            }
+            self.fns.get_function(bytecode_hash, spec_id)
+        }
+    }
+
+    let caller_addr = Address::repeat_byte(0xca);
+    let callee_addr = Address::repeat_byte(0xce);
+    // Returns `0x69`.
+    #[rustfmt::skip]
+    let callee: &[u8] = &[
+        op::PUSH1, 0x69, op::PUSH0, op::MSTORE,
+        op::PUSH1, 0x20, op::PUSH0, op::RETURN,
+    ];
+    // Returns `0x42`, pushed before the call, plus its success and its output.
+    #[rustfmt::skip]
+    let caller = [
+        &[
+            op::PUSH1, 0x42,
+            op::PUSH1, 0x20, op::PUSH0, op::PUSH0, op::PUSH0, op::PUSH0, op::PUSH20,
+        ][..],
+        callee_addr.as_slice(),
+        &[
+            op::GAS, op::CALL,
+            op::ADD, op::PUSH0, op::MLOAD, op::ADD,
+            op::PUSH0, op::MSTORE, op::PUSH1, 0x20, op::PUSH0, op::RETURN,
+        ],
+    ]
+    .concat();
+    let caller_hash = keccak256(&caller);
+    let callee_hash = keccak256(callee);
+
+    let f = unsafe { compiler.jit("caller", &caller[..], SPEC_ID) }.unwrap();
+    let info = EvmCompilerFnInfo { f, spec_id: SPEC_ID };
+
+    let run = |initial: Option<EvmCompilerFnInfo>, replacement: Option<EvmCompilerFnInfo>| {
+        let fns = EvmCompilerFns::new();
+        if let Some(info) = initial {
+            unsafe { fns.insert(caller_hash, info) };
+        }
+        let mut db = CacheDB::new(EmptyDB::default());
+        let code = Bytecode::new_raw(Bytes::copy_from_slice(&caller));
+        db.insert_account_info(caller_addr, AccountInfo::from_bytecode(code));
+        let code = Bytecode::new_raw(Bytes::copy_from_slice(callee));
+        db.insert_account_info(callee_addr, AccountInfo::from_bytecode(code));
+        let mut evm = revm::Evm::builder()
+            .with_db(db)
+            .modify_tx_env(|tx| {
+                tx.transact_to = TxKind::Call(caller_addr);
+                tx.gas_limit = 1_000_000;
+            })
+            .with_external_context(SwapOnCall { fns, caller_hash, callee_hash, replacement })
+            .with_spec_id(SPEC_ID)
+            .append_handler_register(crate::register_handler)
+            .build();
+        evm.transact().unwrap().result
+    };
+
+    let expected = run(None, None);
+    assert!(expected.is_success(), "{expected:?}");
+    assert_eq!(U256::from_be_slice(expected.output().unwrap()), U256::from(0x42 + 1 + 0x69));
+    // Compiled when the caller starts, removed while it is suspended.
+    assert_eq!(run(Some(info), None), expected);
+    // Interpreted when the caller starts, compiled while it is suspended.
+    assert_eq!(run(None, Some(info)), expected);
+    assert_eq!(run(Some(info), Some(info)), expected);
+}
@@ -14,6 +14,7 @@ use revm_interpreter::{
     InterpreterResult,
 };
 use revm_primitives::{hex, keccak256, Address, Bytes, LogData, B256, KECCAK_EMPTY};
+use std::cell::RefCell;
 
 #[macro_use]
 mod macros;
@@ -22,6 +23,7 @@ mod meta;
 
 mod fibonacci;
 mod resume;
+mod swap;
 
 mod runner;
 pub use runner::*;
@@ -33,6 +35,46 @@ const I256_MAX: U256 = U256::from_limbs([
     0x7FFFFFFFFFFFFFFF,
 ]);
 
+/// A value with distinct bytes, so that any byte or limb reordering is observable.
+const BYTE_ORDER_VALUE: U256 = U256::from_limbs([
+    0x191a1b1c1d1e1f20,
+    0x1112131415161718,
+    0x090a0b0c0d0e0f10,
+    0x0102030405060708,
+]);
+const BYTE_ORDER_ADDR: Address = Address::new(hex!("0102030405060708090a0b0c0d0e0f1011121314"));
+
+thread_local! {
+    static PANIC_MESSAGE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Sets a panic handler on `compiler` that records the message of every panic on the current
+/// thread instead of aborting, so that the functions return `FatalExternalError`.
+///
+/// The recorded message is returned by [`take_panic_message`].
+pub fn capture_panics<B: Backend>(compiler: &mut EvmCompiler<B>) {
+    unsafe extern "C" fn handler(msg: *const u8, len: usize) {
+        let msg = std::str::from_utf8(std::slice::from_raw_parts(msg, len)).unwrap();
+        PANIC_MESSAGE.with(|m| *m.borrow_mut() = Some(msg.to_string()));
+    }
+    compiler.set_panic_handler(Some(handler));
+}
+
+/// Returns the message of the last panic recorded on the current thread by [`capture_panics`].
+#[track_caller]
+pub fn take_panic_message() -> String {
+    PANIC_MESSAGE.with(|m| m.borrow_mut().take()).expect("no panic was recorded")
+}
+
+/// Returns the [`RevmcError`] of a compiler error, so that its kind can be matched on.
+#[track_caller]
+#[allow(clippy::let_and_return)]
+pub fn error_kind(e: crate::Error) -> RevmcError {
+    #[cfg(feature = "eyre")]
+    let e = e.downcast::<RevmcError>().unwrap();
+    e
+}
+
 tests! {
     ret {
         empty(@raw {}),
@@ -77,6 +119,30 @@ tests! {
             expected_stack: &[U256::ZERO],
             expected_gas: 5,
         }),
+        push_pop(@raw {
+            bytecode: &[op::PUSH1, 0x42, op::POP],
+            expected_gas: 3 + 2,
+        }),
+        push_pop_add(@raw {
+            bytecode: &[op::PUSH1, 1, op::PUSH1, 2, op::PUSH1, 3, op::POP, op::ADD],
+            expected_stack: &[3_U256],
+            expected_gas: 3 + 3 + 3 + 2 + 3,
+        }),
+        push_pop_underflow(@raw {
+            bytecode: &[op::PUSH1, 1, op::POP, op::POP],
+            expected_return: InstructionResult::StackUnderflow,
+            expected_gas: 3 + 2 + 2,
+        }),
+        swap1_pop(@raw {
+            bytecode: &[op::PUSH1, 1, op::PUSH1, 2, op::SWAP1, op::POP],
+            expected_stack: &[2_U256],
+            expected_gas: 3 + 3 + 3 + 2,
+        }),
+        swap1_pop_underflow(@raw {
+            bytecode: &[op::PUSH1, 1, op::SWAP1, op::POP],
+            expected_return: InstructionResult::StackUnderflow,
+            expected_gas: 3 + 3 + 2,
+        }),
         // LLVM is slow on this, but it passes.
         // overflow_not0(@raw {
         //     bytecode: &[op::PUSH0; 1023],
@@ -123,6 +189,26 @@ tests! {
             expected_stack: &[U256::ZERO],
             expected_gas: 2,
         }),
+        tload_shanghai(@raw {
+            bytecode: &[op::PUSH1, 69, op::TLOAD],
+            spec_id: SpecId::SHANGHAI,
+            expected_return: InstructionResult::NotActivated,
+            expected_stack: &[69_U256],
+            expected_gas: 3,
+        }),
+        tstore_shanghai(@raw {
+            bytecode: &[op::PUSH1, 42, op::PUSH1, 69, op::TSTORE],
+            spec_id: SpecId::SHANGHAI,
+            expected_return: InstructionResult::NotActivated,
+            expected_stack: &[42_U256, 69_U256],
+            expected_gas: 3 + 3,
+        }),
+        tload_cancun(@raw {
+            bytecode: &[op::PUSH1, 69, op::TLOAD],
+            spec_id: SpecId::CANCUN,
+            expected_stack: &[0_U256],
+            expected_gas: 3 + 100,
+        }),
 
         pre_eof_in_legacy(@raw {
             bytecode: &[op::PUSH0, op::PUSH0, op::SWAPN, 0],
@@ -189,6 +275,19 @@ tests! {
             expected_stack: &[2_U256, 1_U256, 3_U256],
             expected_gas: 3 + 3 + 3 + 3,
         }),
+        shuffle(@raw {
+            bytecode: &[
+                op::PUSH1, 1, op::PUSH1, 2, op::PUSH1, 3, op::SWAP2, op::DUP2, op::SUB, op::SWAP1,
+                op::DIV, op::DUP1,
+            ],
+            expected_stack: &[3_U256, 2_U256, 2_U256],
+            expected_gas: 3 + 3 + 3 + 3 + 3 + 3 + 3 + 5 + 3,
+        }),
+        shuffle_after_builtin(@raw {
+            bytecode: &[op::PUSH1, 5, op::PUSH1, 3, op::PUSH1, 4, op::ADDMOD, op::DUP1, op::ADD],
+            expected_stack: &[4_U256],
+            expected_gas: 3 + 3 + 3 + 8 + 3 + 3,
+        }),
     }
 
     control_flow {
@@ -247,6 +346,62 @@ tests! {
             expected_return: InstructionResult::InvalidJump,
             expected_gas: 3 + 2 + 10,
         }),
+        computed_jump(@raw {
+            bytecode: &[
+                op::PUSH1, 3, op::PUSH1, 4, op::ADD, op::JUMP, op::INVALID, op::JUMPDEST,
+                op::PUSH1, 69,
+            ],
+            expected_stack: &[69_U256],
+            expected_gas: 3 + 3 + 3 + 8 + 1 + 3,
+        }),
+        computed_jump_shuffle(@raw {
+            bytecode: &[
+                op::PUSH1, 9, op::PUSH1, 42, op::SWAP1, op::DUP1, op::POP, op::JUMP, op::INVALID,
+                op::JUMPDEST,
+            ],
+            expected_stack: &[42_U256],
+            expected_gas: 3 + 3 + 3 + 3 + 2 + 8 + 1,
+        }),
+        computed_jumpi_invalid_target(@raw {
+            bytecode: &[op::PUSH1, 1, op::PUSH1, 2, op::PUSH1, 2, op::ADD, op::JUMPI],
+            expected_return: InstructionResult::InvalidJump,
+            expected_gas: 3 + 3 + 3 + 3 + 10,
+        }),
+        selector_switch(@raw {
+            bytecode: &selector_dispatch([1, 2, 0xaaaaaaaa, 3]),
+            expected_stack: &[0xaaaaaaaa_U256, 2_U256],
+            expected_gas: 2 + 3 + 3 + 3 + 3 * 22 + 1 + 3,
+        }),
+        selector_switch_first(@raw {
+            bytecode: &selector_dispatch([0xaaaaaaaa, 1, 2, 3]),
+            expected_stack: &[0xaaaaaaaa_U256, 0_U256],
+            expected_gas: 2 + 3 + 3 + 3 + 22 + 1 + 3,
+        }),
+        selector_switch_duplicate(@raw {
+            bytecode: &selector_dispatch([1, 0xaaaaaaaa, 0xaaaaaaaa, 2]),
+            expected_stack: &[0xaaaaaaaa_U256, 1_U256],
+            expected_gas: 2 + 3 + 3 + 3 + 2 * 22 + 1 + 3,
+        }),
+        selector_switch_default(@raw {
+            bytecode: &selector_dispatch([1, 2, 3, 4]),
+            expected_stack: &[0xaaaaaaaa_U256, 0xff_U256],
+            expected_gas: 2 + 3 + 3 + 3 + 4 * 22 + 3,
+        }),
+        jumpi_cmp(@raw {
+            bytecode: &[
+                op::PUSH1, 1, op::CALLDATASIZE, op::GT, op::PUSH1, 9, op::JUMPI, op::PUSH1, 42,
+                op::JUMPDEST,
+            ],
+            expected_gas: 3 + 2 + 3 + 3 + 10 + 1,
+        }),
+        jumpi_cmp_iszero(@raw {
+            bytecode: &[
+                op::PUSH1, 1, op::CALLDATASIZE, op::GT, op::ISZERO, op::PUSH1, 10, op::JUMPI,
+                op::PUSH1, 42, op::JUMPDEST,
+            ],
+            expected_stack: &[42_U256],
+            expected_gas: 3 + 2 + 3 + 3 + 3 + 10 + 3 + 1,
+        }),
         unmodified_stack_after_push_jumpi(@raw {
             bytecode: &[op::PUSH1, 1, op::PUSH1, 5, op::JUMPI, op::JUMPDEST, op::PUSH0, op::ADD],
             expected_return: InstructionResult::StackUnderflow,
@@ -388,6 +543,7 @@ tests! {
         div5(op::DIV, 4_U256, 2_U256 => 2_U256),
         div_by_zero1(op::DIV, 0_U256, 0_U256 => 0_U256),
         div_by_zero2(op::DIV, 32_U256, 0_U256 => 0_U256),
+        div_max_by_zero(op::DIV, U256::MAX, 0_U256 => 0_U256),
 
         rem1(op::MOD, 32_U256, 32_U256 => 0_U256),
         rem2(op::MOD, 1_U256, 2_U256 => 1_U256),
@@ -396,6 +552,7 @@ tests! {
         rem5(op::MOD, 4_U256, 2_U256 => 0_U256),
         rem_by_zero1(op::MOD, 0_U256, 0_U256 => 0_U256),
         rem_by_zero2(op::MOD, 32_U256, 0_U256 => 0_U256),
+        rem_max_by_zero(op::MOD, U256::MAX, 0_U256 => 0_U256),
 
         sdiv1(op::SDIV, 32_U256, 32_U256 => 1_U256),
         sdiv2(op::SDIV, 1_U256, 2_U256 => 0_U256),
@@ -408,6 +565,8 @@ tests! {
         sdiv_min_by_minus_1(op::SDIV, I256_MIN, -1_U256 => I256_MIN),
         sdiv_max1(op::SDIV, I256_MAX, 1_U256 => I256_MAX),
         sdiv_max2(op::SDIV, I256_MAX, -1_U256 => -I256_MAX),
+        sdiv_min_by_zero(op::SDIV, I256_MIN, 0_U256 => 0_U256),
+        sdiv_umax_by_min(op::SDIV, U256::MAX, I256_MIN => 0_U256),
 
         srem1(op::SMOD, 32_U256, 32_U256 => 0_U256),
         srem2(op::SMOD, 1_U256, 2_U256 => 1_U256),
@@ -416,6 +575,11 @@ tests! {
         srem5(op::SMOD, 4_U256, 2_U256 => 0_U256),
         srem_by_zero1(op::SMOD, 0_U256, 0_U256 => 0_U256),
         srem_by_zero2(op::SMOD, 32_U256, 0_U256 => 0_U256),
+        srem_min_by_zero(op::SMOD, I256_MIN, 0_U256 => 0_U256),
+        srem_max_by_zero(op::SMOD, U256::MAX, 0_U256 => 0_U256),
+        srem_min_by_minus_1(op::SMOD, I256_MIN, -1_U256 => 0_U256),
+        srem_max_by_minus_1(op::SMOD, I256_MAX, -1_U256 => 0_U256),
+        srem_min_by_1(op::SMOD, I256_MIN, 1_U256 => 0_U256),
 
         addmod1(op::ADDMOD, 1_U256, 2_U256, 3_U256 => 0_U256),
         addmod2(op::ADDMOD, 1_U256, 2_U256, 4_U256 => 3_U256),
@@ -449,6 +613,17 @@ tests! {
         signextend9(op::SIGNEXTEND, 1_U256, 0x8000_U256 => -0x8000_U256),
         signextend9_extra(op::SIGNEXTEND, 1_U256, 0x118000_U256 => -0x8000_U256),
         signextend10(op::SIGNEXTEND, 1_U256, 0xffff_U256 => U256::MAX),
+
+        fold_address_mask(@raw {
+            bytecode: &[op::PUSH1, 1, op::PUSH1, 1, op::PUSH1, 0xa0, op::SHL, op::SUB, op::CALLER, op::AND],
+            expected_stack: &[DEF_CALLER.into_word().into()],
+            expected_gas: 3 + 3 + 3 + 3 + 3 + 2 + 3,
+        }),
+        fold_nested(@raw {
+            bytecode: &[op::PUSH1, 3, op::PUSH1, 4, op::PUSH1, 5, op::MUL, op::ADD, op::PUSH0, op::NOT, op::XOR],
+            expected_stack: &[!23_U256],
+            expected_gas: 3 + 3 + 3 + 5 + 3 + 2 + 3 + 3,
+        }),
     }
 
     cmp {
@@ -479,9 +654,36 @@ tests! {
         iszero1(op::ISZERO, 0_U256 => 1_U256),
         iszero2(op::ISZERO, 1_U256 => 0_U256),
         iszero3(op::ISZERO, 2_U256 => 0_U256),
+
+        eq_iszero_equal(@raw {
+            bytecode: &[op::PUSH1, 1, op::PUSH1, 1, op::EQ, op::ISZERO],
+            expected_stack: &[0_U256],
+            expected_gas: 3 + 3 + 3 + 3,
+        }),
+        eq_iszero_unequal(@raw {
+            bytecode: &[op::PUSH1, 1, op::PUSH1, 2, op::EQ, op::ISZERO],
+            expected_stack: &[1_U256],
+            expected_gas: 3 + 3 + 3 + 3,
+        }),
+        eq_iszero_not_adjacent(@raw {
+            bytecode: &[op::PUSH1, 1, op::PUSH1, 2, op::EQ, op::PUSH0, op::POP, op::ISZERO],
+            expected_stack: &[1_U256],
+            expected_gas: 3 + 3 + 3 + 2 + 2 + 3,
+        }),
+        eq_iszero_underflow(@raw {
+            bytecode: &[op::PUSH1, 1, op::EQ, op::ISZERO],
+            expected_return: InstructionResult::StackUnderflow,
+            expected_stack: &[1_U256],
+            expected_gas: 3 + 3,
+        }),
+        eq_iszero_iszero(@raw {
+            bytecode: &[op::PUSH1, 1, op::PUSH1, 2, op::EQ, op::ISZERO, op::ISZERO],
+            expected_stack: &[0_U256],
+            expected_gas: 3 + 3 + 3 + 3 + 3,
+        }),
     }
 
-    bitwise {
+    bitwise @also(bitwise_vector = |jit| jit.vector_bitwise(true)) {
         and1(op::AND, 0_U256, 0_U256 => 0_U256),
         and2(op::AND, 1_U256, 1_U256 => 1_U256),
         and3(op::AND, 1_U256, 2_U256 => 0_U256),
@@ -582,6 +784,18 @@ tests! {
             expected_stack: &[DEF_VALUE, DEF_VALUE],
             expected_gas: 4,
         }),
+        callvalue_byte_order(@raw {
+            bytecode: &[op::CALLVALUE],
+            modify_ecx: Some(|ecx| ecx.contract.call_value = BYTE_ORDER_VALUE),
+            expected_stack: &[BYTE_ORDER_VALUE],
+            expected_gas: 2,
+        }),
+        caller_byte_order(@raw {
+            bytecode: &[op::CALLER],
+            modify_ecx: Some(|ecx| ecx.contract.caller = BYTE_ORDER_ADDR),
+            expected_stack: &[BYTE_ORDER_ADDR.into_word().into()],
+            expected_gas: 2,
+        }),
     }
 
     calldata {
@@ -595,6 +809,38 @@ tests! {
             expected_stack: &[0xaa00000000000000000000000000000000000000000000000000000000000000_U256],
             expected_gas: 3 + 3,
         }),
+        // Boundaries of `DEF_CD.len() == 64`.
+        calldataload_full_word_at_end(@raw {
+            bytecode: &[op::PUSH1, 32, op::CALLDATALOAD],
+            expected_stack: &[U256::from_be_slice(&DEF_CD[32..])],
+            expected_gas: 3 + 3,
+        }),
+        calldataload_partial(@raw {
+            bytecode: &[op::PUSH1, 33, op::CALLDATALOAD],
+            expected_stack: &[0xaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa00_U256],
+            expected_gas: 3 + 3,
+        }),
+        calldataload_at_len(@raw {
+            bytecode: &[op::PUSH1, 64, op::CALLDATALOAD],
+            expected_stack: &[0_U256],
+            expected_gas: 3 + 3,
+        }),
+        calldataload_past_len(@raw {
+            bytecode: &[op::PUSH2, 0xff, 0xff, op::CALLDATALOAD],
+            expected_stack: &[0_U256],
+            expected_gas: 3 + 3,
+        }),
+        // Must not be truncated to the pointer width before the bounds check.
+        calldataload_wrapping(@raw {
+            bytecode: &[op::PUSH9, 1, 0, 0, 0, 0, 0, 0, 0, 1, op::CALLDATALOAD],
+            expected_stack: &[0_U256],
+            expected_gas: 3 + 3,
+        }),
+        calldataload_max(@raw {
+            bytecode: &[op::PUSH0, op::NOT, op::CALLDATALOAD],
+            expected_stack: &[0_U256],
+            expected_gas: 2 + 3 + 3,
+        }),
         calldatasize(@raw {
             bytecode: &[op::CALLDATASIZE, op::CALLDATASIZE],
             expected_stack: &[U256::from(DEF_CD.len()), U256::from(DEF_CD.len())],
@@ -605,6 +851,17 @@ tests! {
             expected_memory: &DEF_CD[..32],
             expected_gas: 3 + 2 + 2 + (gas::verylowcopy_cost(32).unwrap() + 3),
         }),
+        // The second `MLOAD` reads memory directly and must observe the builtin's writes.
+        calldatacopy_mload(@raw {
+            bytecode: &[
+                op::PUSH1, 0x40, op::MLOAD,
+                op::PUSH1, 32, op::PUSH0, op::PUSH1, 0x40, op::CALLDATACOPY,
+                op::PUSH1, 0x40, op::MLOAD,
+            ],
+            expected_stack: &[0_U256, U256::from_be_slice(&DEF_CD[..32])],
+            expected_memory: MEMORY_WHAT_INTERPRETER_SAYS,
+            expected_gas: 3 + (3 + 9) + 3 + 2 + 3 + gas::verylowcopy_cost(32).unwrap() + 3 + 3,
+        }),
     }
 
     code {
@@ -710,6 +967,26 @@ tests! {
             op_gas(100)),
         extcodehash2(op::EXTCODEHASH, OTHER_ADDR.into_word().into() => def_codemap()[&OTHER_ADDR].hash_slow().into();
             op_gas(100)),
+        extcodehash_push20(@raw {
+            bytecode: &hex!("73 6969696969696969696969696969696969696969 3f"),
+            expected_stack: &[def_codemap()[&OTHER_ADDR].hash_slow().into()],
+            expected_gas: 3 + 100,
+        }),
+        // Only the low 20 bytes of the immediate are the address.
+        extcodesize_dirty_push32(@raw {
+            bytecode: &hex!("7f ffffffffffffffffffffffff 6969696969696969696969696969696969696969 3b"),
+            expected_stack: &[U256::from(def_codemap()[&OTHER_ADDR].len())],
+            expected_gas: 3 + 100,
+        }),
+        // The address is not pushed by the previous instruction.
+        extcodesize_dup(@raw {
+            bytecode: &hex!("73 6969696969696969696969696969696969696969 80 3b"),
+            expected_stack: &[
+                OTHER_ADDR.into_word().into(),
+                U256::from(def_codemap()[&OTHER_ADDR].len()),
+            ],
+            expected_gas: 3 + 3 + 100,
+        }),
     }
 
     env {
@@ -800,6 +1077,12 @@ tests! {
             expected_memory: &[0; 32],
             expected_gas: 2 + (3 + gas::memory_gas(1)),
         }),
+        dup1_mload(@raw {
+            bytecode: &[op::PUSH1, 0x2a, op::PUSH0, op::MSTORE, op::PUSH0, op::DUP1, op::MLOAD],
+            expected_stack: &[0_U256, 0x2a_U256],
+            expected_memory: &0x2a_U256.to_be_bytes::<32>(),
+            expected_gas: 3 + 2 + (3 + gas::memory_gas(1)) + 2 + 3 + 3,
+        }),
         mload2(@raw {
             bytecode: &[op::PUSH1, 1, op::MLOAD],
             expected_stack: &[0_U256],
@@ -907,6 +1190,13 @@ tests! {
 
     host {
         balance(op::BALANCE, 0_U256 => 0_U256; op_gas(100)),
+        balance_rjump(@raw {
+            // `PUSH1 0x42 PUSH1 1 RJUMPI 22 POP PUSH20 0x69.. BALANCE STOP`
+            bytecode: &eof(&hex!("60426001e10016507369696969696969696969696969696969696969693100")),
+            spec_id: SpecId::PRAGUE_EOF,
+            expected_stack: &[0x42_U256],
+            expected_gas: 3 + 3 + 4 + 100,
+        }),
         sload1(@raw {
             bytecode: &[op::PUSH1, 69, op::SLOAD],
             expected_stack: &[42_U256],
@@ -1242,6 +1532,49 @@ tests! {
                 assert_eq!(host.selfdestructs, [(DEF_ADDR, Address::with_last_byte(0x69))]);
             }),
         }),
+        selfdestruct_static(@raw {
+            bytecode: &[op::PUSH1, 0x69, op::SELFDESTRUCT, op::INVALID],
+            modify_ecx: Some(|ecx| ecx.is_static = true),
+            expected_return: InstructionResult::StateChangeDuringStaticCall,
+            expected_gas: GAS_WHAT_INTERPRETER_SAYS,
+            assert_host: Some(|host| assert!(host.selfdestructs.is_empty())),
+        }),
+        // State changes are rejected in the builtins, which read `EvmContext::is_static`.
+        sstore_static(@raw {
+            bytecode: &[op::PUSH1, 0x42, op::PUSH1, 0x69, op::SSTORE],
+            modify_ecx: Some(|ecx| ecx.is_static = true),
+            expected_return: InstructionResult::StateChangeDuringStaticCall,
+            expected_gas: GAS_WHAT_INTERPRETER_SAYS,
+            assert_host: Some(|host| {
+                assert_eq!(host.storage.get(&0x69_U256), def_storage().get(&0x69_U256));
+            }),
+        }),
+        tstore_static(@raw {
+            bytecode: &[op::PUSH1, 0x42, op::PUSH1, 0x69, op::TSTORE],
+            modify_ecx: Some(|ecx| ecx.is_static = true),
+            expected_return: InstructionResult::StateChangeDuringStaticCall,
+            expected_gas: GAS_WHAT_INTERPRETER_SAYS,
+            assert_host: Some(|host| assert!(host.transient_storage.is_empty())),
+        }),
+        log0_static(@raw {
+            bytecode: &[op::PUSH0, op::PUSH0, op::LOG0],
+            modify_ecx: Some(|ecx| ecx.is_static = true),
+            expected_return: InstructionResult::StateChangeDuringStaticCall,
+            expected_gas: GAS_WHAT_INTERPRETER_SAYS,
+            assert_host: Some(|host| assert!(host.log.is_empty())),
+        }),
+        create_static(@raw {
+            bytecode: &[op::PUSH0, op::PUSH0, op::PUSH0, op::CREATE],
+            modify_ecx: Some(|ecx| ecx.is_static = true),
+            expected_return: InstructionResult::StateChangeDuringStaticCall,
+            expected_gas: GAS_WHAT_INTERPRETER_SAYS,
+        }),
+        create2_static(@raw {
+            bytecode: &[op::PUSH0, op::PUSH0, op::PUSH0, op::PUSH0, op::CREATE2],
+            modify_ecx: Some(|ecx| ecx.is_static = true),
+            expected_return: InstructionResult::StateChangeDuringStaticCall,
+            expected_gas: GAS_WHAT_INTERPRETER_SAYS,
+        }),
     }
 
     regressions {
@@ -1294,7 +1627,7 @@ fn eof_sections(code: &[&[u8]]) -> Bytes {
 }
 
 // We have to expose this because validation fails at invalid type sections
-fn eof_sections_unchecked(code: &[&[u8]]) -> primitives::Eof {
+pub(crate) fn eof_sections_unchecked(code: &[&[u8]]) -> primitives::Eof {
     eof_body(code, vec![eof_subcontainer()]).into_eof()
 }
 
@@ -1323,6 +1656,23 @@ fn eof_subcontainer() -> Bytes {
     eof_body(&[&[op::STOP]], vec![]).into_eof().raw
 }
 
+/// Returns a Solidity-like dispatch on the selector of the calldata, where the function of every
+/// selector pushes its index, and the fallback pushes `0xff`.
+fn selector_dispatch(selectors: [u32; 4]) -> Vec<u8> {
+    let mut code = vec![op::PUSH0, op::CALLDATALOAD, op::PUSH1, 0xe0, op::SHR];
+    let functions = code.len() + selectors.len() * 10 + 3;
+    for (i, selector) in selectors.into_iter().enumerate() {
+        code.extend([op::DUP1, op::PUSH4]);
+        code.extend(selector.to_be_bytes());
+        code.extend([op::EQ, op::PUSH1, (functions + i * 4) as u8, op::JUMPI]);
+    }
+    code.extend([op::PUSH1, 0xff, op::STOP]);
+    for i in 0..selectors.len() {
+        code.extend([op::JUMPDEST, op::PUSH1, i as u8, op::STOP]);
+    }
+    code
+}
+
 fn bytecode_unop(op: u8, a: U256) -> [u8; 34] {
     let mut code = [0; 34];
     let mut i = 0;
@@ -1,4 +1,5 @@
 use super::*;
+use crate::compiler::{MemDisplay, Outcome};
 use interpreter::{AccountLoad, Eip7702CodeLoad, SStoreResult, StateLoad};
 use revm_interpreter::{opcode as op, Contract, DummyHost, Host, SelfDestructResult};
 use revm_primitives::{
@@ -200,6 +201,7 @@ pub fn def_codemap() -> &'static HashMap<Address, primitives::Bytecode> {
 }
 
 /// Wrapper around `DummyHost` that provides a stable environment and storage for testing.
+#[derive(Clone)]
 pub struct TestHost {
     pub host: DummyHost,
     pub code_map: &'static HashMap<Address, primitives::Bytecode>,
@@ -373,6 +375,15 @@ pub fn run_test_case<B: Backend>(test_case: &TestCase<'_>, compiler: &mut EvmCom
     // compiler.debug_assertions(false);
     let f = unsafe { compiler.jit("test", bytecode, spec_id) }.unwrap();
     run_compiled_test_case(test_case, f);
+
+    // Also run the instructions that were folded into constants.
+    let bytecode = compiler.parse(bytecode.into(), spec_id).unwrap();
+    if bytecode.iter_insts().any(|(_, data)| data.flags.contains(InstFlags::CONST)) {
+        compiler.constant_folding(false);
+        let f = unsafe { compiler.jit("test_unfolded", test_case.bytecode, spec_id) }.unwrap();
+        compiler.constant_folding(true);
+        run_compiled_test_case(test_case, f);
+    }
 }
 
 fn run_compiled_test_case(test_case: &TestCase<'_>, f: EvmCompilerFn) {
@@ -471,42 +482,40 @@ fn run_compiled_test_case(test_case: &TestCase<'_>, f: EvmCompilerFn) {
 
         let actual_return = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
 
-        if matches!(
-            actual_return,
-            // We can have a stack overflow/underflow before other error codes due to sections.
-            |InstructionResult::StackOverflow| InstructionResult::StackUnderflow
-            // Any OOG is equivalent. We skip `InvalidOperand` sometimes.
-            | InstructionResult::OutOfGas | InstructionResult::MemoryOOG | InstructionResult::InvalidOperandOOG
-        ) {
-            assert_eq!(
-                actual_return.is_error(),
-                expected_return.is_error(),
-                "return value mismatch: {actual_return:?} != {expected_return:?}"
-            );
-        } else {
-            assert_eq!(actual_return, expected_return, "return value mismatch");
-        }
-
         let actual_stack =
             stack.as_slice().iter().take(*stack_len).map(|x| x.to_u256()).collect::<Vec<_>>();
-
-        // On EVM halt all available gas is consumed, so resulting stack, memory, and gas do not
-        // matter. We do less work than the interpreter by bailing out earlier due to sections.
-        if !actual_return.is_error() {
-            assert_eq!(actual_stack, *expected_stack, "stack mismatch");
-
-            assert_eq!(
-                MemDisplay(ecx.memory.context_memory()),
-                MemDisplay(expected_memory),
-                "interpreter memory mismatch"
-            );
-
-            assert_eq!(ecx.gas.spent(), expected_gas, "gas mismatch");
-        }
-
         let actual_next_action =
             if ecx.next_action.is_none() { &default_action } else { &*ecx.next_action };
-        assert_actions(actual_next_action, expected_next_action);
+        // The gas of the expected result is not compared if it is what the interpreter says.
+        let mut expected_next_action = expected_next_action.clone();
+        if let (
+            InterpreterAction::Return { result: expected },
+            InterpreterAction::Return { result },
+        ) = (&mut expected_next_action, actual_next_action)
+        {
+            if expected.gas.limit() == GAS_WHAT_INTERPRETER_SAYS {
+                expected.gas = result.gas;
+            }
+        }
+        let actual = Outcome {
+            result: actual_return,
+            stack: Some(&actual_stack),
+            memory: ecx.memory.context_memory(),
+            gas_spent: ecx.gas.spent(),
+            gas_refunded: None,
+            next_action: Some(actual_next_action),
+        };
+        let expected = Outcome {
+            result: expected_return,
+            stack: Some(expected_stack),
+            memory: expected_memory,
+            gas_spent: expected_gas,
+            gas_refunded: None,
+            next_action: Some(&expected_next_action),
+        };
+        if let Err(msg) = actual.compare(&expected) {
+            panic!("{msg}");
+        }
 
         if let Some(_assert_host) = assert_host {
             #[cfg(not(feature = "__fuzzing"))]
@@ -519,15 +528,6 @@ fn run_compiled_test_case(test_case: &TestCase<'_>, f: EvmCompilerFn) {
     });
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct MemDisplay<'a>(&'a [u8]);
-impl fmt::Debug for MemDisplay<'_> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let chunks = self.0.chunks(32).map(revm_primitives::hex::encode_prefixed);
-        f.debug_list().entries(chunks).finish()
-    }
-}
-
 #[track_caller]
 fn assert_actions(actual: &InterpreterAction, expected: &InterpreterAction) {
     match (actual, expected) {
@@ -0,0 +1,60 @@
+use super::{with_evm_context, DEF_SPEC};
+use crate::{Backend, EvmCompiler};
+use paste::paste;
+use revm_interpreter::{opcode as op, InstructionResult};
+use revm_primitives::U256;
+
+macro_rules! swap_tests {
+    ($($n:literal),* $(,)?) => {paste! {
+        $(
+            matrix_tests!([<swap $n>] = |jit| run_swap_test(jit, $n));
+        )*
+    }};
+}
+
+swap_tests!(1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16);
+
+fn run_swap_test<B: Backend>(compiler: &mut EvmCompiler<B>, n: u8) {
+    let code = mk_swap_code(n);
+
+    compiler.inspect_stack_length(true);
+    let f = unsafe { compiler.jit("swap", &code, DEF_SPEC) }.unwrap();
+
+    with_evm_context(&code, |ecx, stack, stack_len| {
+        let r = unsafe { f.call(Some(stack), Some(stack_len), ecx) };
+        assert_eq!(r, InstructionResult::Stop);
+        let actual = stack.as_slice()[..*stack_len].iter().map(|x| x.to_u256()).collect::<Vec<_>>();
+        assert_eq!(actual, expected_stack(n), "SWAP{n}");
+        assert_eq!(ecx.gas.spent(), 3 * (n as u64 + 2) + 3, "SWAP{n}");
+    });
+}
+
+/// Pushes `n + 2` distinct values, so that there is one untouched value below the swapped
+/// pair, and then executes `SWAP<n>`.
+fn mk_swap_code(n: u8) -> Vec<u8> {
+    let mut code = Vec::with_capacity((n as usize + 2) * 2 + 1);
+    for i in 0..n + 2 {
+        code.extend([op::PUSH1, sentinel(i)]);
+    }
+    code.push(op::SWAP1 + n - 1);
+    code
+}
+
+/// Returns the expected stack, from bottom to top, after executing [`mk_swap_code`].
+fn expected_stack(n: u8) -> Vec<U256> {
+    let mut stack = (0..n + 2).map(|i| U256::from(sentinel(i))).collect::<Vec<_>>();
+    let top = stack.len() - 1;
+    stack.swap(top, top - n as usize);
+    stack
+}
+
+fn sentinel(i: u8) -> u8 {
+    0xa0 + i
+}
+
+#[test]
+fn test_expected_stack() {
+    let s = |i| U256::from(sentinel(i));
+    assert_eq!(expected_stack(1), [s(0), s(2), s(1)]);
+    assert_eq!(expected_stack(2), [s(0), s(3), s(2), s(1)]);
+}
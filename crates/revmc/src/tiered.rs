@@ -0,0 +1,333 @@
+use crate::{
+    code_hash_symbol_name, Backend, EvmCompiler, EvmCompilerFnInfo, EvmCompilerFns, Hotness,
+    HotnessCounters, Result,
+};
+use revm_primitives::{Bytes, SpecId, B256};
+use rustc_hash::FxHashSet;
+use std::{
+    fmt,
+    sync::{mpsc, Arc, Mutex, PoisonError},
+    thread,
+};
+
+/// A callback called on the compiler thread of a [`TieredCompiler`] after every compilation, with
+/// the bytecode hash and the result.
+///
+/// On success, it is called after the function is inserted into the registry, that is once the
+/// following frames execute the compiled function instead of the interpreter.
+pub type OnCompiled = Box<dyn FnMut(B256, Result<EvmCompilerFnInfo, &crate::Error>) + Send>;
+
+/// Compiles contracts on a background thread, while they keep being executed by the interpreter.
+///
+/// Compiling a contract is requested with [`request`](Self::request), which returns immediately.
+/// Once the contract is compiled, its function is inserted into the [registry](Self::registry),
+/// which can be used as the external context of `revmc_context::register_handler` so that the
+/// following frames execute the compiled function instead of the interpreter.
+///
+/// Alternatively, the executions of the bytecodes by the interpreter can be
+/// [recorded](Self::record), so that only the bytecodes that are hot according to the
+/// [`HotnessPolicy`] are compiled. With the `revm` feature, this is done automatically when the
+/// tiered compiler itself, e.g. in an `Arc`, is used as the external context of `register_handler`.
+///
+/// The compiler is created on the compiler thread, so it does not have to be `Send`:
+///
+/// ```ignore
+/// let tiered = unsafe {
+///     TieredCompiler::spawn(
+///         |worker| {
+///             revmc::llvm::with_llvm_context(|cx| {
+///                 let backend = EvmLlvmBackend::new(cx, false, OptimizationLevel::Aggressive)
+///                     .expect("failed to create the backend");
+///                 worker.run(&mut EvmCompiler::new(backend));
+///             })
+///         },
+///         None,
+///     )
+/// };
+/// ```
+pub struct TieredCompiler {
+    jobs: Option<mpsc::Sender<Job>>,
+    registry: Arc<EvmCompilerFns>,
+    requested: Mutex<FxHashSet<(B256, SpecId)>>,
+    counters: HotnessCounters,
+    policy: HotnessPolicy,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl fmt::Debug for TieredCompiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TieredCompiler")
+            .field("registry", &self.registry)
+            .field("counters", &self.counters)
+            .field("policy", &self.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+struct Job {
+    bytecode_hash: B256,
+    bytecode: Bytes,
+    spec_id: SpecId,
+}
+
+impl TieredCompiler {
+    /// Spawns the compiler thread, which calls `worker`.
+    ///
+    /// `worker` must [run](TieredWorker::run) the given [`TieredWorker`] with the compiler to use,
+    /// which must be in JIT mode.
+    ///
+    /// # Safety
+    ///
+    /// The compiled functions are freed when this is dropped, so none of them may be executing or
+    /// called afterwards, e.g. the `revm::Evm` using the registry must be dropped first. See
+    /// [`EvmCompilerFns::insert`].
+    pub unsafe fn spawn(
+        worker: impl FnOnce(TieredWorker) + Send + 'static,
+        on_compiled: Option<OnCompiled>,
+    ) -> Self {
+        let (jobs, receiver) = mpsc::channel();
+        let registry = Arc::new(EvmCompilerFns::new());
+        let tiered_worker =
+            TieredWorker { jobs: receiver, registry: registry.clone(), on_compiled };
+        let thread = thread::Builder::new()
+            .name("revmc-tiered".into())
+            .spawn(move || worker(tiered_worker))
+            .expect("failed to spawn the compiler thread");
+        Self {
+            jobs: Some(jobs),
+            registry,
+            requested: Mutex::default(),
+            counters: HotnessCounters::new(),
+            policy: HotnessPolicy::default(),
+            thread: Some(thread),
+        }
+    }
+
+    /// Returns the policy deciding which of the [recorded](Self::record) bytecodes are compiled.
+    pub fn policy(&self) -> HotnessPolicy {
+        self.policy
+    }
+
+    /// Sets the policy deciding which of the [recorded](Self::record) bytecodes are compiled.
+    pub fn set_policy(&mut self, policy: HotnessPolicy) {
+        self.policy = policy;
+    }
+
+    /// Returns the execution counters of the [recorded](Self::record) bytecodes.
+    pub fn counters(&self) -> &HotnessCounters {
+        &self.counters
+    }
+
+    /// Returns the registry of the compiled functions.
+    pub fn registry(&self) -> &Arc<EvmCompilerFns> {
+        &self.registry
+    }
+
+    /// Requests compiling the given bytecode for `spec_id` in the background.
+    ///
+    /// Every bytecode hash is only compiled once for each `SpecId`, even if compilation fails.
+    /// Returns `true` if the bytecode was not requested for `spec_id` before.
+    ///
+    /// The functions compiled for each `SpecId` are inserted into the registry separately, so
+    /// compiling a bytecode for another `SpecId` does not replace a function that may be in use.
+    pub fn request(&self, bytecode_hash: B256, bytecode: Bytes, spec_id: SpecId) -> bool {
+        let mut requested = self.requested.lock().unwrap_or_else(PoisonError::into_inner);
+        if !requested.insert((bytecode_hash, spec_id)) {
+            return false;
+        }
+        let jobs = self.jobs.as_ref().expect("compiler thread stopped");
+        // The worker only stops early if it panicked, in which case nothing is compiled anymore.
+        let _ = jobs.send(Job { bytecode_hash, bytecode, spec_id });
+        true
+    }
+
+    /// Records an execution of the given bytecode by the interpreter, and
+    /// [requests](Self::request) compiling it once it is hot according to the
+    /// [policy](Self::policy).
+    ///
+    /// Returns `true` if compiling the bytecode was requested.
+    pub fn record(
+        &self,
+        bytecode_hash: B256,
+        bytecode: &[u8],
+        spec_id: SpecId,
+        hotness: Hotness,
+    ) -> bool {
+        let hotness = self.counters.record(bytecode_hash, hotness);
+        self.policy.is_hot(&hotness)
+            && self.request(bytecode_hash, Bytes::copy_from_slice(bytecode), spec_id)
+    }
+}
+
+// SAFETY: The functions are inserted into the registry by the worker, see `EvmCompilerFns`.
+#[cfg(feature = "revm")]
+unsafe impl crate::EvmCompilerFnLookup for TieredCompiler {
+    fn get_function(&self, bytecode_hash: B256, spec_id: SpecId) -> Option<crate::EvmCompilerFn> {
+        self.registry.get(&bytecode_hash, spec_id).map(|info| info.f)
+    }
+
+    fn on_interpreted(
+        &self,
+        bytecode_hash: B256,
+        contract: &revm_interpreter::Contract,
+        spec_id: SpecId,
+        hotness: Hotness,
+    ) {
+        let bytecode = contract.bytecode.original_byte_slice();
+        self.record(bytecode_hash, bytecode, spec_id, hotness);
+    }
+}
+
+impl Drop for TieredCompiler {
+    fn drop(&mut self) {
+        // Closing the channel stops the worker once the pending requests are compiled.
+        drop(self.jobs.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The thresholds of the execution counters at which a bytecode is hot, and is compiled by a
+/// [`TieredCompiler`].
+///
+/// A bytecode is hot once either threshold is reached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HotnessPolicy {
+    /// The minimum number of invocations.
+    pub min_invocations: u64,
+    /// The minimum total gas used.
+    pub min_gas_used: u64,
+}
+
+impl Default for HotnessPolicy {
+    fn default() -> Self {
+        Self { min_invocations: 1_000, min_gas_used: 10_000_000 }
+    }
+}
+
+impl HotnessPolicy {
+    /// Returns `true` if a bytecode with the given execution counters is hot.
+    pub fn is_hot(&self, hotness: &Hotness) -> bool {
+        hotness.invocations >= self.min_invocations || hotness.gas_used >= self.min_gas_used
+    }
+}
+
+/// The compiler thread of a [`TieredCompiler`].
+pub struct TieredWorker {
+    jobs: mpsc::Receiver<Job>,
+    registry: Arc<EvmCompilerFns>,
+    on_compiled: Option<OnCompiled>,
+}
+
+impl fmt::Debug for TieredWorker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TieredWorker").field("registry", &self.registry).finish_non_exhaustive()
+    }
+}
+
+impl TieredWorker {
+    /// Compiles the requested bytecodes with `compiler` until the [`TieredCompiler`] is dropped.
+    ///
+    /// The compiled functions are owned by `compiler`, and are removed from the registry before
+    /// this returns.
+    pub fn run<B: Backend>(mut self, compiler: &mut EvmCompiler<B>) {
+        for job in self.jobs.iter() {
+            let Job { bytecode_hash, bytecode, spec_id } = job;
+            let name = format!("{}_{spec_id:?}", code_hash_symbol_name(&bytecode_hash));
+            let result = unsafe { compiler.jit(&name, &bytecode[..], spec_id) }
+                .map(|f| EvmCompilerFnInfo { f, spec_id });
+            match &result {
+                Ok(info) => unsafe {
+                    self.registry.insert(bytecode_hash, *info);
+                },
+                Err(e) => debug!(%bytecode_hash, ?spec_id, %e, "failed to compile"),
+            }
+            if let Some(on_compiled) = &mut self.on_compiled {
+                on_compiled(bytecode_hash, result.as_ref().copied());
+            }
+        }
+        self.registry.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::with_evm_context;
+    use revm_interpreter::{opcode as op, InstructionResult};
+    use revm_primitives::{keccak256, U256};
+
+    #[test]
+    fn tiered() {
+        let (sender, receiver) = mpsc::channel();
+        let on_compiled: OnCompiled =
+            Box::new(move |hash, result| sender.send((hash, result.is_ok())).unwrap());
+        let mut tiered = unsafe {
+            TieredCompiler::spawn(
+                |worker| {
+                    crate::llvm::with_llvm_context(|cx| {
+                        let opt_level = crate::OptimizationLevel::Aggressive;
+                        let backend = crate::EvmLlvmBackend::new(cx, false, opt_level).unwrap();
+                        worker.run(&mut EvmCompiler::new(backend));
+                    })
+                },
+                Some(on_compiled),
+            )
+        };
+
+        let add: &[u8] = &[op::PUSH1, 0x42, op::PUSH1, 0x69, op::ADD, op::STOP];
+        let hash = keccak256(add);
+        assert!(tiered.registry().get(&hash, SpecId::CANCUN).is_none());
+        assert!(tiered.request(hash, Bytes::from_static(add), SpecId::CANCUN));
+        assert!(!tiered.request(hash, Bytes::from_static(add), SpecId::CANCUN));
+        assert_eq!(receiver.recv().unwrap(), (hash, true));
+        let info = tiered.registry().get(&hash, SpecId::CANCUN).expect("missing function");
+        assert_eq!(info.spec_id, SpecId::CANCUN);
+        with_evm_context(add, |ecx, stack, stack_len| {
+            let r = unsafe { info.f.call(Some(stack), Some(stack_len), ecx) };
+            assert_eq!(r, InstructionResult::Stop);
+            assert_eq!(*stack_len, 1);
+            assert_eq!(stack.as_slice()[0].to_u256(), U256::from(0x42 + 0x69));
+        });
+
+        // Another `SpecId` is compiled separately, and is added next to the first function.
+        assert!(tiered.request(hash, Bytes::from_static(add), SpecId::SHANGHAI));
+        assert!(!tiered.request(hash, Bytes::from_static(add), SpecId::SHANGHAI));
+        assert_eq!(receiver.recv().unwrap(), (hash, true));
+        assert_eq!(tiered.registry().len(), 2);
+        assert_eq!(tiered.registry().get(&hash, SpecId::CANCUN), Some(info));
+        let shanghai = tiered.registry().get(&hash, SpecId::SHANGHAI).expect("missing function");
+        assert_eq!(shanghai.spec_id, SpecId::SHANGHAI);
+        #[cfg(feature = "revm")]
+        {
+            use crate::EvmCompilerFnLookup;
+            assert_eq!(tiered.get_function(hash, SpecId::CANCUN), Some(info.f));
+            assert_eq!(tiered.get_function(hash, SpecId::SHANGHAI), Some(shanghai.f));
+            assert!(tiered.get_function(hash, SpecId::LONDON).is_none());
+        }
+
+        // Invalid EOF.
+        let invalid = Bytes::from_static(&[0xef, 0x00, 0x01]);
+        let invalid_hash = keccak256(&invalid);
+        assert!(tiered.request(invalid_hash, invalid, SpecId::PRAGUE_EOF));
+        assert_eq!(receiver.recv().unwrap(), (invalid_hash, false));
+        assert!(tiered.registry().get(&invalid_hash, SpecId::PRAGUE_EOF).is_none());
+
+        // Hotness.
+        tiered.set_policy(HotnessPolicy { min_invocations: 2, min_gas_used: u64::MAX });
+        let hot: &[u8] = &[op::PUSH1, 0x01, op::STOP];
+        let hot_hash = keccak256(hot);
+        let hotness = Hotness { invocations: 1, gas_used: 3 };
+        assert!(!tiered.record(hot_hash, hot, SpecId::CANCUN, hotness));
+        assert!(tiered.record(hot_hash, hot, SpecId::CANCUN, hotness));
+        assert!(!tiered.record(hot_hash, hot, SpecId::CANCUN, hotness));
+        assert_eq!(tiered.counters().get(&hot_hash), Some(Hotness { invocations: 3, gas_used: 9 }));
+        assert_eq!(receiver.recv().unwrap(), (hot_hash, true));
+        assert!(tiered.registry().get(&hot_hash, SpecId::CANCUN).is_some());
+
+        let registry = tiered.registry().clone();
+        drop(tiered);
+        assert!(registry.is_empty());
+    }
+}
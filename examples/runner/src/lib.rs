@@ -8,13 +8,11 @@ extern crate alloc;
 // but we don't use it directly, so silence the unused crate dependency warning.
 use revmc_builtins as _;
 
-use alloc::sync::Arc;
 use revm::{
-    handler::register::EvmHandler,
-    primitives::{hex, B256},
+    primitives::{hex, SpecId, B256},
     Database,
 };
-use revmc_context::EvmCompilerFn;
+use revmc_context::{EvmCompilerFn, EvmCompilerFnLookup};
 
 include!("./common.rs");
 
@@ -28,7 +26,8 @@ pub fn build_evm<'a, DB: Database + 'static>(db: DB) -> revm::Evm<'a, ExternalCo
     revm::Evm::builder()
         .with_db(db)
         .with_external_context(ExternalContext::new())
-        .append_handler_register(register_handler)
+        .with_spec_id(SpecId::CANCUN)
+        .append_handler_register(revmc_context::register_handler)
         .build()
 }
 
@@ -38,27 +37,16 @@ impl ExternalContext {
     fn new() -> Self {
         Self
     }
+}
 
-    fn get_function(&self, bytecode_hash: B256) -> Option<EvmCompilerFn> {
+// SAFETY: `fibonacci` is compiled from `FIBONACCI_CODE` for `SpecId::CANCUN`.
+unsafe impl EvmCompilerFnLookup for ExternalContext {
+    fn get_function(&self, bytecode_hash: B256, spec_id: SpecId) -> Option<EvmCompilerFn> {
         // Can use any mapping between bytecode hash and function.
-        if bytecode_hash == FIBONACCI_HASH {
+        if bytecode_hash == FIBONACCI_HASH && spec_id == SpecId::CANCUN {
             return Some(EvmCompilerFn::new(fibonacci));
         }
 
         None
     }
 }
-
-// This `+ 'static` bound is only necessary here because of an internal cfg feature.
-fn register_handler<DB: Database + 'static>(handler: &mut EvmHandler<'_, ExternalContext, DB>) {
-    let prev = handler.execution.execute_frame.clone();
-    handler.execution.execute_frame = Arc::new(move |frame, memory, tables, context| {
-        let interpreter = frame.interpreter_mut();
-        let bytecode_hash = interpreter.contract.hash.unwrap_or_default();
-        if let Some(f) = context.external.get_function(bytecode_hash) {
-            Ok(unsafe { f.call_with_interpreter_and_memory(interpreter, memory, context) })
-        } else {
-            prev(frame, memory, tables, context)
-        }
-    });
-}